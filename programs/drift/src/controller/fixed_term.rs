@@ -0,0 +1,575 @@
+use anchor_lang::prelude::*;
+use solana_program::msg;
+
+use crate::controller::spot_balance::{
+    transfer_spot_balances, update_spot_balances, update_spot_market_cumulative_interest,
+};
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
+use crate::math::constants::TWENTY_FOUR_HOUR;
+use crate::math::fixed_term::{
+    calculate_fixed_term_amount_at_maturity, calculate_fixed_term_fill_rate,
+};
+use crate::math::margin::{
+    calculate_margin_requirement_and_total_collateral_and_liability_info,
+    meets_withdraw_margin_requirement, MarginRequirementType,
+};
+use crate::math::safe_math::SafeMath;
+use crate::math::spot_balance::get_token_amount;
+use crate::state::events::{FixedTermAction, FixedTermRecord};
+use crate::state::fixed_term_pool::{FixedTermPool, FixedTermPoolMap};
+use crate::state::margin_calculation::MarginContext;
+use crate::state::oracle_map::OracleMap;
+use crate::state::perp_market::MarketStatus;
+use crate::state::perp_market_map::PerpMarketMap;
+use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+use crate::state::spot_market_map::SpotMarketMap;
+use crate::state::user::{FixedTermDirection, FixedTermPosition, User};
+use crate::validate;
+
+#[cfg(test)]
+mod tests;
+
+/// Opens an order to lend or borrow principal in a spot market until maturity at a fixed rate.
+/// Maturities fall on day boundaries so orders for the same term meet. Rates are capped at the spot
+/// market's max borrow rate. A lender's deposit has to cover the principal and a borrower has to meet
+/// initial margin as if the order had filled
+pub fn place_fixed_term_order(
+    user: &mut User,
+    market_index: u16,
+    maturity_ts: i64,
+    direction: FixedTermDirection,
+    principal: u64,
+    rate: u32,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    slot: u64,
+    now: i64,
+) -> DriftResult {
+    validate!(
+        !user.is_bankrupt(),
+        ErrorCode::UserBankrupt,
+        "user bankrupt"
+    )?;
+
+    validate!(
+        !user.is_being_liquidated(),
+        ErrorCode::UserIsBeingLiquidated,
+        "user being liquidated"
+    )?;
+
+    {
+        let spot_market = spot_market_map.get_ref(&market_index)?;
+
+        validate!(
+            spot_market.status == MarketStatus::Active,
+            ErrorCode::InvalidFixedTermOrder,
+            "spot market {} not active",
+            market_index
+        )?;
+
+        validate!(
+            rate <= spot_market.max_borrow_rate,
+            ErrorCode::InvalidFixedTermOrder,
+            "rate {} above max borrow rate {}",
+            rate,
+            spot_market.max_borrow_rate
+        )?;
+
+        if direction == FixedTermDirection::Lend {
+            validate_deposit_covers_principal(user, &spot_market, principal)?;
+        }
+    }
+
+    validate!(
+        principal > 0,
+        ErrorCode::InvalidFixedTermOrder,
+        "principal must be greater than 0"
+    )?;
+
+    validate!(
+        maturity_ts > now && maturity_ts % TWENTY_FOUR_HOUR == 0,
+        ErrorCode::InvalidFixedTermOrder,
+        "maturity_ts {} must be a day boundary after now {}",
+        maturity_ts,
+        now
+    )?;
+
+    let fixed_term_position = user.force_get_fixed_term_position_mut(market_index, maturity_ts)?;
+
+    validate!(
+        !fixed_term_position.has_open_order(),
+        ErrorCode::InvalidFixedTermOrder,
+        "already an open order for market {} maturing at {}",
+        market_index,
+        maturity_ts
+    )?;
+
+    fixed_term_position.open_order_principal = principal;
+    fixed_term_position.open_order_rate = rate;
+    fixed_term_position.open_order_slot = slot;
+    fixed_term_position.open_order_direction = direction;
+
+    if direction == FixedTermDirection::Borrow {
+        meets_fixed_term_borrow_margin_requirement(
+            user,
+            market_index,
+            maturity_ts,
+            principal,
+            rate,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Lending only moves existing deposits, it never opens a variable borrow
+fn validate_deposit_covers_principal(
+    user: &User,
+    spot_market: &SpotMarket,
+    principal: u64,
+) -> DriftResult {
+    let deposit_token_amount = match user.get_spot_position(spot_market.market_index) {
+        Ok(spot_position) if spot_position.balance_type == SpotBalanceType::Deposit => {
+            spot_position.get_token_amount(spot_market)?
+        }
+        _ => 0,
+    };
+
+    validate!(
+        deposit_token_amount >= principal.cast()?,
+        ErrorCode::InsufficientDeposit,
+        "deposit {} can't cover principal {}",
+        deposit_token_amount,
+        principal
+    )
+}
+
+/// Margins a borrow order as if it had filled, with the principal deposited and the amount at maturity
+/// owed, then puts the user's positions back
+fn meets_fixed_term_borrow_margin_requirement(
+    user: &mut User,
+    market_index: u16,
+    maturity_ts: i64,
+    principal: u64,
+    rate: u32,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+) -> DriftResult {
+    let amount_at_maturity =
+        calculate_fixed_term_amount_at_maturity(principal, rate, now, maturity_ts)?;
+
+    let spot_position_index = user.force_get_spot_position_index(market_index)?;
+    let fixed_term_position_index =
+        user.get_fixed_term_position_index(market_index, maturity_ts)?;
+    let spot_position = user.spot_positions[spot_position_index];
+    let fixed_term_position = user.fixed_term_positions[fixed_term_position_index];
+
+    let mut spot_market = *spot_market_map.get_ref(&market_index)?;
+    update_spot_balances(
+        principal.cast()?,
+        &SpotBalanceType::Deposit,
+        &mut spot_market,
+        &mut user.spot_positions[spot_position_index],
+        false,
+    )?;
+    user.fixed_term_positions[fixed_term_position_index].amount_at_maturity = fixed_term_position
+        .amount_at_maturity
+        .safe_sub(amount_at_maturity.cast()?)?;
+
+    let result = meets_withdraw_margin_requirement(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarginRequirementType::Initial,
+    );
+
+    user.spot_positions[spot_position_index] = spot_position;
+    user.fixed_term_positions[fixed_term_position_index] = fixed_term_position;
+
+    result.map(|_| ())
+}
+
+pub fn cancel_fixed_term_order(
+    user: &mut User,
+    market_index: u16,
+    maturity_ts: i64,
+) -> DriftResult {
+    let fixed_term_position = user.get_fixed_term_position_mut(market_index, maturity_ts)?;
+
+    validate!(
+        fixed_term_position.has_open_order(),
+        ErrorCode::InvalidFixedTermOrder,
+        "no open order for market {} maturing at {}",
+        market_index,
+        maturity_ts
+    )?;
+
+    clear_fixed_term_order(fixed_term_position);
+
+    Ok(())
+}
+
+fn clear_fixed_term_order(fixed_term_position: &mut FixedTermPosition) {
+    fixed_term_position.open_order_principal = 0;
+    fixed_term_position.open_order_rate = 0;
+    fixed_term_position.open_order_slot = 0;
+    fixed_term_position.open_order_direction = FixedTermDirection::Lend;
+
+    if fixed_term_position.is_available() {
+        *fixed_term_position = FixedTermPosition::default();
+    }
+}
+
+/// Matches a lend order against a borrow order for the same market and maturity. The principal moves
+/// from the lender's deposit to the borrower's now, and the amount at maturity is owed back then through
+/// the market and maturity's fixed term pool
+pub fn fill_fixed_term_orders(
+    lender: &mut User,
+    lender_key: &Pubkey,
+    borrower: &mut User,
+    borrower_key: &Pubkey,
+    fixed_term_pool: &mut FixedTermPool,
+    market_index: u16,
+    maturity_ts: i64,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+) -> DriftResult<u64> {
+    validate!(
+        lender_key != borrower_key,
+        ErrorCode::InvalidFixedTermOrder,
+        "lender and borrower must be different users"
+    )?;
+
+    for user in [&*lender, &*borrower] {
+        validate!(
+            !user.is_bankrupt() && !user.is_being_liquidated(),
+            ErrorCode::InvalidFixedTermOrder,
+            "user being liquidated or bankrupt"
+        )?;
+    }
+
+    validate!(
+        now < maturity_ts,
+        ErrorCode::InvalidFixedTermOrder,
+        "orders maturing at {} already matured",
+        maturity_ts
+    )?;
+
+    fixed_term_pool.validate_market_and_maturity(market_index, maturity_ts)?;
+
+    let lender_position = *lender.get_fixed_term_position_mut(market_index, maturity_ts)?;
+    let borrower_position = *borrower.get_fixed_term_position_mut(market_index, maturity_ts)?;
+
+    validate!(
+        lender_position.has_open_order()
+            && lender_position.open_order_direction == FixedTermDirection::Lend,
+        ErrorCode::InvalidFixedTermOrder,
+        "lender {} has no open lend order",
+        lender_key
+    )?;
+
+    validate!(
+        borrower_position.has_open_order()
+            && borrower_position.open_order_direction == FixedTermDirection::Borrow,
+        ErrorCode::InvalidFixedTermOrder,
+        "borrower {} has no open borrow order",
+        borrower_key
+    )?;
+
+    let rate = calculate_fixed_term_fill_rate(
+        lender_position.open_order_rate,
+        lender_position.open_order_slot,
+        borrower_position.open_order_rate,
+        borrower_position.open_order_slot,
+    )?;
+
+    let principal = lender_position
+        .open_order_principal
+        .min(borrower_position.open_order_principal);
+
+    let amount_at_maturity =
+        calculate_fixed_term_amount_at_maturity(principal, rate, now, maturity_ts)?;
+
+    {
+        let mut spot_market = spot_market_map.get_ref_mut(&market_index)?;
+        update_spot_market_cumulative_interest(&mut spot_market, None, now)?;
+
+        validate_deposit_covers_principal(lender, &spot_market, principal)?;
+
+        transfer_spot_balances(
+            principal.cast()?,
+            &mut spot_market,
+            lender.get_spot_position_mut(market_index)?,
+            borrower.force_get_spot_position_mut(market_index)?,
+        )?;
+    }
+
+    fixed_term_pool.lend_amount_at_maturity = fixed_term_pool
+        .lend_amount_at_maturity
+        .safe_add(amount_at_maturity)?;
+    fixed_term_pool.borrow_amount_at_maturity = fixed_term_pool
+        .borrow_amount_at_maturity
+        .safe_add(amount_at_maturity)?;
+
+    let lender_position = lender.get_fixed_term_position_mut(market_index, maturity_ts)?;
+    lender_position.amount_at_maturity = lender_position
+        .amount_at_maturity
+        .safe_add(amount_at_maturity.cast()?)?;
+    lender_position.open_order_principal =
+        lender_position.open_order_principal.safe_sub(principal)?;
+    if !lender_position.has_open_order() {
+        clear_fixed_term_order(lender_position);
+    }
+
+    let borrower_position = borrower.get_fixed_term_position_mut(market_index, maturity_ts)?;
+    borrower_position.amount_at_maturity = borrower_position
+        .amount_at_maturity
+        .safe_sub(amount_at_maturity.cast()?)?;
+    borrower_position.open_order_principal =
+        borrower_position.open_order_principal.safe_sub(principal)?;
+    if !borrower_position.has_open_order() {
+        clear_fixed_term_order(borrower_position);
+    }
+
+    for user in [&*lender, &*borrower] {
+        meets_withdraw_margin_requirement(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarginRequirementType::Initial,
+        )?;
+    }
+
+    emit!(FixedTermRecord {
+        ts: now,
+        action: FixedTermAction::Fill,
+        market_index,
+        maturity_ts,
+        lender: *lender_key,
+        borrower: *borrower_key,
+        principal,
+        rate,
+        amount_at_maturity,
+    });
+
+    Ok(principal)
+}
+
+/// Settles a fixed term position against its fixed term pool. Borrowers repay the amount at maturity
+/// into the pool from their spot balance, and lenders are paid into theirs from whatever the pool holds,
+/// settling the rest once more borrowers have repaid. A position settles early once the user can be
+/// liquidated so its debt can be liquidated. Returns the amount settled
+pub fn settle_fixed_term_position(
+    user: &mut User,
+    user_key: &Pubkey,
+    fixed_term_pool: &mut FixedTermPool,
+    market_index: u16,
+    maturity_ts: i64,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    liquidation_margin_buffer_ratio: u32,
+    now: i64,
+) -> DriftResult<u64> {
+    fixed_term_pool.validate_market_and_maturity(market_index, maturity_ts)?;
+
+    let position_index = user.get_fixed_term_position_index(market_index, maturity_ts)?;
+    let fixed_term_position = user.fixed_term_positions[position_index];
+
+    validate!(
+        fixed_term_position.amount_at_maturity != 0,
+        ErrorCode::FixedTermPositionCantSettle,
+        "nothing to settle for market {} maturing at {}",
+        market_index,
+        maturity_ts
+    )?;
+
+    if !fixed_term_position.is_matured(now) && !user.is_being_liquidated() {
+        let margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::liquidation(liquidation_margin_buffer_ratio),
+            )?;
+
+        validate!(
+            !margin_calculation.meets_cross_margin_requirement(),
+            ErrorCode::FixedTermPositionCantSettle,
+            "position matures at {} and user can't be liquidated",
+            maturity_ts
+        )?;
+    }
+
+    let mut spot_market = spot_market_map.get_ref_mut(&market_index)?;
+    update_spot_market_cumulative_interest(&mut spot_market, None, now)?;
+
+    settle_fixed_term_position_to_spot_balance(
+        user,
+        user_key,
+        position_index,
+        &mut spot_market,
+        fixed_term_pool,
+        now,
+    )
+}
+
+/// Cancels the user's fixed term orders and settles its positions into spot balances when liquidation
+/// starts, so liquidation and bankruptcy only deal with spot balances. Lending is paid out of what its
+/// pool holds, and the rest stays a position, counted as collateral, until borrowers repay
+pub fn settle_fixed_term_positions_for_liquidation(
+    user: &mut User,
+    user_key: &Pubkey,
+    spot_market_map: &SpotMarketMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
+    now: i64,
+) -> DriftResult {
+    for position_index in 0..user.fixed_term_positions.len() {
+        let fixed_term_position = &mut user.fixed_term_positions[position_index];
+        if fixed_term_position.has_open_order() {
+            clear_fixed_term_order(fixed_term_position);
+        }
+
+        let FixedTermPosition {
+            amount_at_maturity,
+            market_index,
+            maturity_ts,
+            ..
+        } = *fixed_term_position;
+
+        if amount_at_maturity == 0 {
+            continue;
+        }
+
+        let mut spot_market = spot_market_map.get_ref_mut(&market_index)?;
+        update_spot_market_cumulative_interest(&mut spot_market, None, now)?;
+
+        let mut fixed_term_pool = fixed_term_pool_map.get_ref_mut(market_index, maturity_ts)?;
+
+        if amount_at_maturity > 0 && get_pool_token_amount(&fixed_term_pool, &spot_market)? == 0 {
+            continue;
+        }
+
+        settle_fixed_term_position_to_spot_balance(
+            user,
+            user_key,
+            position_index,
+            &mut spot_market,
+            &mut fixed_term_pool,
+            now,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn settle_fixed_term_position_to_spot_balance(
+    user: &mut User,
+    user_key: &Pubkey,
+    position_index: usize,
+    spot_market: &mut SpotMarket,
+    fixed_term_pool: &mut FixedTermPool,
+    now: i64,
+) -> DriftResult<u64> {
+    let FixedTermPosition {
+        amount_at_maturity,
+        market_index,
+        maturity_ts,
+        ..
+    } = user.fixed_term_positions[position_index];
+
+    let amount = if amount_at_maturity > 0 {
+        let pool_token_amount = get_pool_token_amount(fixed_term_pool, spot_market)?;
+        let amount: u64 = pool_token_amount
+            .min(amount_at_maturity.unsigned_abs().cast()?)
+            .cast()?;
+
+        validate!(
+            amount > 0,
+            ErrorCode::FixedTermPositionCantSettle,
+            "pool for market {} maturing at {} has nothing to pay until borrowers settle",
+            market_index,
+            maturity_ts
+        )?;
+
+        transfer_spot_balances(
+            amount.cast()?,
+            spot_market,
+            fixed_term_pool,
+            user.force_get_spot_position_mut(market_index)?,
+        )?;
+
+        fixed_term_pool.lend_amount_at_maturity =
+            fixed_term_pool.lend_amount_at_maturity.safe_sub(amount)?;
+
+        amount
+    } else {
+        let amount = amount_at_maturity.unsigned_abs();
+
+        transfer_spot_balances(
+            amount.cast()?,
+            spot_market,
+            user.force_get_spot_position_mut(market_index)?,
+            fixed_term_pool,
+        )?;
+
+        fixed_term_pool.borrow_amount_at_maturity =
+            fixed_term_pool.borrow_amount_at_maturity.safe_sub(amount)?;
+
+        amount
+    };
+
+    let fixed_term_position = &mut user.fixed_term_positions[position_index];
+    fixed_term_position.amount_at_maturity = if amount_at_maturity > 0 {
+        amount_at_maturity.safe_sub(amount.cast()?)?
+    } else {
+        0
+    };
+    if fixed_term_position.is_available() {
+        *fixed_term_position = FixedTermPosition::default();
+    }
+
+    let (lender, borrower) = if amount_at_maturity > 0 {
+        (*user_key, Pubkey::default())
+    } else {
+        (Pubkey::default(), *user_key)
+    };
+
+    emit!(FixedTermRecord {
+        ts: now,
+        action: FixedTermAction::Settle,
+        market_index,
+        maturity_ts,
+        lender,
+        borrower,
+        principal: 0,
+        rate: 0,
+        amount_at_maturity: amount,
+    });
+
+    Ok(amount)
+}
+
+fn get_pool_token_amount(
+    fixed_term_pool: &FixedTermPool,
+    spot_market: &SpotMarket,
+) -> DriftResult<u128> {
+    get_token_amount(
+        fixed_term_pool.scaled_balance,
+        spot_market,
+        &SpotBalanceType::Deposit,
+    )
+}
@@ -0,0 +1,517 @@
+pub mod fill_and_settle_fixed_term_positions {
+    use anchor_lang::prelude::AccountLoader;
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::controller::fixed_term::{
+        fill_fixed_term_orders, place_fixed_term_order, settle_fixed_term_position,
+        settle_fixed_term_positions_for_liquidation,
+    };
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        PRICE_PRECISION_I64, QUOTE_PRECISION_I64, QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_RATE_PRECISION_U32,
+        SPOT_WEIGHT_PRECISION, TWENTY_FOUR_HOUR,
+    };
+    use crate::state::fixed_term_pool::{FixedTermPool, FixedTermPoolMap};
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::MarketStatus;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{FixedTermDirection, FixedTermPosition, SpotPosition, User};
+    use crate::test_utils::get_spot_positions;
+    use crate::test_utils::*;
+
+    fn get_user(quote_deposit: u64) -> User {
+        User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: quote_deposit * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        }
+    }
+
+    #[test]
+    fn lend_then_settle_at_maturity() {
+        let now = 0_i64;
+        let maturity_ts = 30 * TWENTY_FOUR_HOUR;
+
+        let perp_market_map = PerpMarketMap::empty();
+        let mut oracle_map = OracleMap::empty();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            status: MarketStatus::Active,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 1100 * SPOT_BALANCE_PRECISION,
+            max_borrow_rate: SPOT_RATE_PRECISION_U32,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut lender = get_user(1000);
+        let lender_key = Pubkey::new_unique();
+        let mut borrower = get_user(100);
+        let borrower_key = Pubkey::new_unique();
+
+        let mut fixed_term_pool = FixedTermPool {
+            market_index: 0,
+            maturity_ts,
+            ..FixedTermPool::default()
+        };
+
+        let result = place_fixed_term_order(
+            &mut lender,
+            0,
+            maturity_ts + 1,
+            FixedTermDirection::Lend,
+            1000 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 10,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        );
+        assert_eq!(result, Err(ErrorCode::InvalidFixedTermOrder));
+
+        // lender is the maker at 10%, borrower would pay up to 20%
+        place_fixed_term_order(
+            &mut lender,
+            0,
+            maturity_ts,
+            FixedTermDirection::Lend,
+            1000 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 10,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        )
+        .unwrap();
+
+        place_fixed_term_order(
+            &mut borrower,
+            0,
+            maturity_ts,
+            FixedTermDirection::Borrow,
+            500 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 5,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            2,
+            now,
+        )
+        .unwrap();
+
+        let principal = fill_fixed_term_orders(
+            &mut lender,
+            &lender_key,
+            &mut borrower,
+            &borrower_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            now,
+        )
+        .unwrap();
+
+        assert_eq!(principal, 500 * QUOTE_PRECISION_U64);
+        assert_eq!(lender.fixed_term_positions[0].amount_at_maturity, 504109589);
+        assert_eq!(
+            lender.fixed_term_positions[0].open_order_principal,
+            500 * QUOTE_PRECISION_U64
+        );
+        assert_eq!(
+            borrower.fixed_term_positions[0].amount_at_maturity,
+            -504109589
+        );
+        assert!(!borrower.fixed_term_positions[0].has_open_order());
+        assert_eq!(
+            lender.spot_positions[0].scaled_balance,
+            500 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            borrower.spot_positions[0].scaled_balance,
+            600 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(fixed_term_pool.lend_amount_at_maturity, 504109589);
+        assert_eq!(fixed_term_pool.borrow_amount_at_maturity, 504109589);
+        assert_eq!(fixed_term_pool.scaled_balance, 0);
+
+        let result = settle_fixed_term_position(
+            &mut borrower,
+            &borrower_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            maturity_ts - 1,
+        );
+        assert_eq!(result, Err(ErrorCode::FixedTermPositionCantSettle));
+
+        // lenders are paid out of what borrowers settle into the pool
+        let result = settle_fixed_term_position(
+            &mut lender,
+            &lender_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            maturity_ts,
+        );
+        assert_eq!(result, Err(ErrorCode::FixedTermPositionCantSettle));
+
+        let amount = settle_fixed_term_position(
+            &mut borrower,
+            &borrower_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            maturity_ts,
+        )
+        .unwrap();
+
+        assert_eq!(amount, 504109589);
+        assert_eq!(borrower.spot_positions[0].scaled_balance, 95890411000);
+        assert_eq!(fixed_term_pool.scaled_balance, 504109589000);
+        assert_eq!(fixed_term_pool.borrow_amount_at_maturity, 0);
+
+        let amount = settle_fixed_term_position(
+            &mut lender,
+            &lender_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            maturity_ts,
+        )
+        .unwrap();
+
+        assert_eq!(amount, 504109589);
+        assert_eq!(lender.spot_positions[0].scaled_balance, 1004109589000);
+        assert_eq!(fixed_term_pool.scaled_balance, 0);
+        assert_eq!(fixed_term_pool.lend_amount_at_maturity, 0);
+
+        // the lender's unfilled order keeps its slot, the borrower's is freed
+        assert_eq!(lender.fixed_term_positions[0].amount_at_maturity, 0);
+        assert!(lender.fixed_term_positions[0].has_open_order());
+        assert_eq!(
+            borrower.fixed_term_positions[0],
+            FixedTermPosition::default()
+        );
+    }
+
+    #[test]
+    fn place_checks_rate_deposit_and_margin() {
+        let now = 0_i64;
+        let maturity_ts = 30 * TWENTY_FOUR_HOUR;
+
+        let perp_market_map = PerpMarketMap::empty();
+        let mut oracle_map = OracleMap::empty();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            status: MarketStatus::Active,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            max_borrow_rate: SPOT_RATE_PRECISION_U32,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = get_user(100);
+
+        let result = place_fixed_term_order(
+            &mut user,
+            0,
+            maturity_ts,
+            FixedTermDirection::Lend,
+            100 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 + 1,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        );
+        assert_eq!(result, Err(ErrorCode::InvalidFixedTermOrder));
+
+        let result = place_fixed_term_order(
+            &mut user,
+            0,
+            maturity_ts,
+            FixedTermDirection::Lend,
+            101 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 10,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        );
+        assert_eq!(result, Err(ErrorCode::InsufficientDeposit));
+
+        // the principal is deposited but the amount owed at maturity is more than it
+        let mut borrower = get_user(0);
+        let result = place_fixed_term_order(
+            &mut borrower,
+            0,
+            maturity_ts,
+            FixedTermDirection::Borrow,
+            100 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 10,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        );
+        assert_eq!(result, Err(ErrorCode::InsufficientCollateral));
+        assert_eq!(borrower.spot_positions[0].scaled_balance, 0);
+        assert_eq!(borrower.fixed_term_positions[0].amount_at_maturity, 0);
+
+        place_fixed_term_order(
+            &mut user,
+            0,
+            maturity_ts,
+            FixedTermDirection::Borrow,
+            100 * QUOTE_PRECISION_U64,
+            SPOT_RATE_PRECISION_U32 / 10,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            1,
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            100 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(user.fixed_term_positions[0].amount_at_maturity, 0);
+    }
+
+    #[test]
+    fn settle_early_when_liquidatable() {
+        let now = 0_i64;
+        let maturity_ts = 30 * TWENTY_FOUR_HOUR;
+
+        let perp_market_map = PerpMarketMap::empty();
+        let mut oracle_map = OracleMap::empty();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            status: MarketStatus::Active,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            max_borrow_rate: SPOT_RATE_PRECISION_U32,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = get_user(100);
+        let user_key = Pubkey::new_unique();
+        user.fixed_term_positions[0] = FixedTermPosition {
+            amount_at_maturity: -50 * QUOTE_PRECISION_I64,
+            maturity_ts,
+            market_index: 0,
+            ..FixedTermPosition::default()
+        };
+
+        let mut fixed_term_pool = FixedTermPool {
+            market_index: 0,
+            maturity_ts,
+            borrow_amount_at_maturity: 150 * QUOTE_PRECISION_U64,
+            ..FixedTermPool::default()
+        };
+
+        let result = settle_fixed_term_position(
+            &mut user,
+            &user_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            now,
+        );
+        assert_eq!(result, Err(ErrorCode::FixedTermPositionCantSettle));
+
+        user.fixed_term_positions[0].amount_at_maturity = -150 * QUOTE_PRECISION_I64;
+
+        let amount = settle_fixed_term_position(
+            &mut user,
+            &user_key,
+            &mut fixed_term_pool,
+            0,
+            maturity_ts,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            0,
+            now,
+        )
+        .unwrap();
+
+        assert_eq!(amount, 150 * QUOTE_PRECISION_U64);
+        assert_eq!(user.spot_positions[0].balance_type, SpotBalanceType::Borrow);
+        // borrowing rounds against the user
+        assert_eq!(user.spot_positions[0].scaled_balance, 50000000001);
+        assert_eq!(user.fixed_term_positions[0], FixedTermPosition::default());
+        assert_eq!(fixed_term_pool.scaled_balance, 150 * SPOT_BALANCE_PRECISION);
+        assert_eq!(fixed_term_pool.borrow_amount_at_maturity, 0);
+    }
+
+    #[test]
+    fn settle_when_liquidation_starts() {
+        let now = 0_i64;
+        let maturity_ts = 30 * TWENTY_FOUR_HOUR;
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            status: MarketStatus::Active,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            deposit_balance: 180 * SPOT_BALANCE_PRECISION,
+            max_borrow_rate: SPOT_RATE_PRECISION_U32,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut borrow_pool = FixedTermPool {
+            market_index: 0,
+            maturity_ts,
+            borrow_amount_at_maturity: 150 * QUOTE_PRECISION_U64,
+            ..FixedTermPool::default()
+        };
+        create_anchor_account_info!(borrow_pool, FixedTermPool, borrow_pool_account_info);
+        // borrowers have only repaid part of what the lend is owed
+        let mut lend_pool = FixedTermPool {
+            market_index: 0,
+            maturity_ts: 2 * maturity_ts,
+            scaled_balance: 80 * SPOT_BALANCE_PRECISION,
+            lend_amount_at_maturity: 200 * QUOTE_PRECISION_U64,
+            ..FixedTermPool::default()
+        };
+        create_anchor_account_info!(lend_pool, FixedTermPool, lend_pool_account_info);
+        let mut fixed_term_pool_map = FixedTermPoolMap::empty();
+        fixed_term_pool_map
+            .insert(AccountLoader::try_from(&borrow_pool_account_info).unwrap())
+            .unwrap();
+        fixed_term_pool_map
+            .insert(AccountLoader::try_from(&lend_pool_account_info).unwrap())
+            .unwrap();
+
+        let mut user = get_user(100);
+        let user_key = Pubkey::new_unique();
+        user.fixed_term_positions[0] = FixedTermPosition {
+            amount_at_maturity: -150 * QUOTE_PRECISION_I64,
+            maturity_ts,
+            market_index: 0,
+            ..FixedTermPosition::default()
+        };
+        user.fixed_term_positions[1] = FixedTermPosition {
+            amount_at_maturity: 200 * QUOTE_PRECISION_I64,
+            maturity_ts: 2 * maturity_ts,
+            market_index: 0,
+            ..FixedTermPosition::default()
+        };
+        user.fixed_term_positions[2] = FixedTermPosition {
+            maturity_ts,
+            market_index: 0,
+            open_order_principal: 10 * QUOTE_PRECISION_U64,
+            open_order_direction: FixedTermDirection::Borrow,
+            ..FixedTermPosition::default()
+        };
+
+        settle_fixed_term_positions_for_liquidation(
+            &mut user,
+            &user_key,
+            &spot_market_map,
+            &fixed_term_pool_map,
+            now,
+        )
+        .unwrap();
+
+        // the borrow settles into its pool and the lend is paid what its pool holds
+        assert_eq!(
+            user.spot_positions[0].balance_type,
+            SpotBalanceType::Deposit
+        );
+        // the rounded up borrow costs the user a token unit
+        assert_eq!(user.spot_positions[0].scaled_balance, 29999999000);
+        assert_eq!(user.fixed_term_positions[0], FixedTermPosition::default());
+        assert_eq!(
+            user.fixed_term_positions[1].amount_at_maturity,
+            120 * QUOTE_PRECISION_I64
+        );
+        assert_eq!(user.fixed_term_positions[2], FixedTermPosition::default());
+
+        let borrow_pool = fixed_term_pool_map.get_ref_mut(0, maturity_ts).unwrap();
+        assert_eq!(borrow_pool.scaled_balance, 150 * SPOT_BALANCE_PRECISION);
+        assert_eq!(borrow_pool.borrow_amount_at_maturity, 0);
+        drop(borrow_pool);
+
+        let lend_pool = fixed_term_pool_map.get_ref_mut(0, 2 * maturity_ts).unwrap();
+        assert_eq!(lend_pool.scaled_balance, 0);
+        assert_eq!(lend_pool.lend_amount_at_maturity, 120 * QUOTE_PRECISION_U64);
+    }
+}
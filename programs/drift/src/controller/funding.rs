@@ -11,10 +11,10 @@ use crate::error::DriftResult;
 use crate::get_then_update_id;
 use crate::math::amm;
 use crate::math::casting::Cast;
-use crate::math::constants::{
-    FUNDING_RATE_BUFFER, FUNDING_RATE_OFFSET_DENOMINATOR, ONE_HOUR_I128, TWENTY_FOUR_HOUR,
+use crate::math::constants::{FUNDING_RATE_BUFFER, ONE_HOUR_I128, TWENTY_FOUR_HOUR};
+use crate::math::funding::{
+    calculate_funding_payment, calculate_funding_price_spread, calculate_funding_rate_long_short,
 };
-use crate::math::funding::{calculate_funding_payment, calculate_funding_rate_long_short};
 use crate::math::helpers::on_the_hour_update;
 use crate::math::safe_math::SafeMath;
 use crate::math::stats::calculate_new_twap;
@@ -227,20 +227,19 @@ pub fn update_funding_rate(
             .safe_div(max(ONE_HOUR_I128, market.amm.funding_period as i128))?;
         // funding period = 1 hour, window = 1 day
         // low periodicity => quickly updating/settled funding rates => lower funding rate payment per interval
-        let price_spread = mid_price_twap.cast::<i64>()?.safe_sub(oracle_price_twap)?;
-
-        // add offset 1/FUNDING_RATE_OFFSET_DENOMINATOR*365. if FUNDING_RATE_OFFSET_DENOMINATOR = 5000 => 7.3% annualized rate
-        let price_spread_with_offset = price_spread.safe_add(
-            oracle_price_twap
-                .abs()
-                .safe_div(FUNDING_RATE_OFFSET_DENOMINATOR)?,
+        // premium index is sampled on every mark twap update over the funding period, including the one above
+        let premium_index = market.amm.last_premium_index;
+        let price_spread = calculate_funding_price_spread(
+            premium_index,
+            oracle_price_twap,
+            market.amm.funding_interest_rate,
+            market.amm.funding_premium_clamp,
         )?;
 
         // clamp price divergence based on contract tier for funding rate calculation
         let max_price_spread =
             market.get_max_price_divergence_for_funding_rate(oracle_price_twap)?;
-        let clamped_price_spread =
-            price_spread_with_offset.clamp(-max_price_spread, max_price_spread);
+        let clamped_price_spread = price_spread.clamp(-max_price_spread, max_price_spread);
 
         let funding_rate = clamped_price_spread
             .cast::<i128>()?
@@ -297,6 +296,7 @@ pub fn update_funding_rate(
             cumulative_funding_rate_short: market.amm.cumulative_funding_rate_short,
             mark_price_twap: mid_price_twap,
             oracle_price_twap,
+            premium_index,
             period_revenue: market.amm.net_revenue_since_last_funding,
             base_asset_amount_with_amm: market.amm.base_asset_amount_with_amm,
             base_asset_amount_with_unsettled_lp: market.amm.base_asset_amount_with_unsettled_lp,
@@ -0,0 +1,200 @@
+use solana_program::msg;
+
+use crate::controller::position::{add_new_position, get_position_index};
+use crate::controller::spot_balance::{
+    update_spot_balances, update_spot_market_cumulative_interest,
+};
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
+use crate::math::constants::QUOTE_SPOT_MARKET_INDEX;
+use crate::math::margin::{
+    calculate_margin_requirement_and_total_collateral_and_liability_info, MarginRequirementType,
+};
+use crate::state::margin_calculation::MarginContext;
+use crate::state::oracle_map::OracleMap;
+use crate::state::perp_market_map::PerpMarketMap;
+use crate::state::spot_market::SpotBalanceType;
+use crate::state::spot_market_map::SpotMarketMap;
+use crate::state::user::{PositionFlag, User};
+use crate::validate;
+
+#[cfg(test)]
+mod tests;
+
+/// Moves quote collateral between the user's cross quote deposit and a perp position's isolated collateral.
+/// A positive amount funds the isolated position (marking it isolated if needed), a negative amount returns
+/// collateral to cross.
+pub fn transfer_isolated_perp_position_deposit(
+    user: &mut User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    perp_market_index: u16,
+    amount: i64,
+) -> DriftResult {
+    validate!(
+        amount != 0,
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "transfer amount must be non-zero"
+    )?;
+
+    validate!(
+        !user.is_bankrupt(),
+        ErrorCode::UserBankrupt,
+        "user bankrupt"
+    )?;
+
+    let quote_spot_market_index = perp_market_map
+        .get_ref(&perp_market_index)?
+        .quote_spot_market_index;
+
+    validate!(
+        quote_spot_market_index == QUOTE_SPOT_MARKET_INDEX,
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated positions must be collateralized by spot market {}",
+        QUOTE_SPOT_MARKET_INDEX
+    )?;
+
+    let mut spot_market = spot_market_map.get_quote_spot_market_mut()?;
+    update_spot_market_cumulative_interest(&mut spot_market, None, now)?;
+
+    let token_amount = amount.unsigned_abs().cast::<u128>()?;
+
+    if amount > 0 {
+        validate!(
+            !user.is_being_liquidated(),
+            ErrorCode::UserIsBeingLiquidated,
+            "cant move cross collateral while being liquidated"
+        )?;
+
+        let cross_token_amount = user
+            .get_quote_spot_position()
+            .get_signed_token_amount(&spot_market)?;
+
+        validate!(
+            cross_token_amount >= token_amount.cast::<i128>()?,
+            ErrorCode::InsufficientCollateral,
+            "cross quote deposit {} less than transfer amount {}",
+            cross_token_amount,
+            token_amount
+        )?;
+
+        let position_index = get_position_index(&user.perp_positions, perp_market_index)
+            .or_else(|_| add_new_position(&mut user.perp_positions, perp_market_index))?;
+        let perp_position = &mut user.perp_positions[position_index];
+
+        if !perp_position.is_isolated() {
+            validate!(
+                !perp_position.is_open_position()
+                    && !perp_position.has_open_order()
+                    && !perp_position.is_lp()
+                    && perp_position.quote_asset_amount == 0,
+                ErrorCode::InvalidIsolatedPerpPosition,
+                "perp position must be empty before it can be isolated"
+            )?;
+
+            perp_position.add_position_flag(PositionFlag::IsolatedPosition);
+        }
+
+        validate!(
+            !perp_position.is_being_liquidated(),
+            ErrorCode::IsolatedPerpPositionBeingLiquidated,
+            "isolated position being liquidated"
+        )?;
+
+        update_spot_balances(
+            token_amount,
+            &SpotBalanceType::Borrow,
+            &mut spot_market,
+            user.get_quote_spot_position_mut(),
+            false,
+        )?;
+
+        update_spot_balances(
+            token_amount,
+            &SpotBalanceType::Deposit,
+            &mut spot_market,
+            &mut user.perp_positions[position_index],
+            false,
+        )?;
+    } else {
+        let perp_position = user.get_perp_position_mut(perp_market_index)?;
+
+        validate!(
+            perp_position.is_isolated(),
+            ErrorCode::InvalidIsolatedPerpPosition,
+            "perp position for market {} is not isolated",
+            perp_market_index
+        )?;
+
+        validate!(
+            !perp_position.is_being_liquidated(),
+            ErrorCode::IsolatedPerpPositionBeingLiquidated,
+            "isolated position being liquidated"
+        )?;
+
+        let isolated_token_amount = perp_position.get_isolated_token_amount(&spot_market)?;
+
+        validate!(
+            isolated_token_amount >= token_amount,
+            ErrorCode::InsufficientCollateral,
+            "isolated collateral {} less than transfer amount {}",
+            isolated_token_amount,
+            token_amount
+        )?;
+
+        update_spot_balances(
+            token_amount,
+            &SpotBalanceType::Borrow,
+            &mut spot_market,
+            perp_position,
+            false,
+        )?;
+
+        if perp_position.is_available() {
+            perp_position.remove_position_flag(PositionFlag::IsolatedPosition);
+        }
+
+        update_spot_balances(
+            token_amount,
+            &SpotBalanceType::Deposit,
+            &mut spot_market,
+            user.get_quote_spot_position_mut(),
+            false,
+        )?;
+    }
+
+    drop(spot_market);
+
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarginContext::standard(MarginRequirementType::Initial).strict(true),
+    )?;
+
+    // only the side losing collateral needs to stay above initial margin
+    if amount > 0 {
+        validate!(
+            margin_calculation.meets_cross_margin_requirement(),
+            ErrorCode::InsufficientCollateral,
+            "cross collateral {} below initial margin requirement {}",
+            margin_calculation.total_collateral,
+            margin_calculation.margin_requirement
+        )?;
+    } else if margin_calculation.has_isolated_position_margin_calculation(perp_market_index) {
+        let isolated_margin_calculation =
+            margin_calculation.get_isolated_position_margin_calculation(perp_market_index)?;
+        validate!(
+            isolated_margin_calculation.meets_margin_requirement(),
+            ErrorCode::InsufficientCollateral,
+            "isolated collateral {} below initial margin requirement {}",
+            isolated_margin_calculation.total_collateral,
+            isolated_margin_calculation.margin_requirement
+        )?;
+    }
+
+    Ok(())
+}
@@ -4,12 +4,13 @@ use anchor_lang::prelude::*;
 use solana_program::msg;
 
 use crate::controller::amm::get_fee_pool_tokens;
+use crate::controller::fixed_term::settle_fixed_term_positions_for_liquidation;
 use crate::controller::funding::settle_funding_payment;
 use crate::controller::lp::burn_lp_shares;
 use crate::controller::orders;
 use crate::controller::position::{
-    get_position_index, update_position_and_market, update_quote_asset_amount,
-    update_quote_asset_and_break_even_amount, PositionDirection,
+    get_position_index, update_position_and_market, update_position_with_base_asset_amount,
+    update_quote_asset_amount, update_quote_asset_and_break_even_amount, PositionDirection,
 };
 use crate::controller::repeg::update_amm_and_check_validity;
 use crate::controller::spot_balance::{
@@ -19,21 +20,21 @@ use crate::controller::spot_balance::{
 use crate::controller::spot_position::update_spot_balances_and_cumulative_deposits;
 use crate::error::{DriftResult, ErrorCode};
 use crate::get_then_update_id;
-use crate::math::bankruptcy::is_user_bankrupt;
+use crate::math::bankruptcy::{is_isolated_position_bankrupt, is_user_bankrupt};
 use crate::math::casting::Cast;
 use crate::math::constants::{
     LIQUIDATION_FEE_PRECISION_U128, LIQUIDATION_PCT_PRECISION, QUOTE_PRECISION,
     QUOTE_PRECISION_I128, QUOTE_PRECISION_U64, QUOTE_SPOT_MARKET_INDEX, SPOT_WEIGHT_PRECISION,
 };
 use crate::math::liquidation::{
-    calculate_asset_transfer_for_liability_transfer,
-    calculate_base_asset_amount_to_cover_margin_shortage,
+    calculate_asset_transfer_for_liability_transfer, calculate_auto_deleverage_bankruptcy_price,
+    calculate_auto_deleverage_score, calculate_base_asset_amount_to_cover_margin_shortage,
     calculate_cumulative_deposit_interest_delta_to_resolve_bankruptcy,
     calculate_funding_rate_deltas_to_resolve_bankruptcy,
     calculate_liability_transfer_implied_by_asset_amount,
     calculate_liability_transfer_to_cover_margin_shortage, calculate_liquidation_multiplier,
-    calculate_max_pct_to_liquidate, calculate_perp_if_fee, calculate_spot_if_fee,
-    validate_transfer_satisfies_limit_price, LiquidationMultiplierType,
+    calculate_max_pct_to_liquidate, calculate_perp_if_fee, calculate_pnl_pct_from_entry,
+    calculate_spot_if_fee, validate_transfer_satisfies_limit_price, LiquidationMultiplierType,
 };
 use crate::math::margin::{
     calculate_margin_requirement_and_total_collateral_and_liability_info,
@@ -48,11 +49,12 @@ use crate::math::position::calculate_base_asset_value_with_oracle_price;
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::get_token_value;
 use crate::state::events::{
-    emit_stack, LPAction, LPRecord, LiquidateBorrowForPerpPnlRecord,
+    emit_stack, AutoDeleverageRecord, LPAction, LPRecord, LiquidateBorrowForPerpPnlRecord,
     LiquidatePerpPnlForDepositRecord, LiquidatePerpRecord, LiquidateSpotRecord, LiquidationRecord,
     LiquidationType, OrderAction, OrderActionExplanation, OrderActionRecord, OrderRecord,
     PerpBankruptcyRecord, SpotBankruptcyRecord,
 };
+use crate::state::fixed_term_pool::FixedTermPoolMap;
 use crate::state::margin_calculation::{MarginCalculation, MarginContext, MarketIdentifier};
 use crate::state::oracle_map::OracleMap;
 use crate::state::paused_operations::{PerpOperation, SpotOperation};
@@ -62,7 +64,10 @@ use crate::state::spot_market::SpotBalanceType;
 use crate::state::spot_market_map::SpotMarketMap;
 use crate::state::state::State;
 use crate::state::traits::Size;
-use crate::state::user::{MarketType, Order, OrderStatus, OrderType, User, UserStats};
+use crate::state::user::{
+    MarketType, Order, OrderStatus, OrderType, PositionFlag, User, UserStats,
+};
+use crate::state::user_map::UserMap;
 use crate::validate;
 
 #[cfg(test)]
@@ -81,6 +86,7 @@ pub fn liquidate_perp(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
     slot: u64,
     now: i64,
     state: &State,
@@ -112,6 +118,19 @@ pub fn liquidate_perp(
 
     drop(market);
 
+    // an isolated position is liquidated against its own collateral without touching the rest of the account
+    let isolated_market_index = match user.get_perp_position(market_index) {
+        Ok(position) if position.is_isolated() => {
+            validate!(
+                !position.is_bankrupt(),
+                ErrorCode::UserBankrupt,
+                "isolated position bankrupt",
+            )?;
+            Some(market_index)
+        }
+        _ => None,
+    };
+
     // Settle user's funding payments so that collateral is up to date
     settle_funding_payment(
         user,
@@ -137,7 +156,24 @@ pub fn liquidate_perp(
             .track_market_margin_requirement(MarketIdentifier::perp(market_index))?,
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    let (margin_requirement, total_collateral) =
+        get_liquidation_margin_requirement_and_total_collateral(
+            &margin_calculation,
+            isolated_market_index,
+        )?;
+
+    if let Some(market_index) = isolated_market_index {
+        let isolated_margin_calculation =
+            margin_calculation.get_isolated_position_margin_calculation(market_index)?;
+        let being_liquidated = user.get_perp_position(market_index)?.is_being_liquidated();
+        if !being_liquidated && isolated_margin_calculation.meets_margin_requirement() {
+            msg!("margin calculation: {:?}", isolated_margin_calculation);
+            return Err(ErrorCode::SufficientCollateral);
+        } else if being_liquidated && isolated_margin_calculation.can_exit_liquidation() {
+            user.exit_isolated_position_liquidation(market_index);
+            return Ok(());
+        }
+    } else if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation: {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -163,7 +199,17 @@ pub fn liquidate_perp(
             e
         })?;
 
-    let liquidation_id = user.enter_liquidation(slot)?;
+    let liquidation_id = start_liquidation(
+        user,
+        user_key,
+        isolated_market_index,
+        slot,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        fixed_term_pool_map,
+        now,
+    )?;
     let mut margin_freed = 0_u64;
 
     let position_index = get_position_index(&user.perp_positions, market_index)?;
@@ -184,8 +230,8 @@ pub fn liquidate_perp(
         now,
         slot,
         OrderActionExplanation::Liquidation,
-        None,
-        None,
+        isolated_market_index.map(|_| MarketType::Perp),
+        isolated_market_index,
         None,
     )?;
 
@@ -244,23 +290,32 @@ pub fn liquidate_perp(
                     .track_market_margin_requirement(MarketIdentifier::perp(market_index))?,
             )?;
 
-        let initial_margin_shortage = margin_calculation.margin_shortage()?;
-        let new_margin_shortage = intermediate_margin_calculation.margin_shortage()?;
+        let initial_margin_shortage =
+            get_liquidation_margin_shortage(&margin_calculation, isolated_market_index)?;
+        let new_margin_shortage = get_liquidation_margin_shortage(
+            &intermediate_margin_calculation,
+            isolated_market_index,
+        )?;
 
         margin_freed = initial_margin_shortage
             .saturating_sub(new_margin_shortage)
             .cast::<u64>()?;
-        user.increment_margin_freed(margin_freed)?;
+        match isolated_market_index {
+            Some(market_index) => {
+                user.increment_isolated_position_margin_freed(market_index, margin_freed)?
+            }
+            None => user.increment_margin_freed(margin_freed)?,
+        }
 
-        if intermediate_margin_calculation.can_exit_liquidation()? {
+        if can_exit_liquidation(&intermediate_margin_calculation, isolated_market_index)? {
             emit!(LiquidationRecord {
                 ts: now,
                 liquidation_id,
                 liquidation_type: LiquidationType::LiquidatePerp,
                 user: *user_key,
                 liquidator: *liquidator_key,
-                margin_requirement: margin_calculation.margin_requirement,
-                total_collateral: margin_calculation.total_collateral,
+                margin_requirement,
+                total_collateral,
                 bankrupt: user.is_bankrupt(),
                 canceled_order_ids,
                 margin_freed,
@@ -273,7 +328,10 @@ pub fn liquidate_perp(
                 ..LiquidationRecord::default()
             });
 
-            user.exit_liquidation();
+            match isolated_market_index {
+                Some(market_index) => user.exit_isolated_position_liquidation(market_index),
+                None => user.exit_liquidation(),
+            }
             return Ok(());
         }
 
@@ -327,14 +385,34 @@ pub fn liquidate_perp(
 
     let margin_ratio_with_buffer = margin_ratio.safe_add(liquidation_margin_buffer_ratio)?;
 
-    let margin_shortage = intermediate_margin_calculation.margin_shortage()?;
+    let margin_shortage =
+        get_liquidation_margin_shortage(&intermediate_margin_calculation, isolated_market_index)?;
+    let tracked_market_margin_shortage = if isolated_market_index.is_some() {
+        margin_shortage
+    } else {
+        intermediate_margin_calculation.tracked_market_margin_shortage(margin_shortage)?
+    };
+
+    let base_asset_value =
+        calculate_base_asset_value_with_oracle_price(user_base_asset_amount.cast()?, oracle_price)?
+            .cast::<u64>()?;
 
     let market = perp_market_map.get_ref(&market_index)?;
     let quote_spot_market = spot_market_map.get_ref(&market.quote_spot_market_index)?;
     let quote_oracle_price = oracle_map.get_price_data(&quote_spot_market.oracle)?.price;
-    let liquidator_fee = market.liquidator_fee;
+    // large positions are dutch auctioned, the liquidator fee growing from when liquidation started
+    let is_liquidation_auction = user.perp_positions[position_index].is_liquidation_auction();
+    let liquidation_start_slot = match isolated_market_index {
+        Some(_) => user.perp_positions[position_index].liquidation_start_slot,
+        None => user.liquidation_start_slot,
+    };
+    let liquidator_fee = market.get_liquidator_fee(
+        is_liquidation_auction,
+        slot.safe_sub(liquidation_start_slot)?,
+        liquidation_duration.cast()?,
+    )?;
     let if_liquidation_fee = calculate_perp_if_fee(
-        intermediate_margin_calculation.tracked_market_margin_shortage(margin_shortage)?,
+        tracked_market_margin_shortage,
         user_base_asset_amount,
         margin_ratio_with_buffer,
         liquidator_fee,
@@ -356,13 +434,28 @@ pub fn liquidate_perp(
     drop(market);
     drop(quote_spot_market);
 
-    let max_pct_allowed = calculate_max_pct_to_liquidate(
-        user,
-        margin_shortage,
-        slot,
-        initial_pct_to_liquidate,
-        liquidation_duration,
-    )?;
+    // auctions are paced by the growing liquidator fee instead, so any liquidator can take a slice
+    // an isolated position follows the gradual schedule from when it entered liquidation
+    let max_pct_allowed = if is_liquidation_auction {
+        LIQUIDATION_PCT_PRECISION
+    } else {
+        let (liquidation_start_slot, liquidation_margin_freed) = match isolated_market_index {
+            Some(_) => (
+                user.perp_positions[position_index].liquidation_start_slot,
+                user.perp_positions[position_index].liquidation_margin_freed,
+            ),
+            None => (user.last_active_slot, user.liquidation_margin_freed),
+        };
+
+        calculate_max_pct_to_liquidate(
+            liquidation_start_slot,
+            liquidation_margin_freed,
+            margin_shortage,
+            slot,
+            initial_pct_to_liquidate,
+            liquidation_duration,
+        )?
+    };
     let max_base_asset_amount_allowed_to_be_transferred =
         base_asset_amount_to_cover_margin_shortage
             .cast::<u128>()?
@@ -375,10 +468,6 @@ pub fn liquidate_perp(
         return Ok(());
     }
 
-    let base_asset_value =
-        calculate_base_asset_value_with_oracle_price(user_base_asset_amount.cast()?, oracle_price)?
-            .cast::<u64>()?;
-
     // if position is less than $50, liquidator can liq all of it
     let min_base_asset_amount = if base_asset_value > 50 * QUOTE_PRECISION_U64 {
         0_u64
@@ -495,6 +584,23 @@ pub fn liquidate_perp(
             .total_liquidation_fee
             .safe_add(if_fee.unsigned_abs().cast()?)?;
 
+        // max position sizes never block a liquidation, the transfer is only counted where it can be
+        if market.has_max_position_size() {
+            if user_stats.can_update_limited_perp_position(market_index) {
+                user_stats.update_limited_perp_position(
+                    market_index,
+                    user_position_delta.base_asset_amount,
+                )?;
+            }
+
+            if liquidator_stats.can_update_limited_perp_position(market_index) {
+                liquidator_stats.update_limited_perp_position(
+                    market_index,
+                    liquidator_position_delta.base_asset_amount,
+                )?;
+            }
+        }
+
         (
             user_existing_position_direction,
             user_position_direction_to_close,
@@ -502,21 +608,60 @@ pub fn liquidate_perp(
         )
     };
 
-    let margin_freed_for_perp_position = calculate_margin_freed(
-        user,
-        perp_market_map,
-        spot_market_map,
-        oracle_map,
-        liquidation_margin_buffer_ratio,
-        margin_shortage,
-    )?;
-    margin_freed = margin_freed.safe_add(margin_freed_for_perp_position)?;
-    user.increment_margin_freed(margin_freed_for_perp_position)?;
+    if let Some(market_index) = isolated_market_index {
+        let margin_calculation_after =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::liquidation(liquidation_margin_buffer_ratio),
+            )?;
+        let new_margin_shortage =
+            get_liquidation_margin_shortage(&margin_calculation_after, isolated_market_index)?;
+        let margin_freed_for_perp_position = margin_shortage
+            .saturating_sub(new_margin_shortage)
+            .cast::<u64>()?;
+        margin_freed = margin_freed.safe_add(margin_freed_for_perp_position)?;
+        user.increment_isolated_position_margin_freed(
+            market_index,
+            margin_freed_for_perp_position,
+        )?;
 
-    if base_asset_amount >= base_asset_amount_to_cover_margin_shortage {
-        user.exit_liquidation();
-    } else if is_user_bankrupt(user) {
-        user.enter_bankruptcy();
+        let isolated_position_bankrupt = match user.get_perp_position(market_index) {
+            Ok(position) => is_isolated_position_bankrupt(
+                position,
+                position.get_isolated_token_amount(&*spot_market_map.get_quote_spot_market()?)?,
+            )?,
+            Err(_) => false,
+        };
+
+        if base_asset_amount >= base_asset_amount_to_cover_margin_shortage {
+            user.exit_isolated_position_liquidation(market_index);
+        } else if isolated_position_bankrupt {
+            user.enter_isolated_position_bankruptcy(market_index)?;
+        }
+    } else {
+        let (margin_freed_for_perp_position, margin_calculation_after) = calculate_margin_freed(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            liquidation_margin_buffer_ratio,
+            margin_shortage,
+        )?;
+        margin_freed = margin_freed.safe_add(margin_freed_for_perp_position)?;
+        user.increment_margin_freed(margin_freed_for_perp_position)?;
+
+        if has_covered_margin_shortage(
+            user,
+            base_asset_amount >= base_asset_amount_to_cover_margin_shortage,
+            &margin_calculation_after,
+        )? {
+            user.exit_liquidation();
+        } else if is_user_bankrupt(user) {
+            user.enter_bankruptcy();
+        }
     }
 
     let liquidator_meets_initial_margin_requirement =
@@ -616,6 +761,7 @@ pub fn liquidate_perp(
         maker_order_cumulative_base_asset_amount_filled: Some(base_asset_amount),
         maker_order_cumulative_quote_asset_amount_filled: Some(base_asset_value),
         oracle_price,
+        taker_order_twap_slice: None,
     };
     emit!(fill_record);
 
@@ -625,9 +771,12 @@ pub fn liquidate_perp(
         liquidation_type: LiquidationType::LiquidatePerp,
         user: *user_key,
         liquidator: *liquidator_key,
-        margin_requirement: margin_calculation.margin_requirement,
-        total_collateral: margin_calculation.total_collateral,
-        bankrupt: user.is_bankrupt(),
+        margin_requirement,
+        total_collateral,
+        bankrupt: match isolated_market_index {
+            Some(market_index) => user.is_isolated_position_bankrupt(market_index),
+            None => user.is_bankrupt(),
+        },
         canceled_order_ids,
         margin_freed,
         liquidate_perp: LiquidatePerpRecord {
@@ -645,6 +794,20 @@ pub fn liquidate_perp(
         ..LiquidationRecord::default()
     });
 
+    update_reduce_only_orders_after_liquidation(
+        user,
+        user_key,
+        liquidator,
+        liquidator_key,
+        MarketType::Perp,
+        market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
     Ok(())
 }
 
@@ -660,6 +823,7 @@ pub fn liquidate_spot(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
     now: i64,
     slot: u64,
     state: &State,
@@ -836,7 +1000,7 @@ pub fn liquidate_spot(
             .track_market_margin_requirement(MarketIdentifier::spot(liability_market_index))?,
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation: {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -844,7 +1008,17 @@ pub fn liquidate_spot(
         return Ok(());
     }
 
-    let liquidation_id = user.enter_liquidation(slot)?;
+    let liquidation_id = start_liquidation(
+        user,
+        user_key,
+        None,
+        slot,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        fixed_term_pool_map,
+        now,
+    )?;
     let mut margin_freed = 0_u64;
 
     let canceled_order_ids = orders::cancel_orders(
@@ -950,7 +1124,8 @@ pub fn liquidate_spot(
         )?;
 
     let max_pct_allowed = calculate_max_pct_to_liquidate(
-        user,
+        user.last_active_slot,
+        user.liquidation_margin_freed,
         margin_shortage,
         slot,
         initial_pct_to_liquidate,
@@ -1116,7 +1291,7 @@ pub fn liquidate_spot(
         )?;
     }
 
-    let margin_freed_from_liability = calculate_margin_freed(
+    let (margin_freed_from_liability, margin_calculation_after) = calculate_margin_freed(
         user,
         perp_market_map,
         spot_market_map,
@@ -1127,7 +1302,11 @@ pub fn liquidate_spot(
     margin_freed = margin_freed.safe_add(margin_freed_from_liability)?;
     user.increment_margin_freed(margin_freed_from_liability)?;
 
-    if liability_transfer >= liability_transfer_to_cover_margin_shortage {
+    if has_covered_margin_shortage(
+        user,
+        liability_transfer >= liability_transfer_to_cover_margin_shortage,
+        &margin_calculation_after,
+    )? {
         user.exit_liquidation();
     } else if is_user_bankrupt(user) {
         user.enter_bankruptcy();
@@ -1164,6 +1343,34 @@ pub fn liquidate_spot(
         ..LiquidationRecord::default()
     });
 
+    update_reduce_only_orders_after_liquidation(
+        user,
+        user_key,
+        liquidator,
+        liquidator_key,
+        MarketType::Spot,
+        asset_market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
+    update_reduce_only_orders_after_liquidation(
+        user,
+        user_key,
+        liquidator,
+        liquidator_key,
+        MarketType::Spot,
+        liability_market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
     Ok(())
 }
 
@@ -1179,6 +1386,7 @@ pub fn liquidate_borrow_for_perp_pnl(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
     now: i64,
     slot: u64,
     liquidation_margin_buffer_ratio: u32,
@@ -1223,7 +1431,7 @@ pub fn liquidate_borrow_for_perp_pnl(
 
     drop(liability_spot_market);
 
-    user.get_perp_position(perp_market_index).map_err(|e| {
+    let user_perp_position = user.get_perp_position(perp_market_index).map_err(|e| {
         msg!(
             "User does not have a position for perp market {}",
             perp_market_index
@@ -1231,6 +1439,12 @@ pub fn liquidate_borrow_for_perp_pnl(
         e
     })?;
 
+    validate!(
+        !user_perp_position.is_isolated(),
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated perp position pnl cant be liquidated against cross collateral"
+    )?;
+
     user.get_spot_position(liability_market_index)
         .map_err(|_| {
             msg!(
@@ -1370,7 +1584,7 @@ pub fn liquidate_borrow_for_perp_pnl(
         MarginContext::liquidation(liquidation_margin_buffer_ratio),
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -1378,7 +1592,17 @@ pub fn liquidate_borrow_for_perp_pnl(
         return Ok(());
     }
 
-    let liquidation_id = user.enter_liquidation(slot)?;
+    let liquidation_id = start_liquidation(
+        user,
+        user_key,
+        None,
+        slot,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        fixed_term_pool_map,
+        now,
+    )?;
     let mut margin_freed = 0_u64;
 
     let canceled_order_ids = orders::cancel_orders(
@@ -1469,7 +1693,8 @@ pub fn liquidate_borrow_for_perp_pnl(
         )?;
 
     let max_pct_allowed = calculate_max_pct_to_liquidate(
-        user,
+        user.last_active_slot,
+        user.liquidation_margin_freed,
         margin_shortage,
         slot,
         initial_pct_to_liquidate,
@@ -1580,7 +1805,7 @@ pub fn liquidate_borrow_for_perp_pnl(
         update_quote_asset_amount(user_position, &mut market, -pnl_transfer.cast()?)?;
     }
 
-    let margin_freed_from_liability = calculate_margin_freed(
+    let (margin_freed_from_liability, margin_calculation_after) = calculate_margin_freed(
         user,
         perp_market_map,
         spot_market_map,
@@ -1591,7 +1816,11 @@ pub fn liquidate_borrow_for_perp_pnl(
     margin_freed = margin_freed.safe_add(margin_freed_from_liability)?;
     user.increment_margin_freed(margin_freed_from_liability)?;
 
-    if liability_transfer >= liability_transfer_to_cover_margin_shortage {
+    if has_covered_margin_shortage(
+        user,
+        liability_transfer >= liability_transfer_to_cover_margin_shortage,
+        &margin_calculation_after,
+    )? {
         user.exit_liquidation();
     } else if is_user_bankrupt(user) {
         user.enter_bankruptcy();
@@ -1632,6 +1861,20 @@ pub fn liquidate_borrow_for_perp_pnl(
         ..LiquidationRecord::default()
     });
 
+    update_reduce_only_orders_after_liquidation(
+        user,
+        user_key,
+        liquidator,
+        liquidator_key,
+        MarketType::Spot,
+        liability_market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
     Ok(())
 }
 
@@ -1647,6 +1890,7 @@ pub fn liquidate_perp_pnl_for_deposit(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
     now: i64,
     slot: u64,
     liquidation_margin_buffer_ratio: u32,
@@ -1692,7 +1936,7 @@ pub fn liquidate_perp_pnl_for_deposit(
 
     drop(perp_market);
 
-    user.get_perp_position(perp_market_index).map_err(|e| {
+    let user_perp_position = user.get_perp_position(perp_market_index).map_err(|e| {
         msg!(
             "User does not have a position for perp market {}",
             perp_market_index
@@ -1700,6 +1944,12 @@ pub fn liquidate_perp_pnl_for_deposit(
         e
     })?;
 
+    validate!(
+        !user_perp_position.is_isolated(),
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated perp position pnl cant be liquidated against cross collateral"
+    )?;
+
     user.get_spot_position(asset_market_index).map_err(|_| {
         msg!(
             "User does not have a spot balance for asset market {}",
@@ -1846,7 +2096,7 @@ pub fn liquidate_perp_pnl_for_deposit(
         MarginContext::liquidation(liquidation_margin_buffer_ratio),
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -1854,7 +2104,17 @@ pub fn liquidate_perp_pnl_for_deposit(
         return Ok(());
     }
 
-    let liquidation_id = user.enter_liquidation(slot)?;
+    let liquidation_id = start_liquidation(
+        user,
+        user_key,
+        None,
+        slot,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        fixed_term_pool_map,
+        now,
+    )?;
     let mut margin_freed = 0_u64;
 
     let canceled_order_ids = orders::cancel_orders(
@@ -1969,7 +2229,8 @@ pub fn liquidate_perp_pnl_for_deposit(
         )?;
 
     let max_pct_allowed = calculate_max_pct_to_liquidate(
-        user,
+        user.last_active_slot,
+        user.liquidation_margin_freed,
         margin_shortage,
         slot,
         initial_pct_to_liquidate,
@@ -2075,7 +2336,7 @@ pub fn liquidate_perp_pnl_for_deposit(
         update_quote_asset_amount(user_position, &mut perp_market, pnl_transfer.cast()?)?;
     }
 
-    let margin_freed_from_liability = calculate_margin_freed(
+    let (margin_freed_from_liability, margin_calculation_after) = calculate_margin_freed(
         user,
         perp_market_map,
         spot_market_map,
@@ -2086,7 +2347,11 @@ pub fn liquidate_perp_pnl_for_deposit(
     margin_freed = margin_freed.safe_add(margin_freed_from_liability)?;
     user.increment_margin_freed(margin_freed_from_liability)?;
 
-    if pnl_transfer >= pnl_transfer_to_cover_margin_shortage {
+    if has_covered_margin_shortage(
+        user,
+        pnl_transfer >= pnl_transfer_to_cover_margin_shortage,
+        &margin_calculation_after,
+    )? {
         user.exit_liquidation();
     } else if is_user_bankrupt(user) {
         user.enter_bankruptcy();
@@ -2127,6 +2392,20 @@ pub fn liquidate_perp_pnl_for_deposit(
         ..LiquidationRecord::default()
     });
 
+    update_reduce_only_orders_after_liquidation(
+        user,
+        user_key,
+        liquidator,
+        liquidator_key,
+        MarketType::Spot,
+        asset_market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
     Ok(())
 }
 
@@ -2136,21 +2415,45 @@ pub fn resolve_perp_bankruptcy(
     user_key: &Pubkey,
     liquidator: &mut User,
     liquidator_key: &Pubkey,
+    auto_deleverage_user_map: &UserMap,
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     now: i64,
+    slot: u64,
     insurance_fund_vault_balance: u64,
 ) -> DriftResult<u64> {
-    if !user.is_bankrupt() && is_user_bankrupt(user) {
-        user.enter_bankruptcy();
-    }
+    let is_isolated_position = user
+        .get_perp_position(market_index)
+        .is_ok_and(|position| position.is_isolated());
+
+    if is_isolated_position {
+        let position = user.get_perp_position(market_index)?;
+        if !position.is_bankrupt()
+            && is_isolated_position_bankrupt(
+                position,
+                position.get_isolated_token_amount(&*spot_market_map.get_quote_spot_market()?)?,
+            )?
+        {
+            user.enter_isolated_position_bankruptcy(market_index)?;
+        }
 
-    validate!(
-        user.is_bankrupt(),
-        ErrorCode::UserNotBankrupt,
-        "user not bankrupt",
-    )?;
+        validate!(
+            user.is_isolated_position_bankrupt(market_index),
+            ErrorCode::UserNotBankrupt,
+            "isolated position not bankrupt",
+        )?;
+    } else {
+        if !user.is_bankrupt() && is_user_bankrupt(user) {
+            user.enter_bankruptcy();
+        }
+
+        validate!(
+            user.is_bankrupt(),
+            ErrorCode::UserNotBankrupt,
+            "user not bankrupt",
+        )?;
+    }
 
     validate!(
         !liquidator.is_being_liquidated(),
@@ -2183,6 +2486,40 @@ pub fn resolve_perp_bankruptcy(
         e
     })?;
 
+    if is_isolated_position {
+        // whatever collateral is left in the isolated position goes towards the loss first
+        let perp_market = &mut perp_market_map.get_ref_mut(&market_index)?;
+        let spot_market = &mut spot_market_map.get_quote_spot_market_mut()?;
+        update_spot_market_cumulative_interest(spot_market, None, now)?;
+        let position_index = get_position_index(&user.perp_positions, market_index)?;
+        let isolated_token_amount =
+            user.perp_positions[position_index].get_isolated_token_amount(spot_market)?;
+
+        if isolated_token_amount > 0 {
+            update_spot_balances(
+                isolated_token_amount,
+                &SpotBalanceType::Borrow,
+                spot_market,
+                &mut user.perp_positions[position_index],
+                false,
+            )?;
+
+            update_spot_balances(
+                isolated_token_amount,
+                &SpotBalanceType::Deposit,
+                spot_market,
+                &mut perp_market.pnl_pool,
+                false,
+            )?;
+
+            update_quote_asset_amount(
+                &mut user.perp_positions[position_index],
+                perp_market,
+                isolated_token_amount.cast()?,
+            )?;
+        }
+    }
+
     let loss = user
         .get_perp_position(market_index)?
         .quote_asset_amount
@@ -2194,11 +2531,7 @@ pub fn resolve_perp_bankruptcy(
         "user must have negative pnl"
     )?;
 
-    let MarginCalculation {
-        margin_requirement,
-        total_collateral,
-        ..
-    } = calculate_margin_requirement_and_total_collateral_and_liability_info(
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
         user,
         perp_market_map,
         spot_market_map,
@@ -2206,6 +2539,12 @@ pub fn resolve_perp_bankruptcy(
         MarginContext::standard(MarginRequirementType::Maintenance),
     )?;
 
+    let (margin_requirement, total_collateral) =
+        get_liquidation_margin_requirement_and_total_collateral(
+            &margin_calculation,
+            is_isolated_position.then_some(market_index),
+        )?;
+
     // spot market's insurance fund draw attempt here (before social loss)
     // subtract 1 from available insurance_fund_vault_balance so deposits in insurance vault always remains >= 1
 
@@ -2286,10 +2625,35 @@ pub fn resolve_perp_bankruptcy(
         "loss_to_socialize must be non-positive"
     )?;
 
-    let cumulative_funding_rate_delta = calculate_funding_rate_deltas_to_resolve_bankruptcy(
-        loss_to_socialize,
-        perp_market_map.get_ref(&market_index)?.deref(),
-    )?;
+    // close out the most profitable and most levered positions before socializing what's left
+    let auto_deleverage_payment = if loss_to_socialize < 0 {
+        auto_deleverage_perp_positions(
+            market_index,
+            loss_to_socialize.unsigned_abs(),
+            user_key,
+            liquidator_key,
+            auto_deleverage_user_map,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+        )?
+    } else {
+        0
+    };
+
+    let loss_to_socialize = loss_to_socialize.safe_add(auto_deleverage_payment.cast::<i128>()?)?;
+
+    // auto deleveraging can close out every position, leaving no base to socialize over
+    let cumulative_funding_rate_delta = if loss_to_socialize < 0 {
+        calculate_funding_rate_deltas_to_resolve_bankruptcy(
+            loss_to_socialize,
+            perp_market_map.get_ref(&market_index)?.deref(),
+        )?
+    } else {
+        0
+    };
 
     // socialize loss
     if loss_to_socialize < 0 {
@@ -2326,7 +2690,9 @@ pub fn resolve_perp_bankruptcy(
     }
 
     // exit bankruptcy
-    if !is_user_bankrupt(user) {
+    if is_isolated_position {
+        user.exit_isolated_position_liquidation(market_index);
+    } else if !is_user_bankrupt(user) {
         user.exit_bankruptcy();
     }
 
@@ -2355,6 +2721,249 @@ pub fn resolve_perp_bankruptcy(
     if_payment.cast()
 }
 
+/// Takes the uncovered bankruptcy loss out of profitable perp positions in the market, ordered by
+/// auto deleverage score. Each position only closes the base needed to cover its share of the loss,
+/// trading against the amm at the bankruptcy price. Candidates are chosen by the keeper, so each
+/// must be at least as profitable relative to entry as the average position on its side of the
+/// market. Returns the portion of the loss covered
+pub fn auto_deleverage_perp_positions(
+    market_index: u16,
+    loss: u128,
+    bankrupt_user_key: &Pubkey,
+    liquidator_key: &Pubkey,
+    auto_deleverage_user_map: &UserMap,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+) -> DriftResult<u128> {
+    if auto_deleverage_user_map.0.is_empty() {
+        return Ok(0);
+    }
+
+    let oracle_price = {
+        let market = perp_market_map.get_ref(&market_index)?;
+        oracle_map.get_price_data(&market.amm.oracle)?.price
+    };
+
+    let mut candidates: Vec<(u128, Pubkey)> = Vec::with_capacity(auto_deleverage_user_map.0.len());
+    for user_key in auto_deleverage_user_map.0.keys() {
+        validate!(
+            user_key != bankrupt_user_key && user_key != liquidator_key,
+            ErrorCode::InvalidAutoDeleverageUser,
+            "cant auto deleverage bankrupt user or liquidator {}",
+            user_key
+        )?;
+
+        let mut user = auto_deleverage_user_map.get_ref_mut(user_key)?;
+
+        if user.is_being_liquidated() || user.is_bankrupt() {
+            continue;
+        }
+
+        match user.get_perp_position(market_index) {
+            Ok(position)
+                if position.is_open_position()
+                    && !position.is_lp()
+                    && position.remainder_base_asset_amount == 0 => {}
+            _ => continue,
+        }
+
+        settle_funding_payment(
+            &mut user,
+            user_key,
+            perp_market_map.get_ref_mut(&market_index)?.deref_mut(),
+            now,
+        )?;
+
+        let position = user.get_perp_position(market_index)?;
+
+        let (side_base_asset_amount, side_quote_entry_amount) = {
+            let market = perp_market_map.get_ref(&market_index)?;
+            if position.base_asset_amount > 0 {
+                (
+                    market.amm.base_asset_amount_long,
+                    market.amm.quote_entry_amount_long,
+                )
+            } else {
+                (
+                    market.amm.base_asset_amount_short,
+                    market.amm.quote_entry_amount_short,
+                )
+            }
+        };
+
+        let pnl_pct = calculate_pnl_pct_from_entry(
+            position.base_asset_amount.cast()?,
+            position.quote_entry_amount.cast()?,
+            oracle_price,
+        )?;
+        let side_pnl_pct = calculate_pnl_pct_from_entry(
+            side_base_asset_amount,
+            side_quote_entry_amount,
+            oracle_price,
+        )?;
+
+        validate!(
+            pnl_pct >= side_pnl_pct,
+            ErrorCode::InvalidAutoDeleverageUser,
+            "user {} pnl_pct={} below average for its side side_pnl_pct={}",
+            user_key,
+            pnl_pct,
+            side_pnl_pct
+        )?;
+
+        let unrealized_pnl = position.get_unrealized_pnl(oracle_price)?;
+        if unrealized_pnl <= 0 {
+            continue;
+        }
+
+        let base_asset_value = calculate_base_asset_value_with_oracle_price(
+            position.base_asset_amount.cast()?,
+            oracle_price,
+        )?;
+        let quote_entry_amount = position.quote_entry_amount;
+        let is_isolated_position = position.is_isolated();
+
+        let margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                &user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(MarginRequirementType::Maintenance),
+            )?;
+
+        let (_, total_collateral) = get_liquidation_margin_requirement_and_total_collateral(
+            &margin_calculation,
+            is_isolated_position.then_some(market_index),
+        )?;
+
+        let auto_deleverage_score = calculate_auto_deleverage_score(
+            unrealized_pnl,
+            quote_entry_amount,
+            base_asset_value,
+            total_collateral,
+        )?;
+
+        candidates.push((auto_deleverage_score, *user_key));
+    }
+
+    candidates.sort_by_key(|(auto_deleverage_score, _)| std::cmp::Reverse(*auto_deleverage_score));
+
+    let mut loss_remaining = loss;
+    for (auto_deleverage_score, user_key) in candidates {
+        if loss_remaining == 0 {
+            break;
+        }
+
+        let mut user = auto_deleverage_user_map.get_ref_mut(&user_key)?;
+        let mut market = perp_market_map.get_ref_mut(&market_index)?;
+        let position_index = get_position_index(&user.perp_positions, market_index)?;
+
+        let position = &user.perp_positions[position_index];
+        let existing_base_asset_amount = position.base_asset_amount.unsigned_abs();
+        let direction_to_close = position.get_direction_to_close();
+        let unrealized_pnl = position.get_unrealized_pnl(oracle_price)?.unsigned_abs();
+
+        // only close the share of the position whose pnl is needed to cover the loss
+        let base_asset_amount = standardize_base_asset_amount_ceil(
+            existing_base_asset_amount
+                .cast::<u128>()?
+                .safe_mul(loss_remaining.min(unrealized_pnl))?
+                .safe_div_ceil(unrealized_pnl)?
+                .cast()?,
+            market.amm.order_step_size,
+        )?
+        .min(existing_base_asset_amount);
+
+        let pnl_to_pay = unrealized_pnl
+            .safe_mul(base_asset_amount.cast()?)?
+            .safe_div(existing_base_asset_amount.cast()?)?
+            .min(loss_remaining);
+
+        let bankruptcy_price = calculate_auto_deleverage_bankruptcy_price(
+            oracle_price,
+            base_asset_amount,
+            pnl_to_pay,
+            direction_to_close,
+        )?;
+
+        let (quote_asset_amount, quote_asset_amount_surplus, _) =
+            update_position_with_base_asset_amount(
+                base_asset_amount,
+                direction_to_close,
+                &mut market,
+                &mut user,
+                position_index,
+                Some(bankruptcy_price),
+            )?;
+
+        // what the position paid past the oracle price covers the loss, the amm keeps the rest
+        let base_asset_value =
+            calculate_base_asset_value_with_oracle_price(base_asset_amount.cast()?, oracle_price)?
+                .cast::<u64>()?;
+        let pnl_payment = match direction_to_close {
+            PositionDirection::Long => quote_asset_amount.saturating_sub(base_asset_value),
+            PositionDirection::Short => base_asset_value.saturating_sub(quote_asset_amount),
+        };
+
+        let amm_surplus = quote_asset_amount_surplus.safe_sub(pnl_payment.cast()?)?;
+        market.amm.total_fee = market.amm.total_fee.safe_add(amm_surplus.cast()?)?;
+        market.amm.total_mm_fee = market.amm.total_mm_fee.safe_add(amm_surplus.cast()?)?;
+        market.amm.total_fee_minus_distributions = market
+            .amm
+            .total_fee_minus_distributions
+            .safe_add(amm_surplus.cast()?)?;
+        market.amm.net_revenue_since_last_funding = market
+            .amm
+            .net_revenue_since_last_funding
+            .safe_add(amm_surplus)?;
+
+        let pnl_payment = pnl_payment.cast::<u128>()?.min(loss_remaining);
+        loss_remaining = loss_remaining.safe_sub(pnl_payment)?;
+
+        msg!(
+            "auto deleveraged user {} base_asset_amount {} bankruptcy_price {} pnl_payment {}",
+            user_key,
+            base_asset_amount,
+            bankruptcy_price,
+            pnl_payment
+        );
+
+        emit!(AutoDeleverageRecord {
+            ts: now,
+            market_index,
+            bankrupt_user: *bankrupt_user_key,
+            user: user_key,
+            direction: direction_to_close,
+            base_asset_amount,
+            quote_asset_amount,
+            oracle_price,
+            bankruptcy_price,
+            auto_deleverage_score,
+            pnl_payment,
+        });
+
+        drop(market);
+        orders::update_reduce_only_orders(
+            &mut user,
+            &user_key,
+            MarketType::Perp,
+            market_index,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            None,
+            now,
+            slot,
+        )?;
+    }
+
+    loss.safe_sub(loss_remaining)
+}
+
 pub fn resolve_spot_bankruptcy(
     market_index: u16,
     user: &mut User,
@@ -2516,7 +3125,7 @@ pub fn calculate_margin_freed(
     oracle_map: &mut OracleMap,
     liquidation_margin_buffer_ratio: u32,
     initial_margin_shortage: u128,
-) -> DriftResult<u64> {
+) -> DriftResult<(u64, MarginCalculation)> {
     let margin_calculation_after =
         calculate_margin_requirement_and_total_collateral_and_liability_info(
             user,
@@ -2528,7 +3137,195 @@ pub fn calculate_margin_freed(
 
     let new_margin_shortage = margin_calculation_after.margin_shortage()?;
 
-    initial_margin_shortage
+    // unwinding one leg of a hedge can raise the netted requirement, so the unhedged exposure goes first
+    if user.is_portfolio_margin() && !margin_calculation_after.can_exit_liquidation()? {
+        validate!(
+            new_margin_shortage <= initial_margin_shortage,
+            ErrorCode::InvalidLiquidation,
+            "liquidation increased portfolio margin shortage from {} to {}",
+            initial_margin_shortage,
+            new_margin_shortage
+        )?;
+    }
+
+    let margin_freed = initial_margin_shortage
         .saturating_sub(new_margin_shortage)
-        .cast::<u64>()
+        .cast::<u64>()?;
+
+    Ok((margin_freed, margin_calculation_after))
+}
+
+/// Whether a cross liquidation restored the account. In portfolio margin mode the margin freed by
+/// closing one leg depends on the rest of the group, so the recalculated margin decides instead
+/// of the transfer estimated to cover the shortage
+fn has_covered_margin_shortage(
+    user: &User,
+    transfer_covers_margin_shortage: bool,
+    margin_calculation_after: &MarginCalculation,
+) -> DriftResult<bool> {
+    if user.is_portfolio_margin() {
+        margin_calculation_after.can_exit_liquidation()
+    } else {
+        Ok(transfer_covers_margin_shortage)
+    }
+}
+
+fn get_liquidation_margin_shortage(
+    margin_calculation: &MarginCalculation,
+    isolated_market_index: Option<u16>,
+) -> DriftResult<u128> {
+    match isolated_market_index {
+        Some(market_index) => margin_calculation
+            .get_isolated_position_margin_calculation(market_index)?
+            .margin_shortage(),
+        None => margin_calculation.margin_shortage(),
+    }
+}
+
+fn can_exit_liquidation(
+    margin_calculation: &MarginCalculation,
+    isolated_market_index: Option<u16>,
+) -> DriftResult<bool> {
+    match isolated_market_index {
+        Some(market_index) => Ok(margin_calculation
+            .get_isolated_position_margin_calculation(market_index)?
+            .can_exit_liquidation()),
+        None => margin_calculation.can_exit_liquidation(),
+    }
+}
+
+fn get_liquidation_margin_requirement_and_total_collateral(
+    margin_calculation: &MarginCalculation,
+    isolated_market_index: Option<u16>,
+) -> DriftResult<(u128, i128)> {
+    match isolated_market_index {
+        Some(market_index) => {
+            let isolated_margin_calculation =
+                margin_calculation.get_isolated_position_margin_calculation(market_index)?;
+            Ok((
+                isolated_margin_calculation.margin_requirement,
+                isolated_margin_calculation.total_collateral,
+            ))
+        }
+        None => Ok((
+            margin_calculation.margin_requirement,
+            margin_calculation.total_collateral,
+        )),
+    }
+}
+
+/// Enters the user, or the isolated position, into liquidation. Perp positions worth at least their
+/// market's liquidation_auction_min_notional when liquidation starts stay dutch auctioned until it
+/// ends, even once they've been liquidated below it. Entering cross liquidation also settles the
+/// user's fixed term positions into spot balances
+fn start_liquidation(
+    user: &mut User,
+    user_key: &Pubkey,
+    isolated_market_index: Option<u16>,
+    slot: u64,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    fixed_term_pool_map: &FixedTermPoolMap,
+    now: i64,
+) -> DriftResult<u16> {
+    let is_being_liquidated = match isolated_market_index {
+        Some(market_index) => user.get_perp_position(market_index)?.is_being_liquidated(),
+        None => user.is_being_liquidated(),
+    };
+
+    let liquidation_id = match isolated_market_index {
+        Some(market_index) => user.enter_isolated_position_liquidation(market_index, slot)?,
+        None => user.enter_liquidation(slot)?,
+    };
+
+    if is_being_liquidated {
+        return Ok(liquidation_id);
+    }
+
+    if isolated_market_index.is_none() {
+        settle_fixed_term_positions_for_liquidation(
+            user,
+            user_key,
+            spot_market_map,
+            fixed_term_pool_map,
+            now,
+        )?;
+    }
+
+    for position in user.perp_positions.iter_mut() {
+        let is_liquidated_position = match isolated_market_index {
+            Some(market_index) => position.is_for(market_index),
+            None => !position.is_isolated(),
+        };
+
+        if !is_liquidated_position || position.base_asset_amount == 0 {
+            continue;
+        }
+
+        let market = perp_market_map.get_ref(&position.market_index)?;
+        if market.liquidation_auction_min_notional == 0 {
+            continue;
+        }
+
+        let oracle_price = if market.status == MarketStatus::Settlement {
+            market.expiry_price
+        } else {
+            oracle_map.get_price_data(&market.amm.oracle)?.price
+        };
+
+        let base_asset_value = calculate_base_asset_value_with_oracle_price(
+            position.base_asset_amount.cast()?,
+            oracle_price,
+        )?
+        .cast::<u64>()?;
+
+        if market.is_liquidation_auction(base_asset_value) {
+            position.add_position_flag(PositionFlag::LiquidationAuction);
+        }
+    }
+
+    Ok(liquidation_id)
+}
+
+/// Liquidation transfers change both accounts' positions, so their reduce-only orders are shrunk to fit
+#[allow(clippy::too_many_arguments)]
+fn update_reduce_only_orders_after_liquidation(
+    user: &mut User,
+    user_key: &Pubkey,
+    liquidator: &mut User,
+    liquidator_key: &Pubkey,
+    market_type: MarketType,
+    market_index: u16,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    orders::update_reduce_only_orders(
+        user,
+        user_key,
+        market_type,
+        market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        None,
+        now,
+        slot,
+    )?;
+
+    orders::update_reduce_only_orders(
+        liquidator,
+        liquidator_key,
+        market_type,
+        market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        None,
+        now,
+        slot,
+    )
 }
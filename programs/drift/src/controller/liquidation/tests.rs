@@ -14,8 +14,9 @@ pub mod liquidate_perp {
         AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_I64, BASE_PRECISION_U64,
         LIQUIDATION_FEE_PRECISION, LIQUIDATION_PCT_PRECISION, MARGIN_PRECISION,
         MARGIN_PRECISION_U128, PEG_PRECISION, PRICE_PRECISION, PRICE_PRECISION_U64,
-        QUOTE_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
-        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+        QUOTE_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, QUOTE_PRECISION_U64,
+        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION,
+        SPOT_WEIGHT_PRECISION,
     };
     use crate::math::liquidation::is_user_being_liquidated;
     use crate::math::margin::{
@@ -24,6 +25,7 @@ pub mod liquidate_perp {
     use crate::math::position::calculate_base_asset_value_with_oracle_price;
     use crate::state::margin_calculation::{MarginCalculation, MarginContext};
     use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
     use crate::state::oracle_map::OracleMap;
     use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
     use crate::state::perp_market_map::PerpMarketMap;
@@ -158,6 +160,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -308,6 +311,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -456,6 +460,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -592,6 +597,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -743,6 +749,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -970,6 +977,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1118,6 +1126,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1250,6 +1259,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1374,6 +1384,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1529,6 +1540,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1554,6 +1566,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1599,6 +1612,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1624,6 +1638,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1669,6 +1684,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1681,6 +1697,234 @@ pub mod liquidate_perp {
         assert_eq!(user.perp_positions[0].base_asset_amount, 2000000000);
     }
 
+    #[test]
+    pub fn liquidation_auction_over_multiple_slots() {
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                quote_asset_amount: -150 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: BASE_PRECISION_I128,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+                funding_period: ONE_HOUR,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            number_of_users_with_base: 1,
+            status: MarketStatus::Initialized,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            if_liquidation_fee: LIQUIDATION_FEE_PRECISION / 100,
+            liquidation_auction_min_notional: 1000 * QUOTE_PRECISION_U64,
+            liquidation_auction_end_liquidator_fee: LIQUIDATION_FEE_PRECISION / 50,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: 10 * BASE_PRECISION_U64,
+                slot: 0,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: 20 * BASE_PRECISION_I64,
+                quote_asset_amount: -2000 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -2000 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -2000 * QUOTE_PRECISION_I64,
+                open_orders: 1,
+                open_bids: 10 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 50 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+
+            ..User::default()
+        };
+
+        let mut second_liquidator = User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 500 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut liquidator = User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 500 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        let mut user_stats = UserStats::default();
+        let mut liquidator_stats = UserStats::default();
+        let mut second_liquidator_stats = UserStats::default();
+        let state = State {
+            liquidation_margin_buffer_ratio: MARGIN_PRECISION / 50,
+            initial_pct_to_liquidate: (LIQUIDATION_PCT_PRECISION / 10) as u16,
+            liquidation_duration: 150,
+            ..Default::default()
+        };
+        // auction starts at the flat 1% liquidator fee, after canceling the open order
+        liquidate_perp(
+            0,
+            2 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        assert_eq!(
+            user.perp_positions[0].base_asset_amount,
+            18 * BASE_PRECISION_I64
+        );
+        assert_eq!(
+            liquidator.perp_positions[0].quote_asset_amount,
+            -198 * QUOTE_PRECISION_I64
+        );
+        assert!(user.perp_positions[0].is_liquidation_auction());
+        assert_eq!(user.liquidation_start_slot, 1);
+
+        // a deposit while being liquidated doesn't restart the auction
+        user.last_active_slot = 50;
+
+        // halfway through the auction a second liquidator takes a slice at 1.5%
+        let slot = 76_u64;
+        liquidate_perp(
+            0,
+            2 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut second_liquidator,
+            &liquidator_key,
+            &mut second_liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        assert_eq!(
+            user.perp_positions[0].base_asset_amount,
+            16 * BASE_PRECISION_I64
+        );
+        assert_eq!(
+            second_liquidator.perp_positions[0].quote_asset_amount,
+            -197 * QUOTE_PRECISION_I64
+        );
+
+        // the position stays auctioned even once it's no longer above the min notional
+        perp_market_map
+            .get_ref_mut(&0)
+            .unwrap()
+            .liquidation_auction_min_notional = 5000 * QUOTE_PRECISION_U64;
+
+        // past the end of the auction the fee stays at 2% and the whole shortage can be covered at once
+        let slot = 200_u64;
+        liquidate_perp(
+            0,
+            100 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        assert_eq!(user.status, 0);
+        assert_eq!(
+            user.perp_positions[0].base_asset_amount,
+            BASE_PRECISION_I64 / 10
+        );
+        assert!(!user.perp_positions[0].is_liquidation_auction());
+        assert_eq!(user.liquidation_start_slot, 0);
+    }
+
     #[test]
     pub fn liquidation_accelerated() {
         let now = 1_i64;
@@ -1806,6 +2050,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -1931,6 +2176,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -2059,6 +2305,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -2187,6 +2434,7 @@ pub mod liquidate_perp {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             slot,
             now,
             &state,
@@ -2197,72 +2445,233 @@ pub mod liquidate_perp {
         assert!(!user.is_being_liquidated());
         assert_eq!(market_after.amm.total_liquidation_fee, 41787043);
     }
-}
-
-pub mod liquidate_spot {
-    use crate::state::state::State;
-    use std::ops::Deref;
-    use std::str::FromStr;
-
-    use anchor_lang::Owner;
-    use solana_program::pubkey::Pubkey;
-
-    use crate::controller::liquidation::liquidate_spot;
-    use crate::create_anchor_account_info;
-    use crate::error::ErrorCode;
-    use crate::math::constants::{
-        LIQUIDATION_FEE_PRECISION, LIQUIDATION_PCT_PRECISION, MARGIN_PRECISION,
-        MARGIN_PRECISION_U128, PRICE_PRECISION, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
-        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::math::margin::calculate_margin_requirement_and_total_collateral_and_liability_info;
-    use crate::math::spot_balance::{get_strict_token_value, get_token_amount, get_token_value};
-    use crate::state::margin_calculation::{MarginCalculation, MarginContext};
-    use crate::state::oracle::OracleSource;
-    use crate::state::oracle::{HistoricalOracleData, StrictOraclePrice};
-    use crate::state::oracle_map::OracleMap;
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
-    use crate::test_utils::*;
-    use crate::test_utils::{get_pyth_price, get_spot_positions};
-    use crate::{create_account_info, QUOTE_PRECISION_I64};
 
     #[test]
-    pub fn successful_liquidation_liability_transfer_implied_by_asset_amount() {
+    pub fn fail_liquidating_hedged_perp_leg_in_portfolio_margin() {
         let now = 0_i64;
         let slot = 0_u64;
 
-        let mut sol_oracle_price = get_pyth_price(100, 6);
-        let sol_oracle_price_key =
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            sol_oracle_price,
-            &sol_oracle_price_key,
+            oracle_price,
+            &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
 
-        let perp_market_map = PerpMarketMap::empty();
-
-        let mut usdc_market = SpotMarket {
-            market_index: 0,
-            oracle_source: OracleSource::QuoteAsset,
-            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            decimals: 6,
-            initial_asset_weight: SPOT_WEIGHT_PRECISION,
-            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            deposit_balance: 200 * SPOT_BALANCE_PRECISION,
-            liquidator_fee: 0,
-            historical_oracle_data: HistoricalOracleData {
-                last_oracle_price_twap: QUOTE_PRECISION_I64,
-                last_oracle_price_twap_5min: QUOTE_PRECISION_I64,
-                ..HistoricalOracleData::default()
-            },
-            ..SpotMarket::default()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                quote_asset_amount: -40 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: -10 * BASE_PRECISION_I128,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            number_of_users_with_base: 1,
+            status: MarketStatus::Initialized,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            if_liquidation_fee: LIQUIDATION_FEE_PRECISION / 100,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 5050 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let mut sol_spot_market = SpotMarket {
+            market_index: 1,
+            oracle_source: OracleSource::Pyth,
+            oracle: oracle_price_key,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 9,
+            initial_asset_weight: 8 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
+            initial_liability_weight: 12 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_liability_weight: 11 * SPOT_WEIGHT_PRECISION / 10,
+            deposit_balance: 10 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
+        let spot_market_account_infos = Vec::from([
+            &usdc_spot_market_account_info,
+            &sol_spot_market_account_info,
+        ]);
+        let spot_market_map =
+            SpotMarketMap::load_multiple(spot_market_account_infos, true).unwrap();
+
+        let mut spot_positions = [SpotPosition::default(); 8];
+        spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 50 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 10 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+
+        // long 10 SOL spot hedging a short 10 SOL-PERP that has lost $1040
+        let mut user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: -10 * BASE_PRECISION_I64,
+                quote_asset_amount: -40 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -40 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -40 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions,
+            ..User::default()
+        };
+        user.update_portfolio_margin_status(true).unwrap();
+
+        let mut liquidator = User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 5000 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        let mut user_stats = UserStats::default();
+        let mut liquidator_stats = UserStats::default();
+        let state = State {
+            liquidation_margin_buffer_ratio: 10,
+            initial_pct_to_liquidate: LIQUIDATION_PCT_PRECISION as u16,
+            liquidation_duration: 150,
+            ..Default::default()
+        };
+
+        // closing part of the short unhedges the spot leg, raising the netted requirement
+        let result = liquidate_perp(
+            0,
+            10 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        );
+
+        assert_eq!(result, Err(ErrorCode::InvalidLiquidation));
+    }
+}
+
+pub mod liquidate_spot {
+    use crate::state::state::State;
+    use std::ops::Deref;
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::controller::liquidation::liquidate_spot;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        LIQUIDATION_FEE_PRECISION, LIQUIDATION_PCT_PRECISION, MARGIN_PRECISION,
+        MARGIN_PRECISION_U128, PRICE_PRECISION, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::calculate_margin_requirement_and_total_collateral_and_liability_info;
+    use crate::math::spot_balance::{get_strict_token_value, get_token_amount, get_token_value};
+    use crate::state::margin_calculation::{MarginCalculation, MarginContext};
+    use crate::state::oracle::OracleSource;
+    use crate::state::oracle::{HistoricalOracleData, StrictOraclePrice};
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, QUOTE_PRECISION_I64};
+
+    #[test]
+    pub fn successful_liquidation_liability_transfer_implied_by_asset_amount() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut usdc_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 200 * SPOT_BALANCE_PRECISION,
+            liquidator_fee: 0,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: QUOTE_PRECISION_I64,
+                last_oracle_price_twap_5min: QUOTE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
         };
         create_anchor_account_info!(usdc_market, SpotMarket, usdc_spot_market_account_info);
         let mut sol_market = SpotMarket {
@@ -2346,6 +2755,7 @@ pub mod liquidate_spot {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -2484,6 +2894,7 @@ pub mod liquidate_spot {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -2510,6 +2921,7 @@ pub mod liquidate_spot {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -2648,6 +3060,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -2861,6 +3274,7 @@ pub mod liquidate_spot {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -2985,6 +3399,7 @@ pub mod liquidate_spot {
             &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3110,6 +3525,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3243,6 +3659,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3288,6 +3705,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3333,6 +3751,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3483,6 +3902,7 @@ pub mod liquidate_spot {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -3536,6 +3956,7 @@ pub mod liquidate_borrow_for_perp_pnl {
     use crate::state::margin_calculation::{MarginCalculation, MarginContext};
     use crate::state::oracle::HistoricalOracleData;
     use crate::state::oracle::OracleSource;
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
     use crate::state::oracle_map::OracleMap;
     use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
     use crate::state::perp_market_map::PerpMarketMap;
@@ -3681,6 +4102,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -3838,6 +4260,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -4033,6 +4456,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -4189,6 +4613,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -4336,6 +4761,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -4484,6 +4910,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -4641,6 +5068,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -4687,6 +5115,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -4733,6 +5162,7 @@ pub mod liquidate_borrow_for_perp_pnl {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -4768,6 +5198,7 @@ pub mod liquidate_perp_pnl_for_deposit {
     use crate::state::margin_calculation::{MarginCalculation, MarginContext};
     use crate::state::oracle::HistoricalOracleData;
     use crate::state::oracle::OracleSource;
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
     use crate::state::oracle_map::OracleMap;
     use crate::state::perp_market::{ContractTier, MarketStatus, PerpMarket, AMM};
     use crate::state::perp_market_map::PerpMarketMap;
@@ -4913,6 +5344,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -5069,6 +5501,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             MARGIN_PRECISION / 50,
@@ -5227,6 +5660,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -5383,6 +5817,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -5530,6 +5965,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -5677,6 +6113,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             MARGIN_PRECISION / 50,
@@ -5834,6 +6271,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -5880,6 +6318,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -5926,6 +6365,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             liquidation_buffer,
@@ -6084,6 +6524,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6111,6 +6552,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             &state,
@@ -6131,6 +6573,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6154,6 +6597,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6354,6 +6798,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6375,6 +6820,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6396,6 +6842,7 @@ pub mod liquidate_perp_pnl_for_deposit {
             &market_map,
             &spot_market_map,
             &mut oracle_map,
+            &FixedTermPoolMap::empty(),
             now,
             slot,
             10,
@@ -6420,19 +6867,21 @@ pub mod liquidate_perp_pnl_for_deposit {
 pub mod resolve_perp_bankruptcy {
     use std::str::FromStr;
 
+    use anchor_lang::prelude::AccountLoader;
     use anchor_lang::Owner;
     use solana_program::pubkey::Pubkey;
 
     use crate::controller::funding::settle_funding_payment;
-    use crate::controller::liquidation::resolve_perp_bankruptcy;
+    use crate::controller::liquidation::{auto_deleverage_perp_positions, resolve_perp_bankruptcy};
     use crate::controller::position::PositionDirection;
     use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
     use crate::math::constants::{
         AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_I64, BASE_PRECISION_U64,
         FUNDING_RATE_PRECISION_I128, FUNDING_RATE_PRECISION_I64, LIQUIDATION_FEE_PRECISION,
-        PEG_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, QUOTE_SPOT_MARKET_INDEX,
-        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION,
-        SPOT_WEIGHT_PRECISION,
+        PEG_PRECISION, QUOTE_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64,
+        QUOTE_SPOT_MARKET_INDEX, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
     };
     use crate::state::oracle::{HistoricalOracleData, OracleSource};
     use crate::state::oracle_map::OracleMap;
@@ -6443,6 +6892,7 @@ pub mod resolve_perp_bankruptcy {
     use crate::state::user::{
         Order, OrderStatus, OrderType, PerpPosition, SpotPosition, User, UserStatus,
     };
+    use crate::state::user_map::UserMap;
     use crate::test_utils::*;
     use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
     use crate::{create_account_info, PRICE_PRECISION_I64};
@@ -6569,10 +7019,12 @@ pub mod resolve_perp_bankruptcy {
             &user_key,
             &mut liquidator,
             &liquidator_key,
+            &UserMap::empty(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             now,
+            slot,
             0,
         )
         .unwrap();
@@ -6781,10 +7233,12 @@ pub mod resolve_perp_bankruptcy {
             &user_key,
             &mut liquidator,
             &liquidator_key,
+            &UserMap::empty(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             now,
+            slot,
             0,
         )
         .unwrap();
@@ -6869,39 +7323,9 @@ pub mod resolve_perp_bankruptcy {
 
         assert_eq!(expected_affected_short_user, affected_short_user);
     }
-}
-
-pub mod resolve_spot_bankruptcy {
-    use std::str::FromStr;
-
-    use anchor_lang::Owner;
-    use solana_program::pubkey::Pubkey;
-
-    use crate::controller::liquidation::resolve_spot_bankruptcy;
-    use crate::controller::position::PositionDirection;
-    use crate::create_account_info;
-    use crate::create_anchor_account_info;
-    use crate::math::constants::{
-        AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_U64,
-        FUNDING_RATE_PRECISION_I128, LIQUIDATION_FEE_PRECISION, PEG_PRECISION, QUOTE_PRECISION,
-        QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION,
-        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::math::spot_balance::get_token_amount;
-    use crate::state::oracle::{HistoricalOracleData, OracleSource};
-    use crate::state::oracle_map::OracleMap;
-    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{
-        Order, OrderStatus, OrderType, PerpPosition, SpotPosition, User, UserStatus,
-    };
-    use crate::test_utils::*;
-    use crate::test_utils::{get_orders, get_pyth_price, get_spot_positions};
 
     #[test]
-    pub fn successful_resolve_spot_bankruptcy() {
+    pub fn auto_deleverage_before_socializing_loss() {
         let now = 0_i64;
         let slot = 0_u64;
 
@@ -6921,19 +7345,19 @@ pub mod resolve_spot_bankruptcy {
             amm: AMM {
                 base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
-                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
                 sqrt_k: 100 * AMM_RESERVE_PRECISION,
                 peg_multiplier: 100 * PEG_PRECISION,
-                max_slippage_ratio: 50,
-                max_fill_reserve_fraction: 100,
                 order_step_size: 10000000,
-                quote_asset_amount: -150 * QUOTE_PRECISION_I128,
-                base_asset_amount_with_amm: BASE_PRECISION_I128,
-                base_asset_amount_long: 5 * BASE_PRECISION_I128,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                max_base_asset_reserve: u128::MAX,
+                min_base_asset_reserve: 0,
+                quote_asset_amount: 600 * QUOTE_PRECISION_I128,
                 base_asset_amount_short: -5 * BASE_PRECISION_I128,
+                quote_entry_amount_short: 640 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: -5 * BASE_PRECISION_I128,
                 oracle: oracle_price_key,
                 cumulative_funding_rate_long: 1000 * FUNDING_RATE_PRECISION_I128,
                 cumulative_funding_rate_short: -1000 * FUNDING_RATE_PRECISION_I128,
@@ -6942,7 +7366,8 @@ pub mod resolve_spot_bankruptcy {
             margin_ratio_initial: 1000,
             margin_ratio_maintenance: 500,
             status: MarketStatus::Initialized,
-            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            number_of_users: 3,
+            number_of_users_with_base: 2,
             ..PerpMarket::default()
         };
         create_anchor_account_info!(market, PerpMarket, market_account_info);
@@ -6952,29 +7377,288 @@ pub mod resolve_spot_bankruptcy {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
-            deposit_balance: 1000 * SPOT_BALANCE_PRECISION,
-            borrow_balance: 100 * SPOT_BALANCE_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
         let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
         let mut user = User {
-            orders: get_orders(Order {
+            perp_positions: get_positions(PerpPosition {
                 market_index: 0,
-                status: OrderStatus::Open,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                ..Order::default()
+                quote_asset_amount: -40 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
             }),
-            perp_positions: [PerpPosition::default(); 8],
-            spot_positions: get_spot_positions(SpotPosition {
+            spot_positions: [SpotPosition::default(); 8],
+            status: UserStatus::Bankrupt as u8,
+            next_liquidation_id: 2,
+            ..User::default()
+        };
+
+        let mut liquidator = User::default();
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        // up 28.6% at 2x leverage
+        let mut more_levered_short = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: -2 * BASE_PRECISION_I64,
+                quote_asset_amount: 280 * QUOTE_PRECISION_I64,
+                quote_entry_amount: 280 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: 280 * QUOTE_PRECISION_I64,
+                last_cumulative_funding_rate: -1000 * FUNDING_RATE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        let more_levered_short_key = Pubkey::new_unique();
+        create_anchor_account_info!(
+            more_levered_short,
+            &more_levered_short_key,
+            User,
+            more_levered_short_account_info
+        );
+
+        // up 16.7% at 3x leverage, below the 21.9% average for shorts
+        let mut less_levered_short = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: -3 * BASE_PRECISION_I64,
+                quote_asset_amount: 360 * QUOTE_PRECISION_I64,
+                quote_entry_amount: 360 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: 360 * QUOTE_PRECISION_I64,
+                last_cumulative_funding_rate: -1000 * FUNDING_RATE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        let less_levered_short_key = Pubkey::new_unique();
+        create_anchor_account_info!(
+            less_levered_short,
+            &less_levered_short_key,
+            User,
+            less_levered_short_account_info
+        );
+
+        let mut auto_deleverage_user_map = UserMap::empty();
+        auto_deleverage_user_map
+            .insert(
+                less_levered_short_key,
+                AccountLoader::try_from(&less_levered_short_account_info).unwrap(),
+            )
+            .unwrap();
+        auto_deleverage_user_map
+            .insert(
+                more_levered_short_key,
+                AccountLoader::try_from(&more_levered_short_account_info).unwrap(),
+            )
+            .unwrap();
+
+        // the keeper can't pass a position that's less profitable than its side of the market
+        assert_eq!(
+            auto_deleverage_perp_positions(
+                0,
+                40 * QUOTE_PRECISION,
+                &user_key,
+                &liquidator_key,
+                &auto_deleverage_user_map,
+                &market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                now,
+                slot,
+            ),
+            Err(ErrorCode::InvalidAutoDeleverageUser)
+        );
+
+        let mut auto_deleverage_user_map = UserMap::empty();
+        auto_deleverage_user_map
+            .insert(
+                more_levered_short_key,
+                AccountLoader::try_from(&more_levered_short_account_info).unwrap(),
+            )
+            .unwrap();
+
+        resolve_perp_bankruptcy(
+            0,
+            &mut user,
+            &user_key,
+            &mut liquidator,
+            &liquidator_key,
+            &auto_deleverage_user_map,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            now,
+            slot,
+            0,
+        )
+        .unwrap();
+
+        assert!(!user.is_bankrupt());
+        assert_eq!(user.perp_positions[0].quote_asset_amount, 0);
+
+        // the short only closes half its position, buying it back from the amm at its $140 entry
+        let more_levered_short = auto_deleverage_user_map
+            .get_ref(&more_levered_short_key)
+            .unwrap();
+        assert_eq!(
+            more_levered_short.perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64
+        );
+        assert_eq!(
+            more_levered_short.perp_positions[0].quote_asset_amount,
+            140 * QUOTE_PRECISION_I64
+        );
+
+        // nothing left to socialize
+        let market = market_map.get_ref(&0).unwrap();
+        assert_eq!(market.amm.total_social_loss, 0);
+        assert_eq!(
+            market.amm.cumulative_funding_rate_long,
+            1000 * FUNDING_RATE_PRECISION_I128
+        );
+        assert_eq!(
+            market.amm.cumulative_funding_rate_short,
+            -1000 * FUNDING_RATE_PRECISION_I128
+        );
+        assert_eq!(market.amm.base_asset_amount_short, -4 * BASE_PRECISION_I128);
+        assert_eq!(
+            market.amm.base_asset_amount_with_amm,
+            -4 * BASE_PRECISION_I128
+        );
+        assert_eq!(market.amm.base_asset_reserve, 99 * AMM_RESERVE_PRECISION);
+        assert_eq!(market.amm.quote_asset_amount, 500 * QUOTE_PRECISION_I128);
+        // the amm's curve price is above the oracle, it eats the difference on the base it sold
+        assert_eq!(market.amm.total_fee_minus_distributions, -1010102);
+    }
+}
+
+pub mod resolve_spot_bankruptcy {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::controller::liquidation::resolve_spot_bankruptcy;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_U64,
+        FUNDING_RATE_PRECISION_I128, LIQUIDATION_FEE_PRECISION, PEG_PRECISION, QUOTE_PRECISION,
+        QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::spot_balance::get_token_amount;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{
+        Order, OrderStatus, OrderType, PerpPosition, SpotPosition, User, UserStatus,
+    };
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_pyth_price, get_spot_positions};
+
+    #[test]
+    pub fn successful_resolve_spot_bankruptcy() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                quote_asset_amount: -150 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: BASE_PRECISION_I128,
+                base_asset_amount_long: 5 * BASE_PRECISION_I128,
+                base_asset_amount_short: -5 * BASE_PRECISION_I128,
+                oracle: oracle_price_key,
+                cumulative_funding_rate_long: 1000 * FUNDING_RATE_PRECISION_I128,
+                cumulative_funding_rate_short: -1000 * FUNDING_RATE_PRECISION_I128,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 1000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 100 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                ..Order::default()
+            }),
+            perp_positions: [PerpPosition::default(); 8],
+            spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
                 balance_type: SpotBalanceType::Borrow,
@@ -7036,3 +7720,465 @@ pub mod resolve_spot_bankruptcy {
         assert_eq!(deposit_token_amount, 900 * QUOTE_PRECISION);
     }
 }
+
+pub mod liquidate_isolated_perp_position {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::controller::liquidation::liquidate_perp;
+    use crate::controller::position::PositionDirection;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_I64, BASE_PRECISION_U64,
+        LIQUIDATION_FEE_PRECISION, LIQUIDATION_PCT_PRECISION, PEG_PRECISION, QUOTE_PRECISION_I128,
+        QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{
+        Order, OrderStatus, OrderType, PerpPosition, PositionFlag, SpotPosition, User, UserStats,
+    };
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, PRICE_PRECISION_I64};
+
+    #[test]
+    pub fn liquidation_leaves_cross_account_untouched() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                quote_asset_amount: -150 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: BASE_PRECISION_I128,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            number_of_users_with_base: 1,
+            status: MarketStatus::Initialized,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            if_liquidation_fee: LIQUIDATION_FEE_PRECISION / 100,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 1005 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // plenty of cross collateral, but the isolated position only has $5 behind it
+        let mut user = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -150 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -150 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -150 * QUOTE_PRECISION_I64,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                isolated_position_scaled_balance: 5 * SPOT_BALANCE_PRECISION_U64,
+                position_flag: PositionFlag::IsolatedPosition as u8,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 1000 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut liquidator = User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 50 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        let mut user_stats = UserStats::default();
+        let mut liquidator_stats = UserStats::default();
+        let state = State {
+            liquidation_margin_buffer_ratio: 10,
+            initial_pct_to_liquidate: LIQUIDATION_PCT_PRECISION as u16,
+            liquidation_duration: 150,
+            ..Default::default()
+        };
+        liquidate_perp(
+            0,
+            BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        assert_eq!(user.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(user.perp_positions[0].open_orders, 0);
+        assert_eq!(
+            liquidator.perp_positions[0].base_asset_amount,
+            BASE_PRECISION_I64
+        );
+
+        // loss exceeds the isolated collateral so only the position is marked bankrupt
+        assert!(user.perp_positions[0].is_bankrupt());
+        assert!(!user.is_being_liquidated());
+        assert!(!user.is_bankrupt());
+        assert_eq!(user.status, 0);
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            1000 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            user.perp_positions[0].isolated_position_scaled_balance,
+            5 * SPOT_BALANCE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    pub fn liquidation_is_paced_from_its_own_start_slot() {
+        let now = 0_i64;
+        let slot = 100_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        oracle_price.valid_slot = slot;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                quote_asset_amount: -15000 * QUOTE_PRECISION_I128,
+                base_asset_amount_with_amm: 100 * BASE_PRECISION_I128,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            number_of_users_with_base: 1,
+            status: MarketStatus::Initialized,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+            if_liquidation_fee: LIQUIDATION_FEE_PRECISION / 100,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 11300 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // the cross account was last active long ago, the isolated position is $210 short
+        let mut user = User {
+            last_active_slot: 0,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: 100 * BASE_PRECISION_I64,
+                quote_asset_amount: -15000 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -15000 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -15000 * QUOTE_PRECISION_I64,
+                isolated_position_scaled_balance: 5300 * SPOT_BALANCE_PRECISION_U64,
+                position_flag: PositionFlag::IsolatedPosition as u8,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 1000 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut liquidator = User {
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 5000 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        let mut user_stats = UserStats::default();
+        let mut liquidator_stats = UserStats::default();
+        let state = State {
+            liquidation_margin_buffer_ratio: 10,
+            initial_pct_to_liquidate: (LIQUIDATION_PCT_PRECISION / 100) as u16,
+            liquidation_duration: 150,
+            ..Default::default()
+        };
+
+        liquidate_perp(
+            0,
+            100 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        assert!(user.perp_positions[0].is_being_liquidated());
+        assert_eq!(user.perp_positions[0].liquidation_start_slot, slot);
+        assert_eq!(user.last_active_slot, slot);
+
+        // only the initial pct of the shortage can be liquidated right away
+        let base_liquidated_at_start = liquidator.perp_positions[0].base_asset_amount;
+        assert!(base_liquidated_at_start > 0);
+        assert!(base_liquidated_at_start < BASE_PRECISION_I64);
+        assert!(user.perp_positions[0].liquidation_margin_freed > 0);
+
+        liquidate_perp(
+            0,
+            100 * BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot + 150,
+            now,
+            &state,
+        )
+        .unwrap();
+
+        // the rest is freed once the liquidation duration has passed
+        assert!(!user.perp_positions[0].is_being_liquidated());
+        assert_eq!(user.perp_positions[0].liquidation_start_slot, 0);
+        assert!(liquidator.perp_positions[0].base_asset_amount > base_liquidated_at_start);
+    }
+
+    #[test]
+    pub fn isolated_collateral_covers_position() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 60 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // no cross collateral at all, isolated collateral keeps the position healthy
+        let mut user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -150 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -150 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -150 * QUOTE_PRECISION_I64,
+                isolated_position_scaled_balance: 60 * SPOT_BALANCE_PRECISION_U64,
+                position_flag: PositionFlag::IsolatedPosition as u8,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut liquidator = User::default();
+
+        let user_key = Pubkey::default();
+        let liquidator_key = Pubkey::default();
+
+        let mut user_stats = UserStats::default();
+        let mut liquidator_stats = UserStats::default();
+        let state = State {
+            liquidation_margin_buffer_ratio: 10,
+            initial_pct_to_liquidate: LIQUIDATION_PCT_PRECISION as u16,
+            liquidation_duration: 150,
+            ..Default::default()
+        };
+        let result = liquidate_perp(
+            0,
+            BASE_PRECISION_U64,
+            None,
+            &mut user,
+            &user_key,
+            &mut user_stats,
+            &mut liquidator,
+            &liquidator_key,
+            &mut liquidator_stats,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &FixedTermPoolMap::empty(),
+            slot,
+            now,
+            &state,
+        );
+
+        assert_eq!(result, Err(ErrorCode::SufficientCollateral));
+    }
+}
@@ -1,6 +1,8 @@
 pub mod amm;
+pub mod fixed_term;
 pub mod funding;
 pub mod insurance;
+pub mod isolated_position;
 pub mod liquidation;
 pub mod lp;
 pub mod orders;
@@ -8,6 +10,7 @@ pub mod pda;
 pub mod pnl;
 pub mod position;
 pub mod repeg;
+pub mod shared_margin;
 pub mod spot_balance;
 pub mod spot_position;
 pub mod token;
@@ -26,6 +26,7 @@ use crate::error::DriftResult;
 use crate::error::ErrorCode;
 use crate::get_struct_values;
 use crate::get_then_update_id;
+use crate::load;
 use crate::load_mut;
 use crate::math::amm_jit::calculate_amm_jit_liquidity;
 use crate::math::auction::{calculate_auction_params_for_trigger_order, calculate_auction_prices};
@@ -45,7 +46,8 @@ use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::{get_signed_token_amount, get_token_amount};
 use crate::math::{amm, fees, margin::*, orders::*};
 use crate::state::order_params::{
-    ModifyOrderParams, ModifyOrderPolicy, OrderParams, PlaceOrderOptions, PostOnlyParam,
+    ModifyOrderByIdParams, ModifyOrderId, ModifyOrderParams, ModifyOrderPolicy, OrderParams,
+    OrderParamsBitFlag, PlaceOrderOptions, PostOnlyParam,
 };
 
 use crate::math::amm::calculate_amm_available_liquidity;
@@ -72,14 +74,16 @@ use crate::state::state::FeeStructure;
 use crate::state::state::*;
 use crate::state::traits::Size;
 use crate::state::user::{
-    AssetType, Order, OrderStatus, OrderTriggerCondition, OrderType, UserStats,
+    AssetType, Order, OrderBitFlag, OrderStatus, OrderTriggerCondition, OrderType,
+    SelfTradePreventionMode, UserStats,
 };
 use crate::state::user::{MarketType, User};
 use crate::state::user_map::{UserMap, UserStatsMap};
 use crate::validate;
 use crate::validation;
 use crate::validation::order::{
-    validate_order, validate_order_for_force_reduce_only, validate_spot_order,
+    validate_order, validate_order_for_force_reduce_only, validate_order_link_group,
+    validate_spot_order,
 };
 
 #[cfg(test)]
@@ -90,6 +94,7 @@ mod amm_jit_tests;
 #[cfg(test)]
 mod amm_lp_jit_tests;
 
+/// Returns whether the order increases the user's risk. Orders skipped without an error return false
 pub fn place_perp_order(
     state: &State,
     user: &mut User,
@@ -100,7 +105,7 @@ pub fn place_perp_order(
     clock: &Clock,
     mut params: OrderParams,
     mut options: PlaceOrderOptions,
-) -> DriftResult {
+) -> DriftResult<bool> {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
@@ -171,6 +176,13 @@ pub fn place_perp_order(
     let position_index = get_position_index(&user.perp_positions, market_index)
         .or_else(|_| add_new_position(&mut user.perp_positions, market_index))?;
 
+    validate!(
+        !user.perp_positions[position_index].is_being_liquidated(),
+        ErrorCode::IsolatedPerpPositionBeingLiquidated,
+        "isolated position in market {} is being liquidated",
+        market_index
+    )?;
+
     // Increment open orders for existing position
     let (existing_position_direction, order_base_asset_amount) = {
         validate!(
@@ -219,16 +231,22 @@ pub fn place_perp_order(
     let max_ts = match params.max_ts {
         Some(max_ts) => max_ts,
         None => match params.order_type {
-            OrderType::Market | OrderType::Oracle => {
-                now.safe_add(30_i64.max((auction_duration / 2) as i64))?
-            }
+            OrderType::Market | OrderType::Oracle => now
+                .safe_add(30_i64.max((auction_duration / 2) as i64))?
+                .safe_add(params.get_twap_duration()?)?,
             _ => 0_i64,
         },
     };
 
     if max_ts != 0 && max_ts < now {
         msg!("max_ts ({}) < now ({}), skipping order", max_ts, now);
-        return Ok(());
+        return Ok(false);
+    }
+
+    let max_slot = params.max_slot.unwrap_or(0);
+    if max_slot != 0 && max_slot < slot {
+        msg!("max_slot ({}) < slot ({}), skipping order", max_slot, slot);
+        return Ok(false);
     }
 
     validate!(
@@ -237,7 +255,7 @@ pub fn place_perp_order(
         "must be perp order"
     )?;
 
-    let new_order = Order {
+    let mut new_order = Order {
         status: OrderStatus::Open,
         order_type: params.order_type,
         market_type: params.market_type,
@@ -270,9 +288,29 @@ pub fn place_perp_order(
         auction_end_price,
         auction_duration,
         max_ts,
-        padding: [0; 3],
+        bit_flags: params.get_order_bit_flags(),
+        link_group: params.link_group,
+        self_trade_prevention_mode: params.self_trade_prevention_mode,
+        trailing_offset: params.trailing_offset.unwrap_or(0),
+        twap_start_ts: if params.twap_slices.is_some() { now } else { 0 },
+        twap_interval: params.twap_interval.unwrap_or(0),
+        twap_slices: params.twap_slices.unwrap_or(0),
+        twap_slices_released: params.twap_slices.map_or(0, |_| 1),
+        min_fill_base_asset_amount: params.min_fill_base_asset_amount.unwrap_or(0),
+        max_slot,
+        good_after_ts: params.good_after_ts.unwrap_or(0),
+        display_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
+        visible_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
     };
 
+    if new_order.is_trailing_stop() {
+        update_trailing_stop_trigger_price(
+            &mut new_order,
+            oracle_price_data.price.unsigned_abs(),
+            market.amm.order_tick_size,
+        )?;
+    }
+
     let valid_oracle_price = Some(oracle_map.get_price_data(&market.amm.oracle)?.price);
     match validate_order(&new_order, market, valid_oracle_price, slot) {
         Ok(()) => {}
@@ -280,11 +318,20 @@ pub fn place_perp_order(
             if params.post_only == PostOnlyParam::TryPostOnly =>
         {
             // just want place to succeeds without error if TryPostOnly
-            return Ok(());
+            return Ok(false);
         }
         Err(err) => return Err(err),
     };
 
+    validate_order_link_group(&new_order, &user.orders)?;
+
+    // failing an unfilled fill or kill order only undoes the place when both happen in one instruction
+    validate!(
+        !new_order.is_fill_or_kill() || options.immediate_fill,
+        ErrorCode::InvalidOrderMinFill,
+        "Fill or kill order must be in place_and_make or place_and_take"
+    )?;
+
     let risk_increasing = is_new_order_risk_increasing(
         &new_order,
         user.perp_positions[position_index].base_asset_amount,
@@ -347,6 +394,43 @@ pub fn place_perp_order(
         )?;
     }
 
+    if risk_increasing {
+        let position_plus_order = match params.direction {
+            PositionDirection::Long => user.perp_positions[position_index]
+                .base_asset_amount
+                .safe_add(order_base_asset_amount.cast()?)?,
+            PositionDirection::Short => user.perp_positions[position_index]
+                .base_asset_amount
+                .safe_sub(order_base_asset_amount.cast()?)?,
+        };
+
+        let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+        market.validate_max_position_size(position_plus_order, oracle_price)?;
+    }
+
+    // older reduce-only orders make room for the new one, which itself must fit within the position
+    if new_order.reduce_only {
+        update_reduce_only_orders(
+            user,
+            &user_key,
+            MarketType::Perp,
+            market_index,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            None,
+            now,
+            slot,
+        )?;
+
+        validate!(
+            user.orders[new_order_index].status == OrderStatus::Open,
+            ErrorCode::InvalidOrderNotRiskReducing,
+            "reduce only order {} exceeds the position",
+            new_order.order_id
+        )?;
+    }
+
     let (taker, taker_order, maker, maker_order) =
         get_taker_and_maker_for_order_record(&user_key, &new_order);
 
@@ -382,7 +466,7 @@ pub fn place_perp_order(
 
     user.update_last_active_slot(slot);
 
-    Ok(())
+    Ok(risk_increasing)
 }
 
 fn get_auction_params(
@@ -596,7 +680,7 @@ pub fn cancel_order(
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     now: i64,
-    _slot: u64,
+    slot: u64,
     explanation: OrderActionExplanation,
     filler_key: Option<&Pubkey>,
     filler_reward: u64,
@@ -614,6 +698,8 @@ pub fn cancel_order(
 
     validate!(order_status == OrderStatus::Open, ErrorCode::OrderNotOpen)?;
 
+    let canceled_order = user.orders[order_index];
+
     let oracle = if is_perp_order {
         perp_market_map.get_ref(&order_market_index)?.amm.oracle
     } else {
@@ -645,52 +731,556 @@ pub fn cancel_order(
             maker_order,
             oracle_map.get_price_data(&oracle)?.price,
         )?;
-        emit_stack::<_, { OrderActionRecord::SIZE }>(order_action_record)?;
+        emit_stack::<_, { OrderActionRecord::SIZE }>(order_action_record)?;
+    }
+
+    user.decrement_open_orders(user.orders[order_index].has_auction());
+    if is_perp_order {
+        // Decrement open orders for existing position
+        let position_index = get_position_index(&user.perp_positions, order_market_index)?;
+
+        // only decrease open/bids ask if it's not a trigger order or if it's been triggered
+        if !user.orders[order_index].must_be_triggered() || user.orders[order_index].triggered() {
+            let base_asset_amount_unfilled =
+                user.orders[order_index].get_base_asset_amount_unfilled(None)?;
+            position::decrease_open_bids_and_asks(
+                &mut user.perp_positions[position_index],
+                &order_direction,
+                base_asset_amount_unfilled.cast()?,
+            )?;
+        }
+
+        user.perp_positions[position_index].open_orders -= 1;
+        user.orders[order_index] = Order::default();
+    } else {
+        let spot_position_index = user.get_spot_position_index(order_market_index)?;
+
+        // only decrease open/bids ask if it's not a trigger order or if it's been triggered
+        if !user.orders[order_index].must_be_triggered() || user.orders[order_index].triggered() {
+            let base_asset_amount_unfilled =
+                user.orders[order_index].get_base_asset_amount_unfilled(None)?;
+            decrease_spot_open_bids_and_asks(
+                &mut user.spot_positions[spot_position_index],
+                &order_direction,
+                base_asset_amount_unfilled,
+            )?;
+        }
+        user.spot_positions[spot_position_index].open_orders -= 1;
+        user.orders[order_index] = Order::default();
+    }
+
+    if canceled_order.is_linked() && !canceled_order.is_bracket_child() {
+        update_bracket_children_after_parent_cancel(
+            user,
+            user_key,
+            &canceled_order,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Once the last parent order in a link group is canceled, its bracket children are canceled too.
+/// If the parent was partially filled, the children are activated instead to protect the position
+fn update_bracket_children_after_parent_cancel(
+    user: &mut User,
+    user_key: &Pubkey,
+    parent_order: &Order,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    let has_open_parent = user.orders.iter().any(|order| {
+        order.status == OrderStatus::Open
+            && order.link_group == parent_order.link_group
+            && !order.is_bracket_child()
+    });
+
+    if has_open_parent {
+        return Ok(());
+    }
+
+    if parent_order.base_asset_amount_filled > 0 {
+        activate_bracket_children(user, parent_order.link_group);
+        return Ok(());
+    }
+
+    for order_index in 0..user.orders.len() {
+        let order = &user.orders[order_index];
+        if order.status != OrderStatus::Open
+            || order.link_group != parent_order.link_group
+            || !order.is_bracket_child()
+        {
+            continue;
+        }
+
+        cancel_order(
+            order_index,
+            user,
+            user_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::BracketParentCanceled,
+            None,
+            0,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn activate_bracket_children(user: &mut User, link_group: u8) {
+    for order in user.orders.iter_mut() {
+        if order.status == OrderStatus::Open
+            && order.link_group == link_group
+            && order.is_bracket_child()
+        {
+            msg!("Activating bracket child order {}", order.order_id);
+            order.remove_bit_flag(OrderBitFlag::BracketChild);
+        }
+    }
+}
+
+/// Cancels the orders that are one-cancels-the-other with an order that just filled or triggered.
+/// If the order was a parent that completely filled, its bracket children are activated
+fn update_linked_orders(
+    user: &mut User,
+    user_key: &Pubkey,
+    order: &Order,
+    order_filled: bool,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+    explanation: OrderActionExplanation,
+) -> DriftResult {
+    if !order.is_linked() {
+        return Ok(());
+    }
+
+    let linked_order_indexes: Vec<usize> = user
+        .orders
+        .iter()
+        .enumerate()
+        .filter(|(_, linked_order)| order.is_one_cancels_the_other_with(linked_order))
+        .map(|(order_index, _)| order_index)
+        .collect();
+
+    // activate the children first so canceling the other parents doesn't cancel them
+    if order_filled && !order.is_bracket_child() {
+        activate_bracket_children(user, order.link_group);
+    }
+
+    for order_index in linked_order_indexes {
+        if user.orders[order_index].status != OrderStatus::Open {
+            continue;
+        }
+
+        cancel_order(
+            order_index,
+            user,
+            user_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            explanation,
+            None,
+            0,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn record_linked_order_fill(
+    linked_order_fills: &mut Vec<(Pubkey, Order, bool)>,
+    user_key: &Pubkey,
+    order: &Order,
+    order_filled: bool,
+) {
+    if !order.is_linked() {
+        return;
+    }
+
+    match linked_order_fills
+        .iter_mut()
+        .find(|(key, filled_order, _)| key == user_key && filled_order.order_id == order.order_id)
+    {
+        Some((_, _, filled)) => *filled = order_filled,
+        None => linked_order_fills.push((*user_key, *order, order_filled)),
+    }
+}
+
+fn has_linked_order_filled(
+    linked_order_fills: &[(Pubkey, Order, bool)],
+    user_key: &Pubkey,
+    order: &Order,
+) -> bool {
+    linked_order_fills.iter().any(|(key, filled_order, _)| {
+        key == user_key && filled_order.is_one_cancels_the_other_with(order)
+    })
+}
+
+fn validate_fill_or_kill_order_filled(order: &Order) -> DriftResult {
+    validate!(
+        !order.is_fill_or_kill() || order.status != OrderStatus::Open,
+        ErrorCode::FillOrKillOrderNotFilled,
+        "fill or kill order {} only filled {} of {}",
+        order.order_id,
+        order.base_asset_amount_filled,
+        order.base_asset_amount
+    )
+}
+
+/// Resolves a self trade between a taker order and a crossing maker order of the same authority.
+/// Orders that stay open are decremented and canceled orders are removed right away
+#[allow(clippy::too_many_arguments)]
+fn apply_self_trade_prevention(
+    self_trade_prevention_mode: SelfTradePreventionMode,
+    taker: &mut User,
+    taker_key: &Pubkey,
+    taker_order_index: usize,
+    maker: &mut User,
+    maker_key: &Pubkey,
+    maker_order_index: usize,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    filler_key: &Pubkey,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    let (cancel_taker, cancel_maker, decrement) = calculate_self_trade_prevention_outcome(
+        self_trade_prevention_mode,
+        taker.orders[taker_order_index].get_base_asset_amount_unfilled(None)?,
+        maker.orders[maker_order_index].get_base_asset_amount_unfilled(None)?,
+    );
+
+    if decrement > 0 {
+        if !cancel_taker {
+            decrement_order_base_asset_amount(taker, taker_order_index, decrement)?;
+        }
+
+        if !cancel_maker {
+            decrement_order_base_asset_amount(maker, maker_order_index, decrement)?;
+        }
+    }
+
+    if cancel_maker {
+        cancel_order(
+            maker_order_index,
+            maker,
+            maker_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::SelfTradePrevented,
+            Some(filler_key),
+            0,
+            false,
+        )?;
+    }
+
+    if cancel_taker {
+        cancel_order(
+            taker_order_index,
+            taker,
+            taker_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::SelfTradePrevented,
+            Some(filler_key),
+            0,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn decrement_order_base_asset_amount(
+    user: &mut User,
+    order_index: usize,
+    base_asset_amount: u64,
+) -> DriftResult {
+    let (market_index, market_type, direction) = get_struct_values!(
+        user.orders[order_index],
+        market_index,
+        market_type,
+        direction
+    );
+
+    user.orders[order_index].base_asset_amount = user.orders[order_index]
+        .base_asset_amount
+        .safe_sub(base_asset_amount)?;
+
+    // untriggered orders aren't counted in open bids/asks
+    if user.orders[order_index].must_be_triggered() && !user.orders[order_index].triggered() {
+        return Ok(());
+    }
+
+    if market_type == MarketType::Perp {
+        let position_index = get_position_index(&user.perp_positions, market_index)?;
+        decrease_open_bids_and_asks(
+            &mut user.perp_positions[position_index],
+            &direction,
+            base_asset_amount,
+        )?;
+    } else {
+        let spot_position_index = user.get_spot_position_index(market_index)?;
+        decrease_spot_open_bids_and_asks(
+            &mut user.spot_positions[spot_position_index],
+            &direction,
+            base_asset_amount,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Shrinks a user's resting reduce-only orders in a market so their unfilled sizes sum to at most the
+/// position. Returns the ids of the orders left below the step size, which need to be canceled
+fn shrink_reduce_only_orders(
+    user: &mut User,
+    market_type: MarketType,
+    market_index: u16,
+    existing_position: i64,
+    step_size: u64,
+) -> DriftResult<Vec<u32>> {
+    let allowances = calculate_reduce_only_orders_base_asset_amount_allowed(
+        user,
+        market_type,
+        market_index,
+        existing_position,
+    )?;
+
+    let mut order_ids_to_cancel = vec![];
+    for (order_index, allowance) in allowances {
+        let base_asset_amount_unfilled =
+            user.orders[order_index].get_base_asset_amount_unfilled(None)?;
+        let allowance = standardize_base_asset_amount(allowance, step_size)?;
+
+        if allowance < step_size {
+            order_ids_to_cancel.push(user.orders[order_index].order_id);
+        } else if allowance < base_asset_amount_unfilled {
+            msg!(
+                "Shrinking reduce only order {} from {} to {}",
+                user.orders[order_index].order_id,
+                base_asset_amount_unfilled,
+                allowance
+            );
+            decrement_order_base_asset_amount(
+                user,
+                order_index,
+                base_asset_amount_unfilled.safe_sub(allowance)?,
+            )?;
+        }
+    }
+
+    Ok(order_ids_to_cancel)
+}
+
+/// Keeps a user's reduce-only orders in a market within the position after it changed. Called after
+/// fills as well as every other position change (liquidation, settlement, withdraws, swaps)
+#[allow(clippy::too_many_arguments)]
+pub fn update_reduce_only_orders(
+    user: &mut User,
+    user_key: &Pubkey,
+    market_type: MarketType,
+    market_index: u16,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    filler_key: Option<&Pubkey>,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    let has_reduce_only_orders = user.orders.iter().any(|order| {
+        order.status == OrderStatus::Open
+            && order.reduce_only
+            && order.market_type == market_type
+            && order.market_index == market_index
+    });
+
+    if !has_reduce_only_orders {
+        return Ok(());
+    }
+
+    let (existing_position, step_size) = match market_type {
+        MarketType::Perp => (
+            user.get_perp_position(market_index)?.base_asset_amount,
+            perp_market_map.get_ref(&market_index)?.amm.order_step_size,
+        ),
+        MarketType::Spot => {
+            let spot_market = spot_market_map.get_ref(&market_index)?;
+            (
+                user.get_spot_position(market_index)?
+                    .get_signed_token_amount(&spot_market)?
+                    .cast()?,
+                spot_market.order_step_size,
+            )
+        }
+    };
+
+    let order_ids_to_cancel = shrink_reduce_only_orders(
+        user,
+        market_type,
+        market_index,
+        existing_position,
+        step_size,
+    )?;
+
+    for order_id in order_ids_to_cancel {
+        // canceling a bracket parent can cancel its children first
+        let order_index = match user.get_order_index(order_id) {
+            Ok(order_index) => order_index,
+            Err(_) => continue,
+        };
+
+        cancel_order(
+            order_index,
+            user,
+            user_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::ReduceOnlyOrderIncreasedPosition,
+            filler_key,
+            0,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_reduce_only_orders_after_fill(
+    user: &mut User,
+    user_key: &Pubkey,
+    base_asset_amount_filled: u64,
+    makers_and_referrer: &UserMap,
+    maker_orders_info: &[(Pubkey, usize, u64)],
+    market_type: MarketType,
+    market_index: u16,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    filler_key: &Pubkey,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    if base_asset_amount_filled != 0 {
+        update_reduce_only_orders(
+            user,
+            user_key,
+            market_type,
+            market_index,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            Some(filler_key),
+            now,
+            slot,
+        )?;
     }
 
-    user.decrement_open_orders(user.orders[order_index].has_auction());
-    if is_perp_order {
-        // Decrement open orders for existing position
-        let position_index = get_position_index(&user.perp_positions, order_market_index)?;
-
-        // only decrease open/bids ask if it's not a trigger order or if it's been triggered
-        if !user.orders[order_index].must_be_triggered() || user.orders[order_index].triggered() {
-            let base_asset_amount_unfilled =
-                user.orders[order_index].get_base_asset_amount_unfilled(None)?;
-            position::decrease_open_bids_and_asks(
-                &mut user.perp_positions[position_index],
-                &order_direction,
-                base_asset_amount_unfilled.cast()?,
-            )?;
-        }
+    // makers can also have orders left from the pre-fill check
+    let mut maker_keys: Vec<Pubkey> = maker_orders_info
+        .iter()
+        .map(|(maker_key, _, _)| *maker_key)
+        .collect();
+    maker_keys.sort();
+    maker_keys.dedup();
+
+    for maker_key in maker_keys {
+        let mut maker = makers_and_referrer.get_ref_mut(&maker_key)?;
+        update_reduce_only_orders(
+            &mut maker,
+            &maker_key,
+            market_type,
+            market_index,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            Some(filler_key),
+            now,
+            slot,
+        )?;
+    }
 
-        user.perp_positions[position_index].open_orders -= 1;
-        user.orders[order_index] = Order::default();
-    } else {
-        let spot_position_index = user.get_spot_position_index(order_market_index)?;
+    Ok(())
+}
 
-        // only decrease open/bids ask if it's not a trigger order or if it's been triggered
-        if !user.orders[order_index].must_be_triggered() || user.orders[order_index].triggered() {
-            let base_asset_amount_unfilled =
-                user.orders[order_index].get_base_asset_amount_unfilled(None)?;
-            decrease_spot_open_bids_and_asks(
-                &mut user.spot_positions[spot_position_index],
-                &order_direction,
-                base_asset_amount_unfilled,
+fn update_linked_orders_after_fill(
+    user: &mut User,
+    user_key: &Pubkey,
+    makers_and_referrer: &UserMap,
+    linked_order_fills: Vec<(Pubkey, Order, bool)>,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+) -> DriftResult {
+    for (key, order, order_filled) in linked_order_fills {
+        if key == *user_key {
+            update_linked_orders(
+                user,
+                user_key,
+                &order,
+                order_filled,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                OrderActionExplanation::LinkedOrderFilled,
+            )?;
+        } else {
+            let mut maker = makers_and_referrer.get_ref_mut(&key)?;
+            update_linked_orders(
+                &mut maker,
+                &key,
+                &order,
+                order_filled,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                OrderActionExplanation::LinkedOrderFilled,
             )?;
         }
-        user.spot_positions[spot_position_index].open_orders -= 1;
-        user.orders[order_index] = Order::default();
     }
 
     Ok(())
 }
 
-pub enum ModifyOrderId {
-    UserOrderId(u8),
-    OrderId(u32),
-}
-
+/// Cancels the order and places it again with the modified params. Returns whether the new order
+/// increases the user's risk, or false if the order wasn't found and the policy is TryModify
 pub fn modify_order(
     order_id: ModifyOrderId,
     modify_order_params: ModifyOrderParams,
@@ -700,7 +1290,8 @@ pub fn modify_order(
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     clock: &Clock,
-) -> DriftResult {
+    options: PlaceOrderOptions,
+) -> DriftResult<bool> {
     let user_key = user_loader.key();
     let mut user = load_mut!(user_loader)?;
 
@@ -713,7 +1304,7 @@ pub fn modify_order(
                     if modify_order_params.policy == Some(ModifyOrderPolicy::MustModify) {
                         return Err(e);
                     } else {
-                        return Ok(());
+                        return Ok(false);
                     }
                 }
             }
@@ -725,7 +1316,7 @@ pub fn modify_order(
                 if modify_order_params.policy == Some(ModifyOrderPolicy::MustModify) {
                     return Err(e);
                 } else {
-                    return Ok(());
+                    return Ok(false);
                 }
             }
         },
@@ -733,6 +1324,10 @@ pub fn modify_order(
 
     let existing_order = user.orders[order_index];
 
+    // the order is re-placed in the same link group below, so canceling it shouldn't cancel or
+    // activate its bracket children
+    user.orders[order_index].link_group = 0;
+
     cancel_order(
         order_index,
         &mut user,
@@ -763,8 +1358,8 @@ pub fn modify_order(
             oracle_map,
             clock,
             order_params,
-            PlaceOrderOptions::default(),
-        )?;
+            options,
+        )
     } else {
         place_spot_order(
             state,
@@ -775,11 +1370,69 @@ pub fn modify_order(
             oracle_map,
             clock,
             order_params,
-            PlaceOrderOptions::default(),
+            options,
+        )
+    }
+}
+
+/// Applies a batch of modifications, checking margin once after the last one instead of after
+/// each re-placed order. With all_or_nothing, a missing order fails the whole batch
+pub fn modify_orders(
+    modifications: Vec<ModifyOrderByIdParams>,
+    all_or_nothing: bool,
+    user_loader: &AccountLoader<User>,
+    state: &State,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+) -> DriftResult {
+    validate!(
+        modifications.len() <= 32,
+        ErrorCode::MaxNumberOfOrderModifications,
+        "max 32 order modifications"
+    )?;
+
+    let mut risk_increasing = false;
+    for (i, modification) in modifications.into_iter().enumerate() {
+        let mut modify_order_params = modification.modify_order_params;
+        if all_or_nothing {
+            modify_order_params.policy = Some(ModifyOrderPolicy::MustModify);
+        }
+
+        // only try to expire on first modification
+        let options = PlaceOrderOptions {
+            try_expire_orders: i == 0,
+            enforce_margin_check: false,
+            risk_increasing: false,
+            explanation: OrderActionExplanation::None,
+            immediate_fill: false,
+        };
+
+        let order_risk_increasing = modify_order(
+            modification.order_id,
+            modify_order_params,
+            user_loader,
+            state,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            clock,
+            options,
         )?;
+
+        risk_increasing = risk_increasing || order_risk_increasing;
     }
 
-    Ok(())
+    // the batch is held to the initial margin requirement if any modification increased risk
+    let user = load!(user_loader)?;
+    meets_place_order_margin_requirement(
+        &user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        risk_increasing,
+    )
 }
 
 fn merge_modify_order_params_with_existing_order(
@@ -809,6 +1462,12 @@ fn merge_modify_order_params_with_existing_order(
         });
     let immediate_or_cancel = false;
     let max_ts = modify_order_params.max_ts.or(Some(existing_order.max_ts));
+    let max_slot = modify_order_params
+        .max_slot
+        .or(Some(existing_order.max_slot));
+    let good_after_ts = modify_order_params
+        .good_after_ts
+        .or(Some(existing_order.good_after_ts));
     let trigger_price = modify_order_params
         .trigger_price
         .or(Some(existing_order.trigger_price));
@@ -826,6 +1485,50 @@ fn merge_modify_order_params_with_existing_order(
     let oracle_price_offset = modify_order_params
         .oracle_price_offset
         .or(Some(existing_order.oracle_price_offset));
+    let trailing_offset = modify_order_params
+        .trailing_offset
+        .or(Some(existing_order.trailing_offset));
+    let mut bit_flags = 0_u8;
+    if existing_order.is_bit_flag_set(OrderBitFlag::TrailingPercentage) {
+        bit_flags |= OrderParamsBitFlag::TrailingPercentage as u8;
+    }
+    if existing_order.is_bracket_child() {
+        bit_flags |= OrderParamsBitFlag::BracketChild as u8;
+    }
+    let link_group = existing_order.link_group;
+    // the remaining size of a twap order is spread over its unreleased slices
+    let (twap_slices, twap_interval) = if existing_order.is_twap() {
+        (
+            Some(
+                existing_order
+                    .twap_slices
+                    .saturating_sub(existing_order.twap_slices_released)
+                    .max(1),
+            ),
+            Some(existing_order.twap_interval),
+        )
+    } else {
+        (None, None)
+    };
+    let min_fill_base_asset_amount = if existing_order.min_fill_base_asset_amount > 0 {
+        Some(
+            existing_order
+                .min_fill_base_asset_amount
+                .min(base_asset_amount),
+        )
+    } else {
+        None
+    };
+    // the modified order starts with a fresh tranche
+    let display_base_asset_amount = if existing_order.is_iceberg() {
+        Some(
+            existing_order
+                .display_base_asset_amount
+                .min(base_asset_amount),
+        )
+    } else {
+        None
+    };
     let (auction_duration, auction_start_price, auction_end_price) =
         if modify_order_params.auction_duration.is_some()
             && modify_order_params.auction_start_price.is_some()
@@ -858,6 +1561,16 @@ fn merge_modify_order_params_with_existing_order(
         auction_duration,
         auction_start_price,
         auction_end_price,
+        trailing_offset,
+        bit_flags,
+        link_group,
+        self_trade_prevention_mode: existing_order.self_trade_prevention_mode,
+        twap_slices,
+        twap_interval,
+        min_fill_base_asset_amount,
+        max_slot,
+        good_after_ts,
+        display_base_asset_amount,
     })
 }
 
@@ -938,6 +1651,19 @@ pub fn fill_perp_order(
         "Order must be triggered first"
     )?;
 
+    validate!(
+        !user.orders[order_index].is_bracket_child(),
+        ErrorCode::BracketChildOrderNotActive,
+        "Bracket child order can't be filled before its parent order"
+    )?;
+
+    validate!(
+        !user.orders[order_index].is_dormant(now),
+        ErrorCode::OrderDormant,
+        "Order can't be filled before good_after_ts ({})",
+        user.orders[order_index].good_after_ts
+    )?;
+
     if user.is_bankrupt() {
         msg!("user is bankrupt");
         return Ok(0);
@@ -1015,13 +1741,53 @@ pub fn fill_perp_order(
         (None, None)
     };
 
+    if user.orders[order_index].is_twap() {
+        let market = perp_market_map.get_ref(&market_index)?;
+        let oracle_price_data = *oracle_map.get_price_data(&market.amm.oracle)?;
+        let twap_slice_released = update_twap_order_slices(
+            user,
+            order_index,
+            &oracle_price_data,
+            now,
+            slot,
+            state.min_perp_auction_duration,
+            Some(&market),
+        )?;
+
+        if twap_slice_released {
+            let order_action_record = get_order_action_record(
+                now,
+                OrderAction::Trigger,
+                OrderActionExplanation::TwapSliceReleased,
+                market_index,
+                Some(filler_key),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(user_key),
+                Some(user.orders[order_index]),
+                None,
+                None,
+                oracle_price_data.price,
+            )?;
+            emit_stack::<_, { OrderActionRecord::SIZE }>(order_action_record)?;
+        }
+    }
+
     let maker_orders_info = get_maker_orders_info(
         perp_market_map,
         spot_market_map,
         oracle_map,
         makers_and_referrer,
         &user_key,
-        &user.orders[order_index],
+        user,
+        order_index,
         &mut filler.as_deref_mut(),
         &filler_key,
         state.perp_fee_structure.flat_filler_fee,
@@ -1031,6 +1797,11 @@ pub fn fill_perp_order(
         slot,
     )?;
 
+    // the taker order can be canceled by self trade prevention
+    if user.orders[order_index].status != OrderStatus::Open {
+        return Ok(0);
+    }
+
     let referrer_info = get_referrer_info(
         user_stats,
         &user_key,
@@ -1057,7 +1828,7 @@ pub fn fill_perp_order(
 
     validate_perp_fill_possible(state, user, order_index, slot, makers_and_referrer.0.len())?;
 
-    let should_expire_order = should_expire_order_before_fill(user, order_index, now)?;
+    let should_expire_order = should_expire_order_before_fill(user, order_index, now, slot)?;
 
     let position_index =
         get_position_index(&user.perp_positions, user.orders[order_index].market_index)?;
@@ -1107,6 +1878,15 @@ pub fn fill_perp_order(
         return Ok(0);
     }
 
+    let twap_base_asset_amount_available = user.orders[order_index]
+        .get_twap_base_asset_amount_available(
+            perp_market_map.get_ref(&market_index)?.amm.order_step_size,
+        )?;
+    if twap_base_asset_amount_available == 0 {
+        msg!("Twap order has no released slice left to fill");
+        return Ok(0);
+    }
+
     let (base_asset_amount, quote_asset_amount) = fulfill_perp_order(
         user,
         order_index,
@@ -1287,7 +2067,8 @@ fn get_maker_orders_info(
     oracle_map: &mut OracleMap,
     makers_and_referrer: &UserMap,
     taker_key: &Pubkey,
-    taker_order: &Order,
+    taker: &mut User,
+    taker_order_index: usize,
     filler: &mut Option<&mut User>,
     filler_key: &Pubkey,
     filler_reward: u64,
@@ -1296,6 +2077,7 @@ fn get_maker_orders_info(
     now: i64,
     slot: u64,
 ) -> DriftResult<Vec<(Pubkey, usize, u64)>> {
+    let taker_order = taker.orders[taker_order_index];
     let maker_direction = taker_order.direction.opposite();
 
     let mut maker_orders_info = Vec::with_capacity(16);
@@ -1305,6 +2087,11 @@ fn get_maker_orders_info(
             continue;
         }
 
+        // a self trade can cancel the taker order
+        if taker.orders[taker_order_index].status != OrderStatus::Open {
+            break;
+        }
+
         let mut maker = load_mut!(user_account_loader)?;
 
         if maker.is_being_liquidated() || maker.is_bankrupt() {
@@ -1319,7 +2106,10 @@ fn get_maker_orders_info(
             taker_order.market_index,
             Some(oracle_price),
             slot,
+            now,
             market.amm.order_tick_size,
+            &taker.authority,
+            taker_order.self_trade_prevention_mode,
         )?;
 
         if maker_order_price_and_indexes.is_empty() {
@@ -1332,19 +2122,22 @@ fn get_maker_orders_info(
 
         let initial_margin_ratio = market.margin_ratio_initial;
         let step_size = market.amm.order_step_size;
+        let tick_size = market.amm.order_tick_size;
 
         drop(market);
 
-        for (maker_order_index, maker_order_price) in maker_order_price_and_indexes.iter() {
+        for (maker_order_index, maker_order_price, self_trade_prevention_mode) in
+            maker_order_price_and_indexes.iter()
+        {
             let maker_order_index = *maker_order_index;
             let maker_order_price = *maker_order_price;
 
             let maker_order = &maker.orders[maker_order_index];
-            if !is_maker_for_taker(maker_order, taker_order, slot)? {
+            if !is_maker_for_taker(maker_order, &taker_order, slot)? {
                 continue;
             }
 
-            if !are_orders_same_market_but_different_sides(maker_order, taker_order) {
+            if !are_orders_same_market_but_different_sides(maker_order, &taker_order) {
                 continue;
             }
 
@@ -1364,7 +2157,7 @@ fn get_maker_orders_info(
                 )?
             };
 
-            let should_expire_order = should_expire_order(&maker, maker_order_index, now)?;
+            let should_expire_order = should_expire_order(&maker, maker_order_index, now, slot)?;
 
             let existing_base_asset_amount = maker
                 .get_perp_position(maker.orders[maker_order_index].market_index)?
@@ -1417,6 +2210,42 @@ fn get_maker_orders_info(
                 continue;
             }
 
+            // orders of the same authority never match, a crossing one applies the stp outcome
+            if *self_trade_prevention_mode != SelfTradePreventionMode::None {
+                let taker_price = taker.orders[taker_order_index].get_limit_price(
+                    Some(oracle_price),
+                    None,
+                    slot,
+                    tick_size,
+                )?;
+                let orders_cross = match taker_price {
+                    Some(taker_price) => {
+                        do_orders_cross(maker_direction, maker_order_price, taker_price)
+                    }
+                    None => true,
+                };
+
+                if orders_cross && taker.orders[taker_order_index].status == OrderStatus::Open {
+                    apply_self_trade_prevention(
+                        *self_trade_prevention_mode,
+                        taker,
+                        taker_key,
+                        taker_order_index,
+                        &mut maker,
+                        maker_key,
+                        maker_order_index,
+                        perp_market_map,
+                        spot_market_map,
+                        oracle_map,
+                        filler_key,
+                        now,
+                        slot,
+                    )?;
+                }
+
+                continue;
+            }
+
             insert_maker_order_info(
                 &mut maker_orders_info,
                 (*maker_key, maker_order_index, maker_order_price),
@@ -1547,19 +2376,22 @@ fn fulfill_perp_order(
     };
 
     if fulfillment_methods.is_empty() {
+        validate_fill_or_kill_order_filled(&user.orders[user_order_index])?;
         return Ok((0, 0));
     }
 
     let mut base_asset_amount = 0_u64;
     let mut quote_asset_amount = 0_u64;
     let mut maker_fills: BTreeMap<Pubkey, i64> = BTreeMap::new();
+    let mut linked_order_fills: Vec<(Pubkey, Order, bool)> = vec![];
     let maker_direction = user.orders[user_order_index].direction.opposite();
     for fulfillment_method in fulfillment_methods.iter() {
         if user.orders[user_order_index].status != OrderStatus::Open {
             break;
         }
         let mut market = perp_market_map.get_ref_mut(&market_index)?;
-        let user_order_direction = user.orders[user_order_index].direction;
+        let user_order = user.orders[user_order_index];
+        let user_order_direction = user_order.direction;
 
         let (fill_base_asset_amount, fill_quote_asset_amount) = match fulfillment_method {
             PerpFulfillmentMethod::AMM(maker_price) => {
@@ -1597,6 +2429,28 @@ fn fulfill_perp_order(
             }
             PerpFulfillmentMethod::Match(maker_key, maker_order_index) => {
                 let mut maker = makers_and_referrer.get_ref_mut(maker_key)?;
+                let maker_order = maker.orders[*maker_order_index as usize];
+                if has_linked_order_filled(&linked_order_fills, maker_key, &maker_order) {
+                    continue;
+                }
+
+                if maker_order.reduce_only {
+                    let maker_base_asset_amount =
+                        maker.get_perp_position(market_index)?.base_asset_amount;
+                    let maker_order_ids_to_cancel = shrink_reduce_only_orders(
+                        &mut maker,
+                        MarketType::Perp,
+                        market_index,
+                        maker_base_asset_amount,
+                        market.amm.order_step_size,
+                    )?;
+
+                    // canceled after the loop
+                    if maker_order_ids_to_cancel.contains(&maker_order.order_id) {
+                        continue;
+                    }
+                }
+
                 let mut maker_stats = if maker.authority == user.authority {
                     None
                 } else {
@@ -1642,12 +2496,28 @@ fn fulfill_perp_order(
                         maker_direction,
                         maker_fill_base_asset_amount,
                     )?;
+
+                    record_linked_order_fill(
+                        &mut linked_order_fills,
+                        maker_key,
+                        &maker_order,
+                        maker.orders[*maker_order_index as usize].status != OrderStatus::Open,
+                    );
                 }
 
                 (fill_base_asset_amount, fill_quote_asset_amount)
             }
         };
 
+        if fill_base_asset_amount != 0 {
+            record_linked_order_fill(
+                &mut linked_order_fills,
+                user_key,
+                &user_order,
+                user.orders[user_order_index].status != OrderStatus::Open,
+            );
+        }
+
         base_asset_amount = base_asset_amount.safe_add(fill_base_asset_amount)?;
         quote_asset_amount = quote_asset_amount.safe_add(fill_quote_asset_amount)?;
         market
@@ -1655,6 +2525,36 @@ fn fulfill_perp_order(
             .update_volume_24h(fill_quote_asset_amount, user_order_direction, now)?;
     }
 
+    validate_fill_or_kill_order_filled(&user.orders[user_order_index])?;
+
+    update_linked_orders_after_fill(
+        user,
+        user_key,
+        makers_and_referrer,
+        linked_order_fills,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
+    update_reduce_only_orders_after_fill(
+        user,
+        user_key,
+        base_asset_amount,
+        makers_and_referrer,
+        maker_orders_info,
+        MarketType::Perp,
+        market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        filler_key,
+        now,
+        slot,
+    )?;
+
     validate!(
         (base_asset_amount > 0) == (quote_asset_amount > 0),
         ErrorCode::DefaultError,
@@ -1695,9 +2595,48 @@ fn fulfill_perp_order(
         return Err(ErrorCode::InsufficientCollateral);
     }
 
+    let (market, oracle_price) = {
+        let market = perp_market_map.get_ref(&market_index)?;
+        let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+        (market, oracle_price)
+    };
+
+    let taker_base_asset_amount_filled = match maker_direction {
+        PositionDirection::Long => -base_asset_amount.cast::<i64>()?,
+        PositionDirection::Short => base_asset_amount.cast::<i64>()?,
+    };
+
+    // makers can share the taker's authority, so the taker's limit is checked once every fill is counted
+    if market.has_max_position_size() {
+        user_stats.update_limited_perp_position(market_index, taker_base_asset_amount_filled)?;
+    }
+
     for (maker_key, maker_base_asset_amount_filled) in maker_fills {
         let maker = makers_and_referrer.get_ref(&maker_key)?;
 
+        if market.has_max_position_size() {
+            let mut maker_stats = if maker.authority == user.authority {
+                None
+            } else {
+                Some(makers_and_referrer_stats.get_ref_mut(&maker.authority)?)
+            };
+
+            let maker_stats = match maker_stats.as_deref_mut() {
+                Some(maker_stats) => maker_stats,
+                None => &mut *user_stats,
+            };
+
+            maker_stats
+                .update_limited_perp_position(market_index, maker_base_asset_amount_filled)?;
+
+            validate_authority_perp_position_size(
+                maker_stats,
+                &market,
+                oracle_price,
+                maker_base_asset_amount_filled,
+            )?;
+        }
+
         let margin_type = select_margin_type_for_perp_maker(
             &maker,
             maker_base_asset_amount_filled,
@@ -1724,9 +2663,35 @@ fn fulfill_perp_order(
         }
     }
 
+    if market.has_max_position_size() {
+        validate_authority_perp_position_size(
+            user_stats,
+            &market,
+            oracle_price,
+            taker_base_asset_amount_filled,
+        )?;
+    }
+
     Ok((base_asset_amount, quote_asset_amount))
 }
 
+/// Fills that grew an authority's position in the fill direction must leave it within the market's max position size
+fn validate_authority_perp_position_size(
+    user_stats: &UserStats,
+    market: &PerpMarket,
+    oracle_price: i64,
+    base_asset_amount_filled: i64,
+) -> DriftResult {
+    let base_asset_amount =
+        user_stats.get_limited_perp_position_base_asset_amount(market.market_index);
+
+    if base_asset_amount != 0 && base_asset_amount.signum() == base_asset_amount_filled.signum() {
+        market.validate_max_position_size(base_asset_amount, oracle_price)?;
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::type_complexity)]
 fn get_referrer<'a>(
     referrer_info: &'a Option<(Pubkey, Pubkey)>,
@@ -1828,6 +2793,23 @@ pub fn fulfill_perp_order_with_amm(
                 fee_tier,
             )?;
 
+            let base_asset_amount_available = user.orders[order_index]
+                .get_base_asset_amount_unfilled(Some(existing_base_asset_amount))?
+                .min(
+                    user.orders[order_index]
+                        .get_twap_base_asset_amount_available(market.amm.order_step_size)?,
+                );
+            let min_fill_base_asset_amount = user.orders[order_index]
+                .get_min_fill_base_asset_amount(base_asset_amount_available);
+            if base_asset_amount < min_fill_base_asset_amount {
+                msg!(
+                    "Amm fill {} below order min fill {}",
+                    base_asset_amount,
+                    min_fill_base_asset_amount
+                );
+                return Ok((0, 0));
+            }
+
             let fill_price = if user.orders[order_index].post_only {
                 limit_price
             } else {
@@ -2111,6 +3093,16 @@ pub fn fulfill_perp_order_with_match(
         return Ok((0_u64, 0_u64, 0_u64));
     }
 
+    if get_self_trade_prevention_mode(
+        &taker.authority,
+        taker.orders[taker_order_index].self_trade_prevention_mode,
+        &maker.authority,
+        maker.orders[maker_order_index].self_trade_prevention_mode,
+    ) != SelfTradePreventionMode::None
+    {
+        return Ok((0_u64, 0_u64, 0_u64));
+    }
+
     let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
     let taker_direction: PositionDirection = taker.orders[taker_order_index].direction;
 
@@ -2131,7 +3123,11 @@ pub fn fulfill_perp_order_with_match(
         .get_perp_position(market.market_index)?
         .base_asset_amount;
     let taker_base_asset_amount = taker.orders[taker_order_index]
-        .get_base_asset_amount_unfilled(Some(taker_existing_position))?;
+        .get_base_asset_amount_unfilled(Some(taker_existing_position))?
+        .min(
+            taker.orders[taker_order_index]
+                .get_twap_base_asset_amount_available(market.amm.order_step_size)?,
+        );
 
     let maker_price = maker.orders[maker_order_index].force_get_limit_price(
         Some(oracle_price),
@@ -2143,8 +3139,10 @@ pub fn fulfill_perp_order_with_match(
     let maker_existing_position = maker
         .get_perp_position(market.market_index)?
         .base_asset_amount;
-    let maker_base_asset_amount = maker.orders[maker_order_index]
-        .get_base_asset_amount_unfilled(Some(maker_existing_position))?;
+    let maker_base_asset_amount = maker.orders[maker_order_index].get_visible_base_asset_amount(
+        maker.orders[maker_order_index]
+            .get_base_asset_amount_unfilled(Some(maker_existing_position))?,
+    );
 
     let orders_cross = do_orders_cross(maker_direction, maker_price, taker_price);
 
@@ -2169,6 +3167,22 @@ pub fn fulfill_perp_order_with_match(
         return Ok((0_u64, 0_u64, 0_u64));
     }
 
+    let taker_min_fill_base_asset_amount =
+        taker.orders[taker_order_index].get_min_fill_base_asset_amount(taker_base_asset_amount);
+    let maker_min_fill_base_asset_amount =
+        maker.orders[maker_order_index].get_min_fill_base_asset_amount(maker_base_asset_amount);
+    if base_asset_amount < taker_min_fill_base_asset_amount
+        || base_asset_amount < maker_min_fill_base_asset_amount
+    {
+        msg!(
+            "fill {} below min fill. taker min fill {} maker min fill {}",
+            base_asset_amount,
+            taker_min_fill_base_asset_amount,
+            maker_min_fill_base_asset_amount
+        );
+        return Ok((0_u64, 0_u64, 0_u64));
+    }
+
     let sanitize_clamp_denominator = market.get_sanitize_clamp_denominator()?;
     amm::update_mark_twap_from_estimates(
         &mut market.amm,
@@ -2225,7 +3239,11 @@ pub fn fulfill_perp_order_with_match(
         .base_asset_amount;
 
     let taker_base_asset_amount = taker.orders[taker_order_index]
-        .get_base_asset_amount_unfilled(Some(taker_existing_position))?;
+        .get_base_asset_amount_unfilled(Some(taker_existing_position))?
+        .min(
+            taker.orders[taker_order_index]
+                .get_twap_base_asset_amount_available(market.amm.order_step_size)?,
+        );
 
     let (base_asset_amount_fulfilled_by_maker, quote_asset_amount) =
         calculate_fill_for_matched_orders(
@@ -2487,6 +3505,18 @@ pub fn update_order_after_fill(
         order.status = OrderStatus::Filled;
     }
 
+    if order.is_iceberg() {
+        order.visible_base_asset_amount = order
+            .visible_base_asset_amount
+            .saturating_sub(base_asset_amount);
+
+        if order.visible_base_asset_amount == 0 {
+            order.visible_base_asset_amount = order
+                .display_base_asset_amount
+                .min(order.get_base_asset_amount_unfilled(None)?);
+        }
+    }
+
     Ok(())
 }
 
@@ -2546,6 +3576,19 @@ pub fn trigger_order(
         "Order is already triggered"
     )?;
 
+    validate!(
+        !user.orders[order_index].is_bracket_child(),
+        ErrorCode::BracketChildOrderNotActive,
+        "Bracket child order can't be triggered before its parent order fills"
+    )?;
+
+    validate!(
+        !user.orders[order_index].is_dormant(now),
+        ErrorCode::OrderDormant,
+        "Order can't be triggered before good_after_ts ({})",
+        user.orders[order_index].good_after_ts
+    )?;
+
     validate!(
         market_type == MarketType::Perp,
         ErrorCode::InvalidOrderMarketType,
@@ -2581,10 +3624,26 @@ pub fn trigger_order(
 
     let oracle_price = oracle_price_data.price;
 
+    let trigger_price_updated = update_trailing_stop_trigger_price(
+        &mut user.orders[order_index],
+        oracle_price.unsigned_abs(),
+        perp_market.amm.order_tick_size,
+    )?;
+
     let can_trigger = order_satisfies_trigger_condition(
         &user.orders[order_index],
         oracle_price.unsigned_abs().cast()?,
     )?;
+
+    // persist the ratcheted trigger price even though the order can't be triggered yet
+    if !can_trigger && trigger_price_updated {
+        msg!(
+            "Updated trailing stop trigger price to {}",
+            user.orders[order_index].trigger_price
+        );
+        return Ok(());
+    }
+
     validate!(can_trigger, ErrorCode::OrderDidNotSatisfyTriggerCondition)?;
 
     let worst_case_base_asset_amount_before = user
@@ -2681,6 +3740,22 @@ pub fn trigger_order(
         }
     }
 
+    if user.orders[order_index].status == OrderStatus::Open {
+        let triggered_order = user.orders[order_index];
+        update_linked_orders(
+            user,
+            &user_key,
+            &triggered_order,
+            false,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::LinkedOrderTriggered,
+        )?;
+    }
+
     user.update_last_active_slot(slot);
 
     Ok(())
@@ -2725,6 +3800,58 @@ fn update_trigger_order_params(
     Ok(())
 }
 
+/// Releases the twap slices that are due, restarting the order's auction from the oracle price.
+/// Fillers release due slices when they fill, and an unfilled slice carries over to the next one
+fn update_twap_order_slices(
+    user: &mut User,
+    order_index: usize,
+    oracle_price_data: &OraclePriceData,
+    now: i64,
+    slot: u64,
+    min_auction_duration: u8,
+    perp_market: Option<&PerpMarket>,
+) -> DriftResult<bool> {
+    let slices_due = calculate_twap_slices_due(&user.orders[order_index], now)?;
+    if slices_due <= user.orders[order_index].twap_slices_released {
+        return Ok(false);
+    }
+
+    let order = &mut user.orders[order_index];
+    let had_auction = order.has_auction();
+
+    let (auction_duration, auction_start_price, auction_end_price) =
+        calculate_auction_params_for_trigger_order(
+            order,
+            oracle_price_data,
+            min_auction_duration,
+            perp_market,
+        )?;
+
+    order.twap_slices_released = slices_due;
+    order.slot = slot;
+    order.auction_duration = auction_duration;
+    order.auction_start_price = auction_start_price;
+    order.auction_end_price = auction_end_price;
+
+    msg!(
+        "released twap slice {}/{} auction duration {} start price {} end price {}",
+        slices_due,
+        order.twap_slices,
+        auction_duration,
+        auction_start_price,
+        auction_end_price
+    );
+
+    let has_auction = order.has_auction();
+    if has_auction && !had_auction {
+        user.increment_open_auctions();
+    } else if had_auction && !has_auction {
+        user.decrement_open_auctions();
+    }
+
+    Ok(true)
+}
+
 pub fn force_cancel_orders(
     state: &State,
     user_account_loader: &AccountLoader<User>,
@@ -3040,6 +4167,7 @@ pub fn pay_keeper_flat_reward_for_spot(
     Ok(filler_reward)
 }
 
+/// Returns whether the order increases the user's risk. Orders skipped without an error return false
 pub fn place_spot_order(
     state: &State,
     user: &mut User,
@@ -3050,7 +4178,7 @@ pub fn place_spot_order(
     clock: &Clock,
     params: OrderParams,
     mut options: PlaceOrderOptions,
-) -> DriftResult {
+) -> DriftResult<bool> {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
@@ -3087,14 +4215,22 @@ pub fn place_spot_order(
     let max_ts = match params.max_ts {
         Some(max_ts) => max_ts,
         None => match params.order_type {
-            OrderType::Market | OrderType::Oracle => now.safe_add(30)?,
+            OrderType::Market | OrderType::Oracle => {
+                now.safe_add(30)?.safe_add(params.get_twap_duration()?)?
+            }
             _ => 0_i64,
         },
     };
 
     if max_ts != 0 && max_ts < now {
         msg!("max_ts ({}) < now ({}), skipping order", max_ts, now);
-        return Ok(());
+        return Ok(false);
+    }
+
+    let max_slot = params.max_slot.unwrap_or(0);
+    if max_slot != 0 && max_slot < slot {
+        msg!("max_slot ({}) < slot ({}), skipping order", max_slot, slot);
+        return Ok(false);
     }
 
     let new_order_index = user
@@ -3199,7 +4335,7 @@ pub fn place_spot_order(
         "must be spot order"
     )?;
 
-    let new_order = Order {
+    let mut new_order = Order {
         status: OrderStatus::Open,
         order_type: params.order_type,
         market_type: params.market_type,
@@ -3227,15 +4363,44 @@ pub fn place_spot_order(
         auction_end_price,
         auction_duration,
         max_ts,
-        padding: [0; 3],
+        bit_flags: params.get_order_bit_flags(),
+        link_group: params.link_group,
+        self_trade_prevention_mode: params.self_trade_prevention_mode,
+        trailing_offset: params.trailing_offset.unwrap_or(0),
+        twap_start_ts: if params.twap_slices.is_some() { now } else { 0 },
+        twap_interval: params.twap_interval.unwrap_or(0),
+        twap_slices: params.twap_slices.unwrap_or(0),
+        twap_slices_released: params.twap_slices.map_or(0, |_| 1),
+        min_fill_base_asset_amount: params.min_fill_base_asset_amount.unwrap_or(0),
+        max_slot,
+        good_after_ts: params.good_after_ts.unwrap_or(0),
+        display_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
+        visible_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
     };
 
+    if new_order.is_trailing_stop() {
+        update_trailing_stop_trigger_price(
+            &mut new_order,
+            oracle_price_data.price.unsigned_abs(),
+            spot_market.order_tick_size,
+        )?;
+    }
+
     validate_spot_order(
         &new_order,
         spot_market.order_step_size,
         spot_market.min_order_size,
     )?;
 
+    validate_order_link_group(&new_order, &user.orders)?;
+
+    // failing an unfilled fill or kill order only undoes the place when both happen in one instruction
+    validate!(
+        !new_order.is_fill_or_kill() || options.immediate_fill,
+        ErrorCode::InvalidOrderMinFill,
+        "Fill or kill order must be in place_and_make or place_and_take"
+    )?;
+
     let risk_increasing = is_new_order_risk_increasing(
         &new_order,
         signed_token_amount.cast()?,
@@ -3275,6 +4440,29 @@ pub fn place_spot_order(
         )?;
     }
 
+    // older reduce-only orders make room for the new one, which itself must fit within the position
+    if new_order.reduce_only {
+        update_reduce_only_orders(
+            user,
+            &user_key,
+            MarketType::Spot,
+            params.market_index,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            None,
+            now,
+            slot,
+        )?;
+
+        validate!(
+            user.orders[new_order_index].status == OrderStatus::Open,
+            ErrorCode::InvalidOrderNotRiskReducing,
+            "reduce only order {} exceeds the position",
+            new_order.order_id
+        )?;
+    }
+
     let (taker, taker_order, maker, maker_order) =
         get_taker_and_maker_for_order_record(&user_key, &new_order);
 
@@ -3310,7 +4498,7 @@ pub fn place_spot_order(
 
     user.update_last_active_slot(slot);
 
-    Ok(())
+    Ok(risk_increasing)
 }
 
 pub fn fill_spot_order(
@@ -3378,6 +4566,19 @@ pub fn fill_spot_order(
         "Order must be triggered first"
     )?;
 
+    validate!(
+        !user.orders[order_index].is_bracket_child(),
+        ErrorCode::BracketChildOrderNotActive,
+        "Bracket child order can't be filled before its parent order"
+    )?;
+
+    validate!(
+        !user.orders[order_index].is_dormant(now),
+        ErrorCode::OrderDormant,
+        "Order can't be filled before good_after_ts ({})",
+        user.orders[order_index].good_after_ts
+    )?;
+
     if user.is_bankrupt() {
         msg!("User is bankrupt");
         return Ok(0);
@@ -3410,6 +4611,45 @@ pub fn fill_spot_order(
         (None, None)
     };
 
+    if user.orders[order_index].is_twap() {
+        let oracle_price_data =
+            *oracle_map.get_price_data(&spot_market_map.get_ref(&order_market_index)?.oracle)?;
+        let twap_slice_released = update_twap_order_slices(
+            user,
+            order_index,
+            &oracle_price_data,
+            now,
+            slot,
+            state.default_spot_auction_duration,
+            None,
+        )?;
+
+        if twap_slice_released {
+            let order_action_record = get_order_action_record(
+                now,
+                OrderAction::Trigger,
+                OrderActionExplanation::TwapSliceReleased,
+                order_market_index,
+                Some(filler_key),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(user_key),
+                Some(user.orders[order_index]),
+                None,
+                None,
+                oracle_price_data.price,
+            )?;
+            emit_stack::<_, { OrderActionRecord::SIZE }>(order_action_record)?;
+        }
+    }
+
     let oracle_price = oracle_map
         .get_price_data(&spot_market_map.get_ref_mut(&order_market_index)?.oracle)?
         .price;
@@ -3419,7 +4659,8 @@ pub fn fill_spot_order(
         oracle_map,
         makers_and_referrer,
         &user_key,
-        &user.orders[order_index],
+        user,
+        order_index,
         &mut filler.as_deref_mut(),
         &filler_key,
         state.spot_fee_structure.flat_filler_fee,
@@ -3429,6 +4670,11 @@ pub fn fill_spot_order(
         slot,
     )?;
 
+    // the taker order can be canceled by self trade prevention
+    if user.orders[order_index].status != OrderStatus::Open {
+        return Ok(0);
+    }
+
     {
         let mut quote_market = spot_market_map.get_quote_spot_market_mut()?;
         let oracle_price_data = oracle_map.get_price_data(&quote_market.oracle)?;
@@ -3459,7 +4705,7 @@ pub fn fill_spot_order(
         }
     }
 
-    let should_expire_order = should_expire_order_before_fill(user, order_index, now)?;
+    let should_expire_order = should_expire_order_before_fill(user, order_index, now, slot)?;
 
     let should_cancel_reduce_only = if user.orders[order_index].reduce_only {
         let market_index = user.orders[order_index].market_index;
@@ -3511,6 +4757,17 @@ pub fn fill_spot_order(
         return Ok(0);
     }
 
+    let twap_base_asset_amount_available = user.orders[order_index]
+        .get_twap_base_asset_amount_available(
+            spot_market_map
+                .get_ref(&order_market_index)?
+                .order_step_size,
+        )?;
+    if twap_base_asset_amount_available == 0 {
+        msg!("Twap order has no released slice left to fill");
+        return Ok(0);
+    }
+
     let (base_asset_amount, quote_asset_amount) = fulfill_spot_order(
         user,
         order_index,
@@ -3633,7 +4890,8 @@ fn get_spot_maker_orders_info(
     oracle_map: &mut OracleMap,
     makers_and_referrer: &UserMap,
     taker_key: &Pubkey,
-    taker_order: &Order,
+    taker: &mut User,
+    taker_order_index: usize,
     filler: &mut Option<&mut User>,
     filler_key: &Pubkey,
     filler_reward: u64,
@@ -3642,6 +4900,7 @@ fn get_spot_maker_orders_info(
     now: i64,
     slot: u64,
 ) -> DriftResult<Vec<(Pubkey, usize, u64)>> {
+    let taker_order = taker.orders[taker_order_index];
     let maker_direction = taker_order.direction.opposite();
 
     let mut maker_orders_info = Vec::with_capacity(16);
@@ -3651,6 +4910,11 @@ fn get_spot_maker_orders_info(
             continue;
         }
 
+        // a self trade can cancel the taker order
+        if taker.orders[taker_order_index].status != OrderStatus::Open {
+            break;
+        }
+
         let mut maker = load_mut!(user_account_loader)?;
 
         if maker.is_being_liquidated() || maker.is_bankrupt() {
@@ -3665,7 +4929,10 @@ fn get_spot_maker_orders_info(
             taker_order.market_index,
             Some(oracle_price),
             slot,
+            now,
             market.order_tick_size,
+            &taker.authority,
+            taker_order.self_trade_prevention_mode,
         )?;
 
         if maker_order_price_and_indexes.is_empty() {
@@ -3676,6 +4943,7 @@ fn get_spot_maker_orders_info(
 
         let initial_margin_ratio = market.get_margin_ratio(&MarginRequirementType::Initial)?;
         let step_size = market.order_step_size;
+        let tick_size = market.order_tick_size;
 
         let existing_base_asset_amount = maker
             .get_spot_position(taker_order.market_index)?
@@ -3683,16 +4951,18 @@ fn get_spot_maker_orders_info(
 
         drop(market);
 
-        for (maker_order_index, maker_order_price) in maker_order_price_and_indexes.iter() {
+        for (maker_order_index, maker_order_price, self_trade_prevention_mode) in
+            maker_order_price_and_indexes.iter()
+        {
             let maker_order_index = *maker_order_index;
             let maker_order_price = *maker_order_price;
 
             let maker_order = &maker.orders[maker_order_index];
-            if !is_maker_for_taker(maker_order, taker_order, slot)? {
+            if !is_maker_for_taker(maker_order, &taker_order, slot)? {
                 continue;
             }
 
-            if !are_orders_same_market_but_different_sides(maker_order, taker_order) {
+            if !are_orders_same_market_but_different_sides(maker_order, &taker_order) {
                 continue;
             }
 
@@ -3712,7 +4982,7 @@ fn get_spot_maker_orders_info(
                 )?
             };
 
-            let should_expire_order = should_expire_order(&maker, maker_order_index, now)?;
+            let should_expire_order = should_expire_order(&maker, maker_order_index, now, slot)?;
 
             let should_cancel_reduce_only_order = should_cancel_reduce_only_order(
                 &maker.orders[maker_order_index],
@@ -3760,6 +5030,42 @@ fn get_spot_maker_orders_info(
                 continue;
             }
 
+            // orders of the same authority never match, a crossing one applies the stp outcome
+            if *self_trade_prevention_mode != SelfTradePreventionMode::None {
+                let taker_price = taker.orders[taker_order_index].get_limit_price(
+                    Some(oracle_price),
+                    None,
+                    slot,
+                    tick_size,
+                )?;
+                let orders_cross = match taker_price {
+                    Some(taker_price) => {
+                        do_orders_cross(maker_direction, maker_order_price, taker_price)
+                    }
+                    None => true,
+                };
+
+                if orders_cross && taker.orders[taker_order_index].status == OrderStatus::Open {
+                    apply_self_trade_prevention(
+                        *self_trade_prevention_mode,
+                        taker,
+                        taker_key,
+                        taker_order_index,
+                        &mut maker,
+                        maker_key,
+                        maker_order_index,
+                        perp_market_map,
+                        spot_market_map,
+                        oracle_map,
+                        filler_key,
+                        now,
+                        slot,
+                    )?;
+                }
+
+                continue;
+            }
+
             insert_maker_order_info(
                 &mut maker_orders_info,
                 (*maker_key, maker_order_index, maker_order_price),
@@ -3839,15 +5145,40 @@ fn fulfill_spot_order(
     let mut base_asset_amount = 0_u64;
     let mut quote_asset_amount = 0_u64;
     let mut maker_fills: BTreeMap<Pubkey, i64> = BTreeMap::new();
+    let mut linked_order_fills: Vec<(Pubkey, Order, bool)> = vec![];
     let maker_direction = user.orders[user_order_index].direction.opposite();
     for fulfillment_method in fulfillment_methods.iter() {
         if user.orders[user_order_index].status != OrderStatus::Open {
             break;
         }
 
+        let user_order = user.orders[user_order_index];
         let (base_filled, quote_filled) = match fulfillment_method {
             SpotFulfillmentMethod::Match(maker_key, maker_order_index) => {
                 let mut maker = makers_and_referrer.get_ref_mut(maker_key)?;
+                let maker_order = maker.orders[*maker_order_index as usize];
+                if has_linked_order_filled(&linked_order_fills, maker_key, &maker_order) {
+                    continue;
+                }
+
+                if maker_order.reduce_only {
+                    let maker_token_amount = maker
+                        .get_spot_position(base_market_index)?
+                        .get_signed_token_amount(&base_market)?;
+                    let maker_order_ids_to_cancel = shrink_reduce_only_orders(
+                        &mut maker,
+                        MarketType::Spot,
+                        base_market_index,
+                        maker_token_amount.cast()?,
+                        base_market.order_step_size,
+                    )?;
+
+                    // canceled after the loop
+                    if maker_order_ids_to_cancel.contains(&maker_order.order_id) {
+                        continue;
+                    }
+                }
+
                 let mut maker_stats = if maker.authority == user.authority {
                     None
                 } else {
@@ -3881,6 +5212,13 @@ fn fulfill_spot_order(
                         maker_direction,
                         base_filled,
                     )?;
+
+                    record_linked_order_fill(
+                        &mut linked_order_fills,
+                        maker_key,
+                        &maker_order,
+                        maker.orders[*maker_order_index as usize].status != OrderStatus::Open,
+                    );
                 }
 
                 (base_filled, quote_filled)
@@ -3903,10 +5241,21 @@ fn fulfill_spot_order(
             )?,
         };
 
+        if base_filled != 0 {
+            record_linked_order_fill(
+                &mut linked_order_fills,
+                user_key,
+                &user_order,
+                user.orders[user_order_index].status != OrderStatus::Open,
+            );
+        }
+
         base_asset_amount = base_asset_amount.safe_add(base_filled)?;
         quote_asset_amount = quote_asset_amount.safe_add(quote_filled)?;
     }
 
+    validate_fill_or_kill_order_filled(&user.orders[user_order_index])?;
+
     validate!(
         (base_asset_amount > 0) == (quote_asset_amount > 0),
         ErrorCode::DefaultError,
@@ -3971,6 +5320,34 @@ fn fulfill_spot_order(
     drop(base_market);
     drop(quote_market);
 
+    update_linked_orders_after_fill(
+        user,
+        user_key,
+        makers_and_referrer,
+        linked_order_fills,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+    )?;
+
+    update_reduce_only_orders_after_fill(
+        user,
+        user_key,
+        base_asset_amount,
+        makers_and_referrer,
+        maker_orders_info,
+        MarketType::Spot,
+        base_market_index,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        filler_key,
+        now,
+        slot,
+    )?;
+
     let taker_margin_calculation =
         calculate_margin_requirement_and_total_collateral_and_liability_info(
             user,
@@ -4085,6 +5462,16 @@ pub fn fulfill_spot_order_with_match(
         return Ok((0_u64, 0_u64));
     }
 
+    if get_self_trade_prevention_mode(
+        &taker.authority,
+        taker.orders[taker_order_index].self_trade_prevention_mode,
+        &maker.authority,
+        maker.orders[maker_order_index].self_trade_prevention_mode,
+    ) != SelfTradePreventionMode::None
+    {
+        return Ok((0_u64, 0_u64));
+    }
+
     let market_index = taker.orders[taker_order_index].market_index;
     let oracle_price = oracle_map.get_price_data(&base_market.oracle)?.price;
     let taker_price = match taker.orders[taker_order_index].get_limit_price(
@@ -4106,7 +5493,11 @@ pub fn fulfill_spot_order_with_match(
         .get_standardized_base_asset_amount_unfilled(
             Some(taker_token_amount.cast()?),
             base_market.order_step_size,
-        )?;
+        )?
+        .min(
+            taker.orders[taker_order_index]
+                .get_twap_base_asset_amount_available(base_market.order_step_size)?,
+        );
     let taker_order_slot = taker.orders[taker_order_index].slot;
     let taker_direction = taker.orders[taker_order_index].direction;
 
@@ -4120,11 +5511,17 @@ pub fn fulfill_spot_order_with_match(
     let maker_spot_position_index = maker.get_spot_position_index(market_index)?;
     let maker_token_amount =
         maker.spot_positions[maker_spot_position_index].get_signed_token_amount(base_market)?;
-    let maker_base_asset_amount = maker.orders[maker_order_index]
-        .get_standardized_base_asset_amount_unfilled(
+    let maker_base_asset_amount = maker.orders[maker_order_index].get_visible_base_asset_amount(
+        maker.orders[maker_order_index].get_standardized_base_asset_amount_unfilled(
             Some(maker_token_amount.cast()?),
             base_market.order_step_size,
-        )?;
+        )?,
+    );
+
+    let taker_min_fill_base_asset_amount =
+        taker.orders[taker_order_index].get_min_fill_base_asset_amount(taker_base_asset_amount);
+    let maker_min_fill_base_asset_amount =
+        maker.orders[maker_order_index].get_min_fill_base_asset_amount(maker_base_asset_amount);
 
     let orders_cross = do_orders_cross(maker_direction, maker_price, taker_price);
 
@@ -4189,6 +5586,18 @@ pub fn fulfill_spot_order_with_match(
         return Ok((0_u64, 0_u64));
     }
 
+    if base_asset_amount < taker_min_fill_base_asset_amount
+        || base_asset_amount < maker_min_fill_base_asset_amount
+    {
+        msg!(
+            "fill {} below min fill. taker min fill {} maker min fill {}",
+            base_asset_amount,
+            taker_min_fill_base_asset_amount,
+            maker_min_fill_base_asset_amount
+        );
+        return Ok((0_u64, 0_u64));
+    }
+
     let base_precision = base_market.get_precision();
     validate_fill_price(
         quote_asset_amount,
@@ -4427,9 +5836,15 @@ pub fn fulfill_spot_order_with_external_market(
         .get_standardized_base_asset_amount_unfilled(
             Some(taker_token_amount.cast()?),
             base_market.order_step_size,
-        )?;
+        )?
+        .min(
+            taker.orders[taker_order_index]
+                .get_twap_base_asset_amount_available(base_market.order_step_size)?,
+        );
     let order_direction = taker.orders[taker_order_index].direction;
     let taker_order_slot = taker.orders[taker_order_index].slot;
+    let min_fill_base_asset_amount =
+        taker.orders[taker_order_index].get_min_fill_base_asset_amount(taker_base_asset_amount);
 
     let (max_base_asset_amount, max_quote_asset_amount) =
         get_max_fill_amounts(taker, taker_order_index, base_market, quote_market, true)?;
@@ -4437,6 +5852,15 @@ pub fn fulfill_spot_order_with_external_market(
     let taker_base_asset_amount =
         taker_base_asset_amount.min(max_base_asset_amount.unwrap_or(u64::MAX));
 
+    if taker_base_asset_amount < min_fill_base_asset_amount {
+        msg!(
+            "External fill {} below order min fill {}",
+            taker_base_asset_amount,
+            min_fill_base_asset_amount
+        );
+        return Ok((0, 0));
+    }
+
     let (best_bid, best_ask) = fulfillment_params.get_best_bid_and_ask()?;
     base_market.update_historical_index_price(best_bid, best_ask, now)?;
 
@@ -4482,6 +5906,15 @@ pub fn fulfill_spot_order_with_external_market(
         return Ok((0, 0));
     }
 
+    // the external market can fill less than requested, which can't be undone
+    validate!(
+        base_asset_amount_filled >= min_fill_base_asset_amount,
+        ErrorCode::InvalidOrderMinFill,
+        "External fill {} below order min fill {}",
+        base_asset_amount_filled,
+        min_fill_base_asset_amount
+    )?;
+
     update_spot_balances(
         settled_referrer_rebate as u128,
         &SpotBalanceType::Deposit,
@@ -4695,6 +6128,19 @@ pub fn trigger_spot_order(
         "Order is already triggered"
     )?;
 
+    validate!(
+        !user.orders[order_index].is_bracket_child(),
+        ErrorCode::BracketChildOrderNotActive,
+        "Bracket child order can't be triggered before its parent order fills"
+    )?;
+
+    validate!(
+        !user.orders[order_index].is_dormant(now),
+        ErrorCode::OrderDormant,
+        "Order can't be triggered before good_after_ts ({})",
+        user.orders[order_index].good_after_ts
+    )?;
+
     validate!(
         market_type == MarketType::Spot,
         ErrorCode::InvalidOrderMarketType,
@@ -4737,10 +6183,26 @@ pub fn trigger_spot_order(
 
     let oracle_price = oracle_price_data.price;
 
+    let trigger_price_updated = update_trailing_stop_trigger_price(
+        &mut user.orders[order_index],
+        oracle_price.unsigned_abs(),
+        spot_market.order_tick_size,
+    )?;
+
     let can_trigger = order_satisfies_trigger_condition(
         &user.orders[order_index],
         oracle_price.unsigned_abs().cast()?,
     )?;
+
+    // persist the ratcheted trigger price even though the order can't be triggered yet
+    if !can_trigger && trigger_price_updated {
+        msg!(
+            "Updated trailing stop trigger price to {}",
+            user.orders[order_index].trigger_price
+        );
+        return Ok(());
+    }
+
     validate!(can_trigger, ErrorCode::OrderDidNotSatisfyTriggerCondition)?;
 
     let position_index = user.get_spot_position_index(market_index)?;
@@ -4853,6 +6315,22 @@ pub fn trigger_spot_order(
         }
     }
 
+    if user.orders[order_index].status == OrderStatus::Open {
+        let triggered_order = user.orders[order_index];
+        update_linked_orders(
+            user,
+            &user_key,
+            &triggered_order,
+            false,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::LinkedOrderTriggered,
+        )?;
+    }
+
     user.update_last_active_slot(slot);
 
     Ok(())
@@ -4868,7 +6346,7 @@ pub fn expire_orders(
     slot: u64,
 ) -> DriftResult {
     for order_index in 0..user.orders.len() {
-        if !should_expire_order(user, order_index, now)? {
+        if !should_expire_order(user, order_index, now, slot)? {
             continue;
         }
 
@@ -4756,7 +4756,9 @@ pub mod fill_order {
     use crate::state::spot_market::{SpotBalanceType, SpotMarket};
     use crate::state::spot_market_map::SpotMarketMap;
     use crate::state::state::State;
-    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user::{
+        AuthorityPerpPosition, MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats,
+    };
     use crate::test_utils::*;
     use crate::test_utils::{
         create_account_info, get_orders, get_positions, get_pyth_price, get_spot_positions,
@@ -5472,155 +5474,355 @@ pub mod fill_order {
 
         assert_eq!(err, Err(ErrorCode::MaxOpenInterest));
     }
-}
 
-#[cfg(test)]
-pub mod fulfill_spot_order_with_match {
-    use crate::controller::orders::fulfill_spot_order_with_match;
-    use crate::controller::position::PositionDirection;
-    use crate::math::constants::{
-        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
-        QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-    };
-    use crate::math::spot_balance::calculate_utilization;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::user::{MarketType, Order, OrderType, SpotPosition, User, UserStats};
-    use crate::test_utils::get_orders;
-    use crate::SPOT_UTILIZATION_PRECISION;
+    #[test]
+    fn max_position_size() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
 
-    use super::*;
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-    #[test]
-    fn long_taker_order_fulfilled_start_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap,
+                    last_oracle_price_twap_5min: oracle_price.twap,
+                    last_oracle_price: oracle_price.agg.price,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            max_position_base_asset_amount: BASE_PRECISION_U64 / 2,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut taker = User {
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
             orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
                 order_type: OrderType::Market,
                 direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_start_price: 0,
+                auction_end_price: 102 * PRICE_PRECISION_I64,
                 auction_duration: 5,
+                price: 102 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
-        };
-
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut maker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
-                ..Order::default()
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
             }),
-            spot_positions: maker_spot_positions,
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
-        };
-
-        let now = 1_i64;
-        let slot = 1_u64;
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
 
-        let fee_structure = get_fee_structure();
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        let err = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &UserMap::empty(),
+            &UserStatsMap::empty(),
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
-        )
-        .unwrap();
-
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
+            &clock,
+            FillMode::Fill,
         );
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
-
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
 
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(err, Err(ErrorCode::MaxPositionSize));
     }
 
     #[test]
-    fn long_taker_order_fulfilled_middle_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 161 * SPOT_BALANCE_PRECISION_U64,
+    fn max_position_size_is_per_authority() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap,
+                    last_oracle_price_twap_5min: oracle_price.twap,
+                    last_oracle_price: oracle_price.agg.price,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            max_position_base_asset_amount: BASE_PRECISION_U64 * 3 / 4,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64 / 2,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 102 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 102 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64 / 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        // another sub account of the authority already holds half a base long
+        let mut user_stats = UserStats::default();
+        user_stats.limited_perp_positions[0] = AuthorityPerpPosition {
+            market_index: 0,
+            base_asset_amount: BASE_PRECISION_I64 / 2,
+            ..AuthorityPerpPosition::default()
+        };
+        create_anchor_account_info!(user_stats, UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
+
+        let err = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &UserMap::empty(),
+            &UserStatsMap::empty(),
+            None,
+            &clock,
+            FillMode::Fill,
+        );
+
+        assert_eq!(err, Err(ErrorCode::MaxPositionSize));
+    }
+}
+
+#[cfg(test)]
+pub mod fulfill_spot_order_with_match {
+    use crate::controller::orders::fulfill_spot_order_with_match;
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{
+        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
+        QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+    };
+    use crate::math::spot_balance::calculate_utilization;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::user::{MarketType, Order, OrderType, SpotPosition, User, UserStats};
+    use crate::test_utils::get_orders;
+    use crate::SPOT_UTILIZATION_PRECISION;
+
+    use super::*;
+
+    #[test]
+    fn long_taker_order_fulfilled_start_of_auction() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
             balance_type: SpotBalanceType::Deposit,
             ..SpotPosition::default()
         };
@@ -5664,7 +5866,7 @@ pub mod fulfill_spot_order_with_match {
                 order_type: OrderType::Limit,
                 direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 160 * PRICE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -5676,12 +5878,12 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 161 * SPOT_BALANCE_PRECISION,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 3_i64;
-        let slot = 3_u64;
+        let now = 1_i64;
+        let slot = 1_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -5712,7 +5914,7 @@ pub mod fulfill_spot_order_with_match {
         .unwrap();
 
         let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 920000000);
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
 
         let taker_base_position = taker.spot_positions[1];
         assert_eq!(
@@ -5722,33 +5924,37 @@ pub mod fulfill_spot_order_with_match {
         assert_eq!(taker_base_position.open_bids, 0);
         assert_eq!(taker_base_position.open_orders, 0);
 
-        assert_eq!(taker_stats.taker_volume_30d, 160000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 80000);
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
 
         let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 160048000000);
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
 
         let maker_base_position = maker.spot_positions[1];
         assert_eq!(maker_base_position.scaled_balance, 0);
         assert_eq!(maker_base_position.open_bids, 0);
         assert_eq!(maker_base_position.open_orders, 0);
 
-        assert_eq!(maker_stats.maker_volume_30d, 160000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 48000);
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
 
-        assert_eq!(base_market.total_spot_fee, 32000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 32000000);
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn short_taker_order_fulfilled_start_of_auction() {
+    fn long_taker_order_fulfilled_middle_of_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 161 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -5756,11 +5962,11 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Market,
-                direction: PositionDirection::Short,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
                 auction_duration: 5,
                 ..Order::default()
             }),
@@ -5769,16 +5975,12 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -5787,9 +5989,9 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 160 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -5801,12 +6003,12 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            deposit_balance: 161 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 1_i64;
-        let slot = 1_u64;
+        let now = 3_i64;
+        let slot = 3_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -5837,24 +6039,149 @@ pub mod fulfill_spot_order_with_match {
         .unwrap();
 
         let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
+        assert_eq!(taker_quote_position.scaled_balance, 920000000);
 
         let taker_base_position = taker.spot_positions[1];
-        assert_eq!(taker_base_position.scaled_balance, 0);
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
         assert_eq!(taker_base_position.open_bids, 0);
         assert_eq!(taker_base_position.open_orders, 0);
 
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+        assert_eq!(taker_stats.taker_volume_30d, 160000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 80000);
 
         let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
+        assert_eq!(maker_quote_position.scaled_balance, 160048000000);
 
         let maker_base_position = maker.spot_positions[1];
-        assert_eq!(
-            maker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        assert_eq!(maker_stats.maker_volume_30d, 160000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 48000);
+
+        assert_eq!(base_market.total_spot_fee, 32000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 32000000);
+    }
+
+    #[test]
+    fn short_taker_order_fulfilled_start_of_auction() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(taker_base_position.scaled_balance, 0);
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(
+            maker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
         assert_eq!(maker_base_position.open_bids, 0);
         assert_eq!(maker_base_position.open_orders, 0);
 
@@ -9578,7 +9905,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -9665,7 +9992,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -9772,7 +10100,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -9860,7 +10188,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -9967,7 +10296,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -10044,7 +10373,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10148,7 +10478,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -10291,7 +10621,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10403,7 +10734,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -10493,7 +10824,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10600,7 +10932,7 @@ pub mod get_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 0,
@@ -10717,7 +11049,8 @@ pub mod get_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10814,7 +11147,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -10900,7 +11233,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             QUOTE_PRECISION_U64 / 100,
@@ -10971,7 +11305,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -11059,7 +11393,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -11130,7 +11465,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -11206,7 +11541,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -11274,7 +11610,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -11420,7 +11756,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -11496,7 +11833,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -11586,7 +11923,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -11657,7 +11995,7 @@ pub mod get_spot_maker_orders_info {
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
+        let mut user = User {
             authority: taker_authority,
             orders: get_orders(Order {
                 market_index: 1,
@@ -11777,7 +12115,8 @@ pub mod get_spot_maker_orders_info {
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
-            &user.orders[0],
+            &mut user,
+            0,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -11923,3 +12262,1567 @@ mod update_maker_fills_map {
         assert_eq!(*map.get(&maker_key).unwrap(), -2 * fill as i64);
     }
 }
+
+pub mod linked_orders {
+    use std::str::FromStr;
+
+    use crate::controller::orders::{cancel_order, fulfill_perp_order};
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::get_orders;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION, PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I64,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::events::OrderActionExplanation;
+    use crate::state::fill_mode::FillMode;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{
+        OrderBitFlag, OrderStatus, OrderTriggerCondition, OrderType, SpotPosition, User, UserStats,
+    };
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+
+    use super::*;
+
+    fn get_bracket_orders(parent_base_asset_amount_filled: u64) -> [Order; 32] {
+        get_orders!(
+            Order {
+                order_id: 1,
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                base_asset_amount_filled: parent_base_asset_amount_filled,
+                price: 100 * PRICE_PRECISION_U64,
+                link_group: 1,
+                ..Order::default()
+            },
+            Order {
+                order_id: 2,
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::TriggerMarket,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                trigger_price: 90 * PRICE_PRECISION_U64,
+                trigger_condition: OrderTriggerCondition::Below,
+                reduce_only: true,
+                link_group: 1,
+                bit_flags: OrderBitFlag::BracketChild as u8,
+                ..Order::default()
+            },
+            Order {
+                order_id: 3,
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::TriggerLimit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 110 * PRICE_PRECISION_U64,
+                trigger_price: 110 * PRICE_PRECISION_U64,
+                trigger_condition: OrderTriggerCondition::Above,
+                reduce_only: true,
+                link_group: 1,
+                bit_flags: OrderBitFlag::BracketChild as u8,
+                ..Order::default()
+            }
+        )
+    }
+
+    #[test]
+    fn fill_cancels_linked_orders_and_activates_bracket_children() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                base_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap_5min: (100 * PRICE_PRECISION) as i64,
+
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default_test()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut taker_orders = get_bracket_orders(0);
+        taker_orders[0].order_type = OrderType::Market;
+        taker_orders[0].price = 150 * PRICE_PRECISION_U64;
+        taker_orders[0].auction_end_price = 100 * PRICE_PRECISION_I64;
+        let mut taker = User {
+            orders: taker_orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 3,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::default();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let mut maker = User {
+            authority: maker_authority,
+            orders: get_orders!(
+                Order {
+                    order_id: 1,
+                    market_index: 0,
+                    status: OrderStatus::Open,
+                    post_only: true,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    price: 95 * PRICE_PRECISION_U64,
+                    link_group: 7,
+                    ..Order::default()
+                },
+                Order {
+                    order_id: 2,
+                    market_index: 0,
+                    status: OrderStatus::Open,
+                    post_only: true,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Long,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    price: 80 * PRICE_PRECISION_U64,
+                    link_group: 7,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_bids: BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(maker, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut filler = User::default();
+
+        let fee_structure = get_fee_structure();
+
+        let (_, _, filler_key) = get_user_keys();
+        let taker_key = Pubkey::new_unique();
+
+        let mut taker_stats = UserStats::default();
+
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let mut filler_stats = UserStats::default();
+
+        let (base_asset_amount, _) = fulfill_perp_order(
+            &mut taker,
+            0,
+            &taker_key,
+            &mut taker_stats,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            &[(maker_key, 0, 95 * PRICE_PRECISION_U64)],
+            &mut Some(&mut filler),
+            &filler_key,
+            &mut Some(&mut filler_stats),
+            None,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &fee_structure,
+            100 * PRICE_PRECISION_U64,
+            Some(market.amm.historical_oracle_data.last_oracle_price),
+            now,
+            slot,
+            10,
+            false,
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64);
+
+        // parent filled and both bracket children are live
+        assert_eq!(taker.orders[0], Order::default());
+        assert_eq!(taker.orders[1].status, OrderStatus::Open);
+        assert!(!taker.orders[1].is_bracket_child());
+        assert_eq!(taker.orders[2].status, OrderStatus::Open);
+        assert!(!taker.orders[2].is_bracket_child());
+
+        let taker_position = &taker.perp_positions[0];
+        assert_eq!(taker_position.base_asset_amount, BASE_PRECISION_I64);
+        assert_eq!(taker_position.open_orders, 2);
+        assert_eq!(taker_position.open_bids, 0);
+
+        // maker's other order in the link group is canceled
+        let maker = makers_and_referrers.get_ref_mut(&maker_key).unwrap();
+        assert_eq!(maker.orders[0], Order::default());
+        assert_eq!(maker.orders[1], Order::default());
+
+        let maker_position = &maker.perp_positions[0];
+        assert_eq!(maker_position.base_asset_amount, -BASE_PRECISION_I64);
+        assert_eq!(maker_position.open_orders, 0);
+        assert_eq!(maker_position.open_bids, 0);
+        assert_eq!(maker_position.open_asks, 0);
+    }
+
+    #[test]
+    fn cancel_parent_cancels_bracket_children() {
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                oracle: oracle_price_key,
+                ..AMM::default()
+            },
+            ..PerpMarket::default_test()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        for (parent_base_asset_amount_filled, children_canceled) in
+            [(0, true), (BASE_PRECISION_U64 / 2, false)]
+        {
+            let mut user = User {
+                orders: get_bracket_orders(parent_base_asset_amount_filled),
+                perp_positions: get_positions(PerpPosition {
+                    market_index: 0,
+                    open_orders: 3,
+                    open_bids: (BASE_PRECISION_U64 - parent_base_asset_amount_filled) as i64,
+                    ..PerpPosition::default()
+                }),
+                ..User::default()
+            };
+
+            cancel_order(
+                0,
+                &mut user,
+                &Pubkey::default(),
+                &market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                0,
+                slot,
+                OrderActionExplanation::None,
+                None,
+                0,
+                false,
+            )
+            .unwrap();
+
+            assert_eq!(user.orders[0], Order::default());
+            if children_canceled {
+                assert_eq!(user.orders[1], Order::default());
+                assert_eq!(user.orders[2], Order::default());
+                assert_eq!(user.perp_positions[0].open_orders, 0);
+            } else {
+                // partially filled parent leaves a position for the children to protect
+                assert_eq!(user.orders[1].status, OrderStatus::Open);
+                assert!(!user.orders[1].is_bracket_child());
+                assert_eq!(user.orders[2].status, OrderStatus::Open);
+                assert!(!user.orders[2].is_bracket_child());
+                assert_eq!(user.perp_positions[0].open_orders, 2);
+            }
+            assert_eq!(user.perp_positions[0].open_bids, 0);
+        }
+    }
+}
+
+pub mod min_fill {
+    use std::str::FromStr;
+
+    use crate::controller::orders::{fulfill_perp_order, fulfill_perp_order_with_match};
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION, PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::fill_mode::FillMode;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{OrderBitFlag, OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
+
+    use super::*;
+
+    #[test]
+    fn match_below_min_fill_is_skipped() {
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                min_fill_base_asset_amount: BASE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                post_only: true,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64 / 2,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64 / 2,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut market = PerpMarket::default_test();
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_asset_amount, _, _) = fulfill_perp_order_with_match(
+            &mut market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            &mut None,
+            &mut None,
+            &filler_key,
+            &mut None,
+            &mut None,
+            0,
+            None,
+            Some(100 * PRICE_PRECISION_U64),
+            now,
+            slot,
+            &fee_structure,
+            &mut get_oracle_map(),
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 0);
+        assert_eq!(taker.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(taker.orders[0].base_asset_amount_filled, 0);
+        assert_eq!(maker.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(maker.orders[0].base_asset_amount_filled, 0);
+
+        // the maker's min fill is checked too
+        taker.orders[0].min_fill_base_asset_amount = 0;
+        maker.orders[0].base_asset_amount = BASE_PRECISION_U64 * 2;
+        maker.orders[0].min_fill_base_asset_amount = BASE_PRECISION_U64 * 2;
+        maker.perp_positions[0].open_asks = -BASE_PRECISION_I64 * 2;
+
+        let (base_asset_amount, _, _) = fulfill_perp_order_with_match(
+            &mut market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            &mut None,
+            &mut None,
+            &filler_key,
+            &mut None,
+            &mut None,
+            0,
+            None,
+            Some(100 * PRICE_PRECISION_U64),
+            now,
+            slot,
+            &fee_structure,
+            &mut get_oracle_map(),
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 0);
+        assert_eq!(maker.orders[0].base_asset_amount_filled, 0);
+    }
+
+    #[test]
+    fn fill_or_kill_fails_if_not_fully_filled() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                base_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap_5min: (100 * PRICE_PRECISION) as i64,
+
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default_test()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                immediate_or_cancel: true,
+                bit_flags: OrderBitFlag::FillOrKill as u8,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::default();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let mut maker = User {
+            authority: maker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                post_only: true,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64 / 2,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64 / 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(maker, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut filler = User::default();
+
+        let fee_structure = get_fee_structure();
+
+        let (_, _, filler_key) = get_user_keys();
+        let taker_key = Pubkey::new_unique();
+
+        let mut taker_stats = UserStats::default();
+
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let mut filler_stats = UserStats::default();
+
+        let result = fulfill_perp_order(
+            &mut taker,
+            0,
+            &taker_key,
+            &mut taker_stats,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            &[(maker_key, 0, 100 * PRICE_PRECISION_U64)],
+            &mut Some(&mut filler),
+            &filler_key,
+            &mut Some(&mut filler_stats),
+            None,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &fee_structure,
+            100 * PRICE_PRECISION_U64,
+            Some(market.amm.historical_oracle_data.last_oracle_price),
+            now,
+            slot,
+            10,
+            false,
+            FillMode::Fill,
+        );
+
+        assert_eq!(result, Err(ErrorCode::FillOrKillOrderNotFilled));
+    }
+}
+
+pub mod modify_orders {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::modify_orders;
+    use crate::controller::position::PositionDirection;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION,
+        SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::order_params::{
+        ModifyOrderByIdParams, ModifyOrderId, ModifyOrderParams, ModifyOrderPolicy,
+    };
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{OrderStatus, OrderType, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, get_orders, QUOTE_PRECISION_I64};
+
+    use super::*;
+
+    #[test]
+    fn modify_orders_with_single_margin_check() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap,
+                    last_oracle_price_twap_5min: oracle_price.twap,
+                    last_oracle_price: oracle_price.agg.price,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    market_type: MarketType::Perp,
+                    direction: PositionDirection::Long,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    price: 99 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 2,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    market_type: MarketType::Perp,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    price: 101 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_bids: BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            next_order_id: 3,
+            open_orders: 2,
+            has_open_order: true,
+            ..User::default()
+        };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        let state = State::default();
+
+        let modifications = vec![
+            ModifyOrderByIdParams {
+                order_id: ModifyOrderId::OrderId(1),
+                modify_order_params: ModifyOrderParams {
+                    price: Some(98 * PRICE_PRECISION_U64),
+                    ..ModifyOrderParams::default()
+                },
+            },
+            ModifyOrderByIdParams {
+                order_id: ModifyOrderId::OrderId(2),
+                modify_order_params: ModifyOrderParams {
+                    price: Some(102 * PRICE_PRECISION_U64),
+                    ..ModifyOrderParams::default()
+                },
+            },
+            // missing orders are skipped unless all_or_nothing
+            ModifyOrderByIdParams {
+                order_id: ModifyOrderId::OrderId(99),
+                modify_order_params: ModifyOrderParams::default(),
+            },
+        ];
+
+        modify_orders(
+            modifications.clone(),
+            false,
+            &user_account_loader,
+            &state,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+        )
+        .unwrap();
+
+        {
+            let user = user_account_loader.load().unwrap();
+            let bid = user
+                .orders
+                .iter()
+                .find(|order| order.order_id == 3)
+                .unwrap();
+            assert_eq!(bid.price, 98 * PRICE_PRECISION_U64);
+            assert_eq!(bid.direction, PositionDirection::Long);
+            let ask = user
+                .orders
+                .iter()
+                .find(|order| order.order_id == 4)
+                .unwrap();
+            assert_eq!(ask.price, 102 * PRICE_PRECISION_U64);
+            assert_eq!(ask.direction, PositionDirection::Short);
+            assert_eq!(user.perp_positions[0].open_orders, 2);
+            assert_eq!(user.perp_positions[0].open_bids, BASE_PRECISION_I64);
+            assert_eq!(user.perp_positions[0].open_asks, -BASE_PRECISION_I64);
+        }
+
+        let result = modify_orders(
+            modifications,
+            true,
+            &user_account_loader,
+            &state,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+        );
+        assert_eq!(result, Err(ErrorCode::OrderDoesNotExist));
+    }
+
+    #[test]
+    fn risk_reducing_batch_is_held_to_maintenance_margin() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap,
+                    last_oracle_price_twap_5min: oracle_price.twap,
+                    last_oracle_price: oracle_price.agg.price,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // 80 of collateral covers the 50 maintenance requirement of the long but not the 100 initial
+        let mut user = User {
+            orders: get_orders!(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Short,
+                base_asset_amount: 10 * BASE_PRECISION_U64,
+                price: 110 * PRICE_PRECISION_U64,
+                post_only: true,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: 10 * BASE_PRECISION_I64,
+                quote_asset_amount: -1000 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -1000 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -1000 * QUOTE_PRECISION_I64,
+                open_orders: 1,
+                open_asks: -10 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 80 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            next_order_id: 2,
+            open_orders: 1,
+            has_open_order: true,
+            ..User::default()
+        };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        let state = State::default();
+
+        let reduce_ask = ModifyOrderByIdParams {
+            order_id: ModifyOrderId::UserOrderId(0),
+            modify_order_params: ModifyOrderParams {
+                price: Some(105 * PRICE_PRECISION_U64),
+                policy: Some(ModifyOrderPolicy::MustModify),
+                ..ModifyOrderParams::default()
+            },
+        };
+
+        modify_orders(
+            vec![reduce_ask.clone()],
+            false,
+            &user_account_loader,
+            &state,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+        )
+        .unwrap();
+
+        {
+            let user = user_account_loader.load().unwrap();
+            assert_eq!(user.orders[0].price, 105 * PRICE_PRECISION_U64);
+            assert_eq!(user.perp_positions[0].open_asks, -10 * BASE_PRECISION_I64);
+        }
+
+        // flipping the ask past the position increases risk, so the batch needs initial margin
+        let flip_ask = ModifyOrderByIdParams {
+            order_id: ModifyOrderId::UserOrderId(0),
+            modify_order_params: ModifyOrderParams {
+                base_asset_amount: Some(30 * BASE_PRECISION_U64),
+                ..ModifyOrderParams::default()
+            },
+        };
+
+        let result = modify_orders(
+            vec![reduce_ask, flip_ask],
+            false,
+            &user_account_loader,
+            &state,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+        );
+        assert_eq!(result, Err(ErrorCode::InsufficientCollateral));
+    }
+}
+
+pub mod self_trade_prevention {
+    use std::str::FromStr;
+
+    use crate::controller::orders::{fulfill_perp_order_with_match, get_maker_orders_info};
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION, PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{
+        OrderStatus, OrderType, SelfTradePreventionMode, SpotPosition, User, UserStats,
+    };
+    use crate::state::user_map::UserMap;
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
+
+    use super::*;
+
+    #[test]
+    fn same_authority_match_is_skipped() {
+        let authority = Pubkey::new_unique();
+        let mut taker = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                self_trade_prevention_mode: SelfTradePreventionMode::CancelTaker,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut maker = User {
+            authority,
+            sub_account_id: 1,
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                post_only: true,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut market = PerpMarket::default_test();
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+
+        let (base_asset_amount, _, _) = fulfill_perp_order_with_match(
+            &mut market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut None,
+            0,
+            &maker_key,
+            &mut None,
+            &mut None,
+            &filler_key,
+            &mut None,
+            &mut None,
+            0,
+            None,
+            Some(100 * PRICE_PRECISION_U64),
+            now,
+            slot,
+            &fee_structure,
+            &mut get_oracle_map(),
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 0);
+        assert_eq!(taker.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(maker.perp_positions[0].base_asset_amount, 0);
+
+        // without a mode, only orders of the same user account are kept from matching
+        taker.orders[0].self_trade_prevention_mode = SelfTradePreventionMode::None;
+
+        let (base_asset_amount, _, _) = fulfill_perp_order_with_match(
+            &mut market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut None,
+            0,
+            &maker_key,
+            &mut None,
+            &mut None,
+            &filler_key,
+            &mut None,
+            &mut None,
+            0,
+            None,
+            Some(100 * PRICE_PRECISION_U64),
+            now,
+            slot,
+            &fee_structure,
+            &mut get_oracle_map(),
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64);
+    }
+
+    #[test]
+    fn decrement_and_cancel() {
+        let now = 0_i64;
+        let slot = 1_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                base_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap_5min: (100 * PRICE_PRECISION) as i64,
+
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default_test()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let authority = Pubkey::new_unique();
+        let mut taker = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                self_trade_prevention_mode: SelfTradePreventionMode::DecrementAndCancel,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::default();
+        let mut maker = User {
+            authority,
+            sub_account_id: 1,
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                post_only: true,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64 / 2,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64 / 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(maker, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut filler = User::default();
+
+        let (_, _, filler_key) = get_user_keys();
+        let taker_key = Pubkey::new_unique();
+
+        let maker_orders_info = get_maker_orders_info(
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &makers_and_referrers,
+            &taker_key,
+            &mut taker,
+            0,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            market.amm.historical_oracle_data.last_oracle_price,
+            None,
+            now,
+            slot,
+        )
+        .unwrap();
+
+        // the maker order is never offered to the taker
+        assert_eq!(maker_orders_info, vec![]);
+
+        // taker is decremented by the maker's size and stays open
+        assert_eq!(taker.orders[0].status, OrderStatus::Open);
+        assert_eq!(taker.orders[0].base_asset_amount, BASE_PRECISION_U64 / 2);
+        assert_eq!(taker.perp_positions[0].open_bids, BASE_PRECISION_I64 / 2);
+        assert_eq!(taker.perp_positions[0].base_asset_amount, 0);
+
+        // smaller maker order is canceled
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(maker.orders[0], Order::default());
+        assert_eq!(maker.perp_positions[0].open_asks, 0);
+        assert_eq!(maker.perp_positions[0].open_orders, 0);
+        assert_eq!(maker.perp_positions[0].base_asset_amount, 0);
+    }
+}
+
+pub mod shrink_reduce_only_orders {
+    use crate::controller::orders::shrink_reduce_only_orders;
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{BASE_PRECISION_I64, BASE_PRECISION_U64};
+    use crate::state::user::{
+        MarketType, Order, OrderStatus, OrderTriggerCondition, OrderType, User,
+    };
+    use crate::test_utils::get_positions;
+
+    use super::*;
+
+    #[test]
+    fn shrinks_older_orders() {
+        let mut user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: 3 * BASE_PRECISION_I64 / 2,
+                open_orders: 3,
+                open_asks: -2 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        // take profit orders resting on the book
+        for (order_index, order_id) in [(0, 1), (1, 2)] {
+            user.orders[order_index] = Order {
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                order_id,
+                direction: PositionDirection::Short,
+                reduce_only: true,
+                post_only: true,
+                base_asset_amount: BASE_PRECISION_U64,
+                ..Order::default()
+            };
+        }
+
+        // stop loss that is yet to trigger
+        user.orders[2] = Order {
+            status: OrderStatus::Open,
+            order_type: OrderType::TriggerMarket,
+            trigger_condition: OrderTriggerCondition::Below,
+            order_id: 3,
+            direction: PositionDirection::Short,
+            reduce_only: true,
+            base_asset_amount: BASE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        let order_ids_to_cancel = shrink_reduce_only_orders(
+            &mut user,
+            MarketType::Perp,
+            0,
+            3 * BASE_PRECISION_I64 / 2,
+            BASE_PRECISION_U64 / 10,
+        )
+        .unwrap();
+
+        assert_eq!(order_ids_to_cancel, vec![1]);
+        assert_eq!(user.orders[2].base_asset_amount, BASE_PRECISION_U64);
+        assert_eq!(user.orders[1].base_asset_amount, BASE_PRECISION_U64 / 2);
+        // only triggered orders are in open asks
+        assert_eq!(
+            user.perp_positions[0].open_asks,
+            -3 * BASE_PRECISION_I64 / 2
+        );
+    }
+}
+
+pub mod iceberg {
+    use crate::controller::orders::{fulfill_perp_order_with_match, update_order_after_fill};
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{BASE_PRECISION_I64, BASE_PRECISION_U64, PRICE_PRECISION_U64};
+    use crate::state::perp_market::PerpMarket;
+    use crate::state::user::{OrderStatus, OrderType, User, UserStats};
+    use crate::test_utils::{get_orders, get_positions};
+
+    use super::*;
+
+    #[test]
+    fn replenish_after_fill() {
+        let mut order = Order {
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            base_asset_amount: 5 * BASE_PRECISION_U64 / 2,
+            display_base_asset_amount: BASE_PRECISION_U64,
+            visible_base_asset_amount: BASE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        update_order_after_fill(&mut order, BASE_PRECISION_U64 / 2, 50).unwrap();
+        assert_eq!(order.visible_base_asset_amount, BASE_PRECISION_U64 / 2);
+
+        // tranche filled, replenished from the hidden remainder
+        update_order_after_fill(&mut order, BASE_PRECISION_U64 / 2, 50).unwrap();
+        assert_eq!(order.visible_base_asset_amount, BASE_PRECISION_U64);
+
+        update_order_after_fill(&mut order, BASE_PRECISION_U64, 100).unwrap();
+        assert_eq!(order.visible_base_asset_amount, BASE_PRECISION_U64 / 2);
+
+        update_order_after_fill(&mut order, BASE_PRECISION_U64 / 2, 50).unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.visible_base_asset_amount, 0);
+    }
+
+    #[test]
+    fn match_only_fills_visible_tranche() {
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: 3 * BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: 3 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 0,
+                status: OrderStatus::Open,
+                post_only: true,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: 3 * BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                display_base_asset_amount: BASE_PRECISION_U64,
+                visible_base_asset_amount: BASE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -3 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut market = PerpMarket::default_test();
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_asset_amount, _, _) = fulfill_perp_order_with_match(
+            &mut market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            &mut None,
+            &mut None,
+            &filler_key,
+            &mut None,
+            &mut None,
+            0,
+            None,
+            Some(100 * PRICE_PRECISION_U64),
+            now,
+            slot,
+            &fee_structure,
+            &mut get_oracle_map(),
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64);
+        assert_eq!(maker.orders[0].base_asset_amount_filled, BASE_PRECISION_U64);
+        assert_eq!(
+            maker.orders[0].visible_base_asset_amount,
+            BASE_PRECISION_U64
+        );
+        assert_eq!(
+            maker.perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64
+        );
+        assert_eq!(taker.orders[0].status, OrderStatus::Open);
+    }
+}
@@ -35,7 +35,7 @@ use crate::state::settle_pnl_mode::SettlePnlMode;
 use crate::state::spot_market::{SpotBalance, SpotBalanceType};
 use crate::state::spot_market_map::SpotMarketMap;
 use crate::state::state::State;
-use crate::state::user::{MarketType, User};
+use crate::state::user::{MarketType, SpotPosition, User};
 use crate::validate;
 use anchor_lang::prelude::Pubkey;
 use anchor_lang::prelude::*;
@@ -80,9 +80,36 @@ pub fn settle_pnl(
 
     let position_index = get_position_index(&user.perp_positions, market_index)?;
     let unrealized_pnl = user.perp_positions[position_index].get_unrealized_pnl(oracle_price)?;
+    let is_isolated_position = user.perp_positions[position_index].is_isolated();
 
     // cannot settle negative pnl this way on a user who is in liquidation territory
-    if user.perp_positions[position_index].is_lp() && !user.is_advanced_lp() {
+    if is_isolated_position {
+        // isolated pnl settles against the position's own collateral
+        if unrealized_pnl < 0 {
+            let margin_calc = calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(MarginRequirementType::Maintenance).strict(true),
+            )?;
+
+            if !margin_calc
+                .get_isolated_position_margin_calculation(market_index)?
+                .meets_margin_requirement()
+            {
+                let msg = format!(
+                    "Isolated position does not meet margin requirement while settling Market = {}",
+                    market_index
+                );
+                return mode.result(
+                    ErrorCode::InsufficientCollateralForSettlingPNL,
+                    market_index,
+                    &msg,
+                );
+            }
+        }
+    } else if user.perp_positions[position_index].is_lp() && !user.is_advanced_lp() {
         let margin_calc = calculate_margin_requirement_and_total_collateral_and_liability_info(
             user,
             perp_market_map,
@@ -269,16 +296,38 @@ pub fn settle_pnl(
         0
     };
 
-    let user_unsettled_pnl: i128 =
+    let mut user_unsettled_pnl: i128 =
         user.perp_positions[position_index].get_claimable_pnl(oracle_price, max_pnl_pool_excess)?;
 
-    let pnl_to_settle_with_user = update_pool_balances(
-        perp_market,
-        spot_market,
-        user.get_quote_spot_position(),
-        user_unsettled_pnl,
-        now,
-    )?;
+    let pnl_to_settle_with_user = if is_isolated_position {
+        // isolated collateral can't be borrowed against, so losses settle up to what's deposited
+        let isolated_token_amount =
+            user.perp_positions[position_index].get_isolated_token_amount(spot_market)?;
+        user_unsettled_pnl = user_unsettled_pnl.max(-isolated_token_amount.cast::<i128>()?);
+
+        let isolated_position_balance = SpotPosition {
+            market_index: spot_market.market_index,
+            scaled_balance: user.perp_positions[position_index].isolated_position_scaled_balance,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+
+        update_pool_balances(
+            perp_market,
+            spot_market,
+            &isolated_position_balance,
+            user_unsettled_pnl,
+            now,
+        )?
+    } else {
+        update_pool_balances(
+            perp_market,
+            spot_market,
+            user.get_quote_spot_position(),
+            user_unsettled_pnl,
+            now,
+        )?
+    };
 
     if user_unsettled_pnl == 0 {
         let msg = format!("User has no unsettled pnl for market {}", market_index);
@@ -291,9 +340,15 @@ pub fn settle_pnl(
         return mode.result(ErrorCode::PnlPoolCantSettleUser, market_index, &msg);
     }
 
+    let is_being_liquidated = if is_isolated_position {
+        user.perp_positions[position_index].is_being_liquidated()
+    } else {
+        user.is_being_liquidated()
+    };
+
     let user_must_settle_themself = pnl_to_settle_with_user >= 0
         && max_pnl_pool_excess <= 0
-        && !(pnl_to_settle_with_user > 0 && base_asset_amount == 0 && user.is_being_liquidated())
+        && !(pnl_to_settle_with_user > 0 && base_asset_amount == 0 && is_being_liquidated)
         && !(user.authority.eq(authority) || user.delegate.eq(authority));
 
     if user_must_settle_themself {
@@ -316,7 +371,11 @@ pub fn settle_pnl(
             &SpotBalanceType::Borrow
         },
         spot_market,
-        user.get_quote_spot_position_mut(),
+        if is_isolated_position {
+            &mut user.perp_positions[position_index]
+        } else {
+            user.get_quote_spot_position_mut()
+        },
         false,
     )?;
 
@@ -37,6 +37,7 @@ pub mod delisting_test {
         calculate_perp_position_value_and_pnl, MarginRequirementType,
     };
     use crate::state::events::OrderActionExplanation;
+    use crate::state::fixed_term_pool::FixedTermPoolMap;
     use crate::state::margin_calculation::{MarginCalculation, MarginContext};
     use crate::state::oracle::OracleSource;
     use crate::state::oracle::{HistoricalOracleData, StrictOraclePrice};
@@ -46,6 +47,7 @@ pub mod delisting_test {
     use crate::state::spot_market_map::SpotMarketMap;
     use crate::state::state::{OracleGuardRails, State, ValidityGuardRails};
     use crate::state::user::{OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user_map::UserMap;
     use crate::test_utils::*;
     use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
 
@@ -2401,6 +2403,7 @@ pub mod delisting_test {
                 &market_map,
                 &spot_market_map,
                 &mut oracle_map,
+                &FixedTermPoolMap::empty(),
                 clock.slot,
                 clock.unix_timestamp,
                 &state,
@@ -2481,6 +2484,7 @@ pub mod delisting_test {
                 &market_map,
                 &spot_market_map,
                 &mut oracle_map,
+                &FixedTermPoolMap::empty(),
                 clock.unix_timestamp,
                 clock.slot,
                 10,
@@ -2572,6 +2576,7 @@ pub mod delisting_test {
                 &market_map,
                 &spot_market_map,
                 &mut oracle_map,
+                &FixedTermPoolMap::empty(),
                 clock.unix_timestamp,
                 clock.slot,
                 10,
@@ -2698,10 +2703,12 @@ pub mod delisting_test {
                 &maker_key,
                 &mut liquidator,
                 &liq_key,
+                &UserMap::empty(),
                 &market_map,
                 &spot_market_map,
                 &mut oracle_map,
                 clock.unix_timestamp,
+                clock.slot,
                 0,
             )
             .unwrap();
@@ -0,0 +1,276 @@
+use solana_program::msg;
+use solana_program::pubkey::Pubkey;
+
+use crate::controller::spot_balance::update_spot_market_cumulative_interest;
+use crate::controller::spot_position::transfer_spot_position_deposit;
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
+use crate::math::constants::QUOTE_SPOT_MARKET_INDEX;
+use crate::math::margin::{
+    calculate_margin_requirement_and_total_collateral_and_liability_info,
+    calculate_shared_margin_group_margin_calculation, MarginRequirementType,
+};
+use crate::math::safe_math::SafeMath;
+use crate::math::spot_balance::get_strict_token_value;
+use crate::state::margin_calculation::MarginContext;
+use crate::state::oracle::StrictOraclePrice;
+use crate::state::oracle_map::OracleMap;
+use crate::state::perp_market_map::PerpMarketMap;
+use crate::state::spot_market::SpotBalanceType;
+use crate::state::spot_market_map::SpotMarketMap;
+use crate::state::user::{User, UserStats};
+use crate::state::user_map::UserMap;
+use crate::validate;
+
+#[cfg(test)]
+mod tests;
+
+/// The group must hold every other shared margin sub account of the user's authority, otherwise a
+/// sub account in deficit could be left out of the group calculation
+pub fn validate_shared_margin_group(
+    user: &User,
+    user_key: &Pubkey,
+    user_stats: &UserStats,
+    shared_margin_group: &UserMap,
+) -> DriftResult {
+    validate!(
+        user.is_shared_margin(),
+        ErrorCode::InvalidSharedMarginGroup,
+        "user {} not in shared margin mode",
+        user_key
+    )?;
+
+    validate!(
+        user.authority == user_stats.authority,
+        ErrorCode::InvalidSharedMarginGroup,
+        "user stats authority {} doesnt match user authority {}",
+        user_stats.authority,
+        user.authority
+    )?;
+
+    let mut number_of_sub_accounts = 1_u16;
+    for sub_account_key in shared_margin_group.0.keys() {
+        validate!(
+            sub_account_key != user_key,
+            ErrorCode::InvalidSharedMarginGroup,
+            "user {} passed twice",
+            user_key
+        )?;
+
+        let sub_account = shared_margin_group.get_ref(sub_account_key)?;
+
+        validate!(
+            sub_account.authority == user.authority && sub_account.is_shared_margin(),
+            ErrorCode::InvalidSharedMarginGroup,
+            "sub account {} not in the shared margin group of {}",
+            sub_account_key,
+            user.authority
+        )?;
+
+        number_of_sub_accounts = number_of_sub_accounts.safe_add(1)?;
+    }
+
+    validate!(
+        number_of_sub_accounts == user_stats.number_of_shared_margin_sub_accounts,
+        ErrorCode::InvalidSharedMarginGroup,
+        "shared margin group has {} sub accounts but {} were passed",
+        user_stats.number_of_shared_margin_sub_accounts,
+        number_of_sub_accounts
+    )?;
+
+    Ok(())
+}
+
+/// Moves free quote collateral from the rest of the shared margin group into a sub account that would
+/// otherwise be liquidated. Only happens while the group as a whole is above the liquidation requirement,
+/// and each sub account only gives up collateral it doesn't need for its own initial margin.
+/// Returns true if the sub account no longer needs to be liquidated. The rebalance is best effort, so an
+/// invalid group or any failure falls through to the liquidation
+pub fn rebalance_shared_margin_group(
+    user: &mut User,
+    user_key: &Pubkey,
+    user_stats: &UserStats,
+    shared_margin_group: &UserMap,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    liquidation_margin_buffer_ratio: u32,
+    now: i64,
+) -> DriftResult<bool> {
+    if let Err(error_code) =
+        validate_shared_margin_group(user, user_key, user_stats, shared_margin_group)
+    {
+        msg!(
+            "invalid shared margin group for {}: {:?}",
+            user_key,
+            error_code
+        );
+        return Ok(false);
+    }
+
+    match cover_margin_shortage_from_shared_margin_group(
+        user,
+        user_key,
+        shared_margin_group,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        liquidation_margin_buffer_ratio,
+        now,
+    ) {
+        Ok(can_exit_liquidation) => Ok(can_exit_liquidation),
+        Err(error_code) => {
+            msg!(
+                "shared margin group couldnt cover {} shortage: {:?}",
+                user_key,
+                error_code
+            );
+            Ok(false)
+        }
+    }
+}
+
+fn cover_margin_shortage_from_shared_margin_group(
+    user: &mut User,
+    user_key: &Pubkey,
+    shared_margin_group: &UserMap,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    liquidation_margin_buffer_ratio: u32,
+    now: i64,
+) -> DriftResult<bool> {
+    let context = MarginContext::liquidation(liquidation_margin_buffer_ratio);
+
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+    )?;
+
+    if margin_calculation.can_exit_liquidation()? {
+        if user.is_being_liquidated() {
+            user.exit_liquidation();
+        }
+        return Ok(true);
+    }
+
+    let group_margin_calculation = calculate_shared_margin_group_margin_calculation(
+        user,
+        shared_margin_group,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+    )?;
+
+    if !group_margin_calculation.can_exit_liquidation()? {
+        msg!(
+            "shared margin group total_collateral {} below margin requirement {}",
+            group_margin_calculation.total_collateral,
+            group_margin_calculation.margin_requirement
+        );
+        return Ok(false);
+    }
+
+    update_spot_market_cumulative_interest(
+        &mut *spot_market_map.get_quote_spot_market_mut()?,
+        None,
+        now,
+    )?;
+
+    let (quote_decimals, quote_strict_price) = {
+        let quote_spot_market = spot_market_map.get_quote_spot_market()?;
+        let quote_oracle_price = oracle_map.get_price_data(&quote_spot_market.oracle)?.price;
+        let quote_strict_price = StrictOraclePrice::new(
+            quote_oracle_price,
+            quote_spot_market
+                .historical_oracle_data
+                .last_oracle_price_twap_5min,
+            true,
+        );
+        quote_strict_price.validate()?;
+        (quote_spot_market.decimals, quote_strict_price)
+    };
+
+    let mut margin_shortage = margin_calculation.margin_shortage()?;
+    for sub_account_key in shared_margin_group.0.keys() {
+        if margin_shortage == 0 {
+            break;
+        }
+
+        let mut sub_account = shared_margin_group.get_ref_mut(sub_account_key)?;
+
+        if sub_account.is_being_liquidated() || sub_account.is_bankrupt() {
+            continue;
+        }
+
+        let free_collateral = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &sub_account,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial).strict(true),
+        )?
+        .get_free_collateral()?;
+
+        let mut quote_spot_market = spot_market_map.get_quote_spot_market_mut()?;
+
+        let quote_deposit = match sub_account.get_spot_position(QUOTE_SPOT_MARKET_INDEX) {
+            Ok(spot_position) if spot_position.balance_type == SpotBalanceType::Deposit => {
+                spot_position.get_token_amount(&quote_spot_market)?
+            }
+            _ => 0,
+        };
+
+        // free collateral and the shortage are quote values, the transfer is in quote spot market tokens
+        let free_collateral_token_amount = free_collateral
+            .safe_mul(10_u128.pow(quote_decimals))?
+            .safe_div(quote_strict_price.max().cast()?)?;
+        let margin_shortage_token_amount = margin_shortage
+            .safe_mul(10_u128.pow(quote_decimals))?
+            .safe_div_ceil(quote_strict_price.min().cast()?)?;
+
+        let transfer_amount = margin_shortage_token_amount
+            .min(free_collateral_token_amount)
+            .min(quote_deposit);
+        if transfer_amount == 0 {
+            continue;
+        }
+
+        transfer_spot_position_deposit(
+            transfer_amount.cast()?,
+            &mut quote_spot_market,
+            sub_account.get_quote_spot_position_mut(),
+            user.force_get_spot_position_mut(QUOTE_SPOT_MARKET_INDEX)?,
+        )?;
+
+        msg!(
+            "shared margin sub account {} covered {} of {} shortage",
+            sub_account_key,
+            transfer_amount,
+            user_key
+        );
+
+        let transfer_value =
+            get_strict_token_value(transfer_amount.cast()?, quote_decimals, &quote_strict_price)?;
+        margin_shortage = margin_shortage.saturating_sub(transfer_value.unsigned_abs());
+    }
+
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+    )?;
+
+    let can_exit_liquidation = margin_calculation.can_exit_liquidation()?;
+    if can_exit_liquidation && user.is_being_liquidated() {
+        user.exit_liquidation();
+    }
+
+    Ok(can_exit_liquidation)
+}
@@ -0,0 +1,364 @@
+pub mod rebalance_shared_margin_group {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::controller::shared_margin::rebalance_shared_margin_group;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, PEG_PRECISION, PRICE_PRECISION_I64,
+        QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{PerpPosition, SpotPosition, User, UserStats, UserStatus};
+    use crate::state::user_map::UserMap;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, test_utils::*};
+
+    fn get_user(quote_deposit: u64, base_asset_amount: i64) -> User {
+        User {
+            status: UserStatus::SharedMargin as u8,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount,
+                quote_asset_amount: -100 * base_asset_amount / BASE_PRECISION_I64
+                    * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: quote_deposit * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        }
+    }
+
+    #[test]
+    fn group_covers_shortfall() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 220 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // $1000 long needs $50 maintenance plus a $20 buffer, but only has $20 of collateral
+        let mut user = get_user(20, 10 * BASE_PRECISION_I64);
+        let user_key = Pubkey::new_unique();
+
+        let sub_account_key = Pubkey::new_unique();
+        let mut sub_account = get_user(200, 0);
+        create_anchor_account_info!(sub_account, &sub_account_key, User, sub_account_info);
+        let shared_margin_group = UserMap::load_one(&sub_account_info).unwrap();
+
+        let mut user_stats = UserStats {
+            number_of_shared_margin_sub_accounts: 3,
+            ..UserStats::default()
+        };
+
+        let result = rebalance_shared_margin_group(
+            &mut user,
+            &user_key,
+            &user_stats,
+            &shared_margin_group,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            200,
+            now,
+        );
+        assert_eq!(result, Ok(false));
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            20 * SPOT_BALANCE_PRECISION_U64
+        );
+
+        user_stats.number_of_shared_margin_sub_accounts = 2;
+
+        let rebalanced = rebalance_shared_margin_group(
+            &mut user,
+            &user_key,
+            &user_stats,
+            &shared_margin_group,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            200,
+            now,
+        )
+        .unwrap();
+
+        assert!(rebalanced);
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            70 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            shared_margin_group
+                .get_ref(&sub_account_key)
+                .unwrap()
+                .spot_positions[0]
+                .scaled_balance,
+            150 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            spot_market_map.get_ref(&0).unwrap().deposit_balance,
+            220 * SPOT_BALANCE_PRECISION
+        );
+
+        // already above the liquidation requirement so nothing else moves
+        let rebalanced = rebalance_shared_margin_group(
+            &mut user,
+            &user_key,
+            &user_stats,
+            &shared_margin_group,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            200,
+            now,
+        )
+        .unwrap();
+
+        assert!(rebalanced);
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            70 * SPOT_BALANCE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    fn group_below_requirement() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 40 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = get_user(20, 10 * BASE_PRECISION_I64);
+        let user_key = Pubkey::new_unique();
+
+        // the group only has $40 of collateral against a $70 requirement
+        let sub_account_key = Pubkey::new_unique();
+        let mut sub_account = get_user(20, 0);
+        create_anchor_account_info!(sub_account, &sub_account_key, User, sub_account_info);
+        let shared_margin_group = UserMap::load_one(&sub_account_info).unwrap();
+
+        let user_stats = UserStats {
+            number_of_shared_margin_sub_accounts: 2,
+            ..UserStats::default()
+        };
+
+        let rebalanced = rebalance_shared_margin_group(
+            &mut user,
+            &user_key,
+            &user_stats,
+            &shared_margin_group,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            200,
+            now,
+        )
+        .unwrap();
+
+        assert!(!rebalanced);
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            20 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            shared_margin_group
+                .get_ref(&sub_account_key)
+                .unwrap()
+                .spot_positions[0]
+                .scaled_balance,
+            20 * SPOT_BALANCE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    fn group_errors_dont_block_liquidation() {
+        let now = 0_i64;
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 220 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = get_user(20, 10 * BASE_PRECISION_I64);
+        let user_key = Pubkey::new_unique();
+
+        // the sub account has a position in a perp market that wasn't loaded
+        let sub_account_key = Pubkey::new_unique();
+        let mut sub_account = get_user(200, BASE_PRECISION_I64);
+        sub_account.perp_positions[0].market_index = 1;
+        create_anchor_account_info!(sub_account, &sub_account_key, User, sub_account_info);
+        let shared_margin_group = UserMap::load_one(&sub_account_info).unwrap();
+
+        let user_stats = UserStats {
+            number_of_shared_margin_sub_accounts: 2,
+            ..UserStats::default()
+        };
+
+        let rebalanced = rebalance_shared_margin_group(
+            &mut user,
+            &user_key,
+            &user_stats,
+            &shared_margin_group,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            200,
+            now,
+        )
+        .unwrap();
+
+        assert!(!rebalanced);
+        assert_eq!(
+            user.spot_positions[0].scaled_balance,
+            20 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(
+            shared_margin_group
+                .get_ref(&sub_account_key)
+                .unwrap()
+                .spot_positions[0]
+                .scaled_balance,
+            200 * SPOT_BALANCE_PRECISION_U64
+        );
+    }
+}
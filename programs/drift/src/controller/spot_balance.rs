@@ -9,12 +9,12 @@ use crate::error::{DriftResult, ErrorCode};
 use crate::math::amm::sanitize_new_price;
 use crate::math::casting::Cast;
 use crate::math::constants::{
-    FIVE_MINUTE, IF_FACTOR_PRECISION, ONE_HOUR, QUOTE_SPOT_MARKET_INDEX,
+    FIVE_MINUTE, IF_FACTOR_PRECISION, MARGIN_PRECISION_U128, ONE_HOUR, QUOTE_SPOT_MARKET_INDEX,
     SPOT_MARKET_TOKEN_TWAP_WINDOW,
 };
 use crate::math::spot_balance::{
-    calculate_accumulated_interest, calculate_utilization, get_interest_token_amount,
-    get_spot_balance, get_token_amount, InterestAccumulated,
+    calculate_accumulated_interest, calculate_adaptive_borrow_rate_scale, calculate_utilization,
+    get_interest_token_amount, get_spot_balance, get_token_amount, InterestAccumulated,
 };
 use crate::math::stats::{calculate_new_twap, calculate_weighted_average};
 
@@ -76,6 +76,19 @@ pub fn update_spot_market_twap_stats(
     )?
     .cast()?;
 
+    if spot_market.adaptive_borrow_rate_speed != 0 {
+        spot_market.adaptive_borrow_rate_scale = calculate_adaptive_borrow_rate_scale(
+            spot_market.get_adaptive_borrow_rate_scale(),
+            spot_market.utilization_twap.cast()?,
+            spot_market.optimal_utilization.cast()?,
+            spot_market.adaptive_borrow_rate_speed,
+            spot_market.adaptive_borrow_rate_scale_floor,
+            spot_market.adaptive_borrow_rate_scale_ceiling,
+            since_last.cast()?,
+        )?
+        .cast()?;
+    }
+
     if let Some(oracle_price_data) = oracle_price_data {
         let sanitize_clamp_denominator = spot_market.get_sanitize_clamp_denominator()?;
 
@@ -103,6 +116,33 @@ pub fn update_spot_market_twap_stats(
             FIVE_MINUTE as i64,
         )?;
 
+        if oracle_price_twap > 0 {
+            let since_last_oracle_update = max(
+                0_i64,
+                now.safe_sub(spot_market.historical_oracle_data.last_oracle_price_twap_ts)?,
+            );
+            let from_start = max(1_i64, ONE_HOUR.safe_sub(since_last_oracle_update)?);
+
+            let oracle_price_deviation = capped_oracle_update_price
+                .safe_sub(oracle_price_twap)?
+                .unsigned_abs()
+                .cast::<u128>()?
+                .safe_mul(MARGIN_PRECISION_U128)?
+                .safe_div(oracle_price_twap.cast()?)?
+                .min(u16::MAX.into());
+
+            spot_market.oracle_realized_volatility = oracle_price_deviation
+                .safe_mul(since_last_oracle_update.cast()?)?
+                .safe_add(
+                    spot_market
+                        .oracle_realized_volatility
+                        .cast::<u128>()?
+                        .safe_mul(from_start.cast()?)?,
+                )?
+                .safe_div(since_last_oracle_update.safe_add(from_start)?.cast()?)?
+                .cast()?;
+        }
+
         spot_market.historical_oracle_data.last_oracle_price_twap = oracle_price_twap;
         spot_market
             .historical_oracle_data
@@ -132,6 +172,9 @@ pub fn update_spot_market_cumulative_interest(
     let InterestAccumulated {
         deposit_interest,
         borrow_interest,
+        borrow_rate,
+        deposit_rate,
+        utilization,
     } = calculate_accumulated_interest(spot_market, now)?;
 
     if deposit_interest > 0 && borrow_interest > 1 {
@@ -140,8 +183,14 @@ pub fn update_spot_market_cumulative_interest(
             .safe_mul(spot_market.insurance_fund.total_factor as u128)?
             .safe_div(IF_FACTOR_PRECISION)?;
 
-        let deposit_interest_for_lenders =
-            deposit_interest.safe_sub(deposit_interest_for_stakers)?;
+        // protocol reserve share of the lending spread, also paid into the revenue_pool
+        let deposit_interest_for_reserve = deposit_interest
+            .safe_mul(spot_market.reserve_factor.cast()?)?
+            .safe_div(IF_FACTOR_PRECISION)?;
+
+        let deposit_interest_for_lenders = deposit_interest
+            .safe_sub(deposit_interest_for_stakers)?
+            .safe_sub(deposit_interest_for_reserve)?;
 
         if deposit_interest_for_lenders > 0 {
             spot_market.cumulative_deposit_interest = spot_market
@@ -160,7 +209,21 @@ pub fn update_spot_market_cumulative_interest(
                 deposit_interest_for_stakers,
             )?;
 
-            update_revenue_pool_balances(token_amount, &SpotBalanceType::Deposit, spot_market)?;
+            let reserve_amount = get_interest_token_amount(
+                spot_market.deposit_balance,
+                spot_market,
+                deposit_interest_for_reserve,
+            )?;
+
+            update_revenue_pool_balances(
+                token_amount.safe_add(reserve_amount)?,
+                &SpotBalanceType::Deposit,
+                spot_market,
+            )?;
+
+            let lender_deposit_rate = deposit_rate
+                .safe_mul(deposit_interest_for_lenders)?
+                .safe_div(deposit_interest)?;
 
             emit!(SpotInterestRecord {
                 ts: now,
@@ -172,6 +235,10 @@ pub fn update_spot_market_cumulative_interest(
                 optimal_utilization: spot_market.optimal_utilization,
                 optimal_borrow_rate: spot_market.optimal_borrow_rate,
                 max_borrow_rate: spot_market.max_borrow_rate,
+                borrow_rate: borrow_rate.cast()?,
+                deposit_rate: lender_deposit_rate.cast()?,
+                reserve_amount: reserve_amount.cast()?,
+                utilization: utilization.cast()?,
             });
         }
     }
@@ -10,14 +10,16 @@ use crate::create_account_info;
 use crate::create_anchor_account_info;
 use crate::math::constants::{
     AMM_RESERVE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_I64, LIQUIDATION_FEE_PRECISION,
-    PEG_PRECISION, PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION, QUOTE_PRECISION_I128,
-    QUOTE_PRECISION_I64, QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-    SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_RATE_PRECISION_U32, SPOT_UTILIZATION_PRECISION,
-    SPOT_UTILIZATION_PRECISION_U32, SPOT_WEIGHT_PRECISION,
+    ONE_YEAR, PEG_PRECISION, PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION,
+    QUOTE_PRECISION_I128, QUOTE_PRECISION_I64, QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+    SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_RATE_PRECISION_U32,
+    SPOT_UTILIZATION_PRECISION, SPOT_UTILIZATION_PRECISION_U32, SPOT_WEIGHT_PRECISION,
+    TWENTY_FOUR_HOUR,
 };
 use crate::math::margin::{
     calculate_margin_requirement_and_total_collateral_and_liability_info, MarginRequirementType,
 };
+use crate::math::spot_balance::calculate_borrow_rate;
 use crate::math::spot_withdraw::{
     calculate_max_borrow_token_amount, calculate_min_deposit_token_amount,
     calculate_token_utilization_limits, check_withdraw_limits,
@@ -28,11 +30,12 @@ use crate::state::oracle::{HistoricalOracleData, OracleSource};
 use crate::state::oracle_map::OracleMap;
 use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
 use crate::state::perp_market_map::PerpMarketMap;
-use crate::state::spot_market::{InsuranceFund, SpotBalanceType, SpotMarket};
+use crate::state::spot_market::{BorrowRateKink, InsuranceFund, SpotBalanceType, SpotMarket};
 use crate::state::spot_market_map::SpotMarketMap;
 use crate::state::user::{Order, PerpPosition, SpotPosition, User};
 use crate::test_utils::*;
 use crate::test_utils::{get_pyth_price, get_spot_positions};
+use crate::validation::spot_market::validate_borrow_rate_kinks;
 
 pub fn check_perp_market_valid(
     perp_market: &PerpMarket,
@@ -1671,3 +1674,220 @@ fn check_usdc_spot_market_twap() {
         966501
     );
 }
+
+#[test]
+fn check_oracle_realized_volatility() {
+    let mut now = 0_i64;
+
+    let mut spot_market = SpotMarket {
+        market_index: 1,
+        oracle_source: OracleSource::Pyth,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 9,
+        historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+        status: MarketStatus::Active,
+        ..SpotMarket::default()
+    };
+
+    // price swinging 5% around the twap every minute for a few hours
+    for i in 0..180 {
+        now += 60;
+        let price = if i % 2 == 0 { 105 } else { 95 };
+        let oracle_price_data = OraclePriceData {
+            price: price * PRICE_PRECISION_I64,
+            confidence: 1,
+            delay: 0,
+            has_sufficient_number_of_data_points: true,
+        };
+        update_spot_market_twap_stats(&mut spot_market, Some(&oracle_price_data), now).unwrap();
+    }
+
+    // ~5% in MARGIN_PRECISION, even though the 5 minute and 1 hour twaps barely diverge
+    assert!(spot_market.oracle_realized_volatility > 400);
+    assert!(spot_market.oracle_realized_volatility < 550);
+
+    // decays once the price settles
+    let oracle_price_data = OraclePriceData {
+        price: 100 * PRICE_PRECISION_I64,
+        confidence: 1,
+        delay: 0,
+        has_sufficient_number_of_data_points: true,
+    };
+    for _ in 0..180 {
+        now += 60;
+        update_spot_market_twap_stats(&mut spot_market, Some(&oracle_price_data), now).unwrap();
+    }
+
+    assert!(spot_market.oracle_realized_volatility < 50);
+}
+
+#[test]
+fn check_multi_kink_borrow_rate() {
+    let mut spot_market = SpotMarket {
+        optimal_utilization: SPOT_UTILIZATION_PRECISION_U32 * 8 / 10, // 80%
+        optimal_borrow_rate: SPOT_RATE_PRECISION_U32 / 10,            // 10%
+        max_borrow_rate: SPOT_RATE_PRECISION_U32,                     // 100%
+        ..SpotMarket::default()
+    };
+
+    // single kink
+    assert_eq!(calculate_borrow_rate(&spot_market, 400000).unwrap(), 50000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 900000).unwrap(), 550000);
+
+    spot_market.borrow_rate_kinks = [
+        BorrowRateKink {
+            utilization: SPOT_UTILIZATION_PRECISION_U32 / 2, // 50%
+            borrow_rate: SPOT_RATE_PRECISION_U32 / 25,       // 4%
+        },
+        BorrowRateKink {
+            utilization: SPOT_UTILIZATION_PRECISION_U32 * 9 / 10, // 90%
+            borrow_rate: SPOT_RATE_PRECISION_U32 * 3 / 10,        // 30%
+        },
+        BorrowRateKink::default(),
+    ];
+    validate_borrow_rate_kinks(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &spot_market.borrow_rate_kinks,
+    )
+    .unwrap();
+
+    assert_eq!(calculate_borrow_rate(&spot_market, 250000).unwrap(), 20000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 500000).unwrap(), 40000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 650000).unwrap(), 70000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 800000).unwrap(), 100000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 850000).unwrap(), 200000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 950000).unwrap(), 650000);
+    assert_eq!(
+        calculate_borrow_rate(&spot_market, SPOT_UTILIZATION_PRECISION).unwrap(),
+        1000000
+    );
+
+    // kinks must be sorted by utilization with unused kinks last
+    let mut unsorted_borrow_rate_kinks = spot_market.borrow_rate_kinks;
+    unsorted_borrow_rate_kinks.swap(0, 1);
+    assert!(validate_borrow_rate_kinks(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &unsorted_borrow_rate_kinks,
+    )
+    .is_err());
+
+    unsorted_borrow_rate_kinks = spot_market.borrow_rate_kinks;
+    unsorted_borrow_rate_kinks.swap(1, 2);
+    assert!(validate_borrow_rate_kinks(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &unsorted_borrow_rate_kinks,
+    )
+    .is_err());
+
+    // rates must not fall as utilization rises
+    spot_market.borrow_rate_kinks[0].borrow_rate = SPOT_RATE_PRECISION_U32 / 5;
+    assert!(validate_borrow_rate_kinks(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &spot_market.borrow_rate_kinks,
+    )
+    .is_err());
+}
+
+#[test]
+fn check_adaptive_borrow_rate_scale() {
+    let mut now = TWENTY_FOUR_HOUR / 2;
+
+    let mut spot_market = SpotMarket {
+        market_index: 1,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 6,
+        deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+        borrow_balance: 95 * SPOT_BALANCE_PRECISION,
+        utilization_twap: 950000,
+        optimal_utilization: SPOT_UTILIZATION_PRECISION_U32 * 8 / 10, // 80%
+        optimal_borrow_rate: SPOT_RATE_PRECISION_U32 / 10,            // 10%
+        max_borrow_rate: SPOT_RATE_PRECISION_U32,                     // 100%
+        adaptive_borrow_rate_speed: 10000,                            // 100% per day
+        adaptive_borrow_rate_scale_floor: 5000,                       // .5x
+        adaptive_borrow_rate_scale_ceiling: 30000,                    // 3x
+        status: MarketStatus::Active,
+        ..SpotMarket::default()
+    };
+
+    assert_eq!(calculate_borrow_rate(&spot_market, 950000).unwrap(), 775000);
+
+    // utilization_twap 3/4 of the way from optimal to max for half a day
+    update_spot_market_twap_stats(&mut spot_market, None, now).unwrap();
+    assert_eq!(spot_market.utilization_twap, 950000);
+    assert_eq!(spot_market.adaptive_borrow_rate_scale, 1375000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 950000).unwrap(), 784375);
+    assert_eq!(
+        calculate_borrow_rate(&spot_market, SPOT_UTILIZATION_PRECISION).unwrap(),
+        1000000
+    );
+
+    now += 10 * TWENTY_FOUR_HOUR;
+    update_spot_market_twap_stats(&mut spot_market, None, now).unwrap();
+    assert_eq!(spot_market.adaptive_borrow_rate_scale, 3000000);
+
+    // borrows repaid, scale drifts back down
+    spot_market.borrow_balance = 0;
+    now += 10 * TWENTY_FOUR_HOUR;
+    update_spot_market_twap_stats(&mut spot_market, None, now).unwrap();
+    assert_eq!(spot_market.adaptive_borrow_rate_scale, 500000);
+    assert_eq!(calculate_borrow_rate(&spot_market, 400000).unwrap(), 25000);
+}
+
+#[test]
+fn check_reserve_factor() {
+    let mut spot_market = SpotMarket {
+        market_index: 0,
+        oracle_source: OracleSource::QuoteAsset,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 6,
+        deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+        borrow_balance: 50 * SPOT_BALANCE_PRECISION,
+        optimal_utilization: SPOT_UTILIZATION_PRECISION_U32 * 8 / 10, // 80%
+        optimal_borrow_rate: SPOT_RATE_PRECISION_U32 / 10,            // 10%
+        max_borrow_rate: SPOT_RATE_PRECISION_U32,                     // 100%
+        reserve_factor: 100000,                                       // 10%
+        status: MarketStatus::Active,
+        ..SpotMarket::default()
+    };
+
+    // 6.25% borrow rate at 50% utilization for a year
+    update_spot_market_cumulative_interest(&mut spot_market, None, ONE_YEAR as i64).unwrap();
+
+    assert_eq!(spot_market.cumulative_borrow_interest, 10625000001);
+    assert_eq!(spot_market.cumulative_deposit_interest, 10281250000);
+
+    // 10% of the $3.125 borrowers paid goes to the revenue pool
+    let revenue_pool_token_amount = get_token_amount(
+        spot_market.revenue_pool.scaled_balance,
+        &spot_market,
+        &SpotBalanceType::Deposit,
+    )
+    .unwrap();
+    assert_eq!(revenue_pool_token_amount, 312499);
+
+    let deposit_token_amount = get_token_amount(
+        spot_market.deposit_balance,
+        &spot_market,
+        &SpotBalanceType::Deposit,
+    )
+    .unwrap();
+    let borrow_token_amount = get_token_amount(
+        spot_market.borrow_balance,
+        &spot_market,
+        &SpotBalanceType::Borrow,
+    )
+    .unwrap();
+    // lenders and the revenue pool never receive more than borrowers paid
+    assert_eq!(deposit_token_amount - borrow_token_amount, 49999998);
+}
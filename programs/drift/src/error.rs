@@ -539,6 +539,54 @@ pub enum ErrorCode {
     OracleInsufficientDataPoints,
     #[msg("OracleStaleForAMM")]
     OracleStaleForAMM,
+    #[msg("InvalidOrderTrailingOffset")]
+    InvalidOrderTrailingOffset,
+    #[msg("UserCantBeMigrated")]
+    UserCantBeMigrated,
+    #[msg("InvalidOrderLinkGroup")]
+    InvalidOrderLinkGroup,
+    #[msg("BracketChildOrderNotActive")]
+    BracketChildOrderNotActive,
+    #[msg("InvalidOrderTwap")]
+    InvalidOrderTwap,
+    #[msg("InvalidOrderMinFill")]
+    InvalidOrderMinFill,
+    #[msg("FillOrKillOrderNotFilled")]
+    FillOrKillOrderNotFilled,
+    #[msg("InvalidScaleOrderParams")]
+    InvalidScaleOrderParams,
+    #[msg("InvalidOrderTimeWindow")]
+    InvalidOrderTimeWindow,
+    #[msg("OrderDormant")]
+    OrderDormant,
+    #[msg("InvalidOrderIceberg")]
+    InvalidOrderIceberg,
+    #[msg("InvalidIsolatedPerpPosition")]
+    InvalidIsolatedPerpPosition,
+    #[msg("IsolatedPerpPositionBeingLiquidated")]
+    IsolatedPerpPositionBeingLiquidated,
+    #[msg("MaxPositionSize")]
+    MaxPositionSize,
+    #[msg("InvalidSharedMarginGroup")]
+    InvalidSharedMarginGroup,
+    #[msg("InvalidAutoDeleverageUser")]
+    InvalidAutoDeleverageUser,
+    #[msg("InvalidFixedTermOrder")]
+    InvalidFixedTermOrder,
+    #[msg("NoFixedTermPositionAvailable")]
+    NoFixedTermPositionAvailable,
+    #[msg("FixedTermOrdersDontCross")]
+    FixedTermOrdersDontCross,
+    #[msg("FixedTermPositionCantSettle")]
+    FixedTermPositionCantSettle,
+    #[msg("MaxNumberOfOrderModifications")]
+    MaxNumberOfOrderModifications,
+    #[msg("InvalidMaxPositionSize")]
+    InvalidMaxPositionSize,
+    #[msg("InvalidFundingInterestRate")]
+    InvalidFundingInterestRate,
+    #[msg("InvalidFixedTermPool")]
+    InvalidFixedTermPool,
 }
 
 #[macro_export]
@@ -15,10 +15,10 @@ use crate::math::casting::Cast;
 use crate::math::constants::{
     DEFAULT_LIQUIDATION_MARGIN_BUFFER_RATIO, FEE_POOL_TO_REVENUE_POOL_THRESHOLD,
     IF_FACTOR_PRECISION, INSURANCE_A_MAX, INSURANCE_B_MAX, INSURANCE_C_MAX,
-    INSURANCE_SPECULATIVE_MAX, LIQUIDATION_FEE_PRECISION, MAX_CONCENTRATION_COEFFICIENT,
-    MAX_SQRT_K, MAX_UPDATE_K_PRICE_CHANGE, QUOTE_SPOT_MARKET_INDEX,
-    SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_IMF_PRECISION, SPOT_WEIGHT_PRECISION, THIRTEEN_DAY,
-    TWENTY_FOUR_HOUR,
+    INSURANCE_SPECULATIVE_MAX, LIQUIDATION_FEE_PRECISION, MARGIN_PRECISION,
+    MAX_CONCENTRATION_COEFFICIENT, MAX_SQRT_K, MAX_UPDATE_K_PRICE_CHANGE, PERCENTAGE_PRECISION_U64,
+    QUOTE_SPOT_MARKET_INDEX, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_IMF_PRECISION,
+    SPOT_WEIGHT_PRECISION, THIRTEEN_DAY, TWENTY_FOUR_HOUR,
 };
 use crate::math::cp_curve::get_update_k_result;
 use crate::math::orders::is_multiple_of_step_size;
@@ -43,16 +43,22 @@ use crate::state::perp_market::{
     ContractTier, ContractType, InsuranceClaim, MarketStatus, PerpMarket, PoolBalance, AMM,
 };
 use crate::state::spot_market::{
-    AssetTier, InsuranceFund, SpotBalanceType, SpotFulfillmentConfigStatus, SpotMarket,
+    AssetTier, BorrowRateKink, InsuranceFund, SpotBalanceType, SpotFulfillmentConfigStatus,
+    SpotMarket,
 };
 use crate::state::state::{ExchangeStatus, FeeStructure, OracleGuardRails, State};
 use crate::state::traits::Size;
 use crate::state::user::UserStats;
 use crate::validate;
 use crate::validation::fee_structure::validate_fee_structure;
-use crate::validation::margin::{validate_margin, validate_margin_weights};
+use crate::validation::margin::{
+    validate_margin, validate_margin_weights, validate_volatility_margin_scale,
+    validate_volatility_margin_scale_floor,
+};
 use crate::validation::perp_market::validate_perp_market;
-use crate::validation::spot_market::validate_borrow_rate;
+use crate::validation::spot_market::{
+    validate_adaptive_borrow_rate, validate_borrow_rate, validate_borrow_rate_kinks,
+};
 use crate::{controller, QUOTE_PRECISION_I64};
 use crate::{get_then_update_id, EPOCH_DURATION};
 use crate::{load, FEE_ADJUSTMENT_MAX};
@@ -278,7 +284,17 @@ pub fn handle_initialize_spot_market(
         flash_loan_initial_token_amount: 0,
         total_swap_fee: 0,
         scale_initial_asset_weight_start,
-        padding: [0; 48],
+        volatility_margin_reference: 0,
+        volatility_margin_scale_floor: 0,
+        volatility_margin_scale_ceiling: 0,
+        adaptive_borrow_rate_speed: 0,
+        adaptive_borrow_rate_scale_floor: 0,
+        adaptive_borrow_rate_scale_ceiling: 0,
+        adaptive_borrow_rate_scale: 0,
+        borrow_rate_kinks: [BorrowRateKink::default(); 3],
+        reserve_factor: 0,
+        oracle_realized_volatility: 0,
+        padding: [0; 2],
         insurance_fund: InsuranceFund {
             vault: *ctx.accounts.insurance_fund_vault.to_account_info().key,
             unstaking_period: THIRTEEN_DAY,
@@ -679,7 +695,16 @@ pub fn handle_initialize_perp_market(
         paused_operations: 0,
         quote_spot_market_index: QUOTE_SPOT_MARKET_INDEX,
         fee_adjustment: 0,
-        padding: [0; 46],
+        volatility_margin_reference: 0,
+        volatility_margin_scale_floor: 0,
+        volatility_margin_scale_ceiling: 0,
+        max_position_base_asset_amount: 0,
+        max_position_notional: 0,
+        max_position_open_interest_fraction: 0,
+        liquidation_auction_end_liquidator_fee: 0,
+        liquidation_auction_min_notional: 0,
+        max_funding_price_divergence: 0,
+        max_hourly_funding_rate: 0,
         amm: AMM {
             oracle: *ctx.accounts.oracle.key,
             oracle_source,
@@ -769,12 +794,13 @@ pub fn handle_initialize_perp_market(
             target_base_asset_amount_per_lp: 0,
             per_lp_base: 0,
             padding1: 0,
-            padding2: 0,
+            funding_premium_clamp: 0,
             total_fee_earned_per_lp: 0,
             net_unsettled_funding_pnl: 0,
             quote_asset_amount_with_unsettled_lp: 0,
             reference_price_offset: 0,
-            padding: [0; 12],
+            funding_interest_rate: 0,
+            last_premium_index: 0,
         },
     };
 
@@ -1799,10 +1825,18 @@ pub fn handle_update_perp_market_margin_ratio(
     validate_margin(
         margin_ratio_initial,
         margin_ratio_maintenance,
-        perp_market.liquidator_fee,
+        perp_market.get_max_liquidator_fee(),
         perp_market.amm.max_spread,
     )?;
 
+    validate_volatility_margin_scale_floor(
+        margin_ratio_maintenance,
+        perp_market.volatility_margin_reference,
+        perp_market.volatility_margin_scale_floor,
+        perp_market.get_max_liquidator_fee(),
+        perp_market.if_liquidation_fee,
+    )?;
+
     msg!(
         "perp_market.margin_ratio_initial: {:?} -> {:?}",
         perp_market.margin_ratio_initial,
@@ -1820,6 +1854,55 @@ pub fn handle_update_perp_market_margin_ratio(
     Ok(())
 }
 
+#[access_control(
+    perp_market_valid(&ctx.accounts.perp_market)
+)]
+pub fn handle_update_perp_market_volatility_margin_scale(
+    ctx: Context<AdminUpdatePerpMarket>,
+    volatility_margin_reference: u16,
+    volatility_margin_scale_floor: u16,
+    volatility_margin_scale_ceiling: u16,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    validate_volatility_margin_scale(
+        volatility_margin_reference,
+        volatility_margin_scale_floor,
+        volatility_margin_scale_ceiling,
+    )?;
+
+    validate_volatility_margin_scale_floor(
+        perp_market.margin_ratio_maintenance,
+        volatility_margin_reference,
+        volatility_margin_scale_floor,
+        perp_market.get_max_liquidator_fee(),
+        perp_market.if_liquidation_fee,
+    )?;
+
+    msg!(
+        "perp_market.volatility_margin_reference: {:?} -> {:?}",
+        perp_market.volatility_margin_reference,
+        volatility_margin_reference
+    );
+
+    msg!(
+        "perp_market.volatility_margin_scale_floor: {:?} -> {:?}",
+        perp_market.volatility_margin_scale_floor,
+        volatility_margin_scale_floor
+    );
+
+    msg!(
+        "perp_market.volatility_margin_scale_ceiling: {:?} -> {:?}",
+        perp_market.volatility_margin_scale_ceiling,
+        volatility_margin_scale_ceiling
+    );
+
+    perp_market.volatility_margin_reference = volatility_margin_reference;
+    perp_market.volatility_margin_scale_floor = volatility_margin_scale_floor;
+    perp_market.volatility_margin_scale_ceiling = volatility_margin_scale_ceiling;
+    Ok(())
+}
+
 #[access_control(
     perp_market_valid(&ctx.accounts.perp_market)
 )]
@@ -1960,6 +2043,20 @@ pub fn handle_update_perp_liquidation_fee(
         perp_market.amm.max_spread,
     )?;
 
+    let max_liquidator_fee = if perp_market.liquidation_auction_min_notional != 0 {
+        liquidator_fee.max(perp_market.liquidation_auction_end_liquidator_fee)
+    } else {
+        liquidator_fee
+    };
+
+    validate_volatility_margin_scale_floor(
+        perp_market.margin_ratio_maintenance,
+        perp_market.volatility_margin_reference,
+        perp_market.volatility_margin_scale_floor,
+        max_liquidator_fee,
+        if_liquidation_fee,
+    )?;
+
     msg!(
         "perp_market.liquidator_fee: {:?} -> {:?}",
         perp_market.liquidator_fee,
@@ -2080,6 +2177,12 @@ pub fn handle_update_spot_market_if_factor(
         "total_if_factor must be <= 100%"
     )?;
 
+    validate!(
+        total_if_factor.safe_add(spot_market.reserve_factor)? <= IF_FACTOR_PRECISION.cast()?,
+        ErrorCode::DefaultError,
+        "total_if_factor + reserve_factor must be <= 100%"
+    )?;
+
     msg!(
         "spot_market.user_if_factor: {:?} -> {:?}",
         spot_market.insurance_fund.user_factor,
@@ -2097,6 +2200,33 @@ pub fn handle_update_spot_market_if_factor(
     Ok(())
 }
 
+#[access_control(
+    spot_market_valid(&ctx.accounts.spot_market)
+)]
+pub fn handle_update_spot_market_reserve_factor(
+    ctx: Context<AdminUpdateSpotMarket>,
+    reserve_factor: u32,
+) -> Result<()> {
+    let spot_market = &mut load_mut!(ctx.accounts.spot_market)?;
+
+    validate!(
+        reserve_factor.safe_add(spot_market.insurance_fund.total_factor)?
+            <= IF_FACTOR_PRECISION.cast()?,
+        ErrorCode::DefaultError,
+        "reserve_factor + total_if_factor must be <= 100%"
+    )?;
+
+    msg!(
+        "spot_market.reserve_factor: {:?} -> {:?}",
+        spot_market.reserve_factor,
+        reserve_factor
+    );
+
+    spot_market.reserve_factor = reserve_factor;
+
+    Ok(())
+}
+
 #[access_control(
     spot_market_valid(&ctx.accounts.spot_market)
 )]
@@ -2177,6 +2307,53 @@ pub fn handle_update_spot_market_asset_tier(
     Ok(())
 }
 
+#[access_control(
+    spot_market_valid(&ctx.accounts.spot_market)
+)]
+pub fn handle_update_spot_market_volatility_margin_scale(
+    ctx: Context<AdminUpdateSpotMarket>,
+    volatility_margin_reference: u16,
+    volatility_margin_scale_floor: u16,
+    volatility_margin_scale_ceiling: u16,
+) -> Result<()> {
+    let spot_market = &mut load_mut!(ctx.accounts.spot_market)?;
+
+    validate!(
+        spot_market.market_index != QUOTE_SPOT_MARKET_INDEX,
+        ErrorCode::InvalidSpotMarketAccount,
+        "quote spot market weights cant scale with volatility"
+    )?;
+
+    validate_volatility_margin_scale(
+        volatility_margin_reference,
+        volatility_margin_scale_floor,
+        volatility_margin_scale_ceiling,
+    )?;
+
+    msg!(
+        "spot_market.volatility_margin_reference: {:?} -> {:?}",
+        spot_market.volatility_margin_reference,
+        volatility_margin_reference
+    );
+
+    msg!(
+        "spot_market.volatility_margin_scale_floor: {:?} -> {:?}",
+        spot_market.volatility_margin_scale_floor,
+        volatility_margin_scale_floor
+    );
+
+    msg!(
+        "spot_market.volatility_margin_scale_ceiling: {:?} -> {:?}",
+        spot_market.volatility_margin_scale_ceiling,
+        volatility_margin_scale_ceiling
+    );
+
+    spot_market.volatility_margin_reference = volatility_margin_reference;
+    spot_market.volatility_margin_scale_floor = volatility_margin_scale_floor;
+    spot_market.volatility_margin_scale_ceiling = volatility_margin_scale_ceiling;
+    Ok(())
+}
+
 #[access_control(
     spot_market_valid(&ctx.accounts.spot_market)
 )]
@@ -2249,6 +2426,12 @@ pub fn handle_update_spot_market_borrow_rate(
 ) -> Result<()> {
     let spot_market = &mut load_mut!(ctx.accounts.spot_market)?;
     validate_borrow_rate(optimal_utilization, optimal_borrow_rate, max_borrow_rate)?;
+    validate_borrow_rate_kinks(
+        optimal_utilization,
+        optimal_borrow_rate,
+        max_borrow_rate,
+        &spot_market.borrow_rate_kinks,
+    )?;
 
     msg!(
         "spot_market.optimal_utilization: {:?} -> {:?}",
@@ -2274,6 +2457,93 @@ pub fn handle_update_spot_market_borrow_rate(
     Ok(())
 }
 
+#[access_control(
+    spot_market_valid(&ctx.accounts.spot_market)
+)]
+pub fn handle_update_spot_market_borrow_rate_kinks(
+    ctx: Context<AdminUpdateSpotMarket>,
+    borrow_rate_kinks: [BorrowRateKink; 3],
+) -> Result<()> {
+    let spot_market = &mut load_mut!(ctx.accounts.spot_market)?;
+    validate_borrow_rate_kinks(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &borrow_rate_kinks,
+    )?;
+
+    msg!(
+        "spot_market.borrow_rate_kinks: {:?} -> {:?}",
+        spot_market.borrow_rate_kinks,
+        borrow_rate_kinks
+    );
+
+    spot_market.borrow_rate_kinks = borrow_rate_kinks;
+    Ok(())
+}
+
+#[access_control(
+    spot_market_valid(&ctx.accounts.spot_market)
+)]
+pub fn handle_update_spot_market_adaptive_borrow_rate(
+    ctx: Context<AdminUpdateSpotMarket>,
+    adaptive_borrow_rate_speed: u16,
+    adaptive_borrow_rate_scale_floor: u16,
+    adaptive_borrow_rate_scale_ceiling: u16,
+) -> Result<()> {
+    let spot_market = &mut load_mut!(ctx.accounts.spot_market)?;
+    validate_adaptive_borrow_rate(
+        adaptive_borrow_rate_speed,
+        adaptive_borrow_rate_scale_floor,
+        adaptive_borrow_rate_scale_ceiling,
+    )?;
+
+    msg!(
+        "spot_market.adaptive_borrow_rate_speed: {:?} -> {:?}",
+        spot_market.adaptive_borrow_rate_speed,
+        adaptive_borrow_rate_speed
+    );
+
+    msg!(
+        "spot_market.adaptive_borrow_rate_scale_floor: {:?} -> {:?}",
+        spot_market.adaptive_borrow_rate_scale_floor,
+        adaptive_borrow_rate_scale_floor
+    );
+
+    msg!(
+        "spot_market.adaptive_borrow_rate_scale_ceiling: {:?} -> {:?}",
+        spot_market.adaptive_borrow_rate_scale_ceiling,
+        adaptive_borrow_rate_scale_ceiling
+    );
+
+    spot_market.adaptive_borrow_rate_speed = adaptive_borrow_rate_speed;
+    spot_market.adaptive_borrow_rate_scale_floor = adaptive_borrow_rate_scale_floor;
+    spot_market.adaptive_borrow_rate_scale_ceiling = adaptive_borrow_rate_scale_ceiling;
+
+    // disabling resets the curve, otherwise keep the current scale within the new bounds
+    let adaptive_borrow_rate_scale = if adaptive_borrow_rate_speed == 0 {
+        0
+    } else {
+        let margin_to_percentage_precision =
+            PERCENTAGE_PRECISION_U64.safe_div(MARGIN_PRECISION.cast()?)?;
+        spot_market
+            .get_adaptive_borrow_rate_scale()
+            .cast::<u64>()?
+            .max(u64::from(adaptive_borrow_rate_scale_floor) * margin_to_percentage_precision)
+            .min(u64::from(adaptive_borrow_rate_scale_ceiling) * margin_to_percentage_precision)
+            .cast::<u32>()?
+    };
+
+    msg!(
+        "spot_market.adaptive_borrow_rate_scale: {:?} -> {:?}",
+        spot_market.adaptive_borrow_rate_scale,
+        adaptive_borrow_rate_scale
+    );
+
+    spot_market.adaptive_borrow_rate_scale = adaptive_borrow_rate_scale;
+    Ok(())
+}
+
 #[access_control(
     spot_market_valid(&ctx.accounts.spot_market)
 )]
@@ -2538,6 +2808,78 @@ pub fn handle_update_perp_market_curve_update_intensity(
     Ok(())
 }
 
+#[access_control(
+    perp_market_valid(&ctx.accounts.perp_market)
+)]
+pub fn handle_update_perp_market_funding_interest_rate(
+    ctx: Context<AdminUpdatePerpMarket>,
+    funding_interest_rate: i32,
+    funding_premium_clamp: u16,
+) -> Result<()> {
+    // funding only moves towards the interest rate within the premium clamp
+    validate!(
+        funding_interest_rate == 0 || funding_premium_clamp > 0,
+        ErrorCode::InvalidFundingInterestRate,
+        "funding_interest_rate has no effect without a funding_premium_clamp",
+    )?;
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    msg!(
+        "perp_market.amm.funding_interest_rate: {} -> {}",
+        perp_market.amm.funding_interest_rate,
+        funding_interest_rate
+    );
+
+    msg!(
+        "perp_market.amm.funding_premium_clamp: {} -> {}",
+        perp_market.amm.funding_premium_clamp,
+        funding_premium_clamp
+    );
+
+    perp_market.amm.funding_interest_rate = funding_interest_rate;
+    perp_market.amm.funding_premium_clamp = funding_premium_clamp;
+    Ok(())
+}
+
+#[access_control(
+    perp_market_valid(&ctx.accounts.perp_market)
+)]
+pub fn handle_update_perp_market_funding_rate_caps(
+    ctx: Context<AdminUpdatePerpMarket>,
+    max_hourly_funding_rate: u32,
+    max_funding_price_divergence: u32,
+) -> Result<()> {
+    // 0 falls back to the contract tier's caps
+    validate!(
+        max_hourly_funding_rate.cast::<u64>()? <= PERCENTAGE_PRECISION_U64,
+        ErrorCode::DefaultError,
+        "max_hourly_funding_rate must be <= 100%",
+    )?;
+
+    validate!(
+        max_funding_price_divergence.cast::<u64>()? <= PERCENTAGE_PRECISION_U64,
+        ErrorCode::DefaultError,
+        "max_funding_price_divergence must be <= 100%",
+    )?;
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    msg!(
+        "perp_market.max_hourly_funding_rate: {} -> {}",
+        perp_market.max_hourly_funding_rate,
+        max_hourly_funding_rate
+    );
+
+    msg!(
+        "perp_market.max_funding_price_divergence: {} -> {}",
+        perp_market.max_funding_price_divergence,
+        max_funding_price_divergence
+    );
+
+    perp_market.max_hourly_funding_rate = max_hourly_funding_rate;
+    perp_market.max_funding_price_divergence = max_funding_price_divergence;
+    Ok(())
+}
+
 #[access_control(
     perp_market_valid(&ctx.accounts.perp_market)
 )]
@@ -3029,6 +3371,117 @@ pub fn handle_update_perp_market_max_open_interest(
     Ok(())
 }
 
+#[access_control(
+    perp_market_valid(&ctx.accounts.perp_market)
+)]
+pub fn handle_update_perp_market_max_position_size(
+    ctx: Context<AdminUpdatePerpMarket>,
+    max_position_base_asset_amount: u64,
+    max_position_notional: u64,
+    max_position_open_interest_fraction: u32,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    validate!(
+        is_multiple_of_step_size(
+            max_position_base_asset_amount,
+            perp_market.amm.order_step_size
+        )?,
+        ErrorCode::InvalidMaxPositionSize,
+        "max position not a multiple of the step size"
+    )?;
+
+    validate!(
+        max_position_open_interest_fraction.cast::<u64>()? <= PERCENTAGE_PRECISION_U64,
+        ErrorCode::InvalidMaxPositionSize,
+        "max position open interest fraction {} greater than {}",
+        max_position_open_interest_fraction,
+        PERCENTAGE_PRECISION_U64
+    )?;
+
+    msg!(
+        "perp_market.max_position_base_asset_amount: {:?} -> {:?}",
+        perp_market.max_position_base_asset_amount,
+        max_position_base_asset_amount
+    );
+
+    msg!(
+        "perp_market.max_position_notional: {:?} -> {:?}",
+        perp_market.max_position_notional,
+        max_position_notional
+    );
+
+    msg!(
+        "perp_market.max_position_open_interest_fraction: {:?} -> {:?}",
+        perp_market.max_position_open_interest_fraction,
+        max_position_open_interest_fraction
+    );
+
+    perp_market.max_position_base_asset_amount = max_position_base_asset_amount;
+    perp_market.max_position_notional = max_position_notional;
+    perp_market.max_position_open_interest_fraction = max_position_open_interest_fraction;
+    Ok(())
+}
+
+#[access_control(
+    perp_market_valid(&ctx.accounts.perp_market)
+)]
+pub fn handle_update_perp_market_liquidation_auction(
+    ctx: Context<AdminUpdatePerpMarket>,
+    liquidation_auction_min_notional: u64,
+    liquidation_auction_end_liquidator_fee: u32,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    if liquidation_auction_min_notional != 0 {
+        validate!(
+            liquidation_auction_end_liquidator_fee >= perp_market.liquidator_fee,
+            ErrorCode::DefaultError,
+            "liquidation auction end fee {} less than liquidator fee {}",
+            liquidation_auction_end_liquidator_fee,
+            perp_market.liquidator_fee
+        )?;
+
+        validate!(
+            liquidation_auction_end_liquidator_fee.safe_add(perp_market.if_liquidation_fee)?
+                < LIQUIDATION_FEE_PRECISION,
+            ErrorCode::DefaultError,
+            "Total liquidation fee must be less than 100%"
+        )?;
+
+        validate_margin(
+            perp_market.margin_ratio_initial,
+            perp_market.margin_ratio_maintenance,
+            liquidation_auction_end_liquidator_fee,
+            perp_market.amm.max_spread,
+        )?;
+
+        validate_volatility_margin_scale_floor(
+            perp_market.margin_ratio_maintenance,
+            perp_market.volatility_margin_reference,
+            perp_market.volatility_margin_scale_floor,
+            liquidation_auction_end_liquidator_fee,
+            perp_market.if_liquidation_fee,
+        )?;
+    }
+
+    msg!(
+        "perp_market.liquidation_auction_min_notional: {:?} -> {:?}",
+        perp_market.liquidation_auction_min_notional,
+        liquidation_auction_min_notional
+    );
+
+    msg!(
+        "perp_market.liquidation_auction_end_liquidator_fee: {:?} -> {:?}",
+        perp_market.liquidation_auction_end_liquidator_fee,
+        liquidation_auction_end_liquidator_fee
+    );
+
+    perp_market.liquidation_auction_min_notional = liquidation_auction_min_notional;
+    perp_market.liquidation_auction_end_liquidator_fee = liquidation_auction_end_liquidator_fee;
+    Ok(())
+}
+
 #[access_control(
     perp_market_valid(&ctx.accounts.perp_market)
 )]
@@ -1,16 +1,19 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
+use std::iter::Peekable;
+use std::slice::Iter;
 
-use crate::error::ErrorCode;
+use crate::error::{DriftResult, ErrorCode};
 use crate::instructions::constraints::*;
 use crate::instructions::optional_accounts::{load_maps, AccountMaps};
-use crate::math::constants::QUOTE_SPOT_MARKET_INDEX;
+use crate::math::constants::{QUOTE_SPOT_MARKET_INDEX, TWENTY_FOUR_HOUR};
 use crate::math::insurance::if_shares_to_vault_amount;
 use crate::math::margin::{calculate_user_equity, meets_settle_pnl_maintenance_margin_requirement};
 use crate::math::orders::{estimate_price_from_side, find_bids_and_asks_from_users};
 use crate::math::spot_withdraw::validate_spot_market_vault_amount;
 use crate::optional_accounts::update_prelaunch_oracle;
 use crate::state::fill_mode::FillMode;
+use crate::state::fixed_term_pool::{load_fixed_term_pool_map, FixedTermPool};
 use crate::state::fulfillment_params::drift::MatchFulfillmentParams;
 use crate::state::fulfillment_params::phoenix::PhoenixFulfillmentParams;
 use crate::state::fulfillment_params::serum::SerumFulfillmentParams;
@@ -26,11 +29,13 @@ use crate::state::settle_pnl_mode::SettlePnlMode;
 use crate::state::spot_fulfillment_params::SpotFulfillmentParams;
 use crate::state::spot_market::SpotMarket;
 use crate::state::spot_market_map::{
-    get_writable_spot_market_set, get_writable_spot_market_set_from_many,
+    get_writable_spot_market_set, get_writable_spot_market_set_from_many, SpotMarketMap,
+    SpotMarketSet,
 };
 use crate::state::state::State;
+use crate::state::traits::Size;
 use crate::state::user::{MarketType, OrderStatus, User, UserStats};
-use crate::state::user_map::{load_user_maps, UserMap, UserStatsMap};
+use crate::state::user_map::{load_user_map, load_user_maps, UserMap, UserStatsMap};
 use crate::validation::user::validate_user_is_idle;
 use crate::{controller, load, math, OracleSource};
 use crate::{load_mut, QUOTE_PRECISION_U64};
@@ -705,18 +710,37 @@ pub fn handle_liquidate_perp<'c: 'info, 'info>(
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
     let liquidator_stats = &mut load_mut!(ctx.accounts.liquidator_stats)?;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &get_writable_perp_market_set(market_index),
-        &MarketSet::new(),
+        &get_writable_spot_market_set_for_liquidation(user, vec![]),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let fixed_term_pool_map = load_fixed_term_pool_map(remaining_accounts_iter)?;
+
+    if user.is_shared_margin()
+        && rebalance_shared_margin_group_before_liquidation(
+            remaining_accounts_iter,
+            user,
+            &user_key,
+            Some(user_stats),
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            state,
+            now,
+        )?
+    {
+        return Ok(());
+    }
+
     controller::liquidation::liquidate_perp(
         market_index,
         liquidator_max_base_asset_amount,
@@ -730,6 +754,7 @@ pub fn handle_liquidate_perp<'c: 'info, 'info>(
         &perp_market_map,
         &spot_market_map,
         &mut oracle_map,
+        &fixed_term_pool_map,
         slot,
         now,
         state,
@@ -738,6 +763,98 @@ pub fn handle_liquidate_perp<'c: 'info, 'info>(
     Ok(())
 }
 
+/// Shared margin sub accounts also need the quote spot market writable so the group can cover a shortfall,
+/// and fixed term positions settle into their spot markets when liquidation starts. The fixed term pools
+/// of those positions follow the market accounts
+fn get_writable_spot_market_set_for_liquidation(
+    user: &User,
+    mut market_indexes: Vec<u16>,
+) -> SpotMarketSet {
+    if user.is_shared_margin() {
+        market_indexes.push(QUOTE_SPOT_MARKET_INDEX);
+    }
+
+    for fixed_term_position in user.fixed_term_positions.iter() {
+        if !fixed_term_position.is_available() {
+            market_indexes.push(fixed_term_position.market_index);
+        }
+    }
+
+    get_writable_spot_market_set_from_many(market_indexes)
+}
+
+/// Liquidating a shared margin sub account requires the rest of its group after the market accounts and
+/// fixed term pools, followed by the authority's user stats if the instruction doesn't already take it.
+/// Returns true if the group covered the shortfall and the liquidation should be skipped. Missing or
+/// invalid group accounts just mean there is no rescue
+fn rebalance_shared_margin_group_before_liquidation<'a: 'b, 'b>(
+    remaining_accounts_iter: &mut Peekable<Iter<'a, AccountInfo<'b>>>,
+    user: &mut User,
+    user_key: &Pubkey,
+    user_stats: Option<&UserStats>,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    state: &State,
+    now: i64,
+) -> DriftResult<bool> {
+    let shared_margin_group = match load_user_map(remaining_accounts_iter, true) {
+        Ok(shared_margin_group) => shared_margin_group,
+        Err(error_code) => {
+            msg!(
+                "couldnt load shared margin group for {}: {:?}",
+                user_key,
+                error_code
+            );
+            return Ok(false);
+        }
+    };
+
+    let remaining_user_stats;
+    let user_stats = match user_stats {
+        Some(user_stats) => user_stats,
+        None => match load_user_stats_from_remaining_accounts(remaining_accounts_iter) {
+            Ok(user_stats) => {
+                remaining_user_stats = user_stats;
+                &remaining_user_stats
+            }
+            Err(error_code) => {
+                msg!(
+                    "couldnt load user stats for shared margin group of {}: {:?}",
+                    user_key,
+                    error_code
+                );
+                return Ok(false);
+            }
+        },
+    };
+
+    controller::shared_margin::rebalance_shared_margin_group(
+        user,
+        user_key,
+        user_stats,
+        &shared_margin_group,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        state.liquidation_margin_buffer_ratio,
+        now,
+    )
+}
+
+fn load_user_stats_from_remaining_accounts<'a: 'b, 'b>(
+    remaining_accounts_iter: &mut Peekable<Iter<'a, AccountInfo<'b>>>,
+) -> DriftResult<UserStats> {
+    let user_stats_account_info = remaining_accounts_iter
+        .next()
+        .ok_or(ErrorCode::UserStatsNotFound)?;
+    let user_stats_account_loader: AccountLoader<UserStats> =
+        AccountLoader::try_from(user_stats_account_info)
+            .or(Err(ErrorCode::InvalidUserStatsAccount))?;
+    let user_stats = *load!(user_stats_account_loader)?;
+    Ok(user_stats)
+}
+
 #[access_control(
     liq_not_paused(&ctx.accounts.state)
 )]
@@ -763,18 +880,40 @@ pub fn handle_liquidate_spot<'c: 'info, 'info>(
     let user = &mut load_mut!(ctx.accounts.user)?;
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
-        &get_writable_spot_market_set_from_many(vec![asset_market_index, liability_market_index]),
+        &get_writable_spot_market_set_for_liquidation(
+            user,
+            vec![asset_market_index, liability_market_index],
+        ),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let fixed_term_pool_map = load_fixed_term_pool_map(remaining_accounts_iter)?;
+
+    if user.is_shared_margin()
+        && rebalance_shared_margin_group_before_liquidation(
+            remaining_accounts_iter,
+            user,
+            &user_key,
+            None,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            state,
+            now,
+        )?
+    {
+        return Ok(());
+    }
+
     controller::liquidation::liquidate_spot(
         asset_market_index,
         liability_market_index,
@@ -787,6 +926,7 @@ pub fn handle_liquidate_spot<'c: 'info, 'info>(
         &perp_market_map,
         &spot_market_map,
         &mut oracle_map,
+        &fixed_term_pool_map,
         now,
         clock.slot,
         state,
@@ -820,18 +960,37 @@ pub fn handle_liquidate_borrow_for_perp_pnl<'c: 'info, 'info>(
     let user = &mut load_mut!(ctx.accounts.user)?;
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
-        &get_writable_spot_market_set(spot_market_index),
+        &get_writable_spot_market_set_for_liquidation(user, vec![spot_market_index]),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let fixed_term_pool_map = load_fixed_term_pool_map(remaining_accounts_iter)?;
+
+    if user.is_shared_margin()
+        && rebalance_shared_margin_group_before_liquidation(
+            remaining_accounts_iter,
+            user,
+            &user_key,
+            None,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            state,
+            now,
+        )?
+    {
+        return Ok(());
+    }
+
     controller::liquidation::liquidate_borrow_for_perp_pnl(
         perp_market_index,
         spot_market_index,
@@ -844,6 +1003,7 @@ pub fn handle_liquidate_borrow_for_perp_pnl<'c: 'info, 'info>(
         &perp_market_map,
         &spot_market_map,
         &mut oracle_map,
+        &fixed_term_pool_map,
         now,
         clock.slot,
         state.liquidation_margin_buffer_ratio,
@@ -879,18 +1039,37 @@ pub fn handle_liquidate_perp_pnl_for_deposit<'c: 'info, 'info>(
     let user = &mut load_mut!(ctx.accounts.user)?;
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
-        &get_writable_spot_market_set(spot_market_index),
+        &get_writable_spot_market_set_for_liquidation(user, vec![spot_market_index]),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let fixed_term_pool_map = load_fixed_term_pool_map(remaining_accounts_iter)?;
+
+    if user.is_shared_margin()
+        && rebalance_shared_margin_group_before_liquidation(
+            remaining_accounts_iter,
+            user,
+            &user_key,
+            None,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            state,
+            now,
+        )?
+    {
+        return Ok(());
+    }
+
     controller::liquidation::liquidate_perp_pnl_for_deposit(
         perp_market_index,
         spot_market_index,
@@ -903,6 +1082,7 @@ pub fn handle_liquidate_perp_pnl_for_deposit<'c: 'info, 'info>(
         &perp_market_map,
         &spot_market_map,
         &mut oracle_map,
+        &fixed_term_pool_map,
         now,
         clock.slot,
         state.liquidation_margin_buffer_ratio,
@@ -1065,18 +1245,22 @@ pub fn handle_resolve_perp_bankruptcy<'c: 'info, 'info>(
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
     let state = &ctx.accounts.state;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &get_writable_perp_market_set(market_index),
         &get_writable_spot_market_set(quote_spot_market_index),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    // any users after the markets and oracles are auto deleverage candidates
+    let auto_deleverage_user_map = load_user_map(remaining_accounts_iter, true)?;
+
     {
         let spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
         controller::insurance::attempt_settle_revenue_to_insurance_fund(
@@ -1104,10 +1288,12 @@ pub fn handle_resolve_perp_bankruptcy<'c: 'info, 'info>(
         &user_key,
         liquidator,
         &liquidator_key,
+        &auto_deleverage_user_map,
         &perp_market_map,
         &spot_market_map,
         &mut oracle_map,
         now,
+        clock.slot,
         ctx.accounts.insurance_fund_vault.amount,
     )?;
 
@@ -1247,6 +1433,163 @@ pub fn handle_resolve_spot_bankruptcy<'c: 'info, 'info>(
     Ok(())
 }
 
+pub fn handle_initialize_fixed_term_pool(
+    ctx: Context<InitializeFixedTermPool>,
+    market_index: u16,
+    maturity_ts: i64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    validate!(
+        maturity_ts > clock.unix_timestamp && maturity_ts % TWENTY_FOUR_HOUR == 0,
+        ErrorCode::InvalidFixedTermPool,
+        "maturity_ts {} must be a day boundary after now {}",
+        maturity_ts,
+        clock.unix_timestamp
+    )?;
+
+    let mut fixed_term_pool = ctx
+        .accounts
+        .fixed_term_pool
+        .load_init()
+        .or(Err(ErrorCode::UnableToLoadAccountLoader))?;
+
+    *fixed_term_pool = FixedTermPool {
+        market_index,
+        maturity_ts,
+        ..FixedTermPool::default()
+    };
+
+    Ok(())
+}
+
+#[access_control(
+    fill_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_fill_fixed_term_orders<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, FillFixedTermOrders<'info>>,
+    market_index: u16,
+    maturity_ts: i64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let lender_key = ctx.accounts.lender.key();
+    let borrower_key = ctx.accounts.borrower.key();
+
+    validate!(
+        lender_key != borrower_key,
+        ErrorCode::InvalidFixedTermOrder,
+        "lender and borrower must be different users"
+    )?;
+
+    let lender = &mut load_mut!(ctx.accounts.lender)?;
+    let borrower = &mut load_mut!(ctx.accounts.borrower)?;
+    let fixed_term_pool = &mut load_mut!(ctx.accounts.fixed_term_pool)?;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &get_writable_spot_market_set(market_index),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    controller::fixed_term::fill_fixed_term_orders(
+        lender,
+        &lender_key,
+        borrower,
+        &borrower_key,
+        fixed_term_pool,
+        market_index,
+        maturity_ts,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock.unix_timestamp,
+    )?;
+
+    controller::orders::update_reduce_only_orders(
+        lender,
+        &lender_key,
+        MarketType::Spot,
+        market_index,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        None,
+        clock.unix_timestamp,
+        clock.slot,
+    )?;
+
+    lender.update_last_active_slot(clock.slot);
+    borrower.update_last_active_slot(clock.slot);
+
+    Ok(())
+}
+
+#[access_control(
+    withdraw_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_settle_fixed_term_position<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, SettleFixedTermPosition<'info>>,
+    market_index: u16,
+    maturity_ts: i64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let user_key = ctx.accounts.user.key();
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let fixed_term_pool = &mut load_mut!(ctx.accounts.fixed_term_pool)?;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &get_writable_spot_market_set(market_index),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    controller::fixed_term::settle_fixed_term_position(
+        user,
+        &user_key,
+        fixed_term_pool,
+        market_index,
+        maturity_ts,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        state.liquidation_margin_buffer_ratio,
+        clock.unix_timestamp,
+    )?;
+
+    controller::orders::update_reduce_only_orders(
+        user,
+        &user_key,
+        MarketType::Spot,
+        market_index,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        None,
+        clock.unix_timestamp,
+        clock.slot,
+    )?;
+
+    user.update_last_active_slot(clock.slot);
+
+    Ok(())
+}
+
 #[access_control(
     perp_market_valid(&ctx.accounts.perp_market)
     funding_not_paused(&ctx.accounts.state)
@@ -1848,6 +2191,65 @@ pub struct ResolvePerpPnlDeficit<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+/// Permissionless, anyone can open the pool for a market and maturity before its orders fill
+#[derive(Accounts)]
+#[instruction(market_index: u16, maturity_ts: i64)]
+pub struct InitializeFixedTermPool<'info> {
+    #[account(
+        seeds = [b"spot_market", market_index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub spot_market: AccountLoader<'info, SpotMarket>,
+    #[account(
+        init,
+        seeds = [b"fixed_term_pool", market_index.to_le_bytes().as_ref(), maturity_ts.to_le_bytes().as_ref()],
+        space = FixedTermPool::SIZE,
+        bump,
+        payer = payer
+    )]
+    pub fixed_term_pool: AccountLoader<'info, FixedTermPool>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Permissionless keeper instruction. The authority is any keeper and the orders being matched are the
+/// users' consent, so the only accounts constrained are the pool for the market and maturity
+#[derive(Accounts)]
+#[instruction(market_index: u16, maturity_ts: i64)]
+pub struct FillFixedTermOrders<'info> {
+    pub state: Box<Account<'info, State>>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub lender: AccountLoader<'info, User>,
+    #[account(mut)]
+    pub borrower: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        seeds = [b"fixed_term_pool", market_index.to_le_bytes().as_ref(), maturity_ts.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fixed_term_pool: AccountLoader<'info, FixedTermPool>,
+}
+
+/// Permissionless keeper instruction. Any keeper can settle a matured position, or an unmatured one once
+/// the user can be liquidated, against the pool for its market and maturity
+#[derive(Accounts)]
+#[instruction(market_index: u16, maturity_ts: i64)]
+pub struct SettleFixedTermPosition<'info> {
+    pub state: Box<Account<'info, State>>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        seeds = [b"fixed_term_pool", market_index.to_le_bytes().as_ref(), maturity_ts.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fixed_term_pool: AccountLoader<'info, FixedTermPool>,
+}
+
 #[derive(Accounts)]
 #[instruction(market_index: u16,)]
 pub struct SettleRevenueToInsuranceFund<'info> {
@@ -4,7 +4,7 @@ use anchor_spl::token::{Token, TokenAccount};
 use solana_program::program::invoke;
 use solana_program::system_instruction::transfer;
 
-use crate::controller::orders::{cancel_orders, ModifyOrderId};
+use crate::controller::orders::cancel_orders;
 use crate::controller::position::PositionDirection;
 use crate::controller::spot_balance::update_revenue_pool_balances;
 use crate::controller::spot_position::{
@@ -44,27 +44,32 @@ use crate::state::fulfillment_params::drift::MatchFulfillmentParams;
 use crate::state::fulfillment_params::phoenix::PhoenixFulfillmentParams;
 use crate::state::fulfillment_params::serum::SerumFulfillmentParams;
 use crate::state::oracle::StrictOraclePrice;
+use crate::state::oracle_map::OracleMap;
 use crate::state::order_params::{
-    ModifyOrderParams, OrderParams, PlaceOrderOptions, PostOnlyParam,
+    ModifyOrderByIdParams, ModifyOrderId, ModifyOrderParams, OrderParams, PlaceOrderOptions,
+    PostOnlyParam, ScaleOrderParams,
 };
 use crate::state::paused_operations::PerpOperation;
 use crate::state::perp_market::MarketStatus;
-use crate::state::perp_market_map::{get_writable_perp_market_set, MarketSet};
+use crate::state::perp_market_map::{get_writable_perp_market_set, MarketSet, PerpMarketMap};
 use crate::state::spot_fulfillment_params::SpotFulfillmentParams;
 use crate::state::spot_market::SpotBalanceType;
 use crate::state::spot_market::SpotMarket;
 use crate::state::spot_market_map::{
-    get_writable_spot_market_set, get_writable_spot_market_set_from_many,
+    get_writable_spot_market_set, get_writable_spot_market_set_from_many, SpotMarketMap,
 };
 use crate::state::state::State;
 use crate::state::traits::Size;
-use crate::state::user::{MarketType, OrderType, ReferrerName, User, UserStats};
+use crate::state::user::{
+    migrate_legacy_user_data, FixedTermDirection, MarketType, OrderStatus, OrderType, ReferrerName,
+    User, UserStats, LEGACY_USER_SIZE,
+};
 use crate::state::user_map::{load_user_maps, UserMap, UserStatsMap};
 use crate::validate;
 use crate::validation::user::validate_user_deletion;
 use crate::validation::whitelist::validate_whitelist_token;
 use crate::{controller, math};
-use crate::{get_then_update_id, QUOTE_SPOT_MARKET_INDEX};
+use crate::{get_then_update_id, MAX_SHARED_MARGIN_SUB_ACCOUNTS, QUOTE_SPOT_MARKET_INDEX};
 use crate::{load, THIRTEEN_DAY};
 use anchor_lang::solana_program::sysvar::instructions;
 use anchor_spl::associated_token::AssociatedToken;
@@ -528,6 +533,19 @@ pub fn handle_withdraw<'c: 'info, 'info>(
         user.exit_liquidation();
     }
 
+    controller::orders::update_reduce_only_orders(
+        user,
+        &user_key,
+        MarketType::Spot,
+        market_index,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        None,
+        now,
+        slot,
+    )?;
+
     user.update_last_active_slot(slot);
 
     let mut spot_market = spot_market_map.get_ref_mut(&market_index)?;
@@ -683,6 +701,19 @@ pub fn handle_transfer_deposit<'c: 'info, 'info>(
         from_user.exit_liquidation();
     }
 
+    controller::orders::update_reduce_only_orders(
+        from_user,
+        &from_user_key,
+        MarketType::Spot,
+        market_index,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        None,
+        clock.unix_timestamp,
+        slot,
+    )?;
+
     from_user.update_last_active_slot(slot);
 
     {
@@ -777,6 +808,54 @@ pub fn handle_transfer_deposit<'c: 'info, 'info>(
     Ok(())
 }
 
+#[access_control(
+    deposit_not_paused(&ctx.accounts.state)
+    withdraw_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_transfer_isolated_perp_position_deposit<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, TransferIsolatedPerpPositionDeposit<'info>>,
+    perp_market_index: u16,
+    amount: i64,
+) -> anchor_lang::Result<()> {
+    let state = &ctx.accounts.state;
+    let clock = Clock::get()?;
+    let slot = clock.slot;
+
+    let user = &mut load_mut!(ctx.accounts.user)?;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &get_writable_spot_market_set(QUOTE_SPOT_MARKET_INDEX),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    controller::isolated_position::transfer_isolated_perp_position_deposit(
+        user,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock.unix_timestamp,
+        perp_market_index,
+        amount,
+    )?;
+
+    user.update_last_active_slot(slot);
+
+    let spot_market = spot_market_map.get_ref(&QUOTE_SPOT_MARKET_INDEX)?;
+    math::spot_withdraw::validate_spot_market_vault_amount(
+        &spot_market,
+        ctx.accounts.spot_market_vault.amount,
+    )?;
+
+    Ok(())
+}
+
 #[access_control(
     exchange_not_paused(&ctx.accounts.state)
 )]
@@ -1013,6 +1092,7 @@ pub fn handle_modify_order<'c: 'info, 'info>(
         &spot_market_map,
         &mut oracle_map,
         clock,
+        PlaceOrderOptions::default(),
     )?;
 
     Ok(())
@@ -1050,6 +1130,44 @@ pub fn handle_modify_order_by_user_order_id<'c: 'info, 'info>(
         &spot_market_map,
         &mut oracle_map,
         clock,
+        PlaceOrderOptions::default(),
+    )?;
+
+    Ok(())
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_modify_orders<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, CancelOrder<'info>>,
+    params: Vec<ModifyOrderByIdParams>,
+    all_or_nothing: bool,
+) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    controller::orders::modify_orders(
+        params,
+        all_or_nothing,
+        &ctx.accounts.user,
+        state,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
     )?;
 
     Ok(())
@@ -1086,6 +1204,96 @@ pub fn handle_place_orders<'c: 'info, 'info>(
     let user_key = ctx.accounts.user.key();
     let mut user = load_mut!(ctx.accounts.user)?;
 
+    place_orders(
+        state,
+        &mut user,
+        user_key,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+        &params,
+    )
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_scale_orders<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, PlaceOrder>,
+    params: ScaleOrderParams,
+) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let (tick_size, step_size, min_order_size) = if params.market_type == MarketType::Perp {
+        let market = perp_market_map.get_ref(&params.market_index)?;
+        (
+            market.amm.order_tick_size,
+            market.amm.order_step_size,
+            market.amm.min_order_size,
+        )
+    } else {
+        let market = spot_market_map.get_ref(&params.market_index)?;
+        (
+            market.order_tick_size,
+            market.order_step_size,
+            market.min_order_size,
+        )
+    };
+
+    let order_params = params.get_order_params(tick_size, step_size, min_order_size)?;
+
+    let user_key = ctx.accounts.user.key();
+    let mut user = load_mut!(ctx.accounts.user)?;
+
+    let free_order_slots = user
+        .orders
+        .iter()
+        .filter(|order| order.status == OrderStatus::Init)
+        .count();
+    validate!(
+        order_params.len() <= free_order_slots,
+        ErrorCode::MaxNumberOfOrders,
+        "scale order needs {} order slots, user has {} free",
+        order_params.len(),
+        free_order_slots
+    )?;
+
+    place_orders(
+        state,
+        &mut user,
+        user_key,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+        &order_params,
+    )
+}
+
+fn place_orders(
+    state: &State,
+    user: &mut User,
+    user_key: Pubkey,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+    params: &[OrderParams],
+) -> Result<()> {
     let num_orders = params.len();
     for (i, params) in params.iter().enumerate() {
         validate!(
@@ -1100,28 +1308,29 @@ pub fn handle_place_orders<'c: 'info, 'info>(
             try_expire_orders: i == 0,
             risk_increasing: false,
             explanation: OrderActionExplanation::None,
+            immediate_fill: false,
         };
 
         if params.market_type == MarketType::Perp {
             controller::orders::place_perp_order(
-                &ctx.accounts.state,
-                &mut user,
+                state,
+                user,
                 user_key,
-                &perp_market_map,
-                &spot_market_map,
-                &mut oracle_map,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
                 clock,
                 *params,
                 options,
             )?;
         } else {
             controller::orders::place_spot_order(
-                &ctx.accounts.state,
-                &mut user,
+                state,
+                user,
                 user_key,
-                &perp_market_map,
-                &spot_market_map,
-                &mut oracle_map,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
                 clock,
                 *params,
                 options,
@@ -1186,7 +1395,7 @@ pub fn handle_place_and_take_perp_order<'c: 'info, 'info>(
         &mut oracle_map,
         &Clock::get()?,
         params,
-        PlaceOrderOptions::default(),
+        PlaceOrderOptions::default().immediate_fill(),
     )?;
 
     drop(user);
@@ -1282,7 +1491,7 @@ pub fn handle_place_and_make_perp_order<'c: 'info, 'info>(
         &mut oracle_map,
         clock,
         params,
-        PlaceOrderOptions::default(),
+        PlaceOrderOptions::default().immediate_fill(),
     )?;
 
     let (order_id, authority) = (user.get_last_order_id(), user.authority);
@@ -1451,7 +1660,7 @@ pub fn handle_place_and_take_spot_order<'c: 'info, 'info>(
         &mut oracle_map,
         &clock,
         params,
-        PlaceOrderOptions::default(),
+        PlaceOrderOptions::default().immediate_fill(),
     )?;
 
     drop(user);
@@ -1582,7 +1791,7 @@ pub fn handle_place_and_make_spot_order<'c: 'info, 'info>(
         &mut oracle_map,
         clock,
         params,
-        PlaceOrderOptions::default(),
+        PlaceOrderOptions::default().immediate_fill(),
     )?;
 
     drop(user);
@@ -1634,6 +1843,68 @@ pub fn handle_place_and_make_spot_order<'c: 'info, 'info>(
     Ok(())
 }
 
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_fixed_term_order<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, PlaceOrder>,
+    market_index: u16,
+    maturity_ts: i64,
+    direction: FixedTermDirection,
+    principal: u64,
+    rate: u32,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let user = &mut load_mut!(ctx.accounts.user)?;
+
+    controller::fixed_term::place_fixed_term_order(
+        user,
+        market_index,
+        maturity_ts,
+        direction,
+        principal,
+        rate,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock.slot,
+        clock.unix_timestamp,
+    )?;
+
+    user.update_last_active_slot(clock.slot);
+
+    Ok(())
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_cancel_fixed_term_order(
+    ctx: Context<CancelOrder>,
+    market_index: u16,
+    maturity_ts: i64,
+) -> Result<()> {
+    let user = &mut load_mut!(ctx.accounts.user)?;
+
+    controller::fixed_term::cancel_fixed_term_order(user, market_index, maturity_ts)?;
+
+    Ok(())
+}
+
 #[access_control(
     amm_not_paused(&ctx.accounts.state)
 )]
@@ -1910,6 +2181,79 @@ pub fn handle_update_user_advanced_lp(
     Ok(())
 }
 
+pub fn handle_update_user_portfolio_margin<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, UpdateUserPortfolioMargin<'info>>,
+    _sub_account_id: u16,
+    portfolio_margin: bool,
+) -> Result<()> {
+    let mut user = load_mut!(ctx.accounts.user)?;
+    let state = &ctx.accounts.state;
+    let clock = Clock::get()?;
+
+    validate!(!user.is_being_liquidated(), ErrorCode::LiquidationsOngoing)?;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    user.update_portfolio_margin_status(portfolio_margin)?;
+
+    // leaving portfolio margin removes the hedge offsets, so the account must still meet initial margin
+    if !portfolio_margin {
+        meets_withdraw_margin_requirement(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginRequirementType::Initial,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn handle_update_user_shared_margin(
+    ctx: Context<UpdateUserSharedMargin>,
+    _sub_account_id: u16,
+    shared_margin: bool,
+) -> Result<()> {
+    let mut user = load_mut!(ctx.accounts.user)?;
+    let mut user_stats = load_mut!(ctx.accounts.user_stats)?;
+
+    validate!(!user.is_being_liquidated(), ErrorCode::LiquidationsOngoing)?;
+
+    if user.is_shared_margin() == shared_margin {
+        return Ok(());
+    }
+
+    // liquidations load the whole group, so it has to fit in one transaction
+    validate!(
+        !shared_margin
+            || user_stats.number_of_shared_margin_sub_accounts < MAX_SHARED_MARGIN_SUB_ACCOUNTS,
+        ErrorCode::InvalidSharedMarginGroup,
+        "shared margin group already has {} sub accounts",
+        user_stats.number_of_shared_margin_sub_accounts
+    )?;
+
+    user.update_shared_margin_status(shared_margin)?;
+
+    if shared_margin {
+        safe_increment!(user_stats.number_of_shared_margin_sub_accounts, 1);
+    } else {
+        safe_decrement!(user_stats.number_of_shared_margin_sub_accounts, 1);
+    }
+
+    Ok(())
+}
+
 pub fn handle_delete_user(ctx: Context<DeleteUser>) -> Result<()> {
     let user = &load!(ctx.accounts.user)?;
     let user_stats = &mut load_mut!(ctx.accounts.user_stats)?;
@@ -1923,6 +2267,10 @@ pub fn handle_delete_user(ctx: Context<DeleteUser>) -> Result<()> {
 
     safe_decrement!(user_stats.number_of_sub_accounts, 1);
 
+    if user.is_shared_margin() {
+        safe_decrement!(user_stats.number_of_shared_margin_sub_accounts, 1);
+    }
+
     let state = &mut ctx.accounts.state;
     safe_decrement!(state.number_of_sub_accounts, 1);
 
@@ -1969,6 +2317,43 @@ pub fn handle_reclaim_rent(ctx: Context<ReclaimRent>) -> Result<()> {
     Ok(())
 }
 
+pub fn handle_migrate_user(ctx: Context<MigrateUser>) -> Result<()> {
+    let user = &ctx.accounts.user;
+
+    {
+        let data = user.try_borrow_data()?;
+        validate!(
+            data.len() == LEGACY_USER_SIZE && data[..8] == User::discriminator(),
+            ErrorCode::UserCantBeMigrated,
+            "user account is not a legacy user account"
+        )?;
+    }
+
+    let rent_shortfall = ctx
+        .accounts
+        .rent
+        .minimum_balance(User::SIZE)
+        .saturating_sub(user.try_lamports()?);
+
+    if rent_shortfall > 0 {
+        invoke(
+            &transfer(&ctx.accounts.payer.key(), &user.key(), rent_shortfall),
+            &[
+                ctx.accounts.payer.to_account_info().clone(),
+                user.clone(),
+                ctx.accounts.system_program.to_account_info().clone(),
+            ],
+        )?;
+    }
+
+    user.realloc(User::SIZE, false)?;
+
+    let mut data = user.try_borrow_mut_data()?;
+    migrate_legacy_user_data(&mut data)?;
+
+    Ok(())
+}
+
 #[access_control(
     deposit_not_paused(&ctx.accounts.state)
 )]
@@ -2202,6 +2587,22 @@ pub struct TransferDeposit<'info> {
     pub spot_market_vault: Box<Account<'info, TokenAccount>>,
 }
 
+#[derive(Accounts)]
+pub struct TransferIsolatedPerpPositionDeposit<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [b"spot_market_vault".as_ref(), 0_u16.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub spot_market_vault: Box<Account<'info, TokenAccount>>,
+}
+
 #[derive(Accounts)]
 pub struct PlaceOrder<'info> {
     pub state: Box<Account<'info, State>>,
@@ -2295,6 +2696,40 @@ pub struct UpdateUser<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+#[instruction(
+    sub_account_id: u16,
+)]
+pub struct UpdateUserPortfolioMargin<'info> {
+    #[account(
+        mut,
+        seeds = [b"user", authority.key.as_ref(), sub_account_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    sub_account_id: u16,
+)]
+pub struct UpdateUserSharedMargin<'info> {
+    #[account(
+        mut,
+        seeds = [b"user", authority.key.as_ref(), sub_account_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub user: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub user_stats: AccountLoader<'info, UserStats>,
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct DeleteUser<'info> {
     #[account(
@@ -2313,6 +2748,20 @@ pub struct DeleteUser<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateUser<'info> {
+    /// CHECK: legacy user accounts can't be loaded as User, checked in ix
+    #[account(
+        mut,
+        owner = crate::ID
+    )]
+    pub user: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct ReclaimRent<'info> {
     #[account(
@@ -2914,6 +3363,21 @@ pub fn handle_end_swap<'c: 'info, 'info>(
         margin_type,
     )?;
 
+    for market_index in [in_market_index, out_market_index] {
+        controller::orders::update_reduce_only_orders(
+            &mut user,
+            &user_key,
+            MarketType::Spot,
+            market_index,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            None,
+            now,
+            slot,
+        )?;
+    }
+
     user.update_last_active_slot(slot);
 
     let swap_record = SwapRecord {
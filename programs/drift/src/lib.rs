@@ -12,13 +12,17 @@ use state::oracle::OracleSource;
 
 use crate::controller::position::PositionDirection;
 use crate::state::oracle::PrelaunchOracleParams;
-use crate::state::order_params::{ModifyOrderParams, OrderParams};
+use crate::state::order_params::{
+    ModifyOrderByIdParams, ModifyOrderParams, OrderParams, ScaleOrderParams,
+};
 use crate::state::perp_market::{ContractTier, MarketStatus};
 use crate::state::settle_pnl_mode::SettlePnlMode;
 use crate::state::spot_market::AssetTier;
+use crate::state::spot_market::BorrowRateKink;
 use crate::state::spot_market::SpotFulfillmentConfigStatus;
 use crate::state::state::FeeStructure;
 use crate::state::state::*;
+use crate::state::user::FixedTermDirection;
 use crate::state::user::MarketType;
 
 pub mod controller;
@@ -92,6 +96,14 @@ pub mod drift {
         handle_transfer_deposit(ctx, market_index, amount)
     }
 
+    pub fn transfer_isolated_perp_position_deposit<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, TransferIsolatedPerpPositionDeposit<'info>>,
+        perp_market_index: u16,
+        amount: i64,
+    ) -> anchor_lang::Result<()> {
+        handle_transfer_isolated_perp_position_deposit(ctx, perp_market_index, amount)
+    }
+
     pub fn place_perp_order<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, PlaceOrder>,
         params: OrderParams,
@@ -145,6 +157,14 @@ pub mod drift {
         handle_modify_order_by_user_order_id(ctx, user_order_id, modify_order_params)
     }
 
+    pub fn modify_orders<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, CancelOrder<'info>>,
+        params: Vec<ModifyOrderByIdParams>,
+        all_or_nothing: bool,
+    ) -> Result<()> {
+        handle_modify_orders(ctx, params, all_or_nothing)
+    }
+
     pub fn place_and_take_perp_order<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, PlaceAndTake<'info>>,
         params: OrderParams,
@@ -203,6 +223,32 @@ pub mod drift {
         handle_place_orders(ctx, params)
     }
 
+    pub fn place_scale_orders<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, PlaceOrder>,
+        params: ScaleOrderParams,
+    ) -> Result<()> {
+        handle_place_scale_orders(ctx, params)
+    }
+
+    pub fn place_fixed_term_order<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, PlaceOrder>,
+        market_index: u16,
+        maturity_ts: i64,
+        direction: FixedTermDirection,
+        principal: u64,
+        rate: u32,
+    ) -> Result<()> {
+        handle_place_fixed_term_order(ctx, market_index, maturity_ts, direction, principal, rate)
+    }
+
+    pub fn cancel_fixed_term_order(
+        ctx: Context<CancelOrder>,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> Result<()> {
+        handle_cancel_fixed_term_order(ctx, market_index, maturity_ts)
+    }
+
     pub fn begin_swap<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, Swap<'info>>,
         in_market_index: u16,
@@ -300,6 +346,22 @@ pub mod drift {
         handle_update_user_advanced_lp(ctx, _sub_account_id, advanced_lp)
     }
 
+    pub fn update_user_portfolio_margin<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, UpdateUserPortfolioMargin<'info>>,
+        _sub_account_id: u16,
+        portfolio_margin: bool,
+    ) -> Result<()> {
+        handle_update_user_portfolio_margin(ctx, _sub_account_id, portfolio_margin)
+    }
+
+    pub fn update_user_shared_margin(
+        ctx: Context<UpdateUserSharedMargin>,
+        _sub_account_id: u16,
+        shared_margin: bool,
+    ) -> Result<()> {
+        handle_update_user_shared_margin(ctx, _sub_account_id, shared_margin)
+    }
+
     pub fn delete_user<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, DeleteUser>,
     ) -> Result<()> {
@@ -310,6 +372,10 @@ pub mod drift {
         handle_reclaim_rent(ctx)
     }
 
+    pub fn migrate_user(ctx: Context<MigrateUser>) -> Result<()> {
+        handle_migrate_user(ctx)
+    }
+
     // Keeper Instructions
 
     pub fn fill_perp_order<'c: 'info, 'info>(
@@ -490,6 +556,30 @@ pub mod drift {
         handle_settle_revenue_to_insurance_fund(ctx, spot_market_index)
     }
 
+    pub fn initialize_fixed_term_pool(
+        ctx: Context<InitializeFixedTermPool>,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> Result<()> {
+        handle_initialize_fixed_term_pool(ctx, market_index, maturity_ts)
+    }
+
+    pub fn fill_fixed_term_orders<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, FillFixedTermOrders<'info>>,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> Result<()> {
+        handle_fill_fixed_term_orders(ctx, market_index, maturity_ts)
+    }
+
+    pub fn settle_fixed_term_position<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, SettleFixedTermPosition<'info>>,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> Result<()> {
+        handle_settle_fixed_term_position(ctx, market_index, maturity_ts)
+    }
+
     pub fn update_funding_rate(ctx: Context<UpdateFundingRate>, market_index: u16) -> Result<()> {
         handle_update_funding_rate(ctx, market_index)
     }
@@ -808,6 +898,20 @@ pub mod drift {
         handle_update_perp_market_margin_ratio(ctx, margin_ratio_initial, margin_ratio_maintenance)
     }
 
+    pub fn update_perp_market_volatility_margin_scale(
+        ctx: Context<AdminUpdatePerpMarket>,
+        volatility_margin_reference: u16,
+        volatility_margin_scale_floor: u16,
+        volatility_margin_scale_ceiling: u16,
+    ) -> Result<()> {
+        handle_update_perp_market_volatility_margin_scale(
+            ctx,
+            volatility_margin_reference,
+            volatility_margin_scale_floor,
+            volatility_margin_scale_ceiling,
+        )
+    }
+
     pub fn update_perp_market_funding_period(
         ctx: Context<AdminUpdatePerpMarket>,
         funding_period: i64,
@@ -868,6 +972,13 @@ pub mod drift {
         handle_update_spot_market_if_factor(ctx, spot_market_index, user_if_factor, total_if_factor)
     }
 
+    pub fn update_spot_market_reserve_factor(
+        ctx: Context<AdminUpdateSpotMarket>,
+        reserve_factor: u32,
+    ) -> Result<()> {
+        handle_update_spot_market_reserve_factor(ctx, reserve_factor)
+    }
+
     pub fn update_spot_market_revenue_settle_period(
         ctx: Context<AdminUpdateSpotMarket>,
         revenue_settle_period: i64,
@@ -914,6 +1025,20 @@ pub mod drift {
         )
     }
 
+    pub fn update_spot_market_volatility_margin_scale(
+        ctx: Context<AdminUpdateSpotMarket>,
+        volatility_margin_reference: u16,
+        volatility_margin_scale_floor: u16,
+        volatility_margin_scale_ceiling: u16,
+    ) -> Result<()> {
+        handle_update_spot_market_volatility_margin_scale(
+            ctx,
+            volatility_margin_reference,
+            volatility_margin_scale_floor,
+            volatility_margin_scale_ceiling,
+        )
+    }
+
     pub fn update_spot_market_borrow_rate(
         ctx: Context<AdminUpdateSpotMarket>,
         optimal_utilization: u32,
@@ -928,6 +1053,27 @@ pub mod drift {
         )
     }
 
+    pub fn update_spot_market_borrow_rate_kinks(
+        ctx: Context<AdminUpdateSpotMarket>,
+        borrow_rate_kinks: [BorrowRateKink; 3],
+    ) -> Result<()> {
+        handle_update_spot_market_borrow_rate_kinks(ctx, borrow_rate_kinks)
+    }
+
+    pub fn update_spot_market_adaptive_borrow_rate(
+        ctx: Context<AdminUpdateSpotMarket>,
+        adaptive_borrow_rate_speed: u16,
+        adaptive_borrow_rate_scale_floor: u16,
+        adaptive_borrow_rate_scale_ceiling: u16,
+    ) -> Result<()> {
+        handle_update_spot_market_adaptive_borrow_rate(
+            ctx,
+            adaptive_borrow_rate_speed,
+            adaptive_borrow_rate_scale_floor,
+            adaptive_borrow_rate_scale_ceiling,
+        )
+    }
+
     pub fn update_spot_market_max_token_deposits(
         ctx: Context<AdminUpdateSpotMarket>,
         max_token_deposits: u64,
@@ -1044,6 +1190,30 @@ pub mod drift {
         handle_update_perp_market_curve_update_intensity(ctx, curve_update_intensity)
     }
 
+    pub fn update_perp_market_funding_interest_rate(
+        ctx: Context<AdminUpdatePerpMarket>,
+        funding_interest_rate: i32,
+        funding_premium_clamp: u16,
+    ) -> Result<()> {
+        handle_update_perp_market_funding_interest_rate(
+            ctx,
+            funding_interest_rate,
+            funding_premium_clamp,
+        )
+    }
+
+    pub fn update_perp_market_funding_rate_caps(
+        ctx: Context<AdminUpdatePerpMarket>,
+        max_hourly_funding_rate: u32,
+        max_funding_price_divergence: u32,
+    ) -> Result<()> {
+        handle_update_perp_market_funding_rate_caps(
+            ctx,
+            max_hourly_funding_rate,
+            max_funding_price_divergence,
+        )
+    }
+
     pub fn update_perp_market_target_base_asset_amount_per_lp(
         ctx: Context<AdminUpdatePerpMarket>,
         target_base_asset_amount_per_lp: i32,
@@ -1203,6 +1373,32 @@ pub mod drift {
         handle_update_perp_market_max_open_interest(ctx, max_open_interest)
     }
 
+    pub fn update_perp_market_max_position_size(
+        ctx: Context<AdminUpdatePerpMarket>,
+        max_position_base_asset_amount: u64,
+        max_position_notional: u64,
+        max_position_open_interest_fraction: u32,
+    ) -> Result<()> {
+        handle_update_perp_market_max_position_size(
+            ctx,
+            max_position_base_asset_amount,
+            max_position_notional,
+            max_position_open_interest_fraction,
+        )
+    }
+
+    pub fn update_perp_market_liquidation_auction(
+        ctx: Context<AdminUpdatePerpMarket>,
+        liquidation_auction_min_notional: u64,
+        liquidation_auction_end_liquidator_fee: u32,
+    ) -> Result<()> {
+        handle_update_perp_market_liquidation_auction(
+            ctx,
+            liquidation_auction_min_notional,
+            liquidation_auction_end_liquidator_fee,
+        )
+    }
+
     pub fn update_perp_market_number_of_users(
         ctx: Context<AdminUpdatePerpMarket>,
         number_of_users: Option<u32>,
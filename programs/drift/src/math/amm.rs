@@ -13,6 +13,7 @@ use crate::math::constants::{
     PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO, PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO_I128,
     PRICE_TO_PEG_PRECISION_RATIO, QUOTE_PRECISION_I64,
 };
+use crate::math::funding::calculate_premium;
 use crate::math::orders::standardize_base_asset_amount;
 use crate::math::quote_asset::reserve_to_asset_amount;
 use crate::math::stats::{calculate_new_twap, calculate_rolling_sum, calculate_weighted_average};
@@ -324,11 +325,42 @@ pub fn update_mark_twap(
     )?
     .cast()?;
 
+    update_premium_index(amm, now, bid_price_capped_update, ask_price_capped_update)?;
+
     amm.last_mark_price_twap_ts = now;
 
     mid_twap.cast()
 }
 
+/// Samples the bid/ask premium over the oracle into the time-weighted premium index for the current
+/// funding period. Must run before last_mark_price_twap_ts is updated
+pub fn update_premium_index(
+    amm: &mut AMM,
+    now: i64,
+    bid_price: i64,
+    ask_price: i64,
+) -> DriftResult {
+    let oracle_price = amm.historical_oracle_data.last_oracle_price;
+    if oracle_price <= 0 {
+        return Ok(());
+    }
+
+    let premium = calculate_premium(bid_price, ask_price, oracle_price)?;
+
+    let last_sample_ts = amm.last_mark_price_twap_ts.max(amm.last_funding_rate_ts);
+    let since_funding_period_start = last_sample_ts.safe_sub(amm.last_funding_rate_ts)?;
+    let since_last_sample = now.safe_sub(last_sample_ts)?.max(0);
+
+    amm.last_premium_index = calculate_weighted_average(
+        amm.last_premium_index,
+        premium,
+        since_funding_period_start,
+        since_last_sample,
+    )?;
+
+    Ok(())
+}
+
 pub fn update_mark_twap_from_estimates(
     amm: &mut AMM,
     now: i64,
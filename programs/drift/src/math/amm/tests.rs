@@ -955,3 +955,50 @@ fn test_last_oracle_conf_update() {
 
     assert_eq!(amm.last_oracle_conf_pct, 7307 - 7307 / 5 + 1); //5847
 }
+
+#[test]
+fn update_premium_index_test() {
+    let mut amm = AMM {
+        historical_oracle_data: HistoricalOracleData {
+            last_oracle_price: 100 * PRICE_PRECISION_I64,
+            ..HistoricalOracleData::default()
+        },
+        last_funding_rate_ts: 0,
+        last_mark_price_twap_ts: 0,
+        ..AMM::default()
+    };
+
+    // first sample of the funding period sets the index
+    update_premium_index(
+        &mut amm,
+        600,
+        101 * PRICE_PRECISION_I64,
+        102 * PRICE_PRECISION_I64,
+    )
+    .unwrap();
+    assert_eq!(amm.last_premium_index, PRICE_PRECISION_I64);
+    amm.last_mark_price_twap_ts = 600;
+
+    // oracle inside the spread has no premium, weighted by the time since the last sample
+    // (rounds towards the latest sample)
+    update_premium_index(
+        &mut amm,
+        2400,
+        99 * PRICE_PRECISION_I64,
+        101 * PRICE_PRECISION_I64,
+    )
+    .unwrap();
+    assert_eq!(amm.last_premium_index, PRICE_PRECISION_I64 / 4 - 1);
+    amm.last_mark_price_twap_ts = 2400;
+
+    // new funding period starts over
+    amm.last_funding_rate_ts = 3600;
+    update_premium_index(
+        &mut amm,
+        4000,
+        98 * PRICE_PRECISION_I64,
+        99 * PRICE_PRECISION_I64,
+    )
+    .unwrap();
+    assert_eq!(amm.last_premium_index, -PRICE_PRECISION_I64);
+}
@@ -90,7 +90,8 @@ pub fn calculate_auction_price(
         OrderType::Market
         | OrderType::TriggerMarket
         | OrderType::Limit
-        | OrderType::TriggerLimit => {
+        | OrderType::TriggerLimit
+        | OrderType::TrailingStop => {
             calculate_auction_price_for_fixed_auction(order, slot, tick_size)
         }
         OrderType::Oracle => calculate_auction_price_for_oracle_offset_auction(
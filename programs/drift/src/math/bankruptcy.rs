@@ -1,11 +1,15 @@
+use crate::error::DriftResult;
+use crate::math::casting::Cast;
+use crate::math::safe_math::SafeMath;
 use crate::state::spot_market::SpotBalanceType;
-use crate::state::user::User;
+use crate::state::user::{PerpPosition, User};
 
 #[cfg(test)]
 mod tests;
 
 pub fn is_user_bankrupt(user: &User) -> bool {
-    // user is bankrupt iff they have spot liabilities, no spot assets, and no perp exposure
+    // user is bankrupt iff they have spot liabilities, no spot assets, and no perp exposure.
+    // fixed term positions count as the spot balance they settle into
 
     let mut has_liability = false;
 
@@ -18,7 +22,22 @@ pub fn is_user_bankrupt(user: &User) -> bool {
         }
     }
 
+    for fixed_term_position in user.fixed_term_positions.iter() {
+        if fixed_term_position.amount_at_maturity > 0 || fixed_term_position.has_open_order() {
+            return false;
+        }
+
+        if fixed_term_position.amount_at_maturity < 0 {
+            has_liability = true;
+        }
+    }
+
     for perp_position in user.perp_positions.iter() {
+        // isolated positions go bankrupt on their own, see is_isolated_position_bankrupt
+        if perp_position.is_isolated() {
+            continue;
+        }
+
         if perp_position.base_asset_amount != 0
             || perp_position.quote_asset_amount > 0
             || perp_position.has_open_order()
@@ -34,3 +53,24 @@ pub fn is_user_bankrupt(user: &User) -> bool {
 
     has_liability
 }
+
+pub fn is_isolated_position_bankrupt(
+    perp_position: &PerpPosition,
+    isolated_token_amount: u128,
+) -> DriftResult<bool> {
+    // isolated position is bankrupt iff it has no exposure and its negative pnl exceeds its collateral
+
+    if !perp_position.is_isolated()
+        || perp_position.base_asset_amount != 0
+        || perp_position.has_open_order()
+        || perp_position.is_lp()
+    {
+        return Ok(false);
+    }
+
+    Ok(perp_position
+        .quote_asset_amount
+        .cast::<i128>()?
+        .safe_add(isolated_token_amount.cast()?)?
+        < 0)
+}
@@ -1,6 +1,7 @@
-use crate::math::bankruptcy::is_user_bankrupt;
+use crate::math::bankruptcy::{is_isolated_position_bankrupt, is_user_bankrupt};
+use crate::math::constants::QUOTE_PRECISION;
 use crate::state::spot_market::SpotBalanceType;
-use crate::state::user::{PerpPosition, SpotPosition, User};
+use crate::state::user::{FixedTermPosition, PerpPosition, PositionFlag, SpotPosition, User};
 use crate::test_utils::{get_positions, get_spot_positions};
 
 #[test]
@@ -46,6 +47,37 @@ fn user_with_deposit() {
     assert!(!is_bankrupt);
 }
 
+#[test]
+fn user_with_fixed_term_lend() {
+    let mut user = User {
+        spot_positions: get_spot_positions(SpotPosition {
+            balance_type: SpotBalanceType::Borrow,
+            scaled_balance: 1,
+            ..SpotPosition::default()
+        }),
+        ..User::default()
+    };
+    user.fixed_term_positions[0] = FixedTermPosition {
+        amount_at_maturity: 1,
+        ..FixedTermPosition::default()
+    };
+
+    let is_bankrupt = is_user_bankrupt(&user);
+    assert!(!is_bankrupt);
+}
+
+#[test]
+fn user_with_fixed_term_borrow() {
+    let mut user = User::default();
+    user.fixed_term_positions[0] = FixedTermPosition {
+        amount_at_maturity: -1,
+        ..FixedTermPosition::default()
+    };
+
+    let is_bankrupt = is_user_bankrupt(&user);
+    assert!(is_bankrupt);
+}
+
 #[test]
 fn user_has_position_with_negative_quote() {
     let user = User {
@@ -81,3 +113,43 @@ fn user_with_empty_position_and_balances() {
     let is_bankrupt = is_user_bankrupt(&user);
     assert!(!is_bankrupt);
 }
+
+#[test]
+fn user_with_isolated_position_negative_quote() {
+    let user = User {
+        perp_positions: get_positions(PerpPosition {
+            quote_asset_amount: -1,
+            position_flag: PositionFlag::IsolatedPosition as u8,
+            ..PerpPosition::default()
+        }),
+        ..User::default()
+    };
+
+    // the isolated loss doesn't make the cross account bankrupt
+    let is_bankrupt = is_user_bankrupt(&user);
+    assert!(!is_bankrupt);
+}
+
+#[test]
+fn isolated_position_loss_covered_by_collateral() {
+    let perp_position = PerpPosition {
+        quote_asset_amount: -(QUOTE_PRECISION as i64),
+        position_flag: PositionFlag::IsolatedPosition as u8,
+        ..PerpPosition::default()
+    };
+
+    assert!(!is_isolated_position_bankrupt(&perp_position, QUOTE_PRECISION).unwrap());
+    assert!(is_isolated_position_bankrupt(&perp_position, QUOTE_PRECISION - 1).unwrap());
+}
+
+#[test]
+fn isolated_position_with_base() {
+    let perp_position = PerpPosition {
+        base_asset_amount: 1,
+        quote_asset_amount: -(QUOTE_PRECISION as i64),
+        position_flag: PositionFlag::IsolatedPosition as u8,
+        ..PerpPosition::default()
+    };
+
+    assert!(!is_isolated_position_bankrupt(&perp_position, 0).unwrap());
+}
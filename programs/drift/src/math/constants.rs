@@ -9,6 +9,7 @@ pub const QUOTE_SPOT_MARKET_INDEX: u16 = 0;
 pub const MAX_SPOT_POSITIONS: u8 = 8;
 pub const MAX_PERP_POSITIONS: u8 = 8;
 pub const MAX_OPEN_ORDERS: u8 = 32;
+pub const MAX_SHARED_MARGIN_SUB_ACCOUNTS: u16 = 4;
 
 // PRECISIONS
 pub const AMM_RESERVE_PRECISION: u128 = 1_000_000_000; //expo = -9;
@@ -179,6 +180,8 @@ pub const MAX_MARK_TWAP_DIVERGENCE: u128 = 500_000; // expo = -3
 
 pub const MAX_MARGIN_RATIO: u32 = MARGIN_PRECISION; // 1x leverage
 pub const MIN_MARGIN_RATIO: u32 = MARGIN_PRECISION / 50; // 50x leverage
+pub const PORTFOLIO_MARGIN_BASIS_SHOCK_DIVISOR: u128 = 4; // hedge leg lags the price shock by 25%
+pub const LIQUIDATION_PRICE_SEARCH_MAX_MULTIPLIER: i64 = 100; // short liquidation prices are searched up to 100x the oracle price
 
 pub const MAX_BID_ASK_INVENTORY_SKEW_FACTOR: u64 = 10 * BID_ASK_SPREAD_PRECISION;
 
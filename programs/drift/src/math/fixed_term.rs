@@ -0,0 +1,51 @@
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
+use crate::math::constants::{ONE_YEAR, SPOT_RATE_PRECISION};
+use crate::math::safe_math::SafeMath;
+use crate::validate;
+use solana_program::msg;
+
+#[cfg(test)]
+mod tests;
+
+/// The amount owed at maturity for principal lent from now until maturity at an annualized fixed rate
+pub fn calculate_fixed_term_amount_at_maturity(
+    principal: u64,
+    rate: u32,
+    now: i64,
+    maturity_ts: i64,
+) -> DriftResult<u64> {
+    let time_to_maturity = maturity_ts.safe_sub(now)?.max(0).cast::<u128>()?;
+
+    let interest = principal
+        .cast::<u128>()?
+        .safe_mul(rate.cast()?)?
+        .safe_mul(time_to_maturity)?
+        .safe_div(ONE_YEAR)?
+        .safe_div(SPOT_RATE_PRECISION)?;
+
+    principal.safe_add(interest.cast()?)
+}
+
+/// Lend and borrow orders cross when the lender accepts a rate at or below what the borrower pays.
+/// The order placed first is the maker and sets the rate
+pub fn calculate_fixed_term_fill_rate(
+    lender_rate: u32,
+    lender_order_slot: u64,
+    borrower_rate: u32,
+    borrower_order_slot: u64,
+) -> DriftResult<u32> {
+    validate!(
+        lender_rate <= borrower_rate,
+        ErrorCode::FixedTermOrdersDontCross,
+        "lend rate {} above borrow rate {}",
+        lender_rate,
+        borrower_rate
+    )?;
+
+    if borrower_order_slot < lender_order_slot {
+        Ok(borrower_rate)
+    } else {
+        Ok(lender_rate)
+    }
+}
@@ -0,0 +1,41 @@
+mod calculate_fixed_term_amount_at_maturity {
+    use crate::math::constants::{QUOTE_PRECISION_U64, SPOT_RATE_PRECISION_U32};
+    use crate::math::fixed_term::calculate_fixed_term_amount_at_maturity;
+
+    #[test]
+    fn thirty_days() {
+        let principal = 1000 * QUOTE_PRECISION_U64;
+        let rate = SPOT_RATE_PRECISION_U32 / 10; // 10%
+        let now = 0;
+        let maturity_ts = 30 * 24 * 60 * 60;
+
+        let amount_at_maturity =
+            calculate_fixed_term_amount_at_maturity(principal, rate, now, maturity_ts).unwrap();
+        assert_eq!(amount_at_maturity, 1008219178);
+
+        // no interest accrues past maturity
+        let amount_at_maturity =
+            calculate_fixed_term_amount_at_maturity(principal, rate, maturity_ts + 1, maturity_ts)
+                .unwrap();
+        assert_eq!(amount_at_maturity, principal);
+    }
+}
+
+mod calculate_fixed_term_fill_rate {
+    use crate::error::ErrorCode;
+    use crate::math::fixed_term::calculate_fixed_term_fill_rate;
+
+    #[test]
+    fn maker_sets_rate() {
+        // lender placed first
+        let rate = calculate_fixed_term_fill_rate(50000, 1, 60000, 2).unwrap();
+        assert_eq!(rate, 50000);
+
+        // borrower placed first
+        let rate = calculate_fixed_term_fill_rate(50000, 2, 60000, 1).unwrap();
+        assert_eq!(rate, 60000);
+
+        let result = calculate_fixed_term_fill_rate(60000, 1, 50000, 2);
+        assert_eq!(result, Err(ErrorCode::FixedTermOrdersDontCross));
+    }
+}
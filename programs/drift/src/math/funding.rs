@@ -7,7 +7,8 @@ use crate::math::bn;
 use crate::math::casting::Cast;
 use crate::math::constants::{
     AMM_TO_QUOTE_PRECISION_RATIO, AMM_TO_QUOTE_PRECISION_RATIO_I128, FUNDING_RATE_BUFFER,
-    PRICE_PRECISION, QUOTE_TO_BASE_AMT_FUNDING_PRECISION,
+    FUNDING_RATE_OFFSET_DENOMINATOR, PERCENTAGE_PRECISION_I128, PRICE_PRECISION,
+    QUOTE_TO_BASE_AMT_FUNDING_PRECISION,
 };
 use crate::math::repeg::{calculate_fee_pool, get_total_fee_lower_bound};
 use crate::math::safe_math::SafeMath;
@@ -25,6 +26,11 @@ pub fn calculate_funding_rate_long_short(
     market: &mut PerpMarket,
     funding_rate: i128,
 ) -> DriftResult<(i128, i128, i128)> {
+    // funding is capped at the market's max funding rate, from its contract tier unless configured
+    let max_funding_rate =
+        market.get_max_funding_rate(market.amm.historical_oracle_data.last_oracle_price_twap)?;
+    let funding_rate = funding_rate.clamp(-max_funding_rate, max_funding_rate);
+
     // Calculate the funding payment owed by the net_market_position if funding is not capped
     // If the net market position owes funding payment, the protocol receives payment
     let settled_net_market_position = market
@@ -51,8 +57,12 @@ pub fn calculate_funding_rate_long_short(
         return Ok((funding_rate, funding_rate, uncapped_funding_pnl));
     }
 
-    let (capped_funding_rate, capped_funding_pnl) =
-        calculate_capped_funding_rate(market, uncapped_funding_pnl, funding_rate)?;
+    let (capped_funding_rate, capped_funding_pnl) = calculate_capped_funding_rate(
+        market,
+        uncapped_funding_pnl,
+        funding_rate,
+        max_funding_rate,
+    )?;
 
     let new_total_fee_minus_distributions = market
         .amm
@@ -95,6 +105,7 @@ fn calculate_capped_funding_rate(
     market: &PerpMarket,
     uncapped_funding_pnl: i128, // if negative, users would net receive from protocol
     funding_rate: i128,
+    max_funding_rate: i128,
 ) -> DriftResult<(i128, i128)> {
     // The funding_rate_pnl_limit is the amount of fees the protocol can use before it hits it's lower bound
     let fee_pool = calculate_fee_pool(market)?;
@@ -138,6 +149,9 @@ fn calculate_capped_funding_rate(
         funding_rate
     };
 
+    // the side receiving funding never gets more than the market's max funding rate
+    let capped_funding_rate = capped_funding_rate.clamp(-max_funding_rate, max_funding_rate);
+
     Ok((capped_funding_rate, capped_funding_pnl))
 }
 
@@ -212,3 +226,48 @@ pub fn calculate_funding_payment_in_quote_precision(
 
     Ok(funding_payment_collateral)
 }
+
+/// The premium of the bid over the oracle less the discount of the ask under it.
+/// 0 while the oracle is inside the bid/ask spread
+pub fn calculate_premium(bid_price: i64, ask_price: i64, oracle_price: i64) -> DriftResult<i64> {
+    let bid_premium = bid_price.safe_sub(oracle_price)?.max(0);
+    let ask_discount = oracle_price.safe_sub(ask_price)?.max(0);
+
+    bid_premium.safe_sub(ask_discount)
+}
+
+/// The daily price spread funding is paid on. Follows the premium index, pulled towards the
+/// interest rate by at most the premium clamp. Markets without either keep the legacy offset
+pub fn calculate_funding_price_spread(
+    premium_index: i64,
+    oracle_price_twap: i64,
+    funding_interest_rate: i32,
+    funding_premium_clamp: u16,
+) -> DriftResult<i64> {
+    if funding_interest_rate == 0 && funding_premium_clamp == 0 {
+        // add offset 1/FUNDING_RATE_OFFSET_DENOMINATOR*365. if FUNDING_RATE_OFFSET_DENOMINATOR = 5000 => 7.3% annualized rate
+        return premium_index.safe_add(
+            oracle_price_twap
+                .abs()
+                .safe_div(FUNDING_RATE_OFFSET_DENOMINATOR)?,
+        );
+    }
+
+    let oracle_price_twap = oracle_price_twap.abs().cast::<i128>()?;
+
+    let interest = oracle_price_twap
+        .safe_mul(funding_interest_rate.cast()?)?
+        .safe_div(PERCENTAGE_PRECISION_I128)?
+        .cast::<i64>()?;
+
+    let max_interest_adjustment = oracle_price_twap
+        .safe_mul(funding_premium_clamp.cast()?)?
+        .safe_div(PERCENTAGE_PRECISION_I128)?
+        .cast::<i64>()?;
+
+    premium_index.safe_add(
+        interest
+            .safe_sub(premium_index)?
+            .clamp(-max_interest_adjustment, max_interest_adjustment),
+    )
+}
@@ -710,15 +710,137 @@ fn unsettled_funding_pnl() {
         51000000
     );
 
-    assert_eq!(market.amm.cumulative_funding_rate_long, -140002666); // negative funding
-    assert_eq!(market.amm.cumulative_funding_rate_short, -140002666);
-    assert_eq!(market.amm.last_funding_rate, -140002666);
-    assert_eq!(market.amm.last_24h_avg_funding_rate, -140002666 / 24 + 1);
+    // premium index is the amm ask's discount to the oracle
+    assert_eq!(market.amm.last_premium_index, -3371200);
+    assert_eq!(market.amm.cumulative_funding_rate_long, -140041666); // negative funding
+    assert_eq!(market.amm.cumulative_funding_rate_short, -140041666);
+    assert_eq!(market.amm.last_funding_rate, -140041666);
+    assert_eq!(market.amm.last_24h_avg_funding_rate, -140041666 / 24 + 1);
     assert_eq!(market.amm.last_funding_rate_ts, now);
     assert_eq!(market.amm.net_revenue_since_last_funding, 0); // back to 0
-    assert_eq!(market.amm.total_fee_minus_distributions, 100070722677); //71.722677 gain
+    assert_eq!(market.amm.total_fee_minus_distributions, 100070742656); //71.742656 gain
     assert_eq!(market.amm.total_fee, 0);
 
     assert_ne!(market.amm.net_unsettled_funding_pnl, 0); // important: imbalanced market adds funding rev
-    assert_eq!(market.amm.net_unsettled_funding_pnl, -71722677); // users up
+    assert_eq!(market.amm.net_unsettled_funding_pnl, -71742656); // users up
+}
+
+#[test]
+fn premium_index_funding_price_spread() {
+    let price = PRICE_PRECISION as i64;
+
+    assert_eq!(
+        calculate_premium(99 * price, 101 * price, 100 * price).unwrap(),
+        0
+    );
+    assert_eq!(
+        calculate_premium(101 * price, 102 * price, 100 * price).unwrap(),
+        price
+    );
+    assert_eq!(
+        calculate_premium(98 * price, 99 * price, 100 * price).unwrap(),
+        -price
+    );
+
+    // no interest rate or clamp, funding adds the legacy 1/5000 daily offset
+    let spread = calculate_funding_price_spread(price / 10, 100 * price, 0, 0).unwrap();
+    assert_eq!(spread, price / 10 + price / 50);
+
+    // no clamp, funding is the premium index
+    let spread = calculate_funding_price_spread(price / 10, 100 * price, 100, 0).unwrap();
+    assert_eq!(spread, price / 10);
+
+    // 0.01% interest with a 0.05% clamp, premium within the clamp of interest pays interest
+    let spread = calculate_funding_price_spread(0, 100 * price, 100, 500).unwrap();
+    assert_eq!(spread, price / 100);
+    let spread = calculate_funding_price_spread(price / 25, 100 * price, 100, 500).unwrap();
+    assert_eq!(spread, price / 100);
+
+    // premium beyond the clamp is only pulled in by the clamp
+    let spread = calculate_funding_price_spread(price / 10, 100 * price, 100, 500).unwrap();
+    assert_eq!(spread, price / 20);
+    let spread = calculate_funding_price_spread(-price / 10, 100 * price, 100, 500).unwrap();
+    assert_eq!(spread, -price / 20);
+}
+
+#[test]
+fn configured_funding_rate_caps() {
+    // more longs than shorts, positive funding, amm earns funding
+    let mut market = PerpMarket {
+        amm: AMM {
+            base_asset_reserve: 512295081967,
+            quote_asset_reserve: 488 * AMM_RESERVE_PRECISION,
+            sqrt_k: 500 * AMM_RESERVE_PRECISION,
+            peg_multiplier: 50000000,
+            base_asset_amount_with_amm: 12295081967,
+            base_asset_amount_long: 12295081967 * 2,
+            base_asset_amount_short: -12295081967,
+            total_exchange_fee: QUOTE_PRECISION / 2,
+            total_fee_minus_distributions: (QUOTE_PRECISION as i128) / 2,
+            last_mark_price_twap: 50 * PRICE_PRECISION_U64,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: (49 * PRICE_PRECISION) as i64,
+
+                ..HistoricalOracleData::default()
+            },
+            funding_period: 3600,
+
+            ..AMM::default()
+        },
+        contract_tier: ContractTier::C,
+        ..PerpMarket::default()
+    };
+
+    // contract tier C defaults to a 5% price divergence paid over a day
+    assert_eq!(
+        market
+            .get_max_price_divergence_for_funding_rate(
+                market.amm.historical_oracle_data.last_oracle_price_twap
+            )
+            .unwrap(),
+        2450000
+    );
+    assert_eq!(
+        market
+            .get_max_funding_rate(market.amm.historical_oracle_data.last_oracle_price_twap)
+            .unwrap(),
+        102083333
+    );
+
+    let (long_funding, short_funding, _) =
+        calculate_funding_rate_long_short(&mut market, 41666666).unwrap();
+    assert_eq!(long_funding, 41666666);
+    assert_eq!(short_funding, 41666666);
+
+    // .05% per hour
+    market.max_hourly_funding_rate = 500;
+    market.max_funding_price_divergence = 20000;
+    assert_eq!(
+        market
+            .get_max_price_divergence_for_funding_rate(
+                market.amm.historical_oracle_data.last_oracle_price_twap
+            )
+            .unwrap(),
+        980000
+    );
+    assert_eq!(
+        market
+            .get_max_funding_rate(market.amm.historical_oracle_data.last_oracle_price_twap)
+            .unwrap(),
+        24500000
+    );
+
+    let (long_funding, short_funding, _) =
+        calculate_funding_rate_long_short(&mut market, 41666666).unwrap();
+    assert_eq!(long_funding, 24500000);
+    assert_eq!(short_funding, 24500000);
+
+    // max hourly rate is per hour, longer funding periods pay more
+    market.amm.funding_period = 8 * 3600;
+    assert_eq!(
+        market
+            .get_max_funding_rate(market.amm.historical_oracle_data.last_oracle_price_twap)
+            .unwrap(),
+        196000000
+    );
 }
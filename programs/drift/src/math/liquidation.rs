@@ -3,13 +3,15 @@ use crate::math::casting::Cast;
 use crate::math::constants::{
     AMM_RESERVE_PRECISION_I128, FUNDING_RATE_TO_QUOTE_PRECISION_PRECISION_RATIO,
     LIQUIDATION_FEE_PRECISION, LIQUIDATION_FEE_PRECISION_U128,
-    LIQUIDATION_FEE_TO_MARGIN_PRECISION_RATIO, LIQUIDATION_PCT_PRECISION, PRICE_PRECISION,
-    PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO, QUOTE_PRECISION, SPOT_WEIGHT_PRECISION_U128,
+    LIQUIDATION_FEE_TO_MARGIN_PRECISION_RATIO, LIQUIDATION_PCT_PRECISION, PERCENTAGE_PRECISION,
+    PERCENTAGE_PRECISION_I128, PRICE_PRECISION, PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO,
+    QUOTE_PRECISION, SPOT_WEIGHT_PRECISION_U128,
 };
 use crate::math::margin::calculate_margin_requirement_and_total_collateral_and_liability_info;
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::get_token_amount;
 
+use crate::controller::position::PositionDirection;
 use crate::math::spot_swap::calculate_swap_price;
 use crate::state::margin_calculation::MarginContext;
 use crate::state::oracle_map::OracleMap;
@@ -253,6 +255,31 @@ pub fn calculate_liquidation_multiplier(
     }
 }
 
+/// Linearly grows the liquidator fee from start to end over the liquidation duration
+pub fn calculate_liquidation_auction_liquidator_fee(
+    start_liquidator_fee: u32,
+    end_liquidator_fee: u32,
+    slots_elapsed: u64,
+    liquidation_duration: u64,
+) -> DriftResult<u32> {
+    if end_liquidator_fee <= start_liquidator_fee {
+        return Ok(start_liquidator_fee);
+    }
+
+    if liquidation_duration == 0 || slots_elapsed >= liquidation_duration {
+        return Ok(end_liquidator_fee);
+    }
+
+    let fee_delta = end_liquidator_fee
+        .safe_sub(start_liquidator_fee)?
+        .cast::<u64>()?
+        .safe_mul(slots_elapsed)?
+        .safe_div(liquidation_duration)?
+        .cast::<u32>()?;
+
+    start_liquidator_fee.safe_add(fee_delta)
+}
+
 pub fn calculate_funding_rate_deltas_to_resolve_bankruptcy(
     loss: i128,
     market: &PerpMarket,
@@ -275,6 +302,80 @@ pub fn calculate_funding_rate_deltas_to_resolve_bankruptcy(
         .safe_mul(FUNDING_RATE_TO_QUOTE_PRECISION_PRECISION_RATIO.cast()?)
 }
 
+/// Ranks profitable positions for auto-deleveraging by pnl relative to entry times leverage, so the
+/// most profitable and most levered positions are closed first
+pub fn calculate_auto_deleverage_score(
+    unrealized_pnl: i128,
+    quote_entry_amount: i64,
+    base_asset_value: u128,
+    total_collateral: i128,
+) -> DriftResult<u128> {
+    if unrealized_pnl <= 0 || quote_entry_amount == 0 {
+        return Ok(0);
+    }
+
+    let pnl_pct = unrealized_pnl
+        .unsigned_abs()
+        .safe_mul(PERCENTAGE_PRECISION)?
+        .safe_div(quote_entry_amount.unsigned_abs().cast()?)?;
+
+    // a profitable position with no collateral left is treated as maximally levered
+    let leverage = base_asset_value
+        .safe_mul(PERCENTAGE_PRECISION)?
+        .safe_div(total_collateral.max(1).unsigned_abs())?;
+
+    pnl_pct.safe_mul(leverage)?.safe_div(PERCENTAGE_PRECISION)
+}
+
+/// Pnl relative to entry for a position, or for one side of a market from its aggregate base and
+/// quote entry amounts. Auto deleverage candidates must be at least as profitable as their side
+pub fn calculate_pnl_pct_from_entry(
+    base_asset_amount: i128,
+    quote_entry_amount: i128,
+    oracle_price: i64,
+) -> DriftResult<i128> {
+    if base_asset_amount == 0 || quote_entry_amount == 0 {
+        return Ok(0);
+    }
+
+    let base_asset_value = base_asset_amount
+        .safe_mul(oracle_price.cast()?)?
+        .safe_div(PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO.cast()?)?;
+
+    base_asset_value
+        .safe_add(quote_entry_amount)?
+        .safe_mul(PERCENTAGE_PRECISION_I128)?
+        .safe_div(quote_entry_amount.abs())
+}
+
+/// The price an auto deleveraged position is closed at: the oracle price moved against the position
+/// by the pnl it pays toward the bankruptcy loss
+pub fn calculate_auto_deleverage_bankruptcy_price(
+    oracle_price: i64,
+    base_asset_amount: u64,
+    pnl_payment: u128,
+    direction_to_close: PositionDirection,
+) -> DriftResult<u64> {
+    let price_delta = pnl_payment
+        .safe_mul(BASE_PRECISION)?
+        .safe_div(base_asset_amount.cast()?)?
+        .cast::<i64>()?;
+
+    let bankruptcy_price = match direction_to_close {
+        PositionDirection::Long => oracle_price.safe_add(price_delta)?,
+        PositionDirection::Short => oracle_price.safe_sub(price_delta)?,
+    };
+
+    validate!(
+        bankruptcy_price > 0,
+        ErrorCode::InvalidAutoDeleverageUser,
+        "bankruptcy_price={} must be positive",
+        bankruptcy_price
+    )?;
+
+    bankruptcy_price.cast()
+}
+
 pub fn calculate_cumulative_deposit_interest_delta_to_resolve_bankruptcy(
     borrow: u128,
     spot_market: &SpotMarket,
@@ -320,8 +421,11 @@ pub fn validate_transfer_satisfies_limit_price(
     )
 }
 
+/// The share of the margin shortage that can be liquidated now. It grows from initial_pct_to_liquidate
+/// to 100% over liquidation_duration slots from when the liquidation started, less what's been freed
 pub fn calculate_max_pct_to_liquidate(
-    user: &User,
+    liquidation_start_slot: u64,
+    liquidation_margin_freed: u64,
     margin_shortage: u128,
     slot: u64,
     initial_pct_to_liquidate: u128,
@@ -332,7 +436,7 @@ pub fn calculate_max_pct_to_liquidate(
         return Ok(LIQUIDATION_PCT_PRECISION);
     }
 
-    let slots_elapsed = slot.safe_sub(user.last_active_slot)?;
+    let slots_elapsed = slot.safe_sub(liquidation_start_slot)?;
 
     let pct_freeable = slots_elapsed
         .cast::<u128>()?
@@ -342,11 +446,11 @@ pub fn calculate_max_pct_to_liquidate(
         .safe_add(initial_pct_to_liquidate)?
         .min(LIQUIDATION_PCT_PRECISION);
 
-    let total_margin_shortage = margin_shortage.safe_add(user.liquidation_margin_freed.cast()?)?;
+    let total_margin_shortage = margin_shortage.safe_add(liquidation_margin_freed.cast()?)?;
     let max_margin_freed = total_margin_shortage
         .safe_mul(pct_freeable)?
         .safe_div(LIQUIDATION_PCT_PRECISION)?;
-    let margin_freeable = max_margin_freed.saturating_sub(user.liquidation_margin_freed.cast()?);
+    let margin_freeable = max_margin_freed.saturating_sub(liquidation_margin_freed.cast()?);
 
     margin_freeable
         .safe_mul(LIQUIDATION_PCT_PRECISION)?
@@ -415,6 +415,37 @@ mod calculate_asset_transfer_for_liability_transfer {
     }
 }
 
+mod calculate_liquidation_auction_liquidator_fee {
+    use crate::math::constants::LIQUIDATION_FEE_PRECISION;
+    use crate::math::liquidation::calculate_liquidation_auction_liquidator_fee;
+
+    #[test]
+    fn grows_linearly_over_duration() {
+        let start_fee = LIQUIDATION_FEE_PRECISION / 100; // 1%
+        let end_fee = LIQUIDATION_FEE_PRECISION / 20; // 5%
+
+        let fee = calculate_liquidation_auction_liquidator_fee(start_fee, end_fee, 0, 150).unwrap();
+        assert_eq!(fee, start_fee);
+
+        let fee =
+            calculate_liquidation_auction_liquidator_fee(start_fee, end_fee, 75, 150).unwrap();
+        assert_eq!(fee, 30000); // 3%
+
+        let fee =
+            calculate_liquidation_auction_liquidator_fee(start_fee, end_fee, 300, 150).unwrap();
+        assert_eq!(fee, end_fee);
+
+        // no duration jumps straight to the end fee
+        let fee = calculate_liquidation_auction_liquidator_fee(start_fee, end_fee, 0, 0).unwrap();
+        assert_eq!(fee, end_fee);
+
+        // end fee below start never lowers the fee
+        let fee =
+            calculate_liquidation_auction_liquidator_fee(end_fee, start_fee, 75, 150).unwrap();
+        assert_eq!(fee, end_fee);
+    }
+}
+
 mod calculate_funding_rate_deltas_to_resolve_bankruptcy {
     use crate::math::constants::{BASE_PRECISION_I128, QUOTE_PRECISION_I128};
     use crate::math::liquidation::calculate_funding_rate_deltas_to_resolve_bankruptcy;
@@ -454,6 +485,135 @@ mod calculate_funding_rate_deltas_to_resolve_bankruptcy {
     }
 }
 
+mod calculate_auto_deleverage_score {
+    use crate::math::constants::{QUOTE_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64};
+    use crate::math::liquidation::calculate_auto_deleverage_score;
+
+    #[test]
+    fn losing_position_not_ranked() {
+        let score = calculate_auto_deleverage_score(
+            -10 * QUOTE_PRECISION_I128,
+            -100 * QUOTE_PRECISION_I64,
+            90 * QUOTE_PRECISION,
+            100 * QUOTE_PRECISION_I128,
+        )
+        .unwrap();
+
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn levered_position_ranked_above_same_pnl() {
+        // up 20% at 1x
+        let score = calculate_auto_deleverage_score(
+            20 * QUOTE_PRECISION_I128,
+            -100 * QUOTE_PRECISION_I64,
+            120 * QUOTE_PRECISION,
+            120 * QUOTE_PRECISION_I128,
+        )
+        .unwrap();
+
+        assert_eq!(score, 200000);
+
+        // up 20% at 4x
+        let levered_score = calculate_auto_deleverage_score(
+            20 * QUOTE_PRECISION_I128,
+            -100 * QUOTE_PRECISION_I64,
+            120 * QUOTE_PRECISION,
+            30 * QUOTE_PRECISION_I128,
+        )
+        .unwrap();
+
+        assert_eq!(levered_score, 800000);
+
+        // no collateral left
+        let no_collateral_score = calculate_auto_deleverage_score(
+            20 * QUOTE_PRECISION_I128,
+            -100 * QUOTE_PRECISION_I64,
+            120 * QUOTE_PRECISION,
+            0,
+        )
+        .unwrap();
+
+        assert!(no_collateral_score > levered_score);
+    }
+}
+
+mod calculate_pnl_pct_from_entry {
+    use crate::math::constants::{BASE_PRECISION_I128, PRICE_PRECISION_I64, QUOTE_PRECISION_I128};
+    use crate::math::liquidation::calculate_pnl_pct_from_entry;
+
+    #[test]
+    fn long_and_short() {
+        // long 1 from $80, oracle $100
+        let pnl_pct = calculate_pnl_pct_from_entry(
+            BASE_PRECISION_I128,
+            -80 * QUOTE_PRECISION_I128,
+            100 * PRICE_PRECISION_I64,
+        )
+        .unwrap();
+
+        assert_eq!(pnl_pct, 250000);
+
+        // short 5 from $128, oracle $100
+        let pnl_pct = calculate_pnl_pct_from_entry(
+            -5 * BASE_PRECISION_I128,
+            640 * QUOTE_PRECISION_I128,
+            100 * PRICE_PRECISION_I64,
+        )
+        .unwrap();
+
+        assert_eq!(pnl_pct, 218750);
+
+        let pnl_pct = calculate_pnl_pct_from_entry(0, 0, 100 * PRICE_PRECISION_I64).unwrap();
+
+        assert_eq!(pnl_pct, 0);
+    }
+}
+
+mod calculate_auto_deleverage_bankruptcy_price {
+    use crate::controller::position::PositionDirection;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        BASE_PRECISION_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION,
+    };
+    use crate::math::liquidation::calculate_auto_deleverage_bankruptcy_price;
+
+    #[test]
+    fn moved_against_position() {
+        // closing a short pays $40 over 2 base above the oracle
+        let bankruptcy_price = calculate_auto_deleverage_bankruptcy_price(
+            100 * PRICE_PRECISION_I64,
+            2 * BASE_PRECISION_U64,
+            40 * QUOTE_PRECISION,
+            PositionDirection::Long,
+        )
+        .unwrap();
+
+        assert_eq!(bankruptcy_price, 120 * PRICE_PRECISION_U64);
+
+        // closing a long sells below the oracle
+        let bankruptcy_price = calculate_auto_deleverage_bankruptcy_price(
+            100 * PRICE_PRECISION_I64,
+            2 * BASE_PRECISION_U64,
+            40 * QUOTE_PRECISION,
+            PositionDirection::Short,
+        )
+        .unwrap();
+
+        assert_eq!(bankruptcy_price, 80 * PRICE_PRECISION_U64);
+
+        let result = calculate_auto_deleverage_bankruptcy_price(
+            100 * PRICE_PRECISION_I64,
+            BASE_PRECISION_U64,
+            100 * QUOTE_PRECISION,
+            PositionDirection::Short,
+        );
+
+        assert_eq!(result, Err(ErrorCode::InvalidAutoDeleverageUser));
+    }
+}
+
 mod calculate_cumulative_deposit_interest_delta_to_resolve_bankruptcy {
     use crate::math::constants::{
         QUOTE_PRECISION, SPOT_BALANCE_PRECISION, SPOT_CUMULATIVE_INTEREST_PRECISION,
@@ -806,16 +966,15 @@ mod calculate_spot_if_fee {
 
 mod calculate_max_pct_to_liquidate {
     use crate::math::liquidation::calculate_max_pct_to_liquidate;
-    use crate::state::user::User;
     use crate::{LIQUIDATION_PCT_PRECISION, QUOTE_PRECISION};
 
     #[test]
     fn test() {
-        let user = User::default();
         let margin_shortage = 49 * QUOTE_PRECISION;
 
         let pct = calculate_max_pct_to_liquidate(
-            &user,
+            0,
+            0,
             margin_shortage,
             1,
             LIQUIDATION_PCT_PRECISION / 10,
@@ -825,4 +984,35 @@ mod calculate_max_pct_to_liquidate {
 
         assert_eq!(pct, LIQUIDATION_PCT_PRECISION);
     }
+
+    #[test]
+    fn paced_from_liquidation_start() {
+        let margin_shortage = 100 * QUOTE_PRECISION;
+
+        // half way through, 10% + 50% of the shortage can be freed
+        let pct = calculate_max_pct_to_liquidate(
+            100,
+            0,
+            margin_shortage,
+            105,
+            LIQUIDATION_PCT_PRECISION / 10,
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(pct, LIQUIDATION_PCT_PRECISION * 6 / 10);
+
+        // margin already freed counts against the schedule
+        let pct = calculate_max_pct_to_liquidate(
+            100,
+            (50 * QUOTE_PRECISION) as u64,
+            margin_shortage,
+            105,
+            LIQUIDATION_PCT_PRECISION / 10,
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(pct, LIQUIDATION_PCT_PRECISION * 4 / 10);
+    }
 }
@@ -15,10 +15,16 @@ use crate::{validation, PRICE_PRECISION_I64};
 use crate::math::casting::Cast;
 use crate::math::funding::calculate_funding_payment;
 use crate::math::oracle::{is_oracle_valid_for_action, DriftAction};
+use crate::math::portfolio_margin::{
+    add_portfolio_margin_exposure, calculate_portfolio_margin_offset, PortfolioMarginGroup,
+};
 
 use crate::math::spot_balance::{get_strict_token_value, get_token_value};
 
 use crate::math::safe_math::SafeMath;
+use crate::state::events::{
+    MarginCalculationRecord, PerpPositionMarginRecord, SpotPositionMarginRecord,
+};
 use crate::state::margin_calculation::{MarginCalculation, MarginContext, MarketIdentifier};
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
 use crate::state::oracle_map::OracleMap;
@@ -26,15 +32,27 @@ use crate::state::perp_market::{ContractTier, MarketStatus, PerpMarket};
 use crate::state::perp_market_map::PerpMarketMap;
 use crate::state::spot_market::{AssetTier, SpotBalanceType};
 use crate::state::spot_market_map::SpotMarketMap;
-use crate::state::user::{MarketType, OrderFillSimulation, PerpPosition, User};
+use crate::state::user::{MarketType, OrderFillSimulation, PerpPosition, SpotPosition, User};
+use crate::state::user_map::UserMap;
+use anchor_lang::emit;
+use borsh::{BorshDeserialize, BorshSerialize};
 use num_integer::Roots;
 use solana_program::msg;
 use std::cmp::{max, min, Ordering};
 
+#[cfg(feature = "drift-rs")]
+use crate::math::constants::{LIQUIDATION_PRICE_SEARCH_MAX_MULTIPLIER, PERCENTAGE_PRECISION_I128};
+#[cfg(feature = "drift-rs")]
+use crate::math::spot_balance::get_spot_balance;
+#[cfg(feature = "drift-rs")]
+use solana_program::pubkey::Pubkey;
+#[cfg(feature = "drift-rs")]
+use std::collections::BTreeMap;
+
 #[cfg(test)]
 mod tests;
 
-#[derive(Clone, Copy, PartialEq, Debug, Eq)]
+#[derive(Clone, Copy, PartialEq, Debug, Eq, BorshSerialize, BorshDeserialize)]
 pub enum MarginRequirementType {
     Initial,
     Fill,
@@ -71,6 +89,28 @@ pub fn calculate_size_premium_liability_weight(
     Ok(max_liability_weight)
 }
 
+/// Multiple of a market's static margin ratio/weight haircut implied by its realized volatility,
+/// clamped to the market's floor and ceiling. precision: MARGIN_PRECISION
+pub fn calculate_volatility_margin_scale(
+    realized_volatility: u128, // MARGIN_PRECISION
+    volatility_margin_reference: u16,
+    volatility_margin_scale_floor: u16,
+    volatility_margin_scale_ceiling: u16,
+) -> DriftResult<u128> {
+    if volatility_margin_reference == 0 {
+        return Ok(MARGIN_PRECISION_U128);
+    }
+
+    let scale = realized_volatility
+        .safe_mul(MARGIN_PRECISION_U128)?
+        .safe_div(volatility_margin_reference.cast()?)?;
+
+    Ok(scale.clamp(
+        volatility_margin_scale_floor.cast()?,
+        volatility_margin_scale_ceiling.cast()?,
+    ))
+}
+
 pub fn calculate_size_discount_asset_weight(
     size: u128, // AMM_RESERVE_PRECISION
     imf_factor: u32,
@@ -242,6 +282,60 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     context: MarginContext,
+) -> DriftResult<MarginCalculation> {
+    calculate_margin_requirement_and_total_collateral_and_liability_info_with_record(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+        None,
+    )
+}
+
+/// Cross margin totals of a shared margin sub account and the rest of its group in the user map
+pub fn calculate_shared_margin_group_margin_calculation(
+    user: &User,
+    shared_margin_group: &UserMap,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+) -> DriftResult<MarginCalculation> {
+    let mut group_margin_calculation =
+        calculate_margin_requirement_and_total_collateral_and_liability_info(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            context,
+        )?;
+
+    for sub_account_key in shared_margin_group.0.keys() {
+        let sub_account = shared_margin_group.get_ref(sub_account_key)?;
+        let sub_account_margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                &sub_account,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                context,
+            )?;
+        group_margin_calculation.add_shared_margin_calculation(&sub_account_margin_calculation)?;
+    }
+
+    Ok(group_margin_calculation)
+}
+
+/// Same as calculate_margin_requirement_and_total_collateral_and_liability_info, optionally recording
+/// each position's contribution to the totals
+fn calculate_margin_requirement_and_total_collateral_and_liability_info_with_record(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+    mut record: Option<&mut MarginCalculationRecord>,
 ) -> DriftResult<MarginCalculation> {
     let mut calculation = MarginCalculation::new(context);
 
@@ -251,6 +345,10 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
         0_u32
     };
 
+    // in portfolio margin mode, positions on the same underlying are margined on their net exposure
+    let portfolio_margin = user.is_portfolio_margin();
+    let mut portfolio_margin_groups: Vec<PortfolioMarginGroup> = vec![];
+
     for spot_position in user.spot_positions.iter() {
         validation::position::validate_spot_position(spot_position)?;
 
@@ -258,180 +356,38 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
             continue;
         }
 
-        let spot_market = spot_market_map.get_ref(&spot_position.market_index)?;
-        let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
-            MarketType::Spot,
-            spot_market.market_index,
-            &spot_market.oracle,
-            spot_market.historical_oracle_data.last_oracle_price_twap,
-            spot_market.get_max_confidence_interval_multiplier()?,
+        add_spot_position_margin(
+            &mut calculation,
+            record.as_deref_mut(),
+            &mut portfolio_margin_groups,
+            spot_position,
+            spot_market_map,
+            oracle_map,
+            user_custom_margin_ratio,
+            portfolio_margin,
         )?;
+    }
 
-        calculation.update_all_oracles_valid(is_oracle_valid_for_action(
-            oracle_validity,
-            Some(DriftAction::MarginCalc),
-        )?);
-
-        let strict_oracle_price = StrictOraclePrice::new(
-            oracle_price_data.price,
-            spot_market
-                .historical_oracle_data
-                .last_oracle_price_twap_5min,
-            calculation.context.strict,
-        );
-        strict_oracle_price.validate()?;
-
-        if spot_market.market_index == 0 {
-            let token_amount = spot_position.get_signed_token_amount(&spot_market)?;
-            if token_amount == 0 {
-                validate!(
-                    spot_position.scaled_balance == 0,
-                    ErrorCode::InvalidMarginRatio,
-                    "spot_position.scaled_balance={} when token_amount={}",
-                    spot_position.scaled_balance,
-                    token_amount,
-                )?;
-            }
-
-            let token_value =
-                get_strict_token_value(token_amount, spot_market.decimals, &strict_oracle_price)?;
-
-            match spot_position.balance_type {
-                SpotBalanceType::Deposit => {
-                    calculation.add_total_collateral(token_value)?;
-
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_asset_value(token_value)?;
-                }
-                SpotBalanceType::Borrow => {
-                    let token_value = token_value.unsigned_abs();
-
-                    validate!(
-                        token_value != 0,
-                        ErrorCode::InvalidMarginRatio,
-                        "token_value=0 for token_amount={} in spot market_index={}",
-                        token_amount,
-                        spot_market.market_index,
-                    )?;
-
-                    calculation.add_margin_requirement(
-                        token_value,
-                        token_value,
-                        MarketIdentifier::spot(0),
-                    )?;
-
-                    calculation.add_spot_liability()?;
-
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_liability_value(token_value)?;
-                }
-            }
-        } else {
-            let signed_token_amount = spot_position.get_signed_token_amount(&spot_market)?;
-
-            let OrderFillSimulation {
-                token_amount: worst_case_token_amount,
-                orders_value: worst_case_orders_value,
-                token_value: worst_case_token_value,
-                weighted_token_value: worst_case_weighted_token_value,
-                ..
-            } = spot_position
-                .get_worst_case_fill_simulation(
-                    &spot_market,
-                    &strict_oracle_price,
-                    Some(signed_token_amount),
-                    context.margin_type,
-                )?
-                .apply_user_custom_margin_ratio(
-                    &spot_market,
-                    strict_oracle_price.current,
-                    user_custom_margin_ratio,
-                )?;
-
-            if worst_case_token_amount == 0 {
-                validate!(
-                    spot_position.scaled_balance == 0,
-                    ErrorCode::InvalidMarginRatio,
-                    "spot_position.scaled_balance={} when worst_case_token_amount={}",
-                    spot_position.scaled_balance,
-                    worst_case_token_amount,
-                )?;
-            }
-
-            calculation.add_margin_requirement(
-                spot_position.margin_requirement_for_open_orders()?,
-                0,
-                MarketIdentifier::spot(spot_market.market_index),
-            )?;
-
-            match worst_case_token_value.cmp(&0) {
-                Ordering::Greater => {
-                    calculation
-                        .add_total_collateral(worst_case_weighted_token_value.cast::<i128>()?)?;
-
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_asset_value(worst_case_token_value)?;
-                }
-                Ordering::Less => {
-                    validate!(
-                        worst_case_weighted_token_value.unsigned_abs() >= worst_case_token_value.unsigned_abs(),
-                        ErrorCode::InvalidMarginRatio,
-                        "weighted_token_value < abs(worst_case_token_value) in spot market_index={}",
-                        spot_market.market_index,
-                    )?;
-
-                    validate!(
-                        worst_case_weighted_token_value != 0,
-                        ErrorCode::InvalidOracle,
-                        "weighted_token_value=0 for worst_case_token_amount={} in spot market_index={}",
-                        worst_case_token_amount,
-                        spot_market.market_index,
-                    )?;
-
-                    calculation.add_margin_requirement(
-                        worst_case_weighted_token_value.unsigned_abs(),
-                        worst_case_token_value.unsigned_abs(),
-                        MarketIdentifier::spot(spot_market.market_index),
-                    )?;
-
-                    calculation.add_spot_liability()?;
-                    calculation.update_with_spot_isolated_liability(
-                        spot_market.asset_tier == AssetTier::Isolated,
-                    );
-
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_liability_value(worst_case_token_value.unsigned_abs())?;
-                }
-                Ordering::Equal => {
-                    if spot_position.has_open_order() {
-                        calculation.add_spot_liability()?;
-                        calculation.update_with_spot_isolated_liability(
-                            spot_market.asset_tier == AssetTier::Isolated,
-                        );
-                    }
-                }
-            }
-
-            match worst_case_orders_value.cmp(&0) {
-                Ordering::Greater => {
-                    calculation.add_total_collateral(worst_case_orders_value.cast::<i128>()?)?;
+    // fixed term loans are margined like the spot balance their amount at maturity settles into
+    for fixed_term_position in user.fixed_term_positions.iter() {
+        if fixed_term_position.amount_at_maturity == 0 {
+            continue;
+        }
 
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_asset_value(worst_case_orders_value)?;
-                }
-                Ordering::Less => {
-                    calculation.add_margin_requirement(
-                        worst_case_orders_value.unsigned_abs(),
-                        worst_case_orders_value.unsigned_abs(),
-                        MarketIdentifier::spot(0),
-                    )?;
+        let spot_position = fixed_term_position.get_settled_spot_position(
+            &*spot_market_map.get_ref(&fixed_term_position.market_index)?,
+        )?;
 
-                    #[cfg(feature = "drift-rs")]
-                    calculation.add_spot_liability_value(worst_case_orders_value.unsigned_abs())?;
-                }
-                Ordering::Equal => {}
-            }
-        }
+        add_spot_position_margin(
+            &mut calculation,
+            record.as_deref_mut(),
+            &mut portfolio_margin_groups,
+            &spot_position,
+            spot_market_map,
+            oracle_map,
+            user_custom_margin_ratio,
+            portfolio_margin,
+        )?;
     }
 
     for market_position in user.perp_positions.iter() {
@@ -465,6 +421,18 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
                 .last_oracle_price_twap_5min,
             calculation.context.strict,
         );
+
+        let isolated_collateral = if market_position.is_isolated() {
+            get_strict_token_value(
+                market_position
+                    .get_isolated_token_amount(&quote_spot_market)?
+                    .cast()?,
+                quote_spot_market.decimals,
+                &strict_quote_price,
+            )?
+        } else {
+            0
+        };
         drop(quote_spot_market);
 
         let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
@@ -490,17 +458,65 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
             calculation.track_open_orders_fraction(),
         )?;
 
-        calculation.add_margin_requirement(
-            perp_margin_requirement,
-            worst_case_base_asset_value,
-            MarketIdentifier::perp(market.market_index),
-        )?;
+        if let Some(record) = record.as_deref_mut() {
+            let worst_case_base_asset_amount = market_position.worst_case_base_asset_amount()?;
+            let market_margin_ratio = market.get_margin_ratio(
+                worst_case_base_asset_amount.unsigned_abs(),
+                context.margin_type,
+            )?;
+            let base_margin_ratio = market.get_margin_ratio(0, context.margin_type)?;
+
+            record.perp_positions.push(PerpPositionMarginRecord {
+                market_index: market.market_index,
+                oracle_price: oracle_price_data.price,
+                base_asset_amount: worst_case_base_asset_amount,
+                base_asset_value: worst_case_base_asset_value,
+                margin_ratio: user_custom_margin_ratio.max(market_margin_ratio),
+                size_premium: market_margin_ratio.safe_sub(base_margin_ratio)?,
+                isolated: market_position.is_isolated(),
+                total_collateral: isolated_collateral.safe_add(weighted_pnl)?,
+                margin_requirement: perp_margin_requirement,
+            });
+        }
 
-        if calculation.track_open_orders_fraction() {
-            calculation.add_open_orders_margin_requirement(open_order_margin_requirement)?;
+        if market_position.is_isolated() {
+            calculation.add_isolated_position_margin_calculation(
+                market.market_index,
+                isolated_collateral.safe_add(weighted_pnl)?,
+                perp_margin_requirement,
+                worst_case_base_asset_value,
+            )?;
+        } else {
+            calculation.add_margin_requirement(
+                perp_margin_requirement,
+                worst_case_base_asset_value,
+                MarketIdentifier::perp(market.market_index),
+            )?;
+
+            if calculation.track_open_orders_fraction() {
+                calculation.add_open_orders_margin_requirement(open_order_margin_requirement)?;
+            }
+
+            calculation.add_total_collateral(weighted_pnl)?;
         }
 
-        calculation.add_total_collateral(weighted_pnl)?;
+        if portfolio_margin
+            && !market_position.is_isolated()
+            && !market_position.is_lp()
+            && market.status != MarketStatus::Settlement
+        {
+            let worst_case_base_asset_value = worst_case_base_asset_value.cast::<i128>()?;
+            add_portfolio_margin_exposure(
+                &mut portfolio_margin_groups,
+                &market.amm.oracle,
+                if market_position.worst_case_base_asset_amount()? < 0 {
+                    -worst_case_base_asset_value
+                } else {
+                    worst_case_base_asset_value
+                },
+                perp_margin_requirement,
+            )?;
+        }
 
         #[cfg(feature = "drift-rs")]
         calculation.add_perp_liability_value(worst_case_base_asset_value)?;
@@ -527,11 +543,326 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
         }
     }
 
+    if portfolio_margin {
+        calculation.apply_portfolio_margin_offset(calculate_portfolio_margin_offset(
+            &portfolio_margin_groups,
+        )?)?;
+    }
+
     calculation.validate_num_spot_liabilities()?;
 
+    if let Some(record) = record {
+        record.total_collateral = calculation.total_collateral;
+        record.margin_requirement = calculation.margin_requirement;
+        record.portfolio_margin_offset = calculation.portfolio_margin_offset;
+    }
+
     Ok(calculation)
 }
 
+/// Adds a spot position's collateral or margin requirement to the calculation, including the worst case
+/// fill of its open orders
+fn add_spot_position_margin(
+    calculation: &mut MarginCalculation,
+    record: Option<&mut MarginCalculationRecord>,
+    portfolio_margin_groups: &mut Vec<PortfolioMarginGroup>,
+    spot_position: &SpotPosition,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    user_custom_margin_ratio: u32,
+    portfolio_margin: bool,
+) -> DriftResult {
+    let context = calculation.context;
+
+    let spot_market = spot_market_map.get_ref(&spot_position.market_index)?;
+    let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
+        MarketType::Spot,
+        spot_market.market_index,
+        &spot_market.oracle,
+        spot_market.historical_oracle_data.last_oracle_price_twap,
+        spot_market.get_max_confidence_interval_multiplier()?,
+    )?;
+
+    calculation.update_all_oracles_valid(is_oracle_valid_for_action(
+        oracle_validity,
+        Some(DriftAction::MarginCalc),
+    )?);
+
+    let strict_oracle_price = StrictOraclePrice::new(
+        oracle_price_data.price,
+        spot_market
+            .historical_oracle_data
+            .last_oracle_price_twap_5min,
+        calculation.context.strict,
+    );
+    strict_oracle_price.validate()?;
+
+    let total_collateral_before = calculation.total_collateral;
+    let margin_requirement_before = calculation.margin_requirement;
+
+    if spot_market.market_index == 0 {
+        let token_amount = spot_position.get_signed_token_amount(&spot_market)?;
+        if token_amount == 0 {
+            validate!(
+                spot_position.scaled_balance == 0,
+                ErrorCode::InvalidMarginRatio,
+                "spot_position.scaled_balance={} when token_amount={}",
+                spot_position.scaled_balance,
+                token_amount,
+            )?;
+        }
+
+        let token_value =
+            get_strict_token_value(token_amount, spot_market.decimals, &strict_oracle_price)?;
+
+        match spot_position.balance_type {
+            SpotBalanceType::Deposit => {
+                calculation.add_total_collateral(token_value)?;
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_asset_value(token_value)?;
+            }
+            SpotBalanceType::Borrow => {
+                let token_value = token_value.unsigned_abs();
+
+                validate!(
+                    token_value != 0,
+                    ErrorCode::InvalidMarginRatio,
+                    "token_value=0 for token_amount={} in spot market_index={}",
+                    token_amount,
+                    spot_market.market_index,
+                )?;
+
+                calculation.add_margin_requirement(
+                    token_value,
+                    token_value,
+                    MarketIdentifier::spot(0),
+                )?;
+
+                calculation.add_spot_liability()?;
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_liability_value(token_value)?;
+            }
+        }
+
+        if let Some(record) = record {
+            record.spot_positions.push(SpotPositionMarginRecord {
+                market_index: spot_market.market_index,
+                oracle_price: strict_oracle_price.current,
+                token_amount,
+                token_value,
+                weight: SPOT_WEIGHT_PRECISION,
+                size_premium: 0,
+                total_collateral: calculation
+                    .total_collateral
+                    .safe_sub(total_collateral_before)?,
+                margin_requirement: calculation
+                    .margin_requirement
+                    .safe_sub(margin_requirement_before)?,
+            });
+        }
+    } else {
+        let signed_token_amount = spot_position.get_signed_token_amount(&spot_market)?;
+
+        let OrderFillSimulation {
+            token_amount: worst_case_token_amount,
+            orders_value: worst_case_orders_value,
+            token_value: worst_case_token_value,
+            weighted_token_value: worst_case_weighted_token_value,
+            ..
+        } = spot_position
+            .get_worst_case_fill_simulation(
+                &spot_market,
+                &strict_oracle_price,
+                Some(signed_token_amount),
+                context.margin_type,
+            )?
+            .apply_user_custom_margin_ratio(
+                &spot_market,
+                strict_oracle_price.current,
+                user_custom_margin_ratio,
+            )?;
+
+        if worst_case_token_amount == 0 {
+            validate!(
+                spot_position.scaled_balance == 0,
+                ErrorCode::InvalidMarginRatio,
+                "spot_position.scaled_balance={} when worst_case_token_amount={}",
+                spot_position.scaled_balance,
+                worst_case_token_amount,
+            )?;
+        }
+
+        calculation.add_margin_requirement(
+            spot_position.margin_requirement_for_open_orders()?,
+            0,
+            MarketIdentifier::spot(spot_market.market_index),
+        )?;
+
+        if portfolio_margin {
+            add_portfolio_margin_exposure(
+                portfolio_margin_groups,
+                &spot_market.oracle,
+                worst_case_token_value,
+                worst_case_token_value
+                    .safe_sub(worst_case_weighted_token_value)?
+                    .unsigned_abs()
+                    .safe_add(spot_position.margin_requirement_for_open_orders()?)?,
+            )?;
+        }
+
+        match worst_case_token_value.cmp(&0) {
+            Ordering::Greater => {
+                if portfolio_margin {
+                    // the haircut is charged as margin requirement so the hedge offset can release it
+                    calculation.add_total_collateral(worst_case_token_value)?;
+                    calculation.add_margin_requirement(
+                        worst_case_token_value
+                            .safe_sub(worst_case_weighted_token_value)?
+                            .max(0)
+                            .unsigned_abs(),
+                        0,
+                        MarketIdentifier::spot(spot_market.market_index),
+                    )?;
+                } else {
+                    calculation
+                        .add_total_collateral(worst_case_weighted_token_value.cast::<i128>()?)?;
+                }
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_asset_value(worst_case_token_value)?;
+            }
+            Ordering::Less => {
+                validate!(
+                    worst_case_weighted_token_value.unsigned_abs()
+                        >= worst_case_token_value.unsigned_abs(),
+                    ErrorCode::InvalidMarginRatio,
+                    "weighted_token_value < abs(worst_case_token_value) in spot market_index={}",
+                    spot_market.market_index,
+                )?;
+
+                validate!(
+                    worst_case_weighted_token_value != 0,
+                    ErrorCode::InvalidOracle,
+                    "weighted_token_value=0 for worst_case_token_amount={} in spot market_index={}",
+                    worst_case_token_amount,
+                    spot_market.market_index,
+                )?;
+
+                calculation.add_margin_requirement(
+                    worst_case_weighted_token_value.unsigned_abs(),
+                    worst_case_token_value.unsigned_abs(),
+                    MarketIdentifier::spot(spot_market.market_index),
+                )?;
+
+                calculation.add_spot_liability()?;
+                calculation.update_with_spot_isolated_liability(
+                    spot_market.asset_tier == AssetTier::Isolated,
+                );
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_liability_value(worst_case_token_value.unsigned_abs())?;
+            }
+            Ordering::Equal => {
+                if spot_position.has_open_order() {
+                    calculation.add_spot_liability()?;
+                    calculation.update_with_spot_isolated_liability(
+                        spot_market.asset_tier == AssetTier::Isolated,
+                    );
+                }
+            }
+        }
+
+        match worst_case_orders_value.cmp(&0) {
+            Ordering::Greater => {
+                calculation.add_total_collateral(worst_case_orders_value.cast::<i128>()?)?;
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_asset_value(worst_case_orders_value)?;
+            }
+            Ordering::Less => {
+                calculation.add_margin_requirement(
+                    worst_case_orders_value.unsigned_abs(),
+                    worst_case_orders_value.unsigned_abs(),
+                    MarketIdentifier::spot(0),
+                )?;
+
+                #[cfg(feature = "drift-rs")]
+                calculation.add_spot_liability_value(worst_case_orders_value.unsigned_abs())?;
+            }
+            Ordering::Equal => {}
+        }
+
+        if let Some(record) = record {
+            let (weight, base_weight) = if worst_case_token_value < 0 {
+                (
+                    spot_market.get_liability_weight(
+                        worst_case_token_amount.unsigned_abs(),
+                        &context.margin_type,
+                    )?,
+                    spot_market.get_liability_weight(0, &context.margin_type)?,
+                )
+            } else {
+                (
+                    spot_market.get_asset_weight(
+                        worst_case_token_amount.unsigned_abs(),
+                        strict_oracle_price.current,
+                        &context.margin_type,
+                    )?,
+                    spot_market.get_asset_weight(
+                        0,
+                        strict_oracle_price.current,
+                        &context.margin_type,
+                    )?,
+                )
+            };
+
+            record.spot_positions.push(SpotPositionMarginRecord {
+                market_index: spot_market.market_index,
+                oracle_price: strict_oracle_price.current,
+                token_amount: worst_case_token_amount,
+                token_value: worst_case_token_value,
+                weight,
+                size_premium: weight.abs_diff(base_weight),
+                total_collateral: calculation
+                    .total_collateral
+                    .safe_sub(total_collateral_before)?,
+                margin_requirement: calculation
+                    .margin_requirement
+                    .safe_sub(margin_requirement_before)?,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Reruns a failed margin check recording every position's contribution and emits the breakdown
+/// so the position or weight that caused the failure can be identified from the logs
+pub fn emit_margin_calculation_record(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+) -> DriftResult {
+    let mut record = MarginCalculationRecord::new(user, context.margin_type);
+
+    calculate_margin_requirement_and_total_collateral_and_liability_info_with_record(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+        Some(&mut record),
+    )?;
+
+    emit!(record);
+
+    Ok(())
+}
+
 pub fn validate_any_isolated_tier_requirements(
     user: &User,
     calculation: MarginCalculation,
@@ -599,13 +930,21 @@ pub fn meets_withdraw_margin_requirement(
 
     validate_any_isolated_tier_requirements(user, calculation)?;
 
-    validate!(
-        calculation.meets_margin_requirement(),
-        ErrorCode::InsufficientCollateral,
-        "User attempting to withdraw where total_collateral {} is below initial_margin_requirement {}",
-        calculation.total_collateral,
-        calculation.margin_requirement
-    )?;
+    if !calculation.meets_margin_requirement() {
+        msg!(
+            "User attempting to withdraw where total_collateral {} is below initial_margin_requirement {}",
+            calculation.total_collateral,
+            calculation.margin_requirement
+        );
+        emit_margin_calculation_record(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            context,
+        )?;
+        return Err(ErrorCode::InsufficientCollateral);
+    }
 
     Ok(true)
 }
@@ -639,6 +978,13 @@ pub fn meets_place_order_margin_requirement(
             calculation.margin_requirement,
             margin_type
         );
+        emit_margin_calculation_record(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            context,
+        )?;
         return Err(ErrorCode::InsufficientCollateral);
     }
 
@@ -676,7 +1022,8 @@ pub fn meets_settle_pnl_maintenance_margin_requirement(
         oracle_map,
         MarginContext::standard(MarginRequirementType::Maintenance).strict(true),
     )
-    .map(|calc| calc.meets_margin_requirement())
+    // settling cross pnl only moves cross collateral
+    .map(|calc| calc.meets_cross_margin_requirement())
 }
 
 pub fn meets_maintenance_margin_requirement(
@@ -854,7 +1201,7 @@ pub fn calculate_user_equity(
 
         let market = &perp_market_map.get_ref(&market_position.market_index)?;
 
-        let quote_oracle_price = {
+        let (quote_oracle_price, isolated_collateral_value) = {
             let quote_spot_market = spot_market_map.get_ref(&market.quote_spot_market_index)?;
             let (quote_oracle_price_data, quote_oracle_validity) = oracle_map
                 .get_price_data_and_validity(
@@ -870,7 +1217,15 @@ pub fn calculate_user_equity(
             all_oracles_valid &=
                 is_oracle_valid_for_action(quote_oracle_validity, Some(DriftAction::MarginCalc))?;
 
-            quote_oracle_price_data.price
+            let isolated_collateral_value = get_token_value(
+                market_position
+                    .get_isolated_token_amount(&quote_spot_market)?
+                    .cast()?,
+                quote_spot_market.decimals,
+                quote_oracle_price_data.price,
+            )?;
+
+            (quote_oracle_price_data.price, isolated_collateral_value)
         };
 
         let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
@@ -913,8 +1268,448 @@ pub fn calculate_user_equity(
             .safe_mul(quote_oracle_price.cast()?)?
             .safe_div(PRICE_PRECISION_I128)?;
 
-        net_usd_value = net_usd_value.safe_add(pnl_value)?;
+        net_usd_value = net_usd_value
+            .safe_add(pnl_value)?
+            .safe_add(isolated_collateral_value)?;
     }
 
     Ok((net_usd_value, all_oracles_valid))
 }
+
+/// Price, funding and interest shocks applied to a user's positions. Shocks are relative moves in
+/// PERCENTAGE_PRECISION, e.g. -30% SOL is (MarketIdentifier::spot(1), -300_000). Markets priced by
+/// the same oracle move together.
+#[cfg(feature = "drift-rs")]
+#[derive(Clone, Debug, Default)]
+pub struct MarginScenario {
+    pub price_shocks: Vec<(MarketIdentifier, i64)>,
+    /// funding paid by longs to shorts as a fraction of position notional, per perp market
+    pub funding_shocks: Vec<(u16, i64)>,
+    /// interest accrued on borrows as a fraction of the borrowed amount, per spot market
+    pub interest_shocks: Vec<(u16, i64)>,
+}
+
+#[cfg(feature = "drift-rs")]
+#[derive(Clone, Copy, Debug)]
+pub struct MarketScenarioPnl {
+    pub market: MarketIdentifier,
+    /// change in position value caused by the scenario. precision: QUOTE_PRECISION
+    pub pnl: i128,
+}
+
+#[cfg(feature = "drift-rs")]
+#[derive(Clone, Debug)]
+pub struct MarginScenarioResult {
+    pub margin_calculation: MarginCalculation,
+    /// user falls below the maintenance margin requirement
+    pub can_be_liquidated: bool,
+    pub market_pnls: Vec<MarketScenarioPnl>,
+}
+
+/// Runs each scenario through the same margin calculation the program uses. Cached oracle prices
+/// are restored after every scenario.
+#[cfg(feature = "drift-rs")]
+pub fn simulate_margin_scenarios(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+    scenarios: &[MarginScenario],
+) -> DriftResult<Vec<MarginScenarioResult>> {
+    scenarios
+        .iter()
+        .map(|scenario| {
+            simulate_margin_scenario(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                context,
+                scenario,
+            )
+        })
+        .collect()
+}
+
+#[cfg(feature = "drift-rs")]
+fn simulate_margin_scenario(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+    scenario: &MarginScenario,
+) -> DriftResult<MarginScenarioResult> {
+    // funding and interest accrue at the current prices, before the price shocks
+    let shocked_user = apply_funding_and_interest_shocks(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        scenario,
+    )?;
+
+    let mut original_prices: BTreeMap<Pubkey, OraclePriceData> = BTreeMap::new();
+
+    let result = apply_price_shocks(
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        scenario,
+        &mut original_prices,
+    )
+    .and_then(|_| {
+        evaluate_margin_scenario(
+            user,
+            &shocked_user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            context,
+            &original_prices,
+        )
+    });
+
+    for (oracle, price_data) in original_prices {
+        oracle_map.set_price_data(&oracle, price_data);
+    }
+
+    result
+}
+
+#[cfg(feature = "drift-rs")]
+fn apply_funding_and_interest_shocks(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    scenario: &MarginScenario,
+) -> DriftResult<User> {
+    let mut shocked_user = *user;
+
+    for (market_index, shock) in scenario.funding_shocks.iter() {
+        if let Some(perp_position) = shocked_user
+            .perp_positions
+            .iter_mut()
+            .find(|position| position.market_index == *market_index && !position.is_available())
+        {
+            let market = perp_market_map.get_ref(market_index)?;
+            let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+
+            let funding_payment = calculate_base_asset_value_with_oracle_price(
+                perp_position.base_asset_amount.cast()?,
+                oracle_price,
+            )?
+            .cast::<i128>()?
+            .safe_mul(shock.cast()?)?
+            .safe_div(PERCENTAGE_PRECISION_I128)?;
+
+            // positive funding is paid by longs and received by shorts
+            let funding_payment = if perp_position.base_asset_amount > 0 {
+                -funding_payment
+            } else {
+                funding_payment
+            };
+
+            perp_position.quote_asset_amount = perp_position
+                .quote_asset_amount
+                .safe_add(funding_payment.cast()?)?;
+        }
+    }
+
+    for (market_index, shock) in scenario.interest_shocks.iter() {
+        if let Some(spot_position) = shocked_user.spot_positions.iter_mut().find(|position| {
+            position.market_index == *market_index
+                && !position.is_available()
+                && position.balance_type == SpotBalanceType::Borrow
+        }) {
+            let spot_market = spot_market_map.get_ref(market_index)?;
+            let token_amount = spot_position
+                .get_token_amount(&spot_market)?
+                .cast::<i128>()?;
+
+            let shocked_token_amount = token_amount
+                .safe_add(
+                    token_amount
+                        .safe_mul(shock.cast()?)?
+                        .safe_div(PERCENTAGE_PRECISION_I128)?,
+                )?
+                .max(0)
+                .unsigned_abs();
+
+            spot_position.scaled_balance = get_spot_balance(
+                shocked_token_amount,
+                &spot_market,
+                &SpotBalanceType::Borrow,
+                true,
+            )?
+            .cast()?;
+        }
+    }
+
+    Ok(shocked_user)
+}
+
+#[cfg(feature = "drift-rs")]
+fn apply_price_shocks(
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    scenario: &MarginScenario,
+    original_prices: &mut BTreeMap<Pubkey, OraclePriceData>,
+) -> DriftResult {
+    for (market, shock) in scenario.price_shocks.iter() {
+        let oracle = match market.market_type {
+            MarketType::Perp => perp_market_map.get_ref(&market.market_index)?.amm.oracle,
+            MarketType::Spot => spot_market_map.get_ref(&market.market_index)?.oracle,
+        };
+
+        let original_price_data = match original_prices.get(&oracle) {
+            Some(price_data) => *price_data,
+            None => *oracle_map.get_price_data(&oracle)?,
+        };
+
+        let shocked_price = original_price_data.price.safe_add(
+            original_price_data
+                .price
+                .cast::<i128>()?
+                .safe_mul(shock.cast()?)?
+                .safe_div(PERCENTAGE_PRECISION_I128)?
+                .cast()?,
+        )?;
+
+        validate!(
+            shocked_price > 0,
+            ErrorCode::InvalidOracle,
+            "price shock {} for {:?} leaves a non-positive oracle price",
+            shock,
+            market
+        )?;
+
+        if original_prices.contains_key(&oracle) {
+            validate!(
+                oracle_map.get_price_data(&oracle)?.price == shocked_price,
+                ErrorCode::InvalidMarginCalculation,
+                "conflicting price shocks for markets sharing oracle {}",
+                oracle
+            )?;
+            continue;
+        }
+
+        original_prices.insert(oracle, original_price_data);
+        oracle_map.set_price_data(
+            &oracle,
+            OraclePriceData {
+                price: shocked_price,
+                ..original_price_data
+            },
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "drift-rs")]
+fn evaluate_margin_scenario(
+    user: &User,
+    shocked_user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    context: MarginContext,
+    original_prices: &BTreeMap<Pubkey, OraclePriceData>,
+) -> DriftResult<MarginScenarioResult> {
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+        shocked_user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        context,
+    )?;
+
+    let can_be_liquidated = !calculate_margin_requirement_and_total_collateral_and_liability_info(
+        shocked_user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarginContext::standard(MarginRequirementType::Maintenance),
+    )?
+    .meets_margin_requirement();
+
+    let mut market_pnls = vec![];
+
+    for (spot_position, shocked_spot_position) in user
+        .spot_positions
+        .iter()
+        .zip(shocked_user.spot_positions.iter())
+    {
+        if spot_position.is_available() {
+            continue;
+        }
+
+        let spot_market = spot_market_map.get_ref(&spot_position.market_index)?;
+        let shocked_price = oracle_map.get_price_data(&spot_market.oracle)?.price;
+        let original_price = original_prices
+            .get(&spot_market.oracle)
+            .map_or(shocked_price, |price_data| price_data.price);
+
+        let value = get_token_value(
+            spot_position.get_signed_token_amount(&spot_market)?,
+            spot_market.decimals,
+            original_price,
+        )?;
+        let shocked_value = get_token_value(
+            shocked_spot_position.get_signed_token_amount(&spot_market)?,
+            spot_market.decimals,
+            shocked_price,
+        )?;
+
+        market_pnls.push(MarketScenarioPnl {
+            market: MarketIdentifier::spot(spot_position.market_index),
+            pnl: shocked_value.safe_sub(value)?,
+        });
+    }
+
+    for (perp_position, shocked_perp_position) in user
+        .perp_positions
+        .iter()
+        .zip(shocked_user.perp_positions.iter())
+    {
+        if perp_position.is_available() {
+            continue;
+        }
+
+        let market = perp_market_map.get_ref(&perp_position.market_index)?;
+        let shocked_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+        let original_price = original_prices
+            .get(&market.amm.oracle)
+            .map_or(shocked_price, |price_data| price_data.price);
+
+        let (_, pnl) = calculate_base_asset_value_and_pnl_with_oracle_price(
+            &perp_position.simulate_settled_lp_position(&market, original_price)?,
+            original_price,
+        )?;
+        let (_, shocked_pnl) = calculate_base_asset_value_and_pnl_with_oracle_price(
+            &shocked_perp_position.simulate_settled_lp_position(&market, shocked_price)?,
+            shocked_price,
+        )?;
+
+        market_pnls.push(MarketScenarioPnl {
+            market: MarketIdentifier::perp(perp_position.market_index),
+            pnl: shocked_pnl.safe_sub(pnl)?,
+        });
+    }
+
+    Ok(MarginScenarioResult {
+        margin_calculation,
+        can_be_liquidated,
+        market_pnls,
+    })
+}
+
+/// Oracle price at which the user stops meeting the maintenance margin requirement, holding every
+/// other oracle price constant. The price is searched for with the program's margin calculation, so
+/// size premium liability weights and unrealized pnl asset weights are accounted for. Returns None
+/// if the position has no price exposure or no price in the search range triggers liquidation.
+#[cfg(feature = "drift-rs")]
+pub fn calculate_liquidation_price(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    market: MarketIdentifier,
+) -> DriftResult<Option<i64>> {
+    let (oracle, liquidated_on_price_drop) = match market.market_type {
+        MarketType::Perp => {
+            let base_asset_amount = user
+                .get_perp_position(market.market_index)?
+                .base_asset_amount;
+            if base_asset_amount == 0 {
+                return Ok(None);
+            }
+
+            (
+                perp_market_map.get_ref(&market.market_index)?.amm.oracle,
+                base_asset_amount > 0,
+            )
+        }
+        MarketType::Spot => {
+            let spot_position = user.get_spot_position(market.market_index)?;
+            if spot_position.scaled_balance == 0 {
+                return Ok(None);
+            }
+
+            (
+                spot_market_map.get_ref(&market.market_index)?.oracle,
+                spot_position.balance_type == SpotBalanceType::Deposit,
+            )
+        }
+    };
+
+    let original_price_data = *oracle_map.get_price_data(&oracle)?;
+
+    let liquidation_price = search_liquidation_price(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        &oracle,
+        original_price_data,
+        liquidated_on_price_drop,
+    );
+
+    oracle_map.set_price_data(&oracle, original_price_data);
+
+    liquidation_price
+}
+
+#[cfg(feature = "drift-rs")]
+fn search_liquidation_price(
+    user: &User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    oracle: &Pubkey,
+    original_price_data: OraclePriceData,
+    liquidated_on_price_drop: bool,
+) -> DriftResult<Option<i64>> {
+    let mut meets_maintenance_margin_requirement_at = |price: i64| -> DriftResult<bool> {
+        oracle_map.set_price_data(
+            oracle,
+            OraclePriceData {
+                price,
+                ..original_price_data
+            },
+        );
+        meets_maintenance_margin_requirement(user, perp_market_map, spot_market_map, oracle_map)
+    };
+
+    let current_price = original_price_data.price;
+    if !meets_maintenance_margin_requirement_at(current_price)? {
+        return Ok(Some(current_price));
+    }
+
+    // the user meets maintenance at safe_price and doesn't at liquidation_price
+    let mut safe_price = current_price;
+    let mut liquidation_price = if liquidated_on_price_drop {
+        1
+    } else {
+        current_price.safe_mul(LIQUIDATION_PRICE_SEARCH_MAX_MULTIPLIER)?
+    };
+
+    if meets_maintenance_margin_requirement_at(liquidation_price)? {
+        return Ok(None);
+    }
+
+    while safe_price.abs_diff(liquidation_price) > 1 {
+        let price = safe_price.safe_add(liquidation_price)?.safe_div(2)?;
+        if meets_maintenance_margin_requirement_at(price)? {
+            safe_price = price;
+        } else {
+            liquidation_price = price;
+        }
+    }
+
+    Ok(Some(liquidation_price))
+}
@@ -10,7 +10,7 @@ mod test {
     };
     use crate::math::margin::{calculate_perp_position_value_and_pnl, MarginRequirementType};
     use crate::math::position::calculate_base_asset_value_and_pnl_with_oracle_price;
-    use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
+    use crate::state::oracle::{HistoricalOracleData, OraclePriceData, StrictOraclePrice};
     use crate::state::perp_market::{ContractTier, PerpMarket, AMM};
     use crate::state::spot_market::{AssetTier, SpotMarket};
     use crate::state::user::PerpPosition;
@@ -190,6 +190,101 @@ mod test {
         assert_eq!(asset_weight, 337);
     }
 
+    #[test]
+    fn perp_market_volatility_margin_scale() {
+        let mut perp_market = PerpMarket {
+            amm: AMM {
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: 100 * PRICE_PRECISION_I64,
+                    ..HistoricalOracleData::default()
+                },
+                oracle_std: 5 * PRICE_PRECISION_U64,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            ..PerpMarket::default()
+        };
+
+        // disabled without a reference volatility
+        perp_market.amm.oracle_std = 20 * PRICE_PRECISION_U64;
+        let margin_ratio = perp_market
+            .get_margin_ratio(0, MarginRequirementType::Initial)
+            .unwrap();
+        assert_eq!(margin_ratio, 1000);
+
+        perp_market.volatility_margin_reference = 500; // 5%
+        perp_market.volatility_margin_scale_floor = 5000; // .5x
+        perp_market.volatility_margin_scale_ceiling = 20000; // 2x
+
+        // 20% realized vol hits the ceiling
+        let margin_ratio = perp_market
+            .get_margin_ratio(0, MarginRequirementType::Initial)
+            .unwrap();
+        assert_eq!(margin_ratio, 2000);
+
+        perp_market.amm.oracle_std = 5 * PRICE_PRECISION_U64;
+        let margin_ratio = perp_market
+            .get_margin_ratio(0, MarginRequirementType::Maintenance)
+            .unwrap();
+        assert_eq!(margin_ratio, 500);
+
+        // mark std is used when it's larger
+        perp_market.amm.mark_std = 6 * PRICE_PRECISION_U64;
+        let margin_ratio = perp_market
+            .get_margin_ratio(0, MarginRequirementType::Initial)
+            .unwrap();
+        assert_eq!(margin_ratio, 1200);
+
+        // 1% realized vol hits the floor
+        perp_market.amm.oracle_std = PRICE_PRECISION_U64;
+        perp_market.amm.mark_std = 0;
+        let margin_ratio = perp_market
+            .get_margin_ratio(0, MarginRequirementType::Maintenance)
+            .unwrap();
+        assert_eq!(margin_ratio, 250);
+    }
+
+    #[test]
+    fn spot_market_volatility_margin_scale() {
+        let mut sol_spot_market = SpotMarket {
+            initial_asset_weight: 8000,
+            maintenance_asset_weight: 9000,
+            initial_liability_weight: 12000,
+            maintenance_liability_weight: 11000,
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            oracle_realized_volatility: 1000, // 10%
+            volatility_margin_reference: 500,
+            volatility_margin_scale_floor: 5000,
+            volatility_margin_scale_ceiling: 20000,
+            ..SpotMarket::default()
+        };
+
+        let price = 100 * PRICE_PRECISION_I64;
+
+        // 10% realized volatility doubles the haircuts
+        let asset_weight = sol_spot_market
+            .get_asset_weight(0, price, &MarginRequirementType::Initial)
+            .unwrap();
+        assert_eq!(asset_weight, 6000);
+        let liability_weight = sol_spot_market
+            .get_liability_weight(0, &MarginRequirementType::Maintenance)
+            .unwrap();
+        assert_eq!(liability_weight, 12000);
+
+        // calm markets halve them
+        sol_spot_market.oracle_realized_volatility = 100; // 1%
+        let asset_weight = sol_spot_market
+            .get_asset_weight(0, price, &MarginRequirementType::Maintenance)
+            .unwrap();
+        assert_eq!(asset_weight, 9500);
+        let liability_weight = sol_spot_market
+            .get_liability_weight(0, &MarginRequirementType::Initial)
+            .unwrap();
+        assert_eq!(liability_weight, 11000);
+    }
+
     #[test]
     fn spot_market_scale_initial_asset_weight() {
         let mut sol_spot_market = SpotMarket {
@@ -1447,11 +1542,13 @@ mod calculate_margin_requirement_and_total_collateral_and_liability_info {
     use crate::state::perp_market_map::PerpMarketMap;
     use crate::state::spot_market::{SpotBalanceType, SpotMarket};
     use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{Order, OrderType, PerpPosition, SpotPosition, User};
+    use crate::state::user::{Order, OrderType, PerpPosition, PositionFlag, SpotPosition, User};
     use crate::test_utils::*;
-    use crate::test_utils::{get_positions, get_pyth_price};
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
     use crate::{create_account_info, PRICE_PRECISION_I64};
-    use crate::{create_anchor_account_info, BASE_PRECISION_I64};
+    use crate::{
+        create_anchor_account_info, BASE_PRECISION_I64, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64,
+    };
 
     #[test]
     fn no_perp_position_but_trigger_order() {
@@ -2291,33 +2388,9 @@ mod calculate_margin_requirement_and_total_collateral_and_liability_info {
         assert_eq!(margin_requirement, 10100000);
         assert_eq!(total_collateral, 9500000);
     }
-}
-
-#[cfg(test)]
-mod calculate_max_withdrawable_amount {
-    use std::str::FromStr;
-
-    use anchor_lang::Owner;
-    use solana_program::pubkey::Pubkey;
-
-    use crate::create_account_info;
-    use crate::create_anchor_account_info;
-    use crate::math::constants::{
-        LIQUIDATION_FEE_PRECISION, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::math::margin::calculate_max_withdrawable_amount;
-    use crate::state::oracle::{HistoricalOracleData, OracleSource};
-    use crate::state::oracle_map::OracleMap;
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
-    use crate::test_utils::get_pyth_price;
-    use crate::test_utils::*;
 
     #[test]
-    pub fn usdc_withdraw() {
+    pub fn isolated_perp_position() {
         let slot = 0_u64;
 
         let mut sol_oracle_price = get_pyth_price(100, 6);
@@ -2328,11 +2401,9 @@ mod calculate_max_withdrawable_amount {
             sol_oracle_price,
             &sol_oracle_price_key,
             &pyth_program,
-            oracle_account_info
+            sol_oracle_account_info
         );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
-
-        let market_map = PerpMarketMap::empty();
+        let mut oracle_map = OracleMap::load_one(&sol_oracle_account_info, slot, None).unwrap();
 
         let mut usdc_spot_market = SpotMarket {
             market_index: 0,
@@ -2342,68 +2413,95 @@ mod calculate_max_withdrawable_amount {
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
             deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
-            liquidator_fee: 0,
-            historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
             ..SpotMarket::default()
         };
         create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
-        let mut sol_spot_market = SpotMarket {
-            market_index: 1,
-            oracle_source: OracleSource::Pyth,
-            oracle: sol_oracle_price_key,
-            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            decimals: 9,
-            initial_asset_weight: 8 * SPOT_WEIGHT_PRECISION / 10,
-            maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
-            initial_liability_weight: 12 * SPOT_WEIGHT_PRECISION / 10,
-            maintenance_liability_weight: 11 * SPOT_WEIGHT_PRECISION / 10,
-            liquidator_fee: LIQUIDATION_FEE_PRECISION / 1000,
-            ..SpotMarket::default()
-        };
-        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
-        let spot_market_account_infos = Vec::from([
-            &usdc_spot_market_account_info,
-            &sol_spot_market_account_info,
-        ]);
         let spot_market_map =
-            SpotMarketMap::load_multiple(spot_market_account_infos, true).unwrap();
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
 
-        let mut spot_positions = [SpotPosition::default(); 8];
-        spot_positions[0] = SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        };
-        spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 1,
-            open_bids: 100 * 10_i64.pow(9),
-            ..SpotPosition::default()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: sol_oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        let user = User {
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut user = User {
             orders: [Order::default(); 32],
-            perp_positions: [PerpPosition::default(); 8],
-            spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                isolated_position_scaled_balance: 20 * SPOT_BALANCE_PRECISION_U64,
+                position_flag: PositionFlag::IsolatedPosition as u8,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
 
-        let amount = calculate_max_withdrawable_amount(
-            0,
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
             &user,
-            &market_map,
+            &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
         )
         .unwrap();
 
-        assert_eq!(amount, 10000000000);
+        // isolated position is left out of the cross totals
+        assert_eq!(calculation.margin_requirement, 0);
+        assert_eq!(calculation.total_collateral, 100 * QUOTE_PRECISION_I128);
+
+        let isolated_calculation = calculation
+            .get_isolated_position_margin_calculation(0)
+            .unwrap();
+        assert_eq!(
+            isolated_calculation.margin_requirement,
+            10 * QUOTE_PRECISION
+        );
+        assert_eq!(
+            isolated_calculation.total_collateral,
+            20 * QUOTE_PRECISION_I128
+        );
+        assert!(calculation.meets_margin_requirement());
+
+        // cross collateral can't make up for an isolated shortfall
+        user.perp_positions[0].isolated_position_scaled_balance = 5 * SPOT_BALANCE_PRECISION_U64;
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        assert!(calculation.meets_cross_margin_requirement());
+        assert!(!calculation.isolated_positions_meet_margin_requirement());
+        assert!(!calculation.meets_margin_requirement());
     }
 
     #[test]
-    pub fn sol_withdraw() {
+    pub fn portfolio_margin_offsets_spot_hedge() {
         let slot = 0_u64;
 
         let mut sol_oracle_price = get_pyth_price(100, 6);
@@ -2414,25 +2512,19 @@ mod calculate_max_withdrawable_amount {
             sol_oracle_price,
             &sol_oracle_price_key,
             &pyth_program,
-            oracle_account_info
+            sol_oracle_account_info
         );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
-
-        let market_map = PerpMarketMap::empty();
+        let mut oracle_map = OracleMap::load_one(&sol_oracle_account_info, slot, None).unwrap();
 
         let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
             deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
-            initial_liability_weight: SPOT_WEIGHT_PRECISION,
-            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
-            liquidator_fee: 0,
-            historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
             ..SpotMarket::default()
         };
         create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
@@ -2447,7 +2539,8 @@ mod calculate_max_withdrawable_amount {
             maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
             initial_liability_weight: 12 * SPOT_WEIGHT_PRECISION / 10,
             maintenance_liability_weight: 11 * SPOT_WEIGHT_PRECISION / 10,
-            liquidator_fee: LIQUIDATION_FEE_PRECISION / 1000,
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
             ..SpotMarket::default()
         };
         create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
@@ -2458,66 +2551,342 @@ mod calculate_max_withdrawable_amount {
         let spot_market_map =
             SpotMarketMap::load_multiple(spot_market_account_infos, true).unwrap();
 
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: sol_oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
         let mut spot_positions = [SpotPosition::default(); 8];
         spot_positions[0] = SpotPosition {
             market_index: 0,
-            balance_type: SpotBalanceType::Borrow,
-            scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
             ..SpotPosition::default()
         };
         spot_positions[1] = SpotPosition {
             market_index: 1,
             balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 200 * SPOT_BALANCE_PRECISION_U64,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             ..SpotPosition::default()
         };
-        let user = User {
+
+        // long 1 SOL spot, short 1 SOL-PERP
+        let mut user = User {
             orders: [Order::default(); 32],
-            perp_positions: [PerpPosition::default(); 8],
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: -BASE_PRECISION_I64,
+                quote_asset_amount: 100 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
             spot_positions,
             ..User::default()
         };
 
-        let amount = calculate_max_withdrawable_amount(
-            1,
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
             &user,
-            &market_map,
+            &perp_market_map,
             &spot_market_map,
             &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
         )
         .unwrap();
 
-        assert_eq!(amount, 75000000000);
-    }
+        assert_eq!(calculation.margin_requirement, 10 * QUOTE_PRECISION);
+        assert_eq!(calculation.total_collateral, 180 * QUOTE_PRECISION_I128);
+        assert_eq!(calculation.portfolio_margin_offset, 0);
 
-    #[test]
-    pub fn sol_dust_withdraw() {
-        let slot = 0_u64;
+        user.update_portfolio_margin_status(true).unwrap();
 
-        let mut sol_oracle_price = get_pyth_price(100, 6);
-        let sol_oracle_price_key =
-            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
-        let pyth_program = crate::ids::pyth_program::id();
-        create_account_info!(
-            sol_oracle_price,
-            &sol_oracle_price_key,
-            &pyth_program,
-            oracle_account_info
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        // 20 spot haircut + 10 perp margin replaced by a 5 hedged charge
+        assert_eq!(calculation.margin_requirement, 5 * QUOTE_PRECISION);
+        assert_eq!(calculation.portfolio_margin_offset, 25 * QUOTE_PRECISION);
+        assert_eq!(calculation.total_collateral, 200 * QUOTE_PRECISION_I128);
+
+        // open order margin is charged to the perp leg and stressed with the rest of the group
+        user.perp_positions[0].open_orders = 1;
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        assert_eq!(calculation.margin_requirement, 5 * QUOTE_PRECISION);
+        assert_eq!(
+            calculation.portfolio_margin_offset,
+            25 * QUOTE_PRECISION + QUOTE_PRECISION / 100
         );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        user.perp_positions[0].open_orders = 0;
 
-        let mut usdc_spot_market = SpotMarket {
-            market_index: 0,
-            oracle_source: OracleSource::QuoteAsset,
-            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            decimals: 6,
-            initial_asset_weight: SPOT_WEIGHT_PRECISION,
-            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
-            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+        // without the spot hedge there is nothing to offset
+        user.spot_positions[1] = SpotPosition::default();
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        assert_eq!(calculation.portfolio_margin_offset, 0);
+        assert_eq!(calculation.total_collateral, 100 * QUOTE_PRECISION_I128);
+    }
+}
+
+#[cfg(test)]
+mod calculate_max_withdrawable_amount {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        LIQUIDATION_FEE_PRECISION, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::calculate_max_withdrawable_amount;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
+    use crate::test_utils::get_pyth_price;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn usdc_withdraw() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            liquidator_fee: 0,
+            historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let mut sol_spot_market = SpotMarket {
+            market_index: 1,
+            oracle_source: OracleSource::Pyth,
+            oracle: sol_oracle_price_key,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 9,
+            initial_asset_weight: 8 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
+            initial_liability_weight: 12 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_liability_weight: 11 * SPOT_WEIGHT_PRECISION / 10,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 1000,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
+        let spot_market_account_infos = Vec::from([
+            &usdc_spot_market_account_info,
+            &sol_spot_market_account_info,
+        ]);
+        let spot_market_map =
+            SpotMarketMap::load_multiple(spot_market_account_infos, true).unwrap();
+
+        let mut spot_positions = [SpotPosition::default(); 8];
+        spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 1,
+            open_bids: 100 * 10_i64.pow(9),
+            ..SpotPosition::default()
+        };
+        let user = User {
+            orders: [Order::default(); 32],
+            perp_positions: [PerpPosition::default(); 8],
+            spot_positions,
+            ..User::default()
+        };
+
+        let amount = calculate_max_withdrawable_amount(
+            0,
+            &user,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+        )
+        .unwrap();
+
+        assert_eq!(amount, 10000000000);
+    }
+
+    #[test]
+    pub fn sol_withdraw() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            liquidator_fee: 0,
+            historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let mut sol_spot_market = SpotMarket {
+            market_index: 1,
+            oracle_source: OracleSource::Pyth,
+            oracle: sol_oracle_price_key,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 9,
+            initial_asset_weight: 8 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
+            initial_liability_weight: 12 * SPOT_WEIGHT_PRECISION / 10,
+            maintenance_liability_weight: 11 * SPOT_WEIGHT_PRECISION / 10,
+            liquidator_fee: LIQUIDATION_FEE_PRECISION / 1000,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
+        let spot_market_account_infos = Vec::from([
+            &usdc_spot_market_account_info,
+            &sol_spot_market_account_info,
+        ]);
+        let spot_market_map =
+            SpotMarketMap::load_multiple(spot_market_account_infos, true).unwrap();
+
+        let mut spot_positions = [SpotPosition::default(); 8];
+        spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Borrow,
+            scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 200 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        let user = User {
+            orders: [Order::default(); 32],
+            perp_positions: [PerpPosition::default(); 8],
+            spot_positions,
+            ..User::default()
+        };
+
+        let amount = calculate_max_withdrawable_amount(
+            1,
+            &user,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+        )
+        .unwrap();
+
+        assert_eq!(amount, 75000000000);
+    }
+
+    #[test]
+    pub fn sol_dust_withdraw() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
             maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
             liquidator_fee: 0,
             historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
@@ -3530,3 +3899,489 @@ mod calculate_user_equity {
         assert_eq!(net_usd_value, 1000000000);
     }
 }
+
+#[cfg(test)]
+mod emit_margin_calculation_record {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION,
+        SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::{
+        calculate_margin_requirement_and_total_collateral_and_liability_info_with_record,
+        meets_place_order_margin_requirement, MarginRequirementType,
+    };
+    use crate::state::events::{
+        MarginCalculationRecord, PerpPositionMarginRecord, SpotPositionMarginRecord,
+    };
+    use crate::state::margin_calculation::MarginContext;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{PerpPosition, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+    use crate::{
+        create_account_info, create_anchor_account_info, MarketStatus, AMM_RESERVE_PRECISION,
+        BASE_PRECISION_I128, BASE_PRECISION_I64, PEG_PRECISION, PRICE_PRECISION,
+        PRICE_PRECISION_I64, QUOTE_PRECISION, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64,
+    };
+
+    #[test]
+    pub fn records_each_position_contribution() {
+        let slot = 0_u64;
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap: (100 * PRICE_PRECISION) as i64,
+                    last_oracle_price_twap_5min: (100 * PRICE_PRECISION) as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 2000,
+            margin_ratio_maintenance: 1000,
+            status: MarketStatus::Active,
+            ..PerpMarket::default_test()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price_twap: PRICE_PRECISION_I64,
+                last_oracle_price_twap_5min: PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 10 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let result = meets_place_order_margin_requirement(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            true,
+        );
+        assert_eq!(result, Err(ErrorCode::InsufficientCollateral));
+
+        let mut record = MarginCalculationRecord::new(&user, MarginRequirementType::Initial);
+        let calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info_with_record(
+                &user,
+                &perp_market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                MarginContext::standard(MarginRequirementType::Initial).strict(true),
+                Some(&mut record),
+            )
+            .unwrap();
+
+        assert_eq!(record.total_collateral, calculation.total_collateral);
+        assert_eq!(record.margin_requirement, calculation.margin_requirement);
+        assert_eq!(record.total_collateral, 10 * QUOTE_PRECISION_I128);
+        assert_eq!(record.margin_requirement, 20 * QUOTE_PRECISION);
+
+        assert_eq!(
+            record.spot_positions,
+            vec![SpotPositionMarginRecord {
+                market_index: 0,
+                oracle_price: PRICE_PRECISION_I64,
+                token_amount: 10 * QUOTE_PRECISION_I128,
+                token_value: 10 * QUOTE_PRECISION_I128,
+                weight: SPOT_WEIGHT_PRECISION,
+                size_premium: 0,
+                total_collateral: 10 * QUOTE_PRECISION_I128,
+                margin_requirement: 0,
+            }]
+        );
+
+        assert_eq!(
+            record.perp_positions,
+            vec![PerpPositionMarginRecord {
+                market_index: 0,
+                oracle_price: 100 * PRICE_PRECISION_I64,
+                base_asset_amount: BASE_PRECISION_I128,
+                base_asset_value: 100 * QUOTE_PRECISION,
+                margin_ratio: 2000,
+                size_premium: 0,
+                isolated: false,
+                total_collateral: 0,
+                margin_requirement: 20 * QUOTE_PRECISION,
+            }]
+        );
+
+        // the imf factor shows up as a size premium on top of the base margin ratio
+        let mut record = MarginCalculationRecord::new(&user, MarginRequirementType::Initial);
+        let mut market = perp_market_map.get_ref_mut(&0).unwrap();
+        market.imf_factor = 50000;
+        drop(market);
+
+        calculate_margin_requirement_and_total_collateral_and_liability_info_with_record(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial).strict(true),
+            Some(&mut record),
+        )
+        .unwrap();
+        assert_eq!(record.perp_positions[0].margin_ratio, 2100);
+        assert_eq!(record.perp_positions[0].size_premium, 100);
+    }
+}
+
+#[cfg(all(test, feature = "drift-rs"))]
+mod simulate_margin_scenarios {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, PEG_PRECISION, PERCENTAGE_PRECISION_I64, PRICE_PRECISION_I64,
+        QUOTE_PRECISION_I128, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::{simulate_margin_scenarios, MarginRequirementType, MarginScenario};
+    use crate::state::margin_calculation::{MarginContext, MarketIdentifier};
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, create_anchor_account_info};
+    use crate::{BASE_PRECISION_I64, QUOTE_PRECISION_I64};
+
+    #[test]
+    pub fn price_and_funding_shocks() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            sol_oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&sol_oracle_account_info, slot, None).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: sol_oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let user = User {
+            orders: [Order::default(); 32],
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 10 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let scenarios = [
+            MarginScenario::default(),
+            MarginScenario {
+                price_shocks: vec![(
+                    MarketIdentifier::perp(0),
+                    -PERCENTAGE_PRECISION_I64 * 3 / 10,
+                )],
+                ..MarginScenario::default()
+            },
+            MarginScenario {
+                funding_shocks: vec![(0, PERCENTAGE_PRECISION_I64 / 100)],
+                ..MarginScenario::default()
+            },
+        ];
+
+        let results = simulate_margin_scenarios(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Maintenance),
+            &scenarios,
+        )
+        .unwrap();
+
+        assert_eq!(
+            results[0].margin_calculation.total_collateral,
+            10 * QUOTE_PRECISION_I128
+        );
+        assert!(!results[0].can_be_liquidated);
+        assert_eq!(results[0].market_pnls[1].pnl, 0);
+
+        // -30% SOL wipes out the usdc deposit
+        assert_eq!(
+            results[1].margin_calculation.total_collateral,
+            -20 * QUOTE_PRECISION_I128
+        );
+        assert!(results[1].can_be_liquidated);
+        assert_eq!(results[1].market_pnls[0].pnl, 0);
+        assert_eq!(results[1].market_pnls[1].market, MarketIdentifier::perp(0));
+        assert_eq!(results[1].market_pnls[1].pnl, -30 * QUOTE_PRECISION_I128);
+
+        // longs pay 1% of notional
+        assert_eq!(
+            results[2].margin_calculation.total_collateral,
+            9 * QUOTE_PRECISION_I128
+        );
+        assert_eq!(results[2].market_pnls[1].pnl, -QUOTE_PRECISION_I128);
+
+        // oracle prices are restored once the scenarios are done
+        assert_eq!(
+            oracle_map
+                .get_price_data(&sol_oracle_price_key)
+                .unwrap()
+                .price,
+            100 * PRICE_PRECISION_I64
+        );
+    }
+}
+
+#[cfg(all(test, feature = "drift-rs"))]
+mod calculate_liquidation_price {
+    use std::str::FromStr;
+
+    use anchor_lang::Owner;
+    use solana_program::pubkey::Pubkey;
+
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, PEG_PRECISION, PRICE_PRECISION_I64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::{calculate_liquidation_price, meets_maintenance_margin_requirement};
+    use crate::state::margin_calculation::MarketIdentifier;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::oracle_map::OracleMap;
+    use crate::state::perp_market::{MarketStatus, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{Order, PerpPosition, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_positions, get_pyth_price, get_spot_positions};
+    use crate::{create_account_info, create_anchor_account_info};
+    use crate::{BASE_PRECISION_I64, QUOTE_PRECISION_I64};
+
+    #[test]
+    pub fn perp_long_and_short() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            sol_oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&sol_oracle_account_info, slot, None).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: sol_oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut user = User {
+            orders: [Order::default(); 32],
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 10 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        // 10 + (p - 100) < .05 * p
+        let liquidation_price = calculate_liquidation_price(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarketIdentifier::perp(0),
+        )
+        .unwrap();
+        assert_eq!(liquidation_price, Some(94736841));
+
+        // short: 10 + (100 - p) < .05 * p
+        user.perp_positions[0].base_asset_amount = -BASE_PRECISION_I64;
+        user.perp_positions[0].quote_asset_amount = 100 * QUOTE_PRECISION_I64;
+
+        let liquidation_price = calculate_liquidation_price(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarketIdentifier::perp(0),
+        )
+        .unwrap();
+        assert_eq!(liquidation_price, Some(104761906));
+
+        assert_eq!(
+            oracle_map
+                .get_price_data(&sol_oracle_price_key)
+                .unwrap()
+                .price,
+            100 * PRICE_PRECISION_I64
+        );
+        assert!(meets_maintenance_margin_requirement(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map
+        )
+        .unwrap());
+
+        // a long that can't lose more than the deposit never gets liquidated
+        user.perp_positions[0].base_asset_amount = BASE_PRECISION_I64;
+        user.perp_positions[0].quote_asset_amount = -100 * QUOTE_PRECISION_I64;
+        user.spot_positions[0].scaled_balance = 1000 * SPOT_BALANCE_PRECISION_U64;
+
+        let liquidation_price = calculate_liquidation_price(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarketIdentifier::perp(0),
+        )
+        .unwrap();
+        assert_eq!(liquidation_price, None);
+    }
+}
@@ -9,6 +9,7 @@ pub mod ceil_div;
 pub mod constants;
 pub mod cp_curve;
 pub mod fees;
+pub mod fixed_term;
 mod floor_div;
 pub mod fulfillment;
 pub mod funding;
@@ -21,6 +22,7 @@ pub mod matching;
 pub mod oracle;
 pub mod orders;
 pub mod pnl;
+pub mod portfolio_margin;
 pub mod position;
 pub mod quote_asset;
 pub mod repeg;
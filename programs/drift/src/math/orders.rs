@@ -2,6 +2,7 @@ use std::cmp::min;
 use std::ops::{Neg, Sub};
 
 use solana_program::msg;
+use solana_program::pubkey::Pubkey;
 
 use crate::controller::position::PositionDelta;
 use crate::controller::position::PositionDirection;
@@ -33,7 +34,8 @@ use crate::state::perp_market_map::PerpMarketMap;
 use crate::state::spot_market::SpotMarket;
 use crate::state::spot_market_map::SpotMarketMap;
 use crate::state::user::{
-    MarketType, Order, OrderFillSimulation, OrderStatus, OrderTriggerCondition, PerpPosition, User,
+    MarketType, Order, OrderBitFlag, OrderFillSimulation, OrderStatus, OrderTriggerCondition,
+    PerpPosition, SelfTradePreventionMode, User,
 };
 use crate::state::user_map::UserMap;
 use crate::validate;
@@ -125,8 +127,9 @@ pub fn calculate_base_asset_amount_to_fill_up_to_limit_price(
     limit_price: Option<u64>,
     existing_base_asset_amount: Option<i64>,
 ) -> DriftResult<u64> {
-    let base_asset_amount_unfilled =
-        order.get_base_asset_amount_unfilled(existing_base_asset_amount)?;
+    let base_asset_amount_unfilled = order
+        .get_base_asset_amount_unfilled(existing_base_asset_amount)?
+        .min(order.get_twap_base_asset_amount_available(market.amm.order_step_size)?);
 
     let (max_trade_base_asset_amount, max_trade_direction) = if let Some(limit_price) = limit_price
     {
@@ -344,11 +347,12 @@ pub fn should_expire_order_before_fill(
     user: &User,
     order_index: usize,
     now: i64,
+    slot: u64,
 ) -> DriftResult<bool> {
-    let should_order_be_expired = should_expire_order(user, order_index, now)?;
+    let should_order_be_expired = should_expire_order(user, order_index, now, slot)?;
     if should_order_be_expired && user.orders[order_index].is_limit_order() {
         let now_sub_buffer = now.safe_sub(15)?;
-        if !should_expire_order(user, order_index, now_sub_buffer)? {
+        if !should_expire_order(user, order_index, now_sub_buffer, slot)? {
             msg!("invalid fill. cant force expire limit order until 15s after max_ts. max ts {}, now {}, now plus buffer {}", user.orders[order_index].max_ts, now, now_sub_buffer);
             return Err(ErrorCode::ImpossibleFill);
         }
@@ -358,13 +362,21 @@ pub fn should_expire_order_before_fill(
 }
 
 #[inline(always)]
-pub fn should_expire_order(user: &User, user_order_index: usize, now: i64) -> DriftResult<bool> {
+pub fn should_expire_order(
+    user: &User,
+    user_order_index: usize,
+    now: i64,
+    slot: u64,
+) -> DriftResult<bool> {
     let order = &user.orders[user_order_index];
-    if order.status != OrderStatus::Open || order.max_ts == 0 || order.must_be_triggered() {
+    if order.status != OrderStatus::Open || order.must_be_triggered() {
         return Ok(false);
     }
 
-    Ok(now > order.max_ts)
+    let past_max_ts = order.max_ts != 0 && now > order.max_ts;
+    let past_max_slot = order.max_slot != 0 && slot > order.max_slot;
+
+    Ok(past_max_ts || past_max_slot)
 }
 
 pub fn should_cancel_reduce_only_order(
@@ -379,6 +391,82 @@ pub fn should_cancel_reduce_only_order(
     Ok(should_cancel)
 }
 
+/// Unfilled base amount each resting reduce-only order in a market is allowed so that together they
+/// never exceed the position. Newer orders keep their size first and orders that one-cancel-the-other
+/// share an allowance. Bracket children are skipped until their parent fills
+pub fn calculate_reduce_only_orders_base_asset_amount_allowed(
+    user: &User,
+    market_type: MarketType,
+    market_index: u16,
+    existing_position: i64,
+) -> DriftResult<Vec<(usize, u64)>> {
+    let reducing_direction = if existing_position > 0 {
+        PositionDirection::Short
+    } else {
+        PositionDirection::Long
+    };
+
+    let mut order_indexes: Vec<usize> = user
+        .orders
+        .iter()
+        .enumerate()
+        .filter(|(_, order)| {
+            order.status == OrderStatus::Open
+                && order.reduce_only
+                && order.market_type == market_type
+                && order.market_index == market_index
+                && !order.is_bracket_child()
+        })
+        .map(|(order_index, _)| order_index)
+        .collect();
+
+    // order ids increase so the newest order comes first
+    order_indexes.sort_by(|a, b| user.orders[*b].order_id.cmp(&user.orders[*a].order_id));
+
+    let mut base_asset_amount_remaining = existing_position.unsigned_abs();
+    let mut link_group_allowances: Vec<(u8, u64)> = vec![];
+    let mut allowances = Vec::with_capacity(order_indexes.len());
+    for order_index in order_indexes {
+        let order = &user.orders[order_index];
+
+        if existing_position == 0 || order.direction != reducing_direction {
+            allowances.push((order_index, 0));
+            continue;
+        }
+
+        let link_group_index = if order.is_linked() {
+            link_group_allowances
+                .iter()
+                .position(|(link_group, _)| *link_group == order.link_group)
+        } else {
+            None
+        };
+
+        let shared_allowance = link_group_index.map_or(0, |i| link_group_allowances[i].1);
+
+        let allowance = order
+            .get_base_asset_amount_unfilled(None)?
+            .min(base_asset_amount_remaining.safe_add(shared_allowance)?);
+
+        if allowance > shared_allowance {
+            base_asset_amount_remaining =
+                base_asset_amount_remaining.safe_sub(allowance.safe_sub(shared_allowance)?)?;
+
+            match link_group_index {
+                Some(i) => link_group_allowances[i].1 = allowance,
+                None if order.is_linked() => {
+                    link_group_allowances.push((order.link_group, allowance))
+                }
+                None => {}
+            }
+        }
+
+        allowances.push((order_index, allowance));
+    }
+
+    Ok(allowances)
+}
+
 pub fn order_breaches_maker_oracle_price_bands(
     order: &Order,
     oracle_price: i64,
@@ -582,6 +670,67 @@ pub fn order_satisfies_trigger_condition(order: &Order, oracle_price: u64) -> Dr
     }
 }
 
+pub fn calculate_trailing_stop_trigger_price(
+    order: &Order,
+    oracle_price: u64,
+    tick_size: u64,
+) -> DriftResult<u64> {
+    let trailing_offset = if order.is_bit_flag_set(OrderBitFlag::TrailingPercentage) {
+        oracle_price
+            .cast::<u128>()?
+            .safe_mul(order.trailing_offset.cast()?)?
+            .safe_div(PERCENTAGE_PRECISION)?
+            .cast::<u64>()?
+    } else {
+        order.trailing_offset
+    };
+
+    // round away from the oracle price so the trigger never sits inside the offset
+    match order.trigger_condition {
+        OrderTriggerCondition::Below => standardize_price(
+            oracle_price.saturating_sub(trailing_offset).max(tick_size),
+            tick_size,
+            PositionDirection::Long,
+        ),
+        OrderTriggerCondition::Above => standardize_price(
+            oracle_price.safe_add(trailing_offset)?,
+            tick_size,
+            PositionDirection::Short,
+        ),
+        _ => Err(print_error!(ErrorCode::InvalidTriggerOrderCondition)()),
+    }
+}
+
+/// Ratchets a trailing stop's trigger price toward the oracle price. The trigger price only ever
+/// moves in the direction that tightens the stop. Returns whether the trigger price changed
+pub fn update_trailing_stop_trigger_price(
+    order: &mut Order,
+    oracle_price: u64,
+    tick_size: u64,
+) -> DriftResult<bool> {
+    if !order.is_trailing_stop() {
+        return Ok(false);
+    }
+
+    let trailing_trigger_price =
+        calculate_trailing_stop_trigger_price(order, oracle_price, tick_size)?;
+
+    let new_trigger_price = match order.trigger_condition {
+        OrderTriggerCondition::Below => order.trigger_price.max(trailing_trigger_price),
+        OrderTriggerCondition::Above if order.trigger_price == 0 => trailing_trigger_price,
+        OrderTriggerCondition::Above => order.trigger_price.min(trailing_trigger_price),
+        _ => return Err(print_error!(ErrorCode::InvalidTriggerOrderCondition)()),
+    };
+
+    if new_trigger_price == order.trigger_price {
+        return Ok(false);
+    }
+
+    order.trigger_price = new_trigger_price;
+
+    Ok(true)
+}
+
 pub fn is_new_order_risk_increasing(
     order: &Order,
     position_base_asset_amount: i64,
@@ -729,6 +878,24 @@ fn get_max_fill_amounts_for_market(
     get_max_withdraw_for_market_with_token_amount(market, token_amount, is_leaving_drift)
 }
 
+/// Number of slices of a twap order that are due by now. The first slice is released when the
+/// order is placed and another one every twap_interval after
+pub fn calculate_twap_slices_due(order: &Order, now: i64) -> DriftResult<u16> {
+    if !order.is_twap() {
+        return Ok(0);
+    }
+
+    let intervals_elapsed = now
+        .safe_sub(order.twap_start_ts)?
+        .max(0)
+        .safe_div(order.twap_interval.cast()?)?;
+
+    intervals_elapsed
+        .safe_add(1)?
+        .min(order.twap_slices.cast()?)
+        .cast()
+}
+
 pub fn find_maker_orders(
     user: &User,
     direction: &PositionDirection,
@@ -736,9 +903,12 @@ pub fn find_maker_orders(
     market_index: u16,
     valid_oracle_price: Option<i64>,
     slot: u64,
+    now: i64,
     tick_size: u64,
-) -> DriftResult<Vec<(usize, u64)>> {
-    let mut orders: Vec<(usize, u64)> = Vec::with_capacity(32);
+    taker_authority: &Pubkey,
+    taker_self_trade_prevention_mode: SelfTradePreventionMode,
+) -> DriftResult<Vec<(usize, u64, SelfTradePreventionMode)>> {
+    let mut orders: Vec<(usize, u64, SelfTradePreventionMode)> = Vec::with_capacity(32);
 
     for (order_index, order) in user.orders.iter().enumerate() {
         if order.status != OrderStatus::Open {
@@ -758,14 +928,74 @@ pub fn find_maker_orders(
             continue;
         }
 
+        // bracket children can't be filled until their parent order fills
+        if order.is_bracket_child() {
+            continue;
+        }
+
+        if order.is_dormant(now) {
+            continue;
+        }
+
         let limit_price = order.force_get_limit_price(valid_oracle_price, None, slot, tick_size)?;
 
-        orders.push((order_index, limit_price));
+        let self_trade_prevention_mode = get_self_trade_prevention_mode(
+            taker_authority,
+            taker_self_trade_prevention_mode,
+            &user.authority,
+            order.self_trade_prevention_mode,
+        );
+
+        orders.push((order_index, limit_price, self_trade_prevention_mode));
     }
 
     Ok(orders)
 }
 
+/// The self trade prevention mode that applies between a taker and a maker order. Only orders of
+/// the same authority can self trade, the taker's mode takes precedence and the maker's mode is
+/// used if the taker didn't set one
+pub fn get_self_trade_prevention_mode(
+    taker_authority: &Pubkey,
+    taker_self_trade_prevention_mode: SelfTradePreventionMode,
+    maker_authority: &Pubkey,
+    maker_self_trade_prevention_mode: SelfTradePreventionMode,
+) -> SelfTradePreventionMode {
+    if taker_authority != maker_authority {
+        SelfTradePreventionMode::None
+    } else if taker_self_trade_prevention_mode != SelfTradePreventionMode::None {
+        taker_self_trade_prevention_mode
+    } else {
+        maker_self_trade_prevention_mode
+    }
+}
+
+/// Returns whether to cancel the taker order, whether to cancel the maker order and the base
+/// amount to decrement the order that stays open by
+pub fn calculate_self_trade_prevention_outcome(
+    self_trade_prevention_mode: SelfTradePreventionMode,
+    taker_base_asset_amount_unfilled: u64,
+    maker_base_asset_amount_unfilled: u64,
+) -> (bool, bool, u64) {
+    match self_trade_prevention_mode {
+        SelfTradePreventionMode::None => (false, false, 0),
+        SelfTradePreventionMode::CancelTaker => (true, false, 0),
+        SelfTradePreventionMode::CancelMaker => (false, true, 0),
+        SelfTradePreventionMode::CancelBoth => (true, true, 0),
+        SelfTradePreventionMode::DecrementAndCancel => {
+            let decrement = taker_base_asset_amount_unfilled.min(maker_base_asset_amount_unfilled);
+            let cancel_taker = taker_base_asset_amount_unfilled == decrement;
+            let cancel_maker = maker_base_asset_amount_unfilled == decrement;
+
+            if cancel_taker && cancel_maker {
+                (true, true, 0)
+            } else {
+                (cancel_taker, cancel_maker, decrement)
+            }
+        }
+    }
+}
+
 pub fn calculate_max_perp_order_size(
     user: &User,
     position_index: usize,
@@ -776,11 +1006,7 @@ pub fn calculate_max_perp_order_size(
     oracle_map: &mut OracleMap,
 ) -> DriftResult<u64> {
     // calculate initial margin requirement
-    let MarginCalculation {
-        margin_requirement,
-        total_collateral,
-        ..
-    } = calculate_margin_requirement_and_total_collateral_and_liability_info(
+    let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
         user,
         perp_market_map,
         spot_market_map,
@@ -788,6 +1014,22 @@ pub fn calculate_max_perp_order_size(
         MarginContext::standard(MarginRequirementType::Initial).strict(true),
     )?;
 
+    // an isolated position can only draw on its own collateral
+    let (margin_requirement, total_collateral) =
+        if margin_calculation.has_isolated_position_margin_calculation(market_index) {
+            let isolated_margin_calculation =
+                margin_calculation.get_isolated_position_margin_calculation(market_index)?;
+            (
+                isolated_margin_calculation.margin_requirement,
+                isolated_margin_calculation.total_collateral,
+            )
+        } else {
+            (
+                margin_calculation.margin_requirement,
+                margin_calculation.total_collateral,
+            )
+        };
+
     let user_custom_margin_ratio = user.max_margin_ratio;
 
     let free_collateral = total_collateral.safe_sub(margin_requirement.cast()?)?;
@@ -1216,6 +1458,10 @@ pub fn find_bids_and_asks_from_users(
                 continue;
             }
 
+            if order.is_bracket_child() {
+                continue;
+            }
+
             if !order.is_resting_limit_order(slot)? {
                 continue;
             }
@@ -1224,8 +1470,18 @@ pub fn find_bids_and_asks_from_users(
                 continue;
             }
 
+            if slot > order.max_slot && order.max_slot != 0 {
+                continue;
+            }
+
+            if order.is_dormant(now) {
+                continue;
+            }
+
             let existing_position = user.get_perp_position(market_index)?.base_asset_amount;
-            let base_amount = order.get_base_asset_amount_unfilled(Some(existing_position))?;
+            let base_amount = order.get_visible_base_asset_amount(
+                order.get_base_asset_amount_unfilled(Some(existing_position))?,
+            );
             let limit_price = order.force_get_limit_price(oracle_price, None, slot, tick_size)?;
 
             insert_order(base_amount, limit_price, order.direction);
@@ -344,7 +344,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -363,7 +363,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -382,7 +382,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(is_expired);
     }
@@ -401,7 +401,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -420,7 +420,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -439,10 +439,28 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
+
+    #[test]
+    fn slot_past_max_slot() {
+        let user = User {
+            orders: get_orders(Order {
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                max_slot: 99,
+                ..Order::default()
+            }),
+            ..User::default()
+        };
+
+        let now = 100;
+
+        assert!(!should_expire_order(&user, 0, now, 99).unwrap());
+        assert!(should_expire_order(&user, 0, now, 100).unwrap());
+    }
 }
 
 mod get_max_fill_amounts {
@@ -707,8 +725,10 @@ mod find_maker_orders {
     use crate::math::constants::{PRICE_PRECISION_I64, PRICE_PRECISION_U64};
     use crate::math::orders::find_maker_orders;
     use crate::state::user::{
-        MarketType, Order, OrderStatus, OrderTriggerCondition, OrderType, User,
+        MarketType, Order, OrderBitFlag, OrderStatus, OrderTriggerCondition, OrderType,
+        SelfTradePreventionMode, User,
     };
+    use solana_program::pubkey::Pubkey;
 
     #[test]
     fn no_open_orders() {
@@ -727,7 +747,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -761,7 +784,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -796,7 +822,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -831,7 +860,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -866,7 +898,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -901,7 +936,10 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
@@ -939,11 +977,17 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
-        assert_eq!(orders, vec![(0, PRICE_PRECISION_U64)]);
+        assert_eq!(
+            orders,
+            vec![(0, PRICE_PRECISION_U64, SelfTradePreventionMode::None)]
+        );
     }
 
     #[test]
@@ -979,13 +1023,20 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
         let mut expected_orders = vec![];
         for i in 0..32 {
-            expected_orders.push((i, (i as u64 + 1) * PRICE_PRECISION_U64));
+            expected_orders.push((
+                i,
+                (i as u64 + 1) * PRICE_PRECISION_U64,
+                SelfTradePreventionMode::None,
+            ));
         }
 
         assert_eq!(orders, expected_orders);
@@ -1024,17 +1075,175 @@ mod find_maker_orders {
             market_index,
             Some(oracle_price),
             slot,
+            0,
             tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
         )
         .unwrap();
 
         let mut expected_orders = vec![];
         for i in 0..32 {
-            expected_orders.push((i, (i as u64 + 1) * PRICE_PRECISION_U64));
+            expected_orders.push((
+                i,
+                (i as u64 + 1) * PRICE_PRECISION_U64,
+                SelfTradePreventionMode::None,
+            ));
         }
 
         assert_eq!(orders, expected_orders);
     }
+
+    #[test]
+    fn no_dormant_bracket_children() {
+        let user = User {
+            orders: [Order {
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                market_index: 0,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Long,
+                price: PRICE_PRECISION_U64,
+                link_group: 1,
+                bit_flags: OrderBitFlag::BracketChild as u8,
+                ..Order::default()
+            }; 32],
+            ..User::default()
+        };
+        let direction = PositionDirection::Long;
+        let market_type = MarketType::Perp;
+        let market_index = 0;
+        let oracle_price = PRICE_PRECISION_I64;
+        let slot = 0;
+        let tick_size = 1;
+
+        let orders = find_maker_orders(
+            &user,
+            &direction,
+            &market_type,
+            market_index,
+            Some(oracle_price),
+            slot,
+            0,
+            tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
+        )
+        .unwrap();
+
+        assert_eq!(orders, vec![]);
+    }
+
+    #[test]
+    fn dormant_orders() {
+        let user = User {
+            orders: [Order {
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                market_index: 0,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Long,
+                price: PRICE_PRECISION_U64,
+                good_after_ts: 100,
+                ..Order::default()
+            }; 32],
+            ..User::default()
+        };
+        let direction = PositionDirection::Long;
+        let market_type = MarketType::Perp;
+        let market_index = 0;
+        let oracle_price = PRICE_PRECISION_I64;
+        let slot = 0;
+        let tick_size = 1;
+
+        let orders = find_maker_orders(
+            &user,
+            &direction,
+            &market_type,
+            market_index,
+            Some(oracle_price),
+            slot,
+            99,
+            tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
+        )
+        .unwrap();
+
+        assert_eq!(orders, vec![]);
+
+        let orders = find_maker_orders(
+            &user,
+            &direction,
+            &market_type,
+            market_index,
+            Some(oracle_price),
+            slot,
+            100,
+            tick_size,
+            &Pubkey::default(),
+            SelfTradePreventionMode::None,
+        )
+        .unwrap();
+
+        assert_eq!(orders.len(), 32);
+    }
+
+    #[test]
+    fn self_trade_prevention_mode() {
+        let authority = Pubkey::new_unique();
+        let mut orders = [Order::default(); 32];
+        orders[0] = Order {
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_index: 0,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            price: PRICE_PRECISION_U64,
+            self_trade_prevention_mode: SelfTradePreventionMode::CancelMaker,
+            ..Order::default()
+        };
+
+        let user = User {
+            authority,
+            orders,
+            ..User::default()
+        };
+
+        let find = |taker_authority: &Pubkey, taker_mode: SelfTradePreventionMode| {
+            find_maker_orders(
+                &user,
+                &PositionDirection::Long,
+                &MarketType::Perp,
+                0,
+                Some(PRICE_PRECISION_I64),
+                0,
+                0,
+                1,
+                taker_authority,
+                taker_mode,
+            )
+            .unwrap()
+        };
+
+        // the maker's mode applies if the taker didn't set one
+        assert_eq!(
+            find(&authority, SelfTradePreventionMode::None),
+            vec![(0, PRICE_PRECISION_U64, SelfTradePreventionMode::CancelMaker)]
+        );
+
+        // the taker's mode takes precedence
+        assert_eq!(
+            find(&authority, SelfTradePreventionMode::CancelTaker),
+            vec![(0, PRICE_PRECISION_U64, SelfTradePreventionMode::CancelTaker)]
+        );
+
+        // other authorities can always match
+        assert_eq!(
+            find(&Pubkey::new_unique(), SelfTradePreventionMode::CancelTaker),
+            vec![(0, PRICE_PRECISION_U64, SelfTradePreventionMode::None)]
+        );
+    }
 }
 
 mod calculate_max_spot_order_size {
@@ -3861,3 +4070,327 @@ mod fallback_price_logic {
         assert_eq!(result, 99500000);
     }
 }
+
+mod update_trailing_stop_trigger_price {
+    use crate::math::constants::{PERCENTAGE_PRECISION_U64, PRICE_PRECISION_U64};
+    use crate::math::orders::update_trailing_stop_trigger_price;
+    use crate::state::user::{Order, OrderBitFlag, OrderTriggerCondition, OrderType};
+
+    #[test]
+    fn below_ratchets_up_with_oracle() {
+        let tick_size = PRICE_PRECISION_U64 / 1000;
+        let mut order = Order {
+            order_type: OrderType::TrailingStop,
+            trigger_condition: OrderTriggerCondition::Below,
+            trailing_offset: 5 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 100 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 95 * PRICE_PRECISION_U64);
+
+        // oracle moves up, trigger follows
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 110 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 105 * PRICE_PRECISION_U64);
+
+        // oracle moves down, trigger stays
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 104 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(!updated);
+        assert_eq!(order.trigger_price, 105 * PRICE_PRECISION_U64);
+    }
+
+    #[test]
+    fn above_ratchets_down_with_oracle() {
+        let tick_size = PRICE_PRECISION_U64 / 1000;
+        let mut order = Order {
+            order_type: OrderType::TrailingStop,
+            trigger_condition: OrderTriggerCondition::Above,
+            trailing_offset: 5 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 100 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 105 * PRICE_PRECISION_U64);
+
+        // oracle moves down, trigger follows
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 90 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 95 * PRICE_PRECISION_U64);
+
+        // oracle moves up, trigger stays
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 96 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(!updated);
+        assert_eq!(order.trigger_price, 95 * PRICE_PRECISION_U64);
+    }
+
+    #[test]
+    fn percentage_offset() {
+        let tick_size = PRICE_PRECISION_U64 / 1000;
+        let mut order = Order {
+            order_type: OrderType::TrailingStop,
+            trigger_condition: OrderTriggerCondition::Below,
+            trailing_offset: PERCENTAGE_PRECISION_U64 / 50, // 2%
+            ..Order::default()
+        };
+        order.add_bit_flag(OrderBitFlag::TrailingPercentage);
+
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 100 * PRICE_PRECISION_U64, tick_size)
+                .unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 98 * PRICE_PRECISION_U64);
+
+        // 2% of 123.4567 is 2.469134, rounded down to the tick away from the oracle
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 123_456_700, tick_size).unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 120_987_000);
+    }
+
+    #[test]
+    fn ignores_non_trailing_orders() {
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            trigger_condition: OrderTriggerCondition::Below,
+            trigger_price: 95 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 200 * PRICE_PRECISION_U64, 1).unwrap();
+        assert!(!updated);
+        assert_eq!(order.trigger_price, 95 * PRICE_PRECISION_U64);
+    }
+}
+
+mod calculate_twap_slices_due {
+    use crate::math::orders::calculate_twap_slices_due;
+    use crate::state::user::Order;
+
+    #[test]
+    fn test() {
+        let order = Order {
+            twap_start_ts: 100,
+            twap_interval: 60,
+            twap_slices: 4,
+            twap_slices_released: 1,
+            ..Order::default()
+        };
+
+        assert_eq!(calculate_twap_slices_due(&order, 100).unwrap(), 1);
+        assert_eq!(calculate_twap_slices_due(&order, 159).unwrap(), 1);
+        assert_eq!(calculate_twap_slices_due(&order, 160).unwrap(), 2);
+        assert_eq!(calculate_twap_slices_due(&order, 280).unwrap(), 4);
+        // capped at the number of slices
+        assert_eq!(calculate_twap_slices_due(&order, 10_000).unwrap(), 4);
+        // clock before start
+        assert_eq!(calculate_twap_slices_due(&order, 50).unwrap(), 1);
+
+        // not a twap order
+        assert_eq!(
+            calculate_twap_slices_due(&Order::default(), 10_000).unwrap(),
+            0
+        );
+    }
+}
+
+mod calculate_self_trade_prevention_outcome {
+    use crate::math::orders::{
+        calculate_self_trade_prevention_outcome, get_self_trade_prevention_mode,
+    };
+    use crate::state::user::SelfTradePreventionMode;
+    use solana_program::pubkey::Pubkey;
+
+    #[test]
+    fn self_trade_prevention_mode() {
+        let authority = Pubkey::new_unique();
+        let other_authority = Pubkey::new_unique();
+
+        assert_eq!(
+            get_self_trade_prevention_mode(
+                &authority,
+                SelfTradePreventionMode::CancelTaker,
+                &authority,
+                SelfTradePreventionMode::CancelMaker,
+            ),
+            SelfTradePreventionMode::CancelTaker
+        );
+        assert_eq!(
+            get_self_trade_prevention_mode(
+                &authority,
+                SelfTradePreventionMode::None,
+                &authority,
+                SelfTradePreventionMode::CancelMaker,
+            ),
+            SelfTradePreventionMode::CancelMaker
+        );
+        assert_eq!(
+            get_self_trade_prevention_mode(
+                &authority,
+                SelfTradePreventionMode::CancelTaker,
+                &other_authority,
+                SelfTradePreventionMode::CancelMaker,
+            ),
+            SelfTradePreventionMode::None
+        );
+        assert_eq!(
+            get_self_trade_prevention_mode(
+                &authority,
+                SelfTradePreventionMode::None,
+                &authority,
+                SelfTradePreventionMode::None,
+            ),
+            SelfTradePreventionMode::None
+        );
+    }
+
+    #[test]
+    fn cancel_modes() {
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(SelfTradePreventionMode::CancelTaker, 10, 5),
+            (true, false, 0)
+        );
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(SelfTradePreventionMode::CancelMaker, 10, 5),
+            (false, true, 0)
+        );
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(SelfTradePreventionMode::CancelBoth, 10, 5),
+            (true, true, 0)
+        );
+    }
+
+    #[test]
+    fn decrement_and_cancel() {
+        // maker smaller
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(
+                SelfTradePreventionMode::DecrementAndCancel,
+                10,
+                4
+            ),
+            (false, true, 4)
+        );
+        // taker smaller
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(
+                SelfTradePreventionMode::DecrementAndCancel,
+                3,
+                4
+            ),
+            (true, false, 3)
+        );
+        // same size
+        assert_eq!(
+            calculate_self_trade_prevention_outcome(
+                SelfTradePreventionMode::DecrementAndCancel,
+                4,
+                4
+            ),
+            (true, true, 0)
+        );
+    }
+}
+
+mod calculate_reduce_only_orders_base_asset_amount_allowed {
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::BASE_PRECISION_U64;
+    use crate::math::orders::calculate_reduce_only_orders_base_asset_amount_allowed;
+    use crate::state::user::{MarketType, Order, OrderStatus, User};
+
+    fn reduce_only_order(order_id: u32, direction: PositionDirection, link_group: u8) -> Order {
+        Order {
+            status: OrderStatus::Open,
+            order_id,
+            direction,
+            reduce_only: true,
+            base_asset_amount: BASE_PRECISION_U64,
+            link_group,
+            ..Order::default()
+        }
+    }
+
+    #[test]
+    fn newest_order_keeps_size() {
+        let mut user = User::default();
+        user.orders[0] = reduce_only_order(1, PositionDirection::Short, 0);
+        user.orders[1] = reduce_only_order(2, PositionDirection::Short, 0);
+
+        let allowances = calculate_reduce_only_orders_base_asset_amount_allowed(
+            &user,
+            MarketType::Perp,
+            0,
+            3 * BASE_PRECISION_U64 as i64 / 2,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allowances,
+            vec![(1, BASE_PRECISION_U64), (0, BASE_PRECISION_U64 / 2)]
+        );
+    }
+
+    #[test]
+    fn linked_orders_share_allowance() {
+        let mut user = User::default();
+        user.orders[0] = reduce_only_order(1, PositionDirection::Short, 1);
+        user.orders[1] = reduce_only_order(2, PositionDirection::Short, 1);
+        user.orders[2] = reduce_only_order(3, PositionDirection::Short, 0);
+
+        let allowances = calculate_reduce_only_orders_base_asset_amount_allowed(
+            &user,
+            MarketType::Perp,
+            0,
+            3 * BASE_PRECISION_U64 as i64 / 2,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allowances,
+            vec![
+                (2, BASE_PRECISION_U64),
+                (1, BASE_PRECISION_U64 / 2),
+                (0, BASE_PRECISION_U64 / 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_that_increase_position() {
+        let mut user = User::default();
+        user.orders[0] = reduce_only_order(1, PositionDirection::Long, 0);
+        user.orders[1] = reduce_only_order(2, PositionDirection::Short, 0);
+
+        let allowances = calculate_reduce_only_orders_base_asset_amount_allowed(
+            &user,
+            MarketType::Perp,
+            0,
+            BASE_PRECISION_U64 as i64,
+        )
+        .unwrap();
+
+        assert_eq!(allowances, vec![(1, BASE_PRECISION_U64), (0, 0)]);
+
+        // no position
+        let allowances =
+            calculate_reduce_only_orders_base_asset_amount_allowed(&user, MarketType::Perp, 0, 0)
+                .unwrap();
+
+        assert_eq!(allowances, vec![(1, 0), (0, 0)]);
+    }
+}
@@ -0,0 +1,113 @@
+use solana_program::pubkey::Pubkey;
+
+use crate::error::DriftResult;
+use crate::math::casting::Cast;
+use crate::math::constants::{MARGIN_PRECISION_U128, PORTFOLIO_MARGIN_BASIS_SHOCK_DIVISOR};
+use crate::math::safe_math::SafeMath;
+
+#[cfg(test)]
+mod tests;
+
+/// Exposure to a single underlying across spot and perp markets. Markets priced by the same
+/// oracle are treated as the same underlying.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct PortfolioMarginGroup {
+    pub oracle: Pubkey,
+    /// value of long exposure. precision: QUOTE_PRECISION
+    pub long_value: u128,
+    /// value of short exposure. precision: QUOTE_PRECISION
+    pub short_value: u128,
+    /// sum of the margin charged to each position on its own. precision: QUOTE_PRECISION
+    pub standalone_charge: u128,
+    /// largest charge relative to position value across the group. precision: MARGIN_PRECISION
+    pub shock: u128,
+}
+
+impl PortfolioMarginGroup {
+    pub fn add_exposure(&mut self, value: i128, charge: u128) -> DriftResult {
+        let abs_value = value.unsigned_abs();
+        if abs_value == 0 {
+            return Ok(());
+        }
+
+        if value > 0 {
+            self.long_value = self.long_value.safe_add(abs_value)?;
+        } else {
+            self.short_value = self.short_value.safe_add(abs_value)?;
+        }
+
+        self.standalone_charge = self.standalone_charge.safe_add(charge)?;
+
+        let shock = charge
+            .safe_mul(MARGIN_PRECISION_U128)?
+            .safe_div_ceil(abs_value)?;
+        self.shock = self.shock.max(shock);
+
+        Ok(())
+    }
+
+    pub fn is_hedged(&self) -> bool {
+        self.long_value > 0 && self.short_value > 0
+    }
+
+    /// Worst loss across the stress scenarios: the price moves up or down by the group's shock,
+    /// while the leg on the other side of the hedge lags the move by a fraction of the shock.
+    pub fn calculate_hedged_charge(&self) -> DriftResult<u128> {
+        let shock = self.shock.cast::<i128>()?;
+        let lagged_shock =
+            shock.safe_sub(shock.safe_div(PORTFOLIO_MARGIN_BASIS_SHOCK_DIVISOR.cast()?)?)?;
+
+        let long_value = self.long_value.cast::<i128>()?;
+        let short_value = self.short_value.cast::<i128>()?;
+
+        let price_up_loss = short_value
+            .safe_mul(shock)?
+            .safe_sub(long_value.safe_mul(lagged_shock)?)?;
+        let price_down_loss = long_value
+            .safe_mul(shock)?
+            .safe_sub(short_value.safe_mul(lagged_shock)?)?;
+
+        price_up_loss
+            .max(price_down_loss)
+            .max(0)
+            .unsigned_abs()
+            .safe_div_ceil(MARGIN_PRECISION_U128)
+    }
+
+    /// Margin released by netting the group, zero unless the group has both long and short exposure
+    pub fn calculate_offset(&self) -> DriftResult<u128> {
+        if !self.is_hedged() {
+            return Ok(0);
+        }
+
+        Ok(self
+            .standalone_charge
+            .saturating_sub(self.calculate_hedged_charge()?))
+    }
+}
+
+pub fn add_portfolio_margin_exposure(
+    groups: &mut Vec<PortfolioMarginGroup>,
+    oracle: &Pubkey,
+    value: i128,
+    charge: u128,
+) -> DriftResult {
+    match groups.iter_mut().find(|group| group.oracle == *oracle) {
+        Some(group) => group.add_exposure(value, charge),
+        None => {
+            let mut group = PortfolioMarginGroup {
+                oracle: *oracle,
+                ..PortfolioMarginGroup::default()
+            };
+            group.add_exposure(value, charge)?;
+            groups.push(group);
+            Ok(())
+        }
+    }
+}
+
+pub fn calculate_portfolio_margin_offset(groups: &[PortfolioMarginGroup]) -> DriftResult<u128> {
+    groups.iter().try_fold(0_u128, |offset, group| {
+        offset.safe_add(group.calculate_offset()?)
+    })
+}
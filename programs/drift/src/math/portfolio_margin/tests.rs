@@ -0,0 +1,98 @@
+use solana_program::pubkey::Pubkey;
+
+use crate::math::constants::{QUOTE_PRECISION, QUOTE_PRECISION_I128};
+use crate::math::portfolio_margin::{
+    add_portfolio_margin_exposure, calculate_portfolio_margin_offset, PortfolioMarginGroup,
+};
+
+#[test]
+fn fully_hedged_group() {
+    let mut group = PortfolioMarginGroup::default();
+    // spot deposit with 80% asset weight
+    group
+        .add_exposure(100 * QUOTE_PRECISION_I128, 20 * QUOTE_PRECISION)
+        .unwrap();
+    // perp short with 10% margin ratio
+    group
+        .add_exposure(-100 * QUOTE_PRECISION_I128, 10 * QUOTE_PRECISION)
+        .unwrap();
+
+    assert_eq!(group.shock, 2000);
+    assert_eq!(group.standalone_charge, 30 * QUOTE_PRECISION);
+    assert_eq!(
+        group.calculate_hedged_charge().unwrap(),
+        5 * QUOTE_PRECISION
+    );
+    assert_eq!(group.calculate_offset().unwrap(), 25 * QUOTE_PRECISION);
+}
+
+#[test]
+fn partially_hedged_group() {
+    let mut group = PortfolioMarginGroup::default();
+    group
+        .add_exposure(100 * QUOTE_PRECISION_I128, 20 * QUOTE_PRECISION)
+        .unwrap();
+    group
+        .add_exposure(-50 * QUOTE_PRECISION_I128, 5 * QUOTE_PRECISION)
+        .unwrap();
+
+    // price falls 20% while the short only gains 15%
+    assert_eq!(
+        group.calculate_hedged_charge().unwrap(),
+        25 * QUOTE_PRECISION / 2
+    );
+    assert_eq!(group.calculate_offset().unwrap(), 25 * QUOTE_PRECISION / 2);
+}
+
+#[test]
+fn unhedged_group() {
+    let mut group = PortfolioMarginGroup::default();
+    group
+        .add_exposure(100 * QUOTE_PRECISION_I128, 20 * QUOTE_PRECISION)
+        .unwrap();
+    group
+        .add_exposure(50 * QUOTE_PRECISION_I128, 5 * QUOTE_PRECISION)
+        .unwrap();
+
+    assert!(!group.is_hedged());
+    assert_eq!(group.calculate_offset().unwrap(), 0);
+}
+
+#[test]
+fn exposures_grouped_by_oracle() {
+    let sol_oracle = Pubkey::new_unique();
+    let btc_oracle = Pubkey::new_unique();
+
+    let mut groups = vec![];
+    add_portfolio_margin_exposure(
+        &mut groups,
+        &sol_oracle,
+        100 * QUOTE_PRECISION_I128,
+        20 * QUOTE_PRECISION,
+    )
+    .unwrap();
+    add_portfolio_margin_exposure(
+        &mut groups,
+        &btc_oracle,
+        -100 * QUOTE_PRECISION_I128,
+        10 * QUOTE_PRECISION,
+    )
+    .unwrap();
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(calculate_portfolio_margin_offset(&groups).unwrap(), 0);
+
+    add_portfolio_margin_exposure(
+        &mut groups,
+        &sol_oracle,
+        -100 * QUOTE_PRECISION_I128,
+        10 * QUOTE_PRECISION,
+    )
+    .unwrap();
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(
+        calculate_portfolio_margin_offset(&groups).unwrap(),
+        25 * QUOTE_PRECISION
+    );
+}
@@ -1,9 +1,14 @@
+use std::iter::once;
+
 use crate::error::{DriftResult, ErrorCode};
 use crate::math::casting::Cast;
-use crate::math::constants::{ONE_YEAR, SPOT_RATE_PRECISION, SPOT_UTILIZATION_PRECISION};
+use crate::math::constants::{
+    MARGIN_PRECISION_U128, ONE_YEAR, PERCENTAGE_PRECISION, SPOT_RATE_PRECISION,
+    SPOT_UTILIZATION_PRECISION, SPOT_UTILIZATION_PRECISION_U32, TWENTY_FOUR_HOUR,
+};
 use crate::math::safe_math::{SafeDivFloor, SafeMath};
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
-use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+use crate::state::spot_market::{BorrowRateKink, SpotBalanceType, SpotMarket};
 use crate::state::user::SpotPosition;
 
 pub fn get_spot_balance(
@@ -81,6 +86,12 @@ pub fn get_interest_token_amount(
 pub struct InterestAccumulated {
     pub borrow_interest: u128,
     pub deposit_interest: u128,
+    /// precision: SPOT_RATE_PRECISION
+    pub borrow_rate: u128,
+    /// precision: SPOT_RATE_PRECISION
+    pub deposit_rate: u128,
+    /// precision: SPOT_UTILIZATION_PRECISION
+    pub utilization: u128,
 }
 
 pub fn calculate_utilization(
@@ -118,6 +129,135 @@ pub fn calculate_spot_market_utilization(spot_market: &SpotMarket) -> DriftResul
     Ok(utilization)
 }
 
+/// Points of the piecewise linear borrow rate curve in utilization order, ending at max utilization.
+/// Kinks are validated to be sorted with unused kinks last when they're set, so optimal utilization
+/// only has to be slotted in among them
+pub fn get_borrow_rate_curve(
+    optimal_utilization: u32,
+    optimal_borrow_rate: u32,
+    max_borrow_rate: u32,
+    borrow_rate_kinks: &[BorrowRateKink],
+) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let kinks = borrow_rate_kinks
+        .iter()
+        .take_while(|kink| kink.utilization != 0)
+        .map(|kink| (kink.utilization, kink.borrow_rate));
+
+    kinks
+        .clone()
+        .take_while(move |(utilization, _)| *utilization < optimal_utilization)
+        .chain(once((optimal_utilization, optimal_borrow_rate)))
+        .chain(kinks.skip_while(move |(utilization, _)| *utilization < optimal_utilization))
+        .chain(once((SPOT_UTILIZATION_PRECISION_U32, max_borrow_rate)))
+}
+
+pub fn calculate_borrow_rate(spot_market: &SpotMarket, utilization: u128) -> DriftResult<u128> {
+    let max_borrow_rate = spot_market.max_borrow_rate.cast::<u128>()?;
+    let adaptive_borrow_rate_scale = spot_market.get_adaptive_borrow_rate_scale();
+
+    // past max utilization the slope of the last segment carries on
+    let (mut start_utilization, mut start_borrow_rate) = (0_u128, 0_u128);
+    let (mut end_utilization, mut end_borrow_rate) = (0_u128, 0_u128);
+    for (kink_utilization, kink_borrow_rate) in get_borrow_rate_curve(
+        spot_market.optimal_utilization,
+        spot_market.optimal_borrow_rate,
+        spot_market.max_borrow_rate,
+        &spot_market.borrow_rate_kinks,
+    ) {
+        (start_utilization, start_borrow_rate) = (end_utilization, end_borrow_rate);
+        end_utilization = kink_utilization.cast()?;
+
+        // rates below max utilization are multiplied by the adaptive borrow rate scale, capped at max_borrow_rate
+        end_borrow_rate = if end_utilization < SPOT_UTILIZATION_PRECISION {
+            kink_borrow_rate
+                .cast::<u128>()?
+                .safe_mul(adaptive_borrow_rate_scale)?
+                .safe_div(PERCENTAGE_PRECISION)?
+                .min(max_borrow_rate)
+        } else {
+            max_borrow_rate
+        };
+
+        if utilization <= end_utilization {
+            break;
+        }
+    }
+
+    if end_utilization == start_utilization {
+        return Ok(end_borrow_rate);
+    }
+
+    let borrow_rate_slope = end_borrow_rate
+        .safe_sub(start_borrow_rate)?
+        .safe_mul(SPOT_UTILIZATION_PRECISION)?
+        .safe_div(end_utilization.safe_sub(start_utilization)?)?;
+
+    start_borrow_rate.safe_add(
+        utilization
+            .safe_sub(start_utilization)?
+            .safe_mul(borrow_rate_slope)?
+            .safe_div(SPOT_UTILIZATION_PRECISION)?,
+    )
+}
+
+/// Moves the adaptive borrow rate scale up while utilization_twap is above optimal utilization and
+/// back down while it is below, faster the further utilization_twap is from optimal
+/// precision: PERCENTAGE_PRECISION
+pub fn calculate_adaptive_borrow_rate_scale(
+    adaptive_borrow_rate_scale: u128,
+    utilization_twap: u128,
+    optimal_utilization: u128,
+    adaptive_borrow_rate_speed: u16,
+    adaptive_borrow_rate_scale_floor: u16,
+    adaptive_borrow_rate_scale_ceiling: u16,
+    time_since_last_update: u128,
+) -> DriftResult<u128> {
+    let utilization_deviation = if utilization_twap > optimal_utilization {
+        utilization_twap
+            .safe_sub(optimal_utilization)?
+            .safe_mul(SPOT_UTILIZATION_PRECISION)?
+            .safe_div(
+                SPOT_UTILIZATION_PRECISION
+                    .saturating_sub(optimal_utilization)
+                    .max(1),
+            )?
+            .min(SPOT_UTILIZATION_PRECISION)
+    } else {
+        optimal_utilization
+            .safe_sub(utilization_twap)?
+            .safe_mul(SPOT_UTILIZATION_PRECISION)?
+            .safe_div(optimal_utilization.max(1))?
+    };
+
+    let scale_change = adaptive_borrow_rate_scale
+        .safe_mul(adaptive_borrow_rate_speed.cast()?)?
+        .safe_mul(utilization_deviation)?
+        .safe_mul(time_since_last_update)?
+        .safe_div(MARGIN_PRECISION_U128)?
+        .safe_div(SPOT_UTILIZATION_PRECISION)?
+        .safe_div(TWENTY_FOUR_HOUR.cast()?)?;
+
+    let new_adaptive_borrow_rate_scale = if utilization_twap > optimal_utilization {
+        adaptive_borrow_rate_scale.safe_add(scale_change)?
+    } else {
+        adaptive_borrow_rate_scale.saturating_sub(scale_change)
+    };
+
+    let margin_to_percentage_precision = PERCENTAGE_PRECISION.safe_div(MARGIN_PRECISION_U128)?;
+
+    Ok(new_adaptive_borrow_rate_scale
+        .max(
+            adaptive_borrow_rate_scale_floor
+                .cast::<u128>()?
+                .safe_mul(margin_to_percentage_precision)?,
+        )
+        .min(
+            adaptive_borrow_rate_scale_ceiling
+                .cast::<u128>()?
+                .safe_mul(margin_to_percentage_precision)?,
+        ))
+}
+
 pub fn calculate_accumulated_interest(
     spot_market: &SpotMarket,
     now: i64,
@@ -128,37 +268,16 @@ pub fn calculate_accumulated_interest(
         return Ok(InterestAccumulated {
             borrow_interest: 0,
             deposit_interest: 0,
+            borrow_rate: 0,
+            deposit_rate: 0,
+            utilization,
         });
     }
 
-    let borrow_rate = if utilization > spot_market.optimal_utilization.cast()? {
-        let surplus_utilization = utilization.safe_sub(spot_market.optimal_utilization.cast()?)?;
-
-        let borrow_rate_slope = spot_market
-            .max_borrow_rate
-            .cast::<u128>()?
-            .safe_sub(spot_market.optimal_borrow_rate.cast()?)?
-            .safe_mul(SPOT_UTILIZATION_PRECISION)?
-            .safe_div(
-                SPOT_UTILIZATION_PRECISION.safe_sub(spot_market.optimal_utilization.cast()?)?,
-            )?;
-
-        spot_market.optimal_borrow_rate.cast::<u128>()?.safe_add(
-            surplus_utilization
-                .safe_mul(borrow_rate_slope)?
-                .safe_div(SPOT_UTILIZATION_PRECISION)?,
-        )?
-    } else {
-        let borrow_rate_slope = spot_market
-            .optimal_borrow_rate
-            .cast::<u128>()?
-            .safe_mul(SPOT_UTILIZATION_PRECISION)?
-            .safe_div(spot_market.optimal_utilization.cast()?)?;
-
-        utilization
-            .safe_mul(borrow_rate_slope)?
-            .safe_div(SPOT_UTILIZATION_PRECISION)?
-    };
+    let borrow_rate = calculate_borrow_rate(spot_market, utilization)?;
+    let deposit_rate = borrow_rate
+        .safe_mul(utilization)?
+        .safe_div(SPOT_UTILIZATION_PRECISION)?;
 
     let time_since_last_update = now
         .cast::<u64>()
@@ -189,6 +308,9 @@ pub fn calculate_accumulated_interest(
     Ok(InterestAccumulated {
         borrow_interest,
         deposit_interest,
+        borrow_rate,
+        deposit_rate,
+        utilization,
     })
 }
 
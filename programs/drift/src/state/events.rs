@@ -4,9 +4,10 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use crate::controller::position::PositionDirection;
 use crate::error::{DriftResult, ErrorCode::InvalidOrder};
 use crate::math::casting::Cast;
+use crate::math::margin::MarginRequirementType;
 use crate::math::safe_unwrap::SafeUnwrap;
 use crate::state::traits::Size;
-use crate::state::user::{MarketType, Order};
+use crate::state::user::{MarketType, Order, User};
 use anchor_lang::Discriminator;
 use std::io::Write;
 
@@ -79,6 +80,17 @@ pub struct SpotInterestRecord {
     pub optimal_borrow_rate: u32,
     /// precision: PERCENTAGE_PRECISION
     pub max_borrow_rate: u32,
+    /// the annualized rate borrowers paid over the period
+    /// precision: SPOT_RATE_PRECISION
+    pub borrow_rate: u64,
+    /// the annualized rate lenders earned over the period, after the insurance fund and reserve shares
+    /// precision: SPOT_RATE_PRECISION
+    pub deposit_rate: u64,
+    /// the interest that went to the revenue pool through the reserve factor
+    /// precision: token mint precision
+    pub reserve_amount: u64,
+    /// precision: SPOT_UTILIZATION_PRECISION
+    pub utilization: u64,
 }
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Default)]
@@ -131,6 +143,9 @@ pub struct FundingRateRecord {
     pub base_asset_amount_with_amm: i128,
     /// precision: BASE_PRECISION
     pub base_asset_amount_with_unsettled_lp: i128,
+    /// time-weighted bid/ask premium over the oracle during the funding period
+    /// precision: PRICE_PRECISION
+    pub premium_index: i64,
 }
 
 #[event]
@@ -172,7 +187,7 @@ pub struct OrderRecord {
 }
 
 impl Size for OrderRecord {
-    const SIZE: usize = 192;
+    const SIZE: usize = 280;
 }
 
 #[event]
@@ -225,6 +240,8 @@ pub struct OrderActionRecord {
 
     /// precision: PRICE_PRECISION
     pub oracle_price: i64,
+    /// The slice of the taker's twap order the action belongs to (1-indexed)
+    pub taker_order_twap_slice: Option<u16>,
 }
 
 impl Size for OrderActionRecord {
@@ -298,6 +315,9 @@ pub fn get_order_action_record(
         maker_order_cumulative_quote_asset_amount_filled: maker_order
             .map(|order| order.quote_asset_amount_filled),
         oracle_price,
+        taker_order_twap_slice: taker_order
+            .filter(|order| order.is_twap())
+            .map(|order| order.twap_slices_released),
     })
 }
 
@@ -332,6 +352,11 @@ pub enum OrderActionExplanation {
     OrderFilledWithAMMJitLPSplit,
     OrderFilledWithLPJit,
     DeriskLp,
+    LinkedOrderFilled,
+    LinkedOrderTriggered,
+    BracketParentCanceled,
+    TwapSliceReleased,
+    SelfTradePrevented,
 }
 
 #[event]
@@ -457,6 +482,48 @@ pub struct PerpBankruptcyRecord {
     pub cumulative_funding_rate_delta: i128,
 }
 
+#[event]
+#[derive(Default)]
+pub struct AutoDeleverageRecord {
+    pub ts: i64,
+    pub market_index: u16,
+    pub bankrupt_user: Pubkey,
+    pub user: Pubkey,
+    pub direction: PositionDirection,
+    pub base_asset_amount: u64,
+    pub quote_asset_amount: u64,
+    pub oracle_price: i64,
+    pub bankruptcy_price: u64,
+    pub auto_deleverage_score: u128,
+    pub pnl_payment: u128,
+}
+
+#[event]
+#[derive(Default)]
+pub struct FixedTermRecord {
+    pub ts: i64,
+    pub action: FixedTermAction,
+    pub market_index: u16,
+    pub maturity_ts: i64,
+    pub lender: Pubkey,
+    pub borrower: Pubkey,
+    /// principal lent by a fill, 0 for settlements
+    /// precision: token mint precision
+    pub principal: u64,
+    /// precision: SPOT_RATE_PRECISION
+    pub rate: u32,
+    /// amount due at maturity created by a fill or moved into spot balances by a settlement
+    /// precision: token mint precision
+    pub amount_at_maturity: u64,
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Default)]
+pub enum FixedTermAction {
+    #[default]
+    Fill,
+    Settle,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
 pub struct SpotBankruptcyRecord {
     pub market_index: u16,
@@ -555,6 +622,81 @@ pub struct SwapRecord {
     pub fee: u64,
 }
 
+#[event]
+pub struct MarginCalculationRecord {
+    pub authority: Pubkey,
+    pub sub_account_id: u16,
+    pub margin_requirement_type: MarginRequirementType,
+    /// precision: QUOTE_PRECISION
+    pub total_collateral: i128,
+    /// precision: QUOTE_PRECISION
+    pub margin_requirement: u128,
+    /// precision: QUOTE_PRECISION
+    pub portfolio_margin_offset: u128,
+    pub spot_positions: Vec<SpotPositionMarginRecord>,
+    pub perp_positions: Vec<PerpPositionMarginRecord>,
+}
+
+impl MarginCalculationRecord {
+    pub fn new(user: &User, margin_requirement_type: MarginRequirementType) -> Self {
+        Self {
+            authority: user.authority,
+            sub_account_id: user.sub_account_id,
+            margin_requirement_type,
+            total_collateral: 0,
+            margin_requirement: 0,
+            portfolio_margin_offset: 0,
+            spot_positions: vec![],
+            perp_positions: vec![],
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpotPositionMarginRecord {
+    pub market_index: u16,
+    /// precision: PRICE_PRECISION
+    pub oracle_price: i64,
+    /// token amount assuming the riskier side of open orders fills
+    /// precision: token mint precision
+    pub token_amount: i128,
+    /// precision: QUOTE_PRECISION
+    pub token_value: i128,
+    /// asset weight for deposits, liability weight for borrows, including the size premium
+    /// precision: SPOT_WEIGHT_PRECISION
+    pub weight: u32,
+    /// difference between the weight and the weight for a position of size 0
+    /// precision: SPOT_WEIGHT_PRECISION
+    pub size_premium: u32,
+    /// precision: QUOTE_PRECISION
+    pub total_collateral: i128,
+    /// precision: QUOTE_PRECISION
+    pub margin_requirement: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PerpPositionMarginRecord {
+    pub market_index: u16,
+    /// precision: PRICE_PRECISION
+    pub oracle_price: i64,
+    /// base asset amount assuming the riskier side of open orders fills
+    /// precision: BASE_PRECISION
+    pub base_asset_amount: i128,
+    /// precision: QUOTE_PRECISION
+    pub base_asset_value: u128,
+    /// precision: MARGIN_PRECISION
+    pub margin_ratio: u32,
+    /// difference between the margin ratio and the margin ratio for a position of size 0
+    /// precision: MARGIN_PRECISION
+    pub size_premium: u32,
+    pub isolated: bool,
+    /// weighted unrealized pnl, plus the isolated collateral for isolated positions
+    /// precision: QUOTE_PRECISION
+    pub total_collateral: i128,
+    /// precision: QUOTE_PRECISION
+    pub margin_requirement: u128,
+}
+
 pub fn emit_stack<T: AnchorSerialize + Discriminator, const N: usize>(event: T) -> DriftResult {
     let mut data_buf = [0u8; N];
     let mut out_buf = [0u8; N];
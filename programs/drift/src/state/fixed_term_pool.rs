@@ -0,0 +1,190 @@
+use std::cell::RefMut;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::panic::Location;
+use std::slice::Iter;
+
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use arrayref::array_ref;
+use solana_program::msg;
+
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::safe_math::SafeMath;
+use crate::math::safe_unwrap::SafeUnwrap;
+use crate::state::spot_market::{SpotBalance, SpotBalanceType};
+use crate::state::traits::Size;
+use crate::validate;
+
+/// Holds the principal and repayments of the fixed term positions in a spot market that mature at the
+/// same time. Borrowers settle into the pool and lenders are only ever paid out of it
+#[account(zero_copy(unsafe))]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct FixedTermPool {
+    /// The pool's deposit in the spot market
+    /// precision: SPOT_BALANCE_PRECISION
+    pub scaled_balance: u128,
+    pub maturity_ts: i64,
+    /// Amount at maturity still owed to lenders
+    /// precision: token mint precision
+    pub lend_amount_at_maturity: u64,
+    /// Amount at maturity borrowers have yet to settle
+    /// precision: token mint precision
+    pub borrow_amount_at_maturity: u64,
+    pub market_index: u16,
+    pub padding: [u8; 6],
+}
+
+// implement SIZE const for FixedTermPool
+impl Size for FixedTermPool {
+    const SIZE: usize = 56;
+}
+
+impl FixedTermPool {
+    pub fn validate_market_and_maturity(&self, market_index: u16, maturity_ts: i64) -> DriftResult {
+        validate!(
+            self.market_index == market_index && self.maturity_ts == maturity_ts,
+            ErrorCode::InvalidFixedTermPool,
+            "pool for market {} maturing at {} passed for market {} maturing at {}",
+            self.market_index,
+            self.maturity_ts,
+            market_index,
+            maturity_ts
+        )
+    }
+}
+
+impl SpotBalance for FixedTermPool {
+    fn market_index(&self) -> u16 {
+        self.market_index
+    }
+
+    fn balance_type(&self) -> &SpotBalanceType {
+        &SpotBalanceType::Deposit
+    }
+
+    fn balance(&self) -> u128 {
+        self.scaled_balance
+    }
+
+    fn increase_balance(&mut self, delta: u128) -> DriftResult {
+        self.scaled_balance = self.scaled_balance.safe_add(delta)?;
+        Ok(())
+    }
+
+    fn decrease_balance(&mut self, delta: u128) -> DriftResult {
+        self.scaled_balance = self.scaled_balance.safe_sub(delta)?;
+        Ok(())
+    }
+
+    fn update_balance_type(&mut self, _balance_type: SpotBalanceType) -> DriftResult {
+        Err(ErrorCode::CantUpdatePoolBalanceType)
+    }
+}
+
+pub struct FixedTermPoolMap<'a>(pub BTreeMap<(u16, i64), AccountLoader<'a, FixedTermPool>>);
+
+impl<'a> FixedTermPoolMap<'a> {
+    #[track_caller]
+    #[inline(always)]
+    pub fn get_ref_mut(
+        &self,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> DriftResult<RefMut<FixedTermPool>> {
+        let loader = match self.0.get(&(market_index, maturity_ts)) {
+            Some(loader) => loader,
+            None => {
+                let caller = Location::caller();
+                msg!(
+                    "Could not find fixed term pool for market {} maturing at {} at {}:{}",
+                    market_index,
+                    maturity_ts,
+                    caller.file(),
+                    caller.line()
+                );
+                return Err(ErrorCode::InvalidFixedTermPool);
+            }
+        };
+
+        match loader.load_mut() {
+            Ok(fixed_term_pool) => Ok(fixed_term_pool),
+            Err(e) => {
+                let caller = Location::caller();
+                msg!("{:?}", e);
+                msg!(
+                    "Could not load fixed term pool for market {} maturing at {} at {}:{}",
+                    market_index,
+                    maturity_ts,
+                    caller.file(),
+                    caller.line()
+                );
+                Err(ErrorCode::InvalidFixedTermPool)
+            }
+        }
+    }
+
+    pub fn insert(&mut self, account_loader: AccountLoader<'a, FixedTermPool>) -> DriftResult {
+        let key = {
+            let fixed_term_pool = account_loader
+                .load()
+                .or(Err(ErrorCode::InvalidFixedTermPool))?;
+            (fixed_term_pool.market_index, fixed_term_pool.maturity_ts)
+        };
+
+        validate!(
+            !self.0.contains_key(&key),
+            ErrorCode::InvalidFixedTermPool,
+            "fixed term pool for market {} maturing at {} passed twice",
+            key.0,
+            key.1
+        )?;
+
+        self.0.insert(key, account_loader);
+
+        Ok(())
+    }
+
+    pub fn empty() -> FixedTermPoolMap<'a> {
+        FixedTermPoolMap(BTreeMap::new())
+    }
+}
+
+pub fn load_fixed_term_pool_map<'a: 'b, 'b>(
+    account_info_iter: &mut Peekable<Iter<'a, AccountInfo<'b>>>,
+) -> DriftResult<FixedTermPoolMap<'b>> {
+    let mut fixed_term_pool_map = FixedTermPoolMap::empty();
+
+    let fixed_term_pool_discriminator: [u8; 8] = FixedTermPool::discriminator();
+    while let Some(account_info) = account_info_iter.peek() {
+        let data = account_info
+            .try_borrow_data()
+            .or(Err(ErrorCode::InvalidFixedTermPool))?;
+
+        if data.len() < FixedTermPool::SIZE {
+            break;
+        }
+
+        let account_discriminator = array_ref![data, 0, 8];
+        if account_discriminator != &fixed_term_pool_discriminator {
+            break;
+        }
+
+        let account_info = account_info_iter.next().safe_unwrap()?;
+
+        validate!(
+            account_info.is_writable,
+            ErrorCode::InvalidFixedTermPool,
+            "fixed term pool {} must be writable",
+            account_info.key
+        )?;
+
+        let account_loader: AccountLoader<FixedTermPool> =
+            AccountLoader::try_from(account_info).or(Err(ErrorCode::InvalidFixedTermPool))?;
+
+        fixed_term_pool_map.insert(account_loader)?;
+    }
+
+    Ok(fixed_term_pool_map)
+}
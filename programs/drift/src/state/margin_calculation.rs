@@ -113,6 +113,40 @@ impl MarginContext {
     }
 }
 
+/// Margin results for a perp position that is margined with its own collateral
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IsolatedPositionMarginCalculation {
+    pub market_index: u16,
+    pub total_collateral: i128,
+    pub margin_requirement: u128,
+    pub margin_requirement_plus_buffer: u128,
+}
+
+impl IsolatedPositionMarginCalculation {
+    pub fn meets_margin_requirement(&self) -> bool {
+        self.total_collateral >= self.margin_requirement as i128
+    }
+
+    pub fn can_exit_liquidation(&self) -> bool {
+        self.total_collateral >= self.margin_requirement_plus_buffer as i128
+    }
+
+    pub fn margin_shortage(&self) -> DriftResult<u128> {
+        Ok(self
+            .margin_requirement_plus_buffer
+            .cast::<i128>()?
+            .safe_sub(self.total_collateral)?
+            .unsigned_abs())
+    }
+
+    pub fn get_free_collateral(&self) -> DriftResult<u128> {
+        self.total_collateral
+            .safe_sub(self.margin_requirement.cast::<i128>()?)?
+            .max(0)
+            .cast()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct MarginCalculation {
     pub context: MarginContext,
@@ -133,6 +167,10 @@ pub struct MarginCalculation {
     pub total_perp_pnl: i128,
     pub open_orders_margin_requirement: u128,
     tracked_market_margin_requirement: u128,
+    /// isolated positions are evaluated separately and excluded from the cross totals above
+    pub isolated_position_margin_calculations: [Option<IsolatedPositionMarginCalculation>; 8],
+    /// margin requirement released for hedged exposure when the user is in portfolio margin mode
+    pub portfolio_margin_offset: u128,
 }
 
 impl MarginCalculation {
@@ -153,6 +191,8 @@ impl MarginCalculation {
             total_perp_pnl: 0,
             open_orders_margin_requirement: 0,
             tracked_market_margin_requirement: 0,
+            isolated_position_margin_calculations: [None; 8],
+            portfolio_margin_offset: 0,
         }
     }
 
@@ -161,6 +201,19 @@ impl MarginCalculation {
         Ok(())
     }
 
+    /// Releases the margin netted across hedged exposure from the cross margin requirement
+    pub fn apply_portfolio_margin_offset(&mut self, portfolio_margin_offset: u128) -> DriftResult {
+        let portfolio_margin_offset = portfolio_margin_offset.min(self.margin_requirement);
+        self.portfolio_margin_offset = self
+            .portfolio_margin_offset
+            .safe_add(portfolio_margin_offset)?;
+        self.margin_requirement = self.margin_requirement.safe_sub(portfolio_margin_offset)?;
+        self.margin_requirement_plus_buffer = self
+            .margin_requirement_plus_buffer
+            .saturating_sub(portfolio_margin_offset);
+        Ok(())
+    }
+
     pub fn add_margin_requirement(
         &mut self,
         margin_requirement: u128,
@@ -189,6 +242,92 @@ impl MarginCalculation {
         Ok(())
     }
 
+    pub fn add_isolated_position_margin_calculation(
+        &mut self,
+        market_index: u16,
+        total_collateral: i128,
+        margin_requirement: u128,
+        liability_value: u128,
+    ) -> DriftResult {
+        let margin_requirement_plus_buffer = margin_requirement.safe_add(
+            liability_value.safe_mul(self.context.margin_buffer)? / MARGIN_PRECISION_U128,
+        )?;
+
+        let slot = self
+            .isolated_position_margin_calculations
+            .iter_mut()
+            .find(|calculation| calculation.is_none())
+            .ok_or(ErrorCode::InvalidMarginCalculation)?;
+
+        *slot = Some(IsolatedPositionMarginCalculation {
+            market_index,
+            total_collateral,
+            margin_requirement,
+            margin_requirement_plus_buffer,
+        });
+
+        Ok(())
+    }
+
+    pub fn get_isolated_position_margin_calculation(
+        &self,
+        market_index: u16,
+    ) -> DriftResult<&IsolatedPositionMarginCalculation> {
+        self.isolated_position_margin_calculations
+            .iter()
+            .flatten()
+            .find(|calculation| calculation.market_index == market_index)
+            .ok_or_else(|| {
+                msg!(
+                    "no isolated position margin calculation for market {}",
+                    market_index
+                );
+                ErrorCode::InvalidMarginCalculation
+            })
+    }
+
+    pub fn has_isolated_position_margin_calculation(&self, market_index: u16) -> bool {
+        self.get_isolated_position_margin_calculation(market_index)
+            .is_ok()
+    }
+
+    /// Folds another account's cross totals into this calculation, used to evaluate a shared margin group
+    pub fn add_shared_margin_calculation(
+        &mut self,
+        calculation: &MarginCalculation,
+    ) -> DriftResult {
+        self.total_collateral = self
+            .total_collateral
+            .safe_add(calculation.total_collateral)?;
+        self.margin_requirement = self
+            .margin_requirement
+            .safe_add(calculation.margin_requirement)?;
+        self.margin_requirement_plus_buffer = self
+            .margin_requirement_plus_buffer
+            .safe_add(calculation.margin_requirement_plus_buffer)?;
+        self.num_spot_liabilities = self
+            .num_spot_liabilities
+            .saturating_add(calculation.num_spot_liabilities);
+        self.num_perp_liabilities = self
+            .num_perp_liabilities
+            .saturating_add(calculation.num_perp_liabilities);
+        self.all_oracles_valid &= calculation.all_oracles_valid;
+        self.total_spot_asset_value = self
+            .total_spot_asset_value
+            .safe_add(calculation.total_spot_asset_value)?;
+        self.total_spot_liability_value = self
+            .total_spot_liability_value
+            .safe_add(calculation.total_spot_liability_value)?;
+        self.total_perp_liability_value = self
+            .total_perp_liability_value
+            .safe_add(calculation.total_perp_liability_value)?;
+        self.total_perp_pnl = self.total_perp_pnl.safe_add(calculation.total_perp_pnl)?;
+        self.open_orders_margin_requirement = self
+            .open_orders_margin_requirement
+            .safe_add(calculation.open_orders_margin_requirement)?;
+        Ok(())
+    }
+
     pub fn add_open_orders_margin_requirement(&mut self, margin_requirement: u128) -> DriftResult {
         self.open_orders_margin_requirement = self
             .open_orders_margin_requirement
@@ -263,10 +402,22 @@ impl MarginCalculation {
             .safe_add(self.num_perp_liabilities)
     }
 
+    /// cross collateral must cover the cross requirement and every isolated position must cover its own
     pub fn meets_margin_requirement(&self) -> bool {
+        self.meets_cross_margin_requirement() && self.isolated_positions_meet_margin_requirement()
+    }
+
+    pub fn meets_cross_margin_requirement(&self) -> bool {
         self.total_collateral >= self.margin_requirement as i128
     }
 
+    pub fn isolated_positions_meet_margin_requirement(&self) -> bool {
+        self.isolated_position_margin_calculations
+            .iter()
+            .flatten()
+            .all(|calculation| calculation.meets_margin_requirement())
+    }
+
     pub fn positions_meets_margin_requirement(&self) -> DriftResult<bool> {
         Ok(self.total_collateral
             >= self
@@ -1,5 +1,6 @@
 pub mod events;
 pub mod fill_mode;
+pub mod fixed_term_pool;
 pub mod fulfillment;
 pub mod fulfillment_params;
 pub mod insurance_fund_stake;
@@ -181,6 +181,17 @@ impl<'a> OracleMap<'a> {
         Ok((oracle_price_data, validity_guard_rails))
     }
 
+    /// Overrides the cached price for an oracle so off-chain callers can simulate price moves
+    #[cfg(feature = "drift-rs")]
+    pub fn set_price_data(&mut self, pubkey: &Pubkey, price_data: OraclePriceData) {
+        if self.should_get_quote_asset_price_data(pubkey) {
+            self.quote_asset_price_data = price_data;
+        } else {
+            self.price_data.insert(*pubkey, price_data);
+            self.validity.remove(pubkey);
+        }
+    }
+
     pub fn load<'c>(
         account_info_iter: &'c mut Peekable<Iter<AccountInfo<'a>>>,
         slot: u64,
@@ -1,11 +1,15 @@
 use crate::controller::position::PositionDirection;
-use crate::error::DriftResult;
+use crate::error::{DriftResult, ErrorCode};
 use crate::math::casting::Cast;
+use crate::math::orders::{standardize_base_asset_amount, standardize_price};
 use crate::math::safe_math::SafeMath;
 use crate::math::safe_unwrap::SafeUnwrap;
 use crate::state::events::OrderActionExplanation;
 use crate::state::perp_market::{ContractTier, PerpMarket};
-use crate::state::user::{MarketType, OrderTriggerCondition, OrderType};
+use crate::state::user::{
+    MarketType, OrderBitFlag, OrderTriggerCondition, OrderType, SelfTradePreventionMode,
+};
+use crate::validate;
 use crate::{
     OracleSource, PERCENTAGE_PRECISION_I64, PERCENTAGE_PRECISION_U64, PRICE_PRECISION_I64,
 };
@@ -35,9 +39,53 @@ pub struct OrderParams {
     pub auction_duration: Option<u8>,     // specified in slots
     pub auction_start_price: Option<i64>, // specified in price or oracle_price_offset
     pub auction_end_price: Option<i64>,   // specified in price or oracle_price_offset
+    pub trailing_offset: Option<u64>,     // specified in price or percentage (see bit_flags)
+    pub bit_flags: u8,
+    pub link_group: u8, // orders sharing a nonzero link group are one-cancels-the-other
+    pub self_trade_prevention_mode: SelfTradePreventionMode, // applied when taking from the same authority
+    pub twap_slices: Option<u16>, // splits a market order into slices released every twap_interval
+    pub twap_interval: Option<u32>, // specified in seconds
+    pub min_fill_base_asset_amount: Option<u64>, // smallest fill allowed, unless less is left unfilled
+    pub max_slot: Option<u64>,                   // last slot the order can be filled in
+    pub good_after_ts: Option<i64>,              // order can't be filled before this ts
+    pub display_base_asset_amount: Option<u64>, // iceberg orders only offer tranches of this size to takers
 }
 
 impl OrderParams {
+    pub fn is_bit_flag_set(&self, flag: OrderParamsBitFlag) -> bool {
+        self.bit_flags & (flag as u8) > 0
+    }
+
+    /// Converts the param bit flags into the flags stored on the order
+    pub fn get_order_bit_flags(&self) -> u8 {
+        let mut bit_flags = 0_u8;
+
+        if self.is_bit_flag_set(OrderParamsBitFlag::TrailingPercentage) {
+            bit_flags |= OrderBitFlag::TrailingPercentage as u8;
+        }
+
+        if self.is_bit_flag_set(OrderParamsBitFlag::BracketChild) {
+            bit_flags |= OrderBitFlag::BracketChild as u8;
+        }
+
+        if self.is_bit_flag_set(OrderParamsBitFlag::FillOrKill) {
+            bit_flags |= OrderBitFlag::FillOrKill as u8;
+        }
+
+        bit_flags
+    }
+
+    /// Seconds between the first and last slice of a twap order
+    pub fn get_twap_duration(&self) -> DriftResult<i64> {
+        match (self.twap_slices, self.twap_interval) {
+            (Some(twap_slices), Some(twap_interval)) => twap_slices
+                .saturating_sub(1)
+                .cast::<i64>()?
+                .safe_mul(twap_interval.cast()?),
+            _ => Ok(0),
+        }
+    }
+
     pub fn update_perp_auction_params_limit_orders(
         &mut self,
         perp_market: &PerpMarket,
@@ -638,6 +686,16 @@ fn get_auction_duration(
         .clamp(10, 180) as u8) // 180 slots max
 }
 
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum OrderParamsBitFlag {
+    /// trailing_offset is a percentage of the oracle price (PERCENTAGE_PRECISION)
+    TrailingPercentage = 0b00000001,
+    /// order waits for the parent order in its link group to fill before it can fill or trigger
+    BracketChild = 0b00000010,
+    /// order must be fully filled in place_and_take or the transaction fails. requires immediate_or_cancel
+    FillOrKill = 0b00000100,
+}
+
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, Default)]
 pub enum PostOnlyParam {
     #[default]
@@ -663,6 +721,21 @@ pub struct ModifyOrderParams {
     pub auction_start_price: Option<i64>,
     pub auction_end_price: Option<i64>,
     pub policy: Option<ModifyOrderPolicy>,
+    pub trailing_offset: Option<u64>,
+    pub max_slot: Option<u64>,
+    pub good_after_ts: Option<i64>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModifyOrderId {
+    UserOrderId(u8),
+    OrderId(u32),
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ModifyOrderByIdParams {
+    pub order_id: ModifyOrderId,
+    pub modify_order_params: ModifyOrderParams,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Eq, PartialEq)]
@@ -677,11 +750,153 @@ impl Default for ModifyOrderPolicy {
     }
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct ScaleOrderParams {
+    pub market_type: MarketType,
+    pub direction: PositionDirection,
+    pub market_index: u16,
+    pub total_base_asset_amount: u64,
+    pub start_price: u64,
+    pub end_price: u64,
+    pub number_of_orders: u8,
+    pub size_distribution: SizeDistribution,
+    pub reduce_only: bool,
+    pub post_only: PostOnlyParam,
+    pub max_ts: Option<i64>,
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, Default)]
+pub enum SizeDistribution {
+    #[default]
+    Flat, // every order is the same size
+    Ascending,  // order size grows from start_price to end_price
+    Descending, // order size shrinks from start_price to end_price
+}
+
+impl ScaleOrderParams {
+    /// Splits the ladder into limit orders evenly spaced between start_price and end_price, with
+    /// prices and sizes aligned to the market's tick and step size
+    pub fn get_order_params(
+        &self,
+        tick_size: u64,
+        step_size: u64,
+        min_order_size: u64,
+    ) -> DriftResult<Vec<OrderParams>> {
+        let number_of_orders = self.number_of_orders.cast::<u64>()?;
+
+        validate!(
+            (2..=32).contains(&number_of_orders),
+            ErrorCode::InvalidScaleOrderParams,
+            "number_of_orders ({}) must be between 2 and 32",
+            number_of_orders
+        )?;
+
+        validate!(
+            self.start_price > 0 && self.end_price > 0 && self.start_price != self.end_price,
+            ErrorCode::InvalidScaleOrderParams,
+            "start_price ({}) and end_price ({}) must be different and non zero",
+            self.start_price,
+            self.end_price
+        )?;
+
+        let total_base_asset_amount =
+            standardize_base_asset_amount(self.total_base_asset_amount, step_size)?;
+
+        let weights = (0..number_of_orders)
+            .map(|i| match self.size_distribution {
+                SizeDistribution::Flat => 1,
+                SizeDistribution::Ascending => i + 1,
+                SizeDistribution::Descending => number_of_orders - i,
+            })
+            .collect::<Vec<u64>>();
+        let total_weight = weights.iter().sum::<u64>();
+
+        let mut base_asset_amounts = weights
+            .iter()
+            .map(|weight| {
+                standardize_base_asset_amount(
+                    total_base_asset_amount
+                        .cast::<u128>()?
+                        .safe_mul(weight.cast()?)?
+                        .safe_div(total_weight.cast()?)?
+                        .cast()?,
+                    step_size,
+                )
+            })
+            .collect::<DriftResult<Vec<u64>>>()?;
+
+        // rounding leftovers go to the largest order
+        let remainder = total_base_asset_amount.safe_sub(base_asset_amounts.iter().sum::<u64>())?;
+        let largest_order_index = match self.size_distribution {
+            SizeDistribution::Descending => 0,
+            _ => base_asset_amounts.len() - 1,
+        };
+        base_asset_amounts[largest_order_index] =
+            base_asset_amounts[largest_order_index].safe_add(remainder)?;
+
+        let start_price = self.start_price.cast::<i128>()?;
+        let price_range = self.end_price.cast::<i128>()?.safe_sub(start_price)?;
+
+        let mut order_params: Vec<OrderParams> = Vec::with_capacity(base_asset_amounts.len());
+        for (i, base_asset_amount) in base_asset_amounts.into_iter().enumerate() {
+            validate!(
+                base_asset_amount >= min_order_size.max(step_size),
+                ErrorCode::InvalidScaleOrderParams,
+                "scale order {} base_asset_amount ({}) below min order size ({})",
+                i,
+                base_asset_amount,
+                min_order_size.max(step_size)
+            )?;
+
+            let price = standardize_price(
+                start_price
+                    .safe_add(
+                        price_range
+                            .safe_mul(i as i128)?
+                            .safe_div(number_of_orders.safe_sub(1)?.cast()?)?,
+                    )?
+                    .cast()?,
+                tick_size,
+                self.direction,
+            )?;
+
+            if let Some(previous) = order_params.last() {
+                validate!(
+                    price != previous.price,
+                    ErrorCode::InvalidScaleOrderParams,
+                    "scale orders {} and {} both have price {} after rounding to tick size {}",
+                    i - 1,
+                    i,
+                    price,
+                    tick_size
+                )?;
+            }
+
+            order_params.push(OrderParams {
+                order_type: OrderType::Limit,
+                market_type: self.market_type,
+                direction: self.direction,
+                base_asset_amount,
+                price,
+                market_index: self.market_index,
+                reduce_only: self.reduce_only,
+                post_only: self.post_only,
+                max_ts: self.max_ts,
+                ..OrderParams::default()
+            });
+        }
+
+        Ok(order_params)
+    }
+}
+
 pub struct PlaceOrderOptions {
     pub try_expire_orders: bool,
     pub enforce_margin_check: bool,
     pub risk_increasing: bool,
     pub explanation: OrderActionExplanation,
+    /// the order is filled in the same instruction it is placed (place_and_take / place_and_make)
+    pub immediate_fill: bool,
 }
 
 impl Default for PlaceOrderOptions {
@@ -691,6 +906,7 @@ impl Default for PlaceOrderOptions {
             enforce_margin_check: true,
             risk_increasing: false,
             explanation: OrderActionExplanation::None,
+            immediate_fill: false,
         }
     }
 }
@@ -704,4 +920,9 @@ impl PlaceOrderOptions {
         self.explanation = explanation;
         self
     }
+
+    pub fn immediate_fill(mut self) -> Self {
+        self.immediate_fill = true;
+        self
+    }
 }
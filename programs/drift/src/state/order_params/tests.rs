@@ -1185,7 +1185,19 @@ mod get_close_perp_params {
             auction_end_price: params.auction_end_price.unwrap_or(0),
             auction_duration: params.auction_duration.unwrap_or(0),
             max_ts: 100,
-            padding: [0; 3],
+            bit_flags: params.get_order_bit_flags(),
+            link_group: params.link_group,
+            self_trade_prevention_mode: params.self_trade_prevention_mode,
+            trailing_offset: params.trailing_offset.unwrap_or(0),
+            twap_start_ts: 0,
+            twap_interval: params.twap_interval.unwrap_or(0),
+            twap_slices: params.twap_slices.unwrap_or(0),
+            twap_slices_released: params.twap_slices.map_or(0, |_| 1),
+            min_fill_base_asset_amount: params.min_fill_base_asset_amount.unwrap_or(0),
+            max_slot: params.max_slot.unwrap_or(0),
+            good_after_ts: params.good_after_ts.unwrap_or(0),
+            display_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
+            visible_base_asset_amount: params.display_base_asset_amount.unwrap_or(0),
         }
     }
 
@@ -1250,3 +1262,133 @@ mod get_close_perp_params {
         validate_order(&order, &perp_market, Some(oracle_price), slot).unwrap();
     }
 }
+
+mod get_scale_order_params {
+    use crate::error::ErrorCode;
+    use crate::state::order_params::{PostOnlyParam, ScaleOrderParams, SizeDistribution};
+    use crate::state::user::{MarketType, OrderType};
+    use crate::{PositionDirection, BASE_PRECISION_U64, PRICE_PRECISION_U64};
+
+    const TICK_SIZE: u64 = PRICE_PRECISION_U64 / 100;
+    const STEP_SIZE: u64 = BASE_PRECISION_U64 / 1000;
+
+    #[test]
+    fn flat() {
+        let params = ScaleOrderParams {
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            market_index: 0,
+            total_base_asset_amount: BASE_PRECISION_U64,
+            start_price: 100 * PRICE_PRECISION_U64,
+            end_price: 99 * PRICE_PRECISION_U64,
+            number_of_orders: 3,
+            size_distribution: SizeDistribution::Flat,
+            reduce_only: false,
+            post_only: PostOnlyParam::MustPostOnly,
+            max_ts: None,
+        };
+
+        let order_params = params
+            .get_order_params(TICK_SIZE, STEP_SIZE, STEP_SIZE)
+            .unwrap();
+
+        assert_eq!(order_params.len(), 3);
+        assert_eq!(
+            order_params.iter().map(|p| p.price).collect::<Vec<u64>>(),
+            vec![100000000, 99500000, 99000000]
+        );
+        assert_eq!(
+            order_params
+                .iter()
+                .map(|p| p.base_asset_amount)
+                .collect::<Vec<u64>>(),
+            vec![333000000, 333000000, 334000000]
+        );
+        assert!(order_params.iter().all(|p| p.order_type == OrderType::Limit
+            && p.post_only == PostOnlyParam::MustPostOnly
+            && p.direction == PositionDirection::Long));
+    }
+
+    #[test]
+    fn ascending_and_descending() {
+        let params = ScaleOrderParams {
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            market_index: 0,
+            total_base_asset_amount: BASE_PRECISION_U64,
+            start_price: 100 * PRICE_PRECISION_U64,
+            end_price: 103 * PRICE_PRECISION_U64,
+            number_of_orders: 4,
+            size_distribution: SizeDistribution::Ascending,
+            ..ScaleOrderParams::default()
+        };
+
+        let order_params = params
+            .get_order_params(TICK_SIZE, STEP_SIZE, STEP_SIZE)
+            .unwrap();
+        assert_eq!(
+            order_params
+                .iter()
+                .map(|p| p.base_asset_amount)
+                .collect::<Vec<u64>>(),
+            vec![100000000, 200000000, 300000000, 400000000]
+        );
+
+        let params = ScaleOrderParams {
+            size_distribution: SizeDistribution::Descending,
+            total_base_asset_amount: BASE_PRECISION_U64 + 1,
+            ..params
+        };
+        let order_params = params
+            .get_order_params(TICK_SIZE, STEP_SIZE, STEP_SIZE)
+            .unwrap();
+        assert_eq!(
+            order_params
+                .iter()
+                .map(|p| p.base_asset_amount)
+                .collect::<Vec<u64>>(),
+            vec![400000000, 300000000, 200000000, 100000000]
+        );
+        assert_eq!(
+            order_params.iter().map(|p| p.price).collect::<Vec<u64>>(),
+            vec![100000000, 101000000, 102000000, 103000000]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let params = ScaleOrderParams {
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            total_base_asset_amount: BASE_PRECISION_U64,
+            start_price: 100 * PRICE_PRECISION_U64,
+            end_price: 99 * PRICE_PRECISION_U64,
+            number_of_orders: 3,
+            ..ScaleOrderParams::default()
+        };
+
+        // too many orders
+        let result = ScaleOrderParams {
+            number_of_orders: 33,
+            ..params
+        }
+        .get_order_params(TICK_SIZE, STEP_SIZE, STEP_SIZE);
+        assert_eq!(result, Err(ErrorCode::InvalidScaleOrderParams));
+
+        // same start and end price
+        let result = ScaleOrderParams {
+            end_price: params.start_price,
+            ..params
+        }
+        .get_order_params(TICK_SIZE, STEP_SIZE, STEP_SIZE);
+        assert_eq!(result, Err(ErrorCode::InvalidScaleOrderParams));
+
+        // orders smaller than min order size
+        let result = params.get_order_params(TICK_SIZE, STEP_SIZE, BASE_PRECISION_U64 / 2);
+        assert_eq!(result, Err(ErrorCode::InvalidScaleOrderParams));
+
+        // price levels collapse onto the same tick
+        let result = params.get_order_params(PRICE_PRECISION_U64, STEP_SIZE, STEP_SIZE);
+        assert_eq!(result, Err(ErrorCode::InvalidScaleOrderParams));
+    }
+}
@@ -13,15 +13,17 @@ use crate::math::constants::{
 use crate::math::constants::{
     AMM_RESERVE_PRECISION_I128, AMM_TO_QUOTE_PRECISION_RATIO, BID_ASK_SPREAD_PRECISION,
     BID_ASK_SPREAD_PRECISION_U128, DEFAULT_REVENUE_SINCE_LAST_FUNDING_SPREAD_RETREAT,
-    LP_FEE_SLICE_DENOMINATOR, LP_FEE_SLICE_NUMERATOR, MARGIN_PRECISION_U128, PERCENTAGE_PRECISION,
+    FUNDING_RATE_BUFFER, LP_FEE_SLICE_DENOMINATOR, LP_FEE_SLICE_NUMERATOR, MARGIN_PRECISION_U128,
+    MAX_MARGIN_RATIO, MIN_MARGIN_RATIO, ONE_HOUR_I128, ONE_MILLION_QUOTE, PERCENTAGE_PRECISION,
     PERCENTAGE_PRECISION_I128, PERCENTAGE_PRECISION_I64, PERCENTAGE_PRECISION_U64, PRICE_PRECISION,
     SPOT_WEIGHT_PRECISION, TWENTY_FOUR_HOUR,
 };
 use crate::math::helpers::get_proportion_i128;
+use crate::math::liquidation::calculate_liquidation_auction_liquidator_fee;
 
 use crate::math::margin::{
     calculate_size_discount_asset_weight, calculate_size_premium_liability_weight,
-    MarginRequirementType,
+    calculate_volatility_margin_scale, MarginRequirementType,
 };
 use crate::math::safe_math::SafeMath;
 use crate::math::stats;
@@ -32,6 +34,7 @@ use crate::state::oracle::{
 };
 use crate::state::spot_market::{AssetTier, SpotBalance, SpotBalanceType};
 use crate::state::traits::{MarketIndexOffset, Size};
+use crate::validate;
 use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::state::paused_operations::PerpOperation;
@@ -144,7 +147,7 @@ impl AMMLiquiditySplit {
 }
 
 #[account(zero_copy(unsafe))]
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Default, Eq, PartialEq, Debug)]
 #[repr(C)]
 pub struct PerpMarket {
     /// The perp market's address. It is a pda of the market index
@@ -221,43 +224,38 @@ pub struct PerpMarket {
     /// E.g. if this is -50 and the fee is 5bps, the new fee will be 2.5bps
     /// if this is 50 and the fee is 5bps, the new fee will be 7.5bps
     pub fee_adjustment: i16,
-    pub padding: [u8; 46],
-}
-
-impl Default for PerpMarket {
-    fn default() -> Self {
-        PerpMarket {
-            pubkey: Pubkey::default(),
-            amm: AMM::default(),
-            pnl_pool: PoolBalance::default(),
-            name: [0; 32],
-            insurance_claim: InsuranceClaim::default(),
-            unrealized_pnl_max_imbalance: 0,
-            expiry_ts: 0,
-            expiry_price: 0,
-            next_fill_record_id: 0,
-            next_funding_rate_record_id: 0,
-            next_curve_record_id: 0,
-            imf_factor: 0,
-            unrealized_pnl_imf_factor: 0,
-            liquidator_fee: 0,
-            if_liquidation_fee: 0,
-            margin_ratio_initial: 0,
-            margin_ratio_maintenance: 0,
-            unrealized_pnl_initial_asset_weight: 0,
-            unrealized_pnl_maintenance_asset_weight: 0,
-            number_of_users_with_base: 0,
-            number_of_users: 0,
-            market_index: 0,
-            status: MarketStatus::default(),
-            contract_type: ContractType::default(),
-            contract_tier: ContractTier::default(),
-            paused_operations: 0,
-            quote_spot_market_index: 0,
-            fee_adjustment: 0,
-            padding: [0; 46],
-        }
-    }
+    /// The realized volatility at which the static margin ratios apply. Margin ratios scale with
+    /// realized volatility relative to it, disabled when 0
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_reference: u16,
+    /// The smallest multiple of the static margin ratios volatility scaling can produce
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_scale_floor: u16,
+    /// The largest multiple of the static margin ratios volatility scaling can produce
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_scale_ceiling: u16,
+    /// The largest position an authority can hold across its sub accounts, disabled when 0
+    /// precision: BASE_PRECISION
+    pub max_position_base_asset_amount: u64,
+    /// The largest position notional at the oracle price an authority can hold across its sub accounts, disabled when 0
+    /// precision: QUOTE_PRECISION
+    pub max_position_notional: u64,
+    /// The largest share of the market's open interest an authority can hold across its sub accounts, disabled when 0
+    /// Open interest is floored at $1M notional so the limit doesn't block a new market from trading
+    /// precision: PERCENTAGE_PRECISION
+    pub max_position_open_interest_fraction: u32,
+    /// The liquidator fee a liquidation auction grows to over the state's liquidation_duration
+    /// precision: LIQUIDATOR_FEE_PRECISION
+    pub liquidation_auction_end_liquidator_fee: u32,
+    /// Positions worth at least this much at the oracle price when liquidation starts are liquidated by dutch auction, disabled when 0
+    /// precision: QUOTE_PRECISION
+    pub liquidation_auction_min_notional: u64,
+    /// The most the mark/oracle spread funding is paid on can diverge from the oracle twap, the contract tier's when 0
+    /// precision: PERCENTAGE_PRECISION
+    pub max_funding_price_divergence: u32,
+    /// The most funding can pay per hour as a fraction of the oracle twap, the max price divergence over a day when 0
+    /// precision: PERCENTAGE_PRECISION
+    pub max_hourly_funding_rate: u32,
 }
 
 impl Size for PerpMarket {
@@ -363,6 +361,14 @@ impl PerpMarket {
         self,
         oracle_price_twap: i64,
     ) -> DriftResult<i64> {
+        if self.max_funding_price_divergence != 0 {
+            return oracle_price_twap
+                .cast::<i128>()?
+                .safe_mul(self.max_funding_price_divergence.cast()?)?
+                .safe_div(PERCENTAGE_PRECISION_I128)?
+                .cast();
+        }
+
         // clamp to to 3% price divergence for safer markets and higher for lower contract tiers
         if self.contract_tier.is_as_safe_as_contract(&ContractTier::B) {
             oracle_price_twap.safe_div(33) // 3%
@@ -373,6 +379,31 @@ impl PerpMarket {
         }
     }
 
+    /// The most funding can pay per funding period. Without a max hourly funding rate, it's the contract
+    /// tier's max price divergence paid over a day
+    pub fn get_max_funding_rate(&self, oracle_price_twap: i64) -> DriftResult<i128> {
+        let oracle_price_twap = oracle_price_twap.abs();
+
+        let max_daily_price_spread = if self.max_hourly_funding_rate != 0 {
+            oracle_price_twap
+                .cast::<i128>()?
+                .safe_mul(self.max_hourly_funding_rate.cast()?)?
+                .safe_mul(24)?
+                .safe_div(PERCENTAGE_PRECISION_I128)?
+        } else {
+            self.get_max_price_divergence_for_funding_rate(oracle_price_twap)?
+                .cast()?
+        };
+
+        let period_adjustment = 24_i128
+            .safe_mul(ONE_HOUR_I128)?
+            .safe_div(ONE_HOUR_I128.max(self.amm.funding_period.cast()?))?;
+
+        max_daily_price_spread
+            .safe_mul(FUNDING_RATE_BUFFER.cast()?)?
+            .safe_div(period_adjustment)
+    }
+
     pub fn get_margin_ratio(
         &self,
         size: u128,
@@ -392,6 +423,18 @@ impl PerpMarket {
             MarginRequirementType::Maintenance => self.margin_ratio_maintenance,
         };
 
+        let volatility_margin_scale = self.get_volatility_margin_scale()?;
+        let default_margin_ratio = if volatility_margin_scale != MARGIN_PRECISION_U128 {
+            default_margin_ratio
+                .cast::<u128>()?
+                .safe_mul(volatility_margin_scale)?
+                .safe_div(MARGIN_PRECISION_U128)?
+                .cast::<u32>()?
+                .clamp(MIN_MARGIN_RATIO, MAX_MARGIN_RATIO)
+        } else {
+            default_margin_ratio
+        };
+
         let size_adj_margin_ratio = calculate_size_premium_liability_weight(
             size,
             self.imf_factor,
@@ -404,6 +447,143 @@ impl PerpMarket {
         Ok(margin_ratio)
     }
 
+    /// Multiple of the static margin ratios implied by realized volatility, the larger of the oracle
+    /// and mark price standard deviations relative to the oracle twap
+    /// precision: MARGIN_PRECISION
+    pub fn get_volatility_margin_scale(&self) -> DriftResult<u128> {
+        let oracle_price_twap = self.amm.historical_oracle_data.last_oracle_price_twap;
+        if self.volatility_margin_reference == 0 || oracle_price_twap <= 0 {
+            return Ok(MARGIN_PRECISION_U128);
+        }
+
+        let realized_volatility = self
+            .amm
+            .oracle_std
+            .max(self.amm.mark_std)
+            .cast::<u128>()?
+            .safe_mul(MARGIN_PRECISION_U128)?
+            .safe_div(oracle_price_twap.cast()?)?;
+
+        calculate_volatility_margin_scale(
+            realized_volatility,
+            self.volatility_margin_reference,
+            self.volatility_margin_scale_floor,
+            self.volatility_margin_scale_ceiling,
+        )
+    }
+
+    pub fn has_max_position_size(&self) -> bool {
+        self.max_position_base_asset_amount != 0
+            || self.max_position_notional != 0
+            || self.max_position_open_interest_fraction != 0
+    }
+
+    /// The largest position an authority can hold, the tightest of the configured limits
+    /// precision: BASE_PRECISION
+    pub fn get_max_position_base_asset_amount(
+        &self,
+        oracle_price: i64,
+    ) -> DriftResult<Option<u128>> {
+        let mut max_position: Option<u128> = None;
+
+        if self.max_position_base_asset_amount != 0 {
+            max_position = Some(self.max_position_base_asset_amount.cast()?);
+        }
+
+        if self.max_position_notional != 0 && oracle_price > 0 {
+            let max_position_for_notional = self
+                .max_position_notional
+                .cast::<u128>()?
+                .safe_mul(PRICE_PRECISION)?
+                .safe_mul(AMM_TO_QUOTE_PRECISION_RATIO)?
+                .safe_div(oracle_price.cast()?)?;
+            max_position = Some(
+                max_position.map_or(max_position_for_notional, |max_position| {
+                    max_position.min(max_position_for_notional)
+                }),
+            );
+        }
+
+        if self.max_position_open_interest_fraction != 0 {
+            let min_open_interest = if oracle_price > 0 {
+                ONE_MILLION_QUOTE
+                    .cast::<u128>()?
+                    .safe_mul(PRICE_PRECISION)?
+                    .safe_mul(AMM_TO_QUOTE_PRECISION_RATIO)?
+                    .safe_div(oracle_price.cast()?)?
+            } else {
+                0
+            };
+
+            let max_position_for_open_interest = self
+                .get_open_interest()
+                .max(min_open_interest)
+                .safe_mul(self.max_position_open_interest_fraction.cast()?)?
+                .safe_div(PERCENTAGE_PRECISION)?;
+            max_position = Some(
+                max_position.map_or(max_position_for_open_interest, |max_position| {
+                    max_position.min(max_position_for_open_interest)
+                }),
+            );
+        }
+
+        Ok(max_position)
+    }
+
+    pub fn validate_max_position_size(
+        &self,
+        base_asset_amount: i64,
+        oracle_price: i64,
+    ) -> DriftResult {
+        if let Some(max_position) = self.get_max_position_base_asset_amount(oracle_price)? {
+            validate!(
+                base_asset_amount.unsigned_abs().cast::<u128>()? <= max_position,
+                ErrorCode::MaxPositionSize,
+                "position base asset amount {} breaches max position size {} for perp market {}",
+                base_asset_amount,
+                max_position,
+                self.market_index
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_liquidation_auction(&self, base_asset_value: u64) -> bool {
+        self.liquidation_auction_min_notional != 0
+            && base_asset_value >= self.liquidation_auction_min_notional
+    }
+
+    /// The largest liquidator fee the market can pay, which maintenance margin must stay above
+    pub fn get_max_liquidator_fee(&self) -> u32 {
+        if self.liquidation_auction_min_notional != 0 {
+            self.liquidator_fee
+                .max(self.liquidation_auction_end_liquidator_fee)
+        } else {
+            self.liquidator_fee
+        }
+    }
+
+    /// Large positions are auctioned with a liquidator fee that grows from liquidator_fee to
+    /// liquidation_auction_end_liquidator_fee, everything else pays the flat liquidator_fee
+    pub fn get_liquidator_fee(
+        &self,
+        is_liquidation_auction: bool,
+        slots_elapsed: u64,
+        liquidation_duration: u64,
+    ) -> DriftResult<u32> {
+        if !is_liquidation_auction {
+            return Ok(self.liquidator_fee);
+        }
+
+        calculate_liquidation_auction_liquidator_fee(
+            self.liquidator_fee,
+            self.liquidation_auction_end_liquidator_fee,
+            slots_elapsed,
+            liquidation_duration,
+        )
+    }
+
     pub fn get_unrealized_asset_weight(
         &self,
         unrealized_pnl: i128,
@@ -867,12 +1047,20 @@ pub struct AMM {
     /// expo for unit of per_lp, base 10 (if per_lp_base=X, then per_lp unit is 10^X)
     pub per_lp_base: i8,
     pub padding1: u8,
-    pub padding2: u16,
+    /// the most the interest rate component can move funding away from the premium index, per day
+    /// precision: PERCENTAGE_PRECISION
+    pub funding_premium_clamp: u16,
     pub total_fee_earned_per_lp: u64,
     pub net_unsettled_funding_pnl: i64,
     pub quote_asset_amount_with_unsettled_lp: i64,
     pub reference_price_offset: i32,
-    pub padding: [u8; 12],
+    /// the daily interest rate funding is pulled towards while the premium index is within funding_premium_clamp of it.
+    /// while both are 0, funding adds the legacy 1/FUNDING_RATE_OFFSET_DENOMINATOR daily offset instead
+    /// precision: PERCENTAGE_PRECISION
+    pub funding_interest_rate: i32,
+    /// time-weighted average of the bid/ask premium over the oracle since the last funding rate update
+    /// precision: PRICE_PRECISION
+    pub last_premium_index: i64,
 }
 
 impl Default for AMM {
@@ -956,12 +1144,13 @@ impl Default for AMM {
             target_base_asset_amount_per_lp: 0,
             per_lp_base: 0,
             padding1: 0,
-            padding2: 0,
+            funding_premium_clamp: 0,
             total_fee_earned_per_lp: 0,
             net_unsettled_funding_pnl: 0,
             quote_asset_amount_with_unsettled_lp: 0,
             reference_price_offset: 0,
-            padding: [0; 12],
+            funding_interest_rate: 0,
+            last_premium_index: 0,
         }
     }
 }
@@ -40,3 +40,106 @@ mod amm {
         assert_eq!(discount, 10000000); // $1
     }
 }
+
+mod get_max_position_base_asset_amount {
+    use crate::error::ErrorCode;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::{
+        BASE_PRECISION, BASE_PRECISION_I128, BASE_PRECISION_I64, BASE_PRECISION_U64,
+        PERCENTAGE_PRECISION, PRICE_PRECISION_I64, QUOTE_PRECISION_U64,
+    };
+
+    #[test]
+    fn tightest_limit_applies() {
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_amount_long: 100_000 * BASE_PRECISION_I128,
+                base_asset_amount_short: -80_000 * BASE_PRECISION_I128,
+                ..AMM::default()
+            },
+            ..PerpMarket::default()
+        };
+
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            None
+        );
+        assert!(market
+            .validate_max_position_size(1_000_000 * BASE_PRECISION_I64, oracle_price)
+            .is_ok());
+
+        market.max_position_base_asset_amount = 50_000 * BASE_PRECISION_U64;
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(50_000 * BASE_PRECISION)
+        );
+
+        // $2M at $100 is 20k base
+        market.max_position_notional = 2_000_000 * QUOTE_PRECISION_U64;
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(20_000 * BASE_PRECISION)
+        );
+
+        // 10% of 100k base open interest
+        market.max_position_open_interest_fraction = (PERCENTAGE_PRECISION / 10) as u32;
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(10_000 * BASE_PRECISION)
+        );
+
+        assert!(market
+            .validate_max_position_size(-10_000 * BASE_PRECISION_I64, oracle_price)
+            .is_ok());
+        assert_eq!(
+            market.validate_max_position_size(-10_001 * BASE_PRECISION_I64, oracle_price),
+            Err(ErrorCode::MaxPositionSize)
+        );
+    }
+
+    #[test]
+    fn open_interest_fraction_is_floored() {
+        let mut market = PerpMarket {
+            max_position_open_interest_fraction: (PERCENTAGE_PRECISION / 10) as u32,
+            ..PerpMarket::default()
+        };
+
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        // 10% of the $1M open interest floor at $100
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(1_000 * BASE_PRECISION)
+        );
+
+        market.amm.base_asset_amount_long = 5_000 * BASE_PRECISION_I128;
+        market.amm.base_asset_amount_short = -5_000 * BASE_PRECISION_I128;
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(1_000 * BASE_PRECISION)
+        );
+
+        market.amm.base_asset_amount_long = 50_000 * BASE_PRECISION_I128;
+        market.amm.base_asset_amount_short = -50_000 * BASE_PRECISION_I128;
+        assert_eq!(
+            market
+                .get_max_position_base_asset_amount(oracle_price)
+                .unwrap(),
+            Some(5_000 * BASE_PRECISION)
+        );
+    }
+}
@@ -7,13 +7,14 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use crate::error::{DriftResult, ErrorCode};
 use crate::math::casting::Cast;
 use crate::math::constants::{
-    AMM_RESERVE_PRECISION, FIVE_MINUTE, MARGIN_PRECISION, ONE_HOUR, SPOT_WEIGHT_PRECISION_U128,
+    AMM_RESERVE_PRECISION, FIVE_MINUTE, MARGIN_PRECISION, MARGIN_PRECISION_U128, ONE_HOUR,
+    PERCENTAGE_PRECISION, SPOT_WEIGHT_PRECISION_U128,
 };
 #[cfg(test)]
 use crate::math::constants::{PRICE_PRECISION_I64, SPOT_CUMULATIVE_INTEREST_PRECISION};
 use crate::math::margin::{
     calculate_size_discount_asset_weight, calculate_size_premium_liability_weight,
-    MarginRequirementType,
+    calculate_volatility_margin_scale, MarginRequirementType,
 };
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::{calculate_utilization, get_token_amount, get_token_value};
@@ -178,7 +179,41 @@ pub struct SpotMarket {
     /// disabled when 0
     /// precision: QUOTE_PRECISION
     pub scale_initial_asset_weight_start: u64,
-    pub padding: [u8; 48],
+    /// The realized volatility at which the static asset and liability weights apply. Weight
+    /// haircuts scale with realized volatility relative to it, disabled when 0
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_reference: u16,
+    /// The smallest multiple of the static weight haircuts volatility scaling can produce
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_scale_floor: u16,
+    /// The largest multiple of the static weight haircuts volatility scaling can produce
+    /// precision: MARGIN_PRECISION
+    pub volatility_margin_scale_ceiling: u16,
+    /// How fast the adaptive borrow rate scale moves per day while utilization_twap is fully above
+    /// or below optimal_utilization, disabled when 0
+    /// precision: MARGIN_PRECISION
+    pub adaptive_borrow_rate_speed: u16,
+    /// The smallest multiple of the borrow rate curve the adaptive borrow rate scale can reach
+    /// precision: MARGIN_PRECISION
+    pub adaptive_borrow_rate_scale_floor: u16,
+    /// The largest multiple of the borrow rate curve the adaptive borrow rate scale can reach
+    /// precision: MARGIN_PRECISION
+    pub adaptive_borrow_rate_scale_ceiling: u16,
+    /// The current multiple applied to the borrow rates below max utilization
+    /// precision: PERCENTAGE_PRECISION
+    pub adaptive_borrow_rate_scale: u32,
+    /// Additional points on the borrow rate curve besides optimal and max utilization
+    /// Sorted by utilization, a kink with zero utilization is unused and must come last
+    pub borrow_rate_kinks: [BorrowRateKink; 3],
+    /// The share of interest paid by borrowers that goes to the revenue pool instead of lenders,
+    /// on top of the insurance fund's total_factor
+    /// precision: IF_FACTOR_PRECISION
+    pub reserve_factor: u32,
+    /// Realized volatility of the oracle, the one hour average of the oracle price's absolute
+    /// deviation from its twap relative to the twap
+    /// precision: MARGIN_PRECISION
+    pub oracle_realized_volatility: u16,
+    pub padding: [u8; 2],
 }
 
 impl Default for SpotMarket {
@@ -239,7 +274,17 @@ impl Default for SpotMarket {
             flash_loan_initial_token_amount: 0,
             total_swap_fee: 0,
             scale_initial_asset_weight_start: 0,
-            padding: [0; 48],
+            volatility_margin_reference: 0,
+            volatility_margin_scale_floor: 0,
+            volatility_margin_scale_ceiling: 0,
+            adaptive_borrow_rate_speed: 0,
+            adaptive_borrow_rate_scale_floor: 0,
+            adaptive_borrow_rate_scale_ceiling: 0,
+            adaptive_borrow_rate_scale: 0,
+            borrow_rate_kinks: [BorrowRateKink::default(); 3],
+            reserve_factor: 0,
+            oracle_realized_volatility: 0,
+            padding: [0; 2],
         }
     }
 }
@@ -323,6 +368,9 @@ impl SpotMarket {
             MarginRequirementType::Maintenance => self.maintenance_asset_weight,
         };
 
+        let default_asset_weight =
+            self.apply_volatility_margin_scale_to_asset_weight(default_asset_weight)?;
+
         let size_based_asset_weight = calculate_size_discount_asset_weight(
             size_in_amm_reserve_precision,
             self.imf_factor,
@@ -381,6 +429,9 @@ impl SpotMarket {
             MarginRequirementType::Maintenance => self.maintenance_liability_weight,
         };
 
+        let default_liability_weight =
+            self.apply_volatility_margin_scale_to_liability_weight(default_liability_weight)?;
+
         let size_based_liability_weight = calculate_size_premium_liability_weight(
             size_in_amm_reserve_precision,
             self.imf_factor,
@@ -393,6 +444,59 @@ impl SpotMarket {
         Ok(liability_weight)
     }
 
+    /// Multiple applied to the borrow rate curve below max utilization, 1x when adaptive borrow rates are disabled
+    /// precision: PERCENTAGE_PRECISION
+    pub fn get_adaptive_borrow_rate_scale(&self) -> u128 {
+        if self.adaptive_borrow_rate_speed == 0 || self.adaptive_borrow_rate_scale == 0 {
+            PERCENTAGE_PRECISION
+        } else {
+            self.adaptive_borrow_rate_scale.into()
+        }
+    }
+
+    /// Multiple of the static weight haircuts implied by the oracle's realized volatility
+    /// precision: MARGIN_PRECISION
+    pub fn get_volatility_margin_scale(&self) -> DriftResult<u128> {
+        calculate_volatility_margin_scale(
+            self.oracle_realized_volatility.cast()?,
+            self.volatility_margin_reference,
+            self.volatility_margin_scale_floor,
+            self.volatility_margin_scale_ceiling,
+        )
+    }
+
+    fn apply_volatility_margin_scale_to_asset_weight(&self, asset_weight: u32) -> DriftResult<u32> {
+        let volatility_margin_scale = self.get_volatility_margin_scale()?;
+        if volatility_margin_scale == MARGIN_PRECISION_U128 {
+            return Ok(asset_weight);
+        }
+
+        let haircut = SPOT_WEIGHT_PRECISION_U128
+            .saturating_sub(asset_weight.cast()?)
+            .safe_mul(volatility_margin_scale)?
+            .safe_div(MARGIN_PRECISION_U128)?;
+
+        SPOT_WEIGHT_PRECISION_U128.saturating_sub(haircut).cast()
+    }
+
+    fn apply_volatility_margin_scale_to_liability_weight(
+        &self,
+        liability_weight: u32,
+    ) -> DriftResult<u32> {
+        let volatility_margin_scale = self.get_volatility_margin_scale()?;
+        if volatility_margin_scale == MARGIN_PRECISION_U128 {
+            return Ok(liability_weight);
+        }
+
+        let premium = liability_weight
+            .cast::<u128>()?
+            .saturating_sub(SPOT_WEIGHT_PRECISION_U128)
+            .safe_mul(volatility_margin_scale)?
+            .safe_div(MARGIN_PRECISION_U128)?;
+
+        SPOT_WEIGHT_PRECISION_U128.safe_add(premium)?.cast()
+    }
+
     // get liability weight as if it were perp market margin requirement
     pub fn get_margin_ratio(
         &self,
@@ -599,6 +703,16 @@ pub enum AssetTier {
     Unlisted,
 }
 
+#[zero_copy(unsafe)]
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct BorrowRateKink {
+    /// precision: SPOT_UTILIZATION_PRECISION
+    pub utilization: u32,
+    /// precision: SPOT_RATE_PRECISION
+    pub borrow_rate: u32,
+}
+
 #[zero_copy(unsafe)]
 #[derive(Default, Eq, PartialEq, Debug)]
 #[repr(C)]
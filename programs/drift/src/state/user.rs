@@ -16,7 +16,8 @@ use crate::math::position::{
 };
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::{
-    get_signed_token_amount, get_strict_token_value, get_token_amount, get_token_value,
+    get_signed_token_amount, get_spot_balance, get_strict_token_value, get_token_amount,
+    get_token_value,
 };
 use crate::math::stats::calculate_rolling_sum;
 use crate::state::oracle::StrictOraclePrice;
@@ -32,6 +33,7 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::msg;
 use std::cmp::max;
 use std::fmt;
+use std::mem::size_of;
 use std::ops::Neg;
 use std::panic::Location;
 
@@ -45,11 +47,61 @@ pub enum UserStatus {
     Bankrupt = 0b00000010,
     ReduceOnly = 0b00000100,
     AdvancedLp = 0b00001000,
+    PortfolioMargin = 0b00010000,
+    SharedMargin = 0b00100000,
 }
 
 // implement SIZE const for User
 impl Size for User {
-    const SIZE: usize = 4376;
+    const SIZE: usize = 6840;
+}
+
+/// Size of user accounts created before orders and perp positions were extended.
+/// These accounts can't be loaded until they go through `migrate_user`
+pub const LEGACY_USER_SIZE: usize = 4376;
+const LEGACY_PERP_POSITION_SIZE: usize = 96;
+const LEGACY_ORDER_SIZE: usize = 96;
+
+/// Rewrites a legacy user account that has been reallocated to `User::SIZE` into the current layout.
+/// Orders and perp positions keep their legacy bytes as a prefix and the rest of each one is
+/// zeroed, as is everything after the legacy tail
+pub fn migrate_legacy_user_data(data: &mut [u8]) -> DriftResult {
+    validate!(
+        data.len() == User::SIZE,
+        ErrorCode::UserCantBeMigrated,
+        "user account must be reallocated to {} bytes before migration",
+        User::SIZE
+    )?;
+
+    let legacy_data = data[..LEGACY_USER_SIZE].to_vec();
+
+    // discriminator, authority, delegate, name and spot positions are unchanged
+    let header_size = 8 + 32 + 32 + 32 + 8 * size_of::<SpotPosition>();
+    let legacy_tail_start = header_size + 8 * LEGACY_PERP_POSITION_SIZE + 32 * LEGACY_ORDER_SIZE;
+
+    data[header_size..].fill(0);
+
+    let mut legacy_offset = header_size;
+    let mut offset = header_size;
+    for _ in 0..8 {
+        data[offset..offset + LEGACY_PERP_POSITION_SIZE].copy_from_slice(
+            &legacy_data[legacy_offset..legacy_offset + LEGACY_PERP_POSITION_SIZE],
+        );
+        legacy_offset += LEGACY_PERP_POSITION_SIZE;
+        offset += size_of::<PerpPosition>();
+    }
+
+    for _ in 0..32 {
+        data[offset..offset + LEGACY_ORDER_SIZE]
+            .copy_from_slice(&legacy_data[legacy_offset..legacy_offset + LEGACY_ORDER_SIZE]);
+        legacy_offset += LEGACY_ORDER_SIZE;
+        offset += size_of::<Order>();
+    }
+
+    let tail_size = LEGACY_USER_SIZE - legacy_tail_start;
+    data[offset..offset + tail_size].copy_from_slice(&legacy_data[legacy_tail_start..]);
+
+    Ok(())
 }
 
 #[account(zero_copy(unsafe))]
@@ -117,7 +169,13 @@ pub struct User {
     pub open_auctions: u8,
     /// Whether or not user has open order with auction
     pub has_open_auction: bool,
-    pub padding: [u8; 21],
+    pub padding1: [u8; 5],
+    /// The slot the user entered liquidation. Large perp positions are dutch auctioned from here
+    /// Defaults to zero when not being liquidated
+    pub liquidation_start_slot: u64,
+    pub padding: [u8; 8],
+    /// The user's fixed term loans and their open orders
+    pub fixed_term_positions: [FixedTermPosition; 4],
 }
 
 impl User {
@@ -137,6 +195,14 @@ impl User {
         self.status & (UserStatus::AdvancedLp as u8) > 0
     }
 
+    pub fn is_portfolio_margin(&self) -> bool {
+        self.status & (UserStatus::PortfolioMargin as u8) > 0
+    }
+
+    pub fn is_shared_margin(&self) -> bool {
+        self.status & (UserStatus::SharedMargin as u8) > 0
+    }
+
     pub fn add_user_status(&mut self, status: UserStatus) {
         self.status |= status as u8;
     }
@@ -240,6 +306,63 @@ impl User {
         Ok(&mut self.perp_positions[position_index])
     }
 
+    pub fn get_fixed_term_position_index(
+        &self,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> DriftResult<usize> {
+        self.fixed_term_positions
+            .iter()
+            .position(|fixed_term_position| {
+                !fixed_term_position.is_available()
+                    && fixed_term_position.market_index == market_index
+                    && fixed_term_position.maturity_ts == maturity_ts
+            })
+            .ok_or(ErrorCode::UserHasNoPositionInMarket)
+    }
+
+    pub fn get_fixed_term_position_mut(
+        &mut self,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> DriftResult<&mut FixedTermPosition> {
+        self.get_fixed_term_position_index(market_index, maturity_ts)
+            .map(move |index| &mut self.fixed_term_positions[index])
+    }
+
+    pub fn force_get_fixed_term_position_mut(
+        &mut self,
+        market_index: u16,
+        maturity_ts: i64,
+    ) -> DriftResult<&mut FixedTermPosition> {
+        let index = match self.get_fixed_term_position_index(market_index, maturity_ts) {
+            Ok(index) => index,
+            Err(_) => {
+                let index = self
+                    .fixed_term_positions
+                    .iter()
+                    .position(|fixed_term_position| fixed_term_position.is_available())
+                    .ok_or(ErrorCode::NoFixedTermPositionAvailable)?;
+
+                self.fixed_term_positions[index] = FixedTermPosition {
+                    market_index,
+                    maturity_ts,
+                    ..FixedTermPosition::default()
+                };
+
+                index
+            }
+        };
+
+        Ok(&mut self.fixed_term_positions[index])
+    }
+
+    pub fn has_fixed_term_position(&self) -> bool {
+        self.fixed_term_positions
+            .iter()
+            .any(|fixed_term_position| !fixed_term_position.is_available())
+    }
+
     pub fn get_order_index(&self, order_id: u32) -> DriftResult<usize> {
         self.orders
             .iter()
@@ -323,6 +446,7 @@ impl User {
 
         self.add_user_status(UserStatus::BeingLiquidated);
         self.liquidation_margin_freed = 0;
+        self.liquidation_start_slot = slot;
         self.last_active_slot = slot;
         Ok(get_then_update_id!(self, next_liquidation_id))
     }
@@ -331,6 +455,7 @@ impl User {
         self.remove_user_status(UserStatus::BeingLiquidated);
         self.remove_user_status(UserStatus::Bankrupt);
         self.liquidation_margin_freed = 0;
+        self.clear_liquidation_auctions();
     }
 
     pub fn enter_bankruptcy(&mut self) {
@@ -342,6 +467,59 @@ impl User {
         self.remove_user_status(UserStatus::BeingLiquidated);
         self.remove_user_status(UserStatus::Bankrupt);
         self.liquidation_margin_freed = 0;
+        self.clear_liquidation_auctions();
+    }
+
+    /// isolated positions keep their own liquidation state
+    fn clear_liquidation_auctions(&mut self) {
+        self.liquidation_start_slot = 0;
+        for position in self
+            .perp_positions
+            .iter_mut()
+            .filter(|position| !position.is_isolated())
+        {
+            position.remove_position_flag(PositionFlag::LiquidationAuction);
+        }
+    }
+
+    pub fn enter_isolated_position_liquidation(
+        &mut self,
+        market_index: u16,
+        slot: u64,
+    ) -> DriftResult<u16> {
+        if self.get_perp_position(market_index)?.is_being_liquidated() {
+            return self.next_liquidation_id.safe_sub(1);
+        }
+
+        let position = self.get_perp_position_mut(market_index)?;
+        position.add_position_flag(PositionFlag::BeingLiquidated);
+        position.liquidation_start_slot = slot;
+        position.liquidation_margin_freed = 0;
+        self.last_active_slot = slot;
+        Ok(get_then_update_id!(self, next_liquidation_id))
+    }
+
+    /// position may already be closed out and available by the time liquidation ends
+    pub fn exit_isolated_position_liquidation(&mut self, market_index: u16) {
+        if let Ok(position) = self.get_perp_position_mut(market_index) {
+            position.remove_position_flag(PositionFlag::BeingLiquidated);
+            position.remove_position_flag(PositionFlag::Bankrupt);
+            position.remove_position_flag(PositionFlag::LiquidationAuction);
+            position.liquidation_start_slot = 0;
+            position.liquidation_margin_freed = 0;
+        }
+    }
+
+    pub fn is_isolated_position_bankrupt(&self, market_index: u16) -> bool {
+        self.get_perp_position(market_index)
+            .is_ok_and(|position| position.is_bankrupt())
+    }
+
+    pub fn enter_isolated_position_bankruptcy(&mut self, market_index: u16) -> DriftResult {
+        let position = self.get_perp_position_mut(market_index)?;
+        position.remove_position_flag(PositionFlag::BeingLiquidated);
+        position.add_position_flag(PositionFlag::Bankrupt);
+        Ok(())
     }
 
     pub fn increment_margin_freed(&mut self, margin_free: u64) -> DriftResult {
@@ -349,6 +527,19 @@ impl User {
         Ok(())
     }
 
+    /// position may already be closed out and available after it's liquidated
+    pub fn increment_isolated_position_margin_freed(
+        &mut self,
+        market_index: u16,
+        margin_freed: u64,
+    ) -> DriftResult {
+        if let Ok(position) = self.get_perp_position_mut(market_index) {
+            position.liquidation_margin_freed =
+                position.liquidation_margin_freed.safe_add(margin_freed)?;
+        }
+        Ok(())
+    }
+
     pub fn update_last_active_slot(&mut self, slot: u64) {
         if !self.is_being_liquidated() {
             self.last_active_slot = slot;
@@ -373,11 +564,15 @@ impl User {
         self.open_orders = self.open_orders.saturating_sub(1);
         self.has_open_order = self.open_orders > 0;
         if is_auction {
-            self.open_auctions = self.open_auctions.saturating_sub(1);
-            self.has_open_auction = self.open_auctions > 0;
+            self.decrement_open_auctions();
         }
     }
 
+    pub fn decrement_open_auctions(&mut self) {
+        self.open_auctions = self.open_auctions.saturating_sub(1);
+        self.has_open_auction = self.open_auctions > 0;
+    }
+
     pub fn qualifies_for_withdraw_fee(&self, user_stats: &UserStats, slot: u64) -> bool {
         // only qualifies for user with recent last_active_slot (~25 seconds)
         if slot.saturating_sub(self.last_active_slot) >= 50 {
@@ -411,6 +606,26 @@ impl User {
         Ok(())
     }
 
+    pub fn update_portfolio_margin_status(&mut self, portfolio_margin: bool) -> DriftResult {
+        if portfolio_margin {
+            self.add_user_status(UserStatus::PortfolioMargin);
+        } else {
+            self.remove_user_status(UserStatus::PortfolioMargin);
+        }
+
+        Ok(())
+    }
+
+    pub fn update_shared_margin_status(&mut self, shared_margin: bool) -> DriftResult {
+        if shared_margin {
+            self.add_user_status(UserStatus::SharedMargin);
+        } else {
+            self.remove_user_status(UserStatus::SharedMargin);
+        }
+
+        Ok(())
+    }
+
     pub fn has_room_for_new_order(&self) -> bool {
         for order in self.orders.iter() {
             if order.status == OrderStatus::Init {
@@ -757,6 +972,28 @@ pub struct PerpPosition {
     /// The number of open orders
     pub open_orders: u8,
     pub per_lp_base: i8,
+    /// The quote collateral set aside for this position when it is margined in isolation.
+    /// Stored as a deposit balance in the quote spot market
+    /// precision: SPOT_BALANCE_PRECISION
+    pub isolated_position_scaled_balance: u64,
+    /// The slot an isolated position entered liquidation. Paces how much of it can be liquidated
+    pub liquidation_start_slot: u64,
+    /// The margin freed since an isolated position entered liquidation
+    /// precision: QUOTE_PRECISION
+    pub liquidation_margin_freed: u64,
+    /// Bit flags for the position, see PositionFlag
+    pub position_flag: u8,
+    pub padding: [u8; 7],
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum PositionFlag {
+    /// Position is margined with its own collateral instead of the user's cross collateral
+    IsolatedPosition = 0b00000001,
+    BeingLiquidated = 0b00000010,
+    Bankrupt = 0b00000100,
+    /// Position was large enough to be dutch auctioned when its liquidation started
+    LiquidationAuction = 0b00001000,
 }
 
 impl PerpPosition {
@@ -769,6 +1006,40 @@ impl PerpPosition {
             && !self.has_open_order()
             && !self.has_unsettled_pnl()
             && !self.is_lp()
+            && self.isolated_position_scaled_balance == 0
+    }
+
+    pub fn is_isolated(&self) -> bool {
+        self.position_flag & PositionFlag::IsolatedPosition as u8 > 0
+    }
+
+    pub fn is_being_liquidated(&self) -> bool {
+        self.position_flag & (PositionFlag::BeingLiquidated as u8 | PositionFlag::Bankrupt as u8)
+            > 0
+    }
+
+    pub fn is_bankrupt(&self) -> bool {
+        self.position_flag & PositionFlag::Bankrupt as u8 > 0
+    }
+
+    pub fn is_liquidation_auction(&self) -> bool {
+        self.position_flag & PositionFlag::LiquidationAuction as u8 > 0
+    }
+
+    pub fn add_position_flag(&mut self, flag: PositionFlag) {
+        self.position_flag |= flag as u8;
+    }
+
+    pub fn remove_position_flag(&mut self, flag: PositionFlag) {
+        self.position_flag &= !(flag as u8);
+    }
+
+    pub fn get_isolated_token_amount(&self, spot_market: &SpotMarket) -> DriftResult<u128> {
+        get_token_amount(
+            self.isolated_position_scaled_balance.cast()?,
+            spot_market,
+            &SpotBalanceType::Deposit,
+        )
     }
 
     pub fn is_open_position(&self) -> bool {
@@ -962,6 +1233,43 @@ impl PerpPosition {
     }
 }
 
+impl SpotBalance for PerpPosition {
+    fn market_index(&self) -> u16 {
+        QUOTE_SPOT_MARKET_INDEX
+    }
+
+    fn balance_type(&self) -> &SpotBalanceType {
+        &SpotBalanceType::Deposit
+    }
+
+    fn balance(&self) -> u128 {
+        self.isolated_position_scaled_balance as u128
+    }
+
+    fn increase_balance(&mut self, delta: u128) -> DriftResult {
+        self.isolated_position_scaled_balance = self
+            .isolated_position_scaled_balance
+            .safe_add(delta.cast()?)?;
+        Ok(())
+    }
+
+    fn decrease_balance(&mut self, delta: u128) -> DriftResult {
+        self.isolated_position_scaled_balance = self
+            .isolated_position_scaled_balance
+            .safe_sub(delta.cast()?)?;
+        Ok(())
+    }
+
+    fn update_balance_type(&mut self, balance_type: SpotBalanceType) -> DriftResult {
+        validate!(
+            balance_type == SpotBalanceType::Deposit,
+            ErrorCode::InvalidIsolatedPerpPosition,
+            "isolated position collateral cannot be borrowed"
+        )?;
+        Ok(())
+    }
+}
+
 pub(crate) type PerpPositions = [PerpPosition; 8];
 
 #[cfg(test)]
@@ -1004,6 +1312,74 @@ impl PerpPosition {
     }
 }
 
+#[zero_copy(unsafe)]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct FixedTermPosition {
+    /// The amount settled into the spot balance at maturity, principal plus the fixed interest.
+    /// Positive when lending, negative when borrowing
+    /// precision: token mint precision
+    pub amount_at_maturity: i64,
+    /// The unix timestamp the loan matures at
+    pub maturity_ts: i64,
+    /// The principal left to fill on the open order, 0 when there is no open order
+    /// precision: token mint precision
+    pub open_order_principal: u64,
+    /// The slot the open order was placed. Fills happen at the rate of the earlier order
+    pub open_order_slot: u64,
+    /// The annualized fixed rate of the open order. The least a lender accepts or the most a borrower pays
+    /// precision: SPOT_RATE_PRECISION
+    pub open_order_rate: u32,
+    /// The spot market the loan is denominated in
+    pub market_index: u16,
+    pub open_order_direction: FixedTermDirection,
+    pub padding: [u8; 1],
+}
+
+impl FixedTermPosition {
+    pub fn is_available(&self) -> bool {
+        self.amount_at_maturity == 0 && !self.has_open_order()
+    }
+
+    pub fn has_open_order(&self) -> bool {
+        self.open_order_principal != 0
+    }
+
+    pub fn is_matured(&self, now: i64) -> bool {
+        now >= self.maturity_ts
+    }
+
+    /// The spot position the amount at maturity settles into, so the loan can be margined like a spot balance
+    pub fn get_settled_spot_position(&self, spot_market: &SpotMarket) -> DriftResult<SpotPosition> {
+        let balance_type = if self.amount_at_maturity > 0 {
+            SpotBalanceType::Deposit
+        } else {
+            SpotBalanceType::Borrow
+        };
+
+        let scaled_balance = get_spot_balance(
+            self.amount_at_maturity.unsigned_abs().cast()?,
+            spot_market,
+            &balance_type,
+            balance_type == SpotBalanceType::Borrow,
+        )?;
+
+        Ok(SpotPosition {
+            scaled_balance: scaled_balance.cast()?,
+            market_index: self.market_index,
+            balance_type,
+            ..SpotPosition::default()
+        })
+    }
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, Default)]
+pub enum FixedTermDirection {
+    #[default]
+    Lend,
+    Borrow,
+}
+
 #[zero_copy(unsafe)]
 #[repr(C)]
 #[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
@@ -1065,7 +1441,40 @@ pub struct Order {
     pub trigger_condition: OrderTriggerCondition,
     /// How many slots the auction lasts
     pub auction_duration: u8,
-    pub padding: [u8; 3],
+    /// Bit flags for additional order configuration. See OrderBitFlag
+    pub bit_flags: u8,
+    /// Orders sharing a nonzero link group are one-cancels-the-other. Bracket children in the
+    /// group stay dormant until their parent order fills
+    pub link_group: u8,
+    /// What happens when the order takes liquidity from an order of the same authority
+    pub self_trade_prevention_mode: SelfTradePreventionMode,
+    /// How far the trigger price trails the oracle price. Only relevant for trailing stop orders
+    /// precision: PRICE_PRECISION or PERCENTAGE_PRECISION if the TrailingPercentage flag is set
+    pub trailing_offset: u64,
+    /// The unix timestamp the first slice of a twap order was released
+    pub twap_start_ts: i64,
+    /// Seconds between the release of twap slices
+    pub twap_interval: u32,
+    /// Number of slices a twap order is split into. 0 if the order isn't a twap order
+    pub twap_slices: u16,
+    /// Number of twap slices released to fillers so far
+    pub twap_slices_released: u16,
+    /// The smallest amount a single fill can be for the order, unless less is left unfilled
+    /// precision for perps: BASE_PRECISION
+    /// precision for spot: token mint precision
+    pub min_fill_base_asset_amount: u64,
+    /// The last slot the order can be filled in. 0 if the order doesn't expire by slot
+    pub max_slot: u64,
+    /// The unix timestamp before which the order is dormant and can't be filled. 0 if the order
+    /// is active once placed
+    pub good_after_ts: i64,
+    /// Size of the tranches an iceberg order offers to takers. 0 if the order isn't an iceberg order
+    /// precision for perps: BASE_PRECISION
+    /// precision for spot: token mint precision
+    pub display_base_asset_amount: u64,
+    /// What is left of an iceberg order's current tranche. Replenished from the rest of the order
+    /// once the tranche fills
+    pub visible_base_asset_amount: u64,
 }
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
@@ -1079,6 +1488,25 @@ impl Order {
         (self.max_ts - now).max(0)
     }
 
+    /// Dormant orders can't be filled or matched against until their good_after_ts
+    pub fn is_dormant(&self, now: i64) -> bool {
+        self.good_after_ts != 0 && now < self.good_after_ts
+    }
+
+    pub fn is_iceberg(&self) -> bool {
+        self.display_base_asset_amount != 0
+    }
+
+    /// The part of the unfilled amount a maker order offers to takers. Iceberg orders only offer
+    /// their current tranche
+    pub fn get_visible_base_asset_amount(&self, base_asset_amount_unfilled: u64) -> u64 {
+        if self.is_iceberg() {
+            base_asset_amount_unfilled.min(self.visible_base_asset_amount)
+        } else {
+            base_asset_amount_unfilled
+        }
+    }
+
     pub fn has_oracle_price_offset(self) -> bool {
         self.oracle_price_offset != 0
     }
@@ -1231,10 +1659,76 @@ impl Order {
     pub fn must_be_triggered(&self) -> bool {
         matches!(
             self.order_type,
-            OrderType::TriggerMarket | OrderType::TriggerLimit
+            OrderType::TriggerMarket | OrderType::TriggerLimit | OrderType::TrailingStop
         )
     }
 
+    pub fn is_trailing_stop(&self) -> bool {
+        self.order_type == OrderType::TrailingStop
+    }
+
+    pub fn is_twap(&self) -> bool {
+        self.twap_slices > 0
+    }
+
+    /// Twap orders can only be filled up to the slices released so far. Every slice but the last
+    /// is base_asset_amount / twap_slices rounded down to the step size
+    pub fn get_twap_base_asset_amount_available(&self, step_size: u64) -> DriftResult<u64> {
+        let base_asset_amount_released =
+            if !self.is_twap() || self.twap_slices_released >= self.twap_slices {
+                self.base_asset_amount
+            } else {
+                let slice_base_asset_amount = standardize_base_asset_amount(
+                    self.base_asset_amount.safe_div(self.twap_slices.cast()?)?,
+                    step_size,
+                )?;
+                slice_base_asset_amount.safe_mul(self.twap_slices_released.cast()?)?
+            };
+
+        Ok(base_asset_amount_released.saturating_sub(self.base_asset_amount_filled))
+    }
+
+    /// Fill or kill orders must be fully filled by the fill they are placed with
+    pub fn is_fill_or_kill(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::FillOrKill)
+    }
+
+    /// The smallest fill allowed for the order, capped at the amount available to fill
+    pub fn get_min_fill_base_asset_amount(&self, base_asset_amount_available: u64) -> u64 {
+        self.min_fill_base_asset_amount
+            .min(base_asset_amount_available)
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.link_group != 0
+    }
+
+    /// Bracket children can't be filled or triggered until their parent order fills
+    pub fn is_bracket_child(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::BracketChild)
+    }
+
+    /// Whether other is an open order that gets canceled once this order fills or triggers
+    pub fn is_one_cancels_the_other_with(&self, other: &Order) -> bool {
+        self.is_linked()
+            && other.status == OrderStatus::Open
+            && other.order_id != self.order_id
+            && other.link_group == self.link_group
+            && other.is_bracket_child() == self.is_bracket_child()
+    }
+
+    pub fn is_bit_flag_set(&self, flag: OrderBitFlag) -> bool {
+        self.bit_flags & (flag as u8) > 0
+    }
+
+    pub fn add_bit_flag(&mut self, flag: OrderBitFlag) {
+        self.bit_flags |= flag as u8;
+    }
+
+    pub fn remove_bit_flag(&mut self, flag: OrderBitFlag) {
+        self.bit_flags &= !(flag as u8);
+    }
+
     pub fn triggered(&self) -> bool {
         matches!(
             self.trigger_condition,
@@ -1264,7 +1758,10 @@ impl Order {
     pub fn is_market_order(&self) -> bool {
         matches!(
             self.order_type,
-            OrderType::Market | OrderType::TriggerMarket | OrderType::Oracle
+            OrderType::Market
+                | OrderType::TriggerMarket
+                | OrderType::Oracle
+                | OrderType::TrailingStop
         )
     }
 
@@ -1319,7 +1816,19 @@ impl Default for Order {
             auction_end_price: 0,
             auction_duration: 0,
             max_ts: 0,
-            padding: [0; 3],
+            bit_flags: 0,
+            link_group: 0,
+            self_trade_prevention_mode: SelfTradePreventionMode::None,
+            trailing_offset: 0,
+            twap_start_ts: 0,
+            twap_interval: 0,
+            twap_slices: 0,
+            twap_slices_released: 0,
+            min_fill_base_asset_amount: 0,
+            max_slot: 0,
+            good_after_ts: 0,
+            display_base_asset_amount: 0,
+            visible_base_asset_amount: 0,
         }
     }
 }
@@ -1345,6 +1854,33 @@ pub enum OrderType {
     TriggerLimit,
     /// Market order where the auction prices are oracle offsets
     Oracle,
+    /// Trigger market order whose trigger price trails the oracle price by trailing_offset
+    TrailingStop,
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum OrderBitFlag {
+    /// trailing_offset is a percentage of the oracle price rather than a price offset
+    TrailingPercentage = 0b00000001,
+    /// Order is a dormant bracket child waiting on the parent order in its link group to fill
+    BracketChild = 0b00000010,
+    /// Order must be fully filled in the fill it is placed with or not at all
+    FillOrKill = 0b00000100,
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, Default)]
+pub enum SelfTradePreventionMode {
+    /// Orders of the same authority can match. Orders of the same user account never match
+    #[default]
+    None,
+    /// Cancel the taker order
+    CancelTaker,
+    /// Cancel the maker order and keep matching the taker order against other makers
+    CancelMaker,
+    /// Cancel both orders
+    CancelBoth,
+    /// Decrement both orders by the smaller unfilled size and cancel the smaller order
+    DecrementAndCancel,
 }
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, Default)]
@@ -1373,7 +1909,7 @@ impl fmt::Display for MarketType {
 }
 
 #[account(zero_copy(unsafe))]
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Default, Eq, PartialEq, Debug)]
 #[repr(C)]
 pub struct UserStats {
     /// The authority for all of a users sub accounts
@@ -1413,37 +1949,86 @@ pub struct UserStats {
     /// Whether the user is a referrer. Sub account 0 can not be deleted if user is a referrer
     pub is_referrer: bool,
     pub disable_update_perp_bid_ask_twap: bool,
-    pub padding: [u8; 50],
-}
-
-impl Default for UserStats {
-    fn default() -> Self {
-        UserStats {
-            authority: Pubkey::default(),
-            referrer: Pubkey::default(),
-            fees: UserFees::default(),
-            next_epoch_ts: 0,
-            maker_volume_30d: 0,
-            taker_volume_30d: 0,
-            filler_volume_30d: 0,
-            last_maker_volume_30d_ts: 0,
-            last_taker_volume_30d_ts: 0,
-            last_filler_volume_30d_ts: 0,
-            if_staked_quote_asset_amount: 0,
-            number_of_sub_accounts: 0,
-            number_of_sub_accounts_created: 0,
-            is_referrer: false,
-            disable_update_perp_bid_ask_twap: false,
-            padding: [0; 50],
-        }
-    }
+    /// The number of sub accounts in the authority's shared margin group
+    pub number_of_shared_margin_sub_accounts: u16,
+    /// The positions held across all sub accounts in perp markets with a max position size
+    pub limited_perp_positions: [AuthorityPerpPosition; 2],
+    pub padding: [u8; 16],
 }
 
 impl Size for UserStats {
     const SIZE: usize = 240;
 }
 
+#[zero_copy(unsafe)]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct AuthorityPerpPosition {
+    /// The base asset amount summed across the authority's sub accounts
+    /// precision: BASE_PRECISION
+    pub base_asset_amount: i64,
+    pub market_index: u16,
+    pub padding: [u8; 6],
+}
+
+impl AuthorityPerpPosition {
+    pub fn is_for(&self, market_index: u16) -> bool {
+        self.market_index == market_index && !self.is_available()
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.base_asset_amount == 0
+    }
+}
+
 impl UserStats {
+    pub fn get_limited_perp_position_base_asset_amount(&self, market_index: u16) -> i64 {
+        self.limited_perp_positions
+            .iter()
+            .find(|position| position.is_for(market_index))
+            .map_or(0, |position| position.base_asset_amount)
+    }
+
+    pub fn can_update_limited_perp_position(&self, market_index: u16) -> bool {
+        self.limited_perp_positions
+            .iter()
+            .any(|position| position.is_for(market_index) || position.is_available())
+    }
+
+    /// Adds a sub account's fill to the authority's position in a market with a max position size
+    /// Returns the authority's base asset amount after the fill
+    pub fn update_limited_perp_position(
+        &mut self,
+        market_index: u16,
+        delta: i64,
+    ) -> DriftResult<i64> {
+        if delta == 0 {
+            return Ok(self.get_limited_perp_position_base_asset_amount(market_index));
+        }
+
+        let position_index = match self
+            .limited_perp_positions
+            .iter()
+            .position(|position| position.is_for(market_index))
+        {
+            Some(position_index) => position_index,
+            None => {
+                let position_index = self
+                    .limited_perp_positions
+                    .iter()
+                    .position(|position| position.is_available())
+                    .ok_or(ErrorCode::MaxNumberOfPositions)?;
+                self.limited_perp_positions[position_index].market_index = market_index;
+                position_index
+            }
+        };
+
+        let position = &mut self.limited_perp_positions[position_index];
+        position.base_asset_amount = position.base_asset_amount.safe_add(delta)?;
+
+        Ok(position.base_asset_amount)
+    }
+
     pub fn update_maker_volume_30d(&mut self, quote_asset_amount: u64, now: i64) -> DriftResult {
         let since_last = max(1_i64, now.safe_sub(self.last_maker_volume_30d_ts)?);
 
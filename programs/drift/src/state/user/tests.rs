@@ -1940,3 +1940,196 @@ mod get_user_stats_age_ts {
         assert_eq!(age, 0);
     }
 }
+
+mod is_one_cancels_the_other_with {
+    use crate::state::user::{Order, OrderBitFlag, OrderStatus};
+
+    #[test]
+    fn test() {
+        let order = Order {
+            status: OrderStatus::Open,
+            order_id: 1,
+            link_group: 1,
+            ..Order::default()
+        };
+
+        let sibling = Order {
+            order_id: 2,
+            ..order
+        };
+        assert!(order.is_one_cancels_the_other_with(&sibling));
+
+        // same order
+        assert!(!order.is_one_cancels_the_other_with(&order));
+
+        // different link group
+        let other_group = Order {
+            link_group: 2,
+            ..sibling
+        };
+        assert!(!order.is_one_cancels_the_other_with(&other_group));
+
+        // not open
+        let filled = Order {
+            status: OrderStatus::Filled,
+            ..sibling
+        };
+        assert!(!order.is_one_cancels_the_other_with(&filled));
+
+        // parent and bracket child aren't siblings
+        let bracket_child = Order {
+            bit_flags: OrderBitFlag::BracketChild as u8,
+            ..sibling
+        };
+        assert!(!order.is_one_cancels_the_other_with(&bracket_child));
+        assert!(bracket_child.is_one_cancels_the_other_with(&Order {
+            order_id: 3,
+            ..bracket_child
+        }));
+
+        // unlinked orders are never siblings
+        let unlinked = Order {
+            link_group: 0,
+            ..order
+        };
+        assert!(!unlinked.is_one_cancels_the_other_with(&Order {
+            order_id: 2,
+            ..unlinked
+        }));
+    }
+}
+
+mod get_twap_base_asset_amount_available {
+    use crate::math::constants::BASE_PRECISION_U64;
+    use crate::state::user::Order;
+
+    #[test]
+    fn test() {
+        let step_size = BASE_PRECISION_U64 / 1000;
+        let mut order = Order {
+            base_asset_amount: 10 * BASE_PRECISION_U64,
+            twap_slices: 3,
+            twap_slices_released: 1,
+            twap_interval: 60,
+            ..Order::default()
+        };
+
+        // slice rounded down to step size
+        let slice = 3333 * step_size;
+        assert_eq!(
+            order
+                .get_twap_base_asset_amount_available(step_size)
+                .unwrap(),
+            slice
+        );
+
+        order.base_asset_amount_filled = slice;
+        assert_eq!(
+            order
+                .get_twap_base_asset_amount_available(step_size)
+                .unwrap(),
+            0
+        );
+
+        order.twap_slices_released = 2;
+        assert_eq!(
+            order
+                .get_twap_base_asset_amount_available(step_size)
+                .unwrap(),
+            slice
+        );
+
+        // last slice releases the remainder
+        order.twap_slices_released = 3;
+        assert_eq!(
+            order
+                .get_twap_base_asset_amount_available(step_size)
+                .unwrap(),
+            10 * BASE_PRECISION_U64 - slice
+        );
+
+        // not a twap order
+        let order = Order {
+            base_asset_amount: 10 * BASE_PRECISION_U64,
+            base_asset_amount_filled: BASE_PRECISION_U64,
+            ..Order::default()
+        };
+        assert_eq!(
+            order
+                .get_twap_base_asset_amount_available(step_size)
+                .unwrap(),
+            9 * BASE_PRECISION_U64
+        );
+    }
+}
+
+mod migrate_legacy_user_data {
+    use crate::state::traits::Size;
+    use crate::state::user::{
+        migrate_legacy_user_data, Order, OrderStatus, PerpPosition, SpotPosition, User,
+        LEGACY_USER_SIZE,
+    };
+    use anchor_lang::prelude::Pubkey;
+    use anchor_lang::Discriminator;
+    use std::mem::size_of;
+
+    #[test]
+    fn legacy_fields_keep_their_values() {
+        let mut user = User {
+            authority: Pubkey::new_unique(),
+            next_order_id: 7,
+            last_active_slot: 9,
+            has_open_auction: true,
+            ..User::default()
+        };
+        user.spot_positions[0] = SpotPosition {
+            scaled_balance: 1,
+            ..SpotPosition::default()
+        };
+        user.perp_positions[7] = PerpPosition {
+            last_cumulative_funding_rate: 1,
+            base_asset_amount: 3,
+            market_index: 2,
+            open_orders: 1,
+            per_lp_base: -1,
+            ..PerpPosition::default()
+        };
+        user.orders[31] = Order {
+            slot: 4,
+            status: OrderStatus::Open,
+            order_id: 5,
+            auction_duration: 10,
+            ..Order::default()
+        };
+
+        let mut expected = User::discriminator().to_vec();
+        expected.extend_from_slice(bytemuck::bytes_of(&user));
+        assert_eq!(expected.len(), User::SIZE);
+
+        // build the legacy account by dropping the fields appended to each struct
+        let header_size = 8 + 32 + 32 + 32 + 8 * size_of::<SpotPosition>();
+        let mut data = expected[..header_size].to_vec();
+        let mut offset = header_size;
+        for _ in 0..8 {
+            data.extend_from_slice(&expected[offset..offset + 96]);
+            offset += size_of::<PerpPosition>();
+        }
+        for _ in 0..32 {
+            data.extend_from_slice(&expected[offset..offset + 96]);
+            offset += size_of::<Order>();
+        }
+        data.extend_from_slice(&expected[offset..offset + 112]);
+        assert_eq!(data.len(), LEGACY_USER_SIZE);
+
+        data.resize(User::SIZE, u8::MAX);
+        migrate_legacy_user_data(&mut data).unwrap();
+
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn must_be_reallocated_first() {
+        let mut data = vec![0_u8; LEGACY_USER_SIZE];
+        assert!(migrate_legacy_user_data(&mut data).is_err());
+    }
+}
@@ -4,7 +4,8 @@ use bytes::BytesMut;
 
 use pyth::pc::Price;
 
-use crate::state::user::{Order, PerpPosition, SpotPosition};
+use crate::state::traits::Size;
+use crate::state::user::{migrate_legacy_user_data, Order, PerpPosition, SpotPosition, User};
 
 pub fn get_positions(position: PerpPosition) -> [PerpPosition; 8] {
     let mut positions = [PerpPosition::default(); 8];
@@ -33,6 +34,15 @@ macro_rules! get_orders {
     };
 }
 
+/// Re-lays out serialized legacy user account bytes so they can be loaded with the current User
+/// layout. Everything added since then is zeroed
+pub fn upgrade_legacy_user_account_bytes(legacy_bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = legacy_bytes.to_vec();
+    bytes.resize(User::SIZE, 0);
+    migrate_legacy_user_data(&mut bytes).unwrap();
+    bytes
+}
+
 pub fn get_spot_positions(spot_position: SpotPosition) -> [SpotPosition; 8] {
     let mut spot_positions = [SpotPosition::default(); 8];
     if spot_position.market_index == 0 {
@@ -1,8 +1,10 @@
 use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
 use crate::math::constants::{
-    LIQUIDATION_FEE_TO_MARGIN_PRECISION_RATIO, MAX_MARGIN_RATIO, MIN_MARGIN_RATIO,
-    SPOT_IMF_PRECISION, SPOT_WEIGHT_PRECISION,
+    LIQUIDATION_FEE_TO_MARGIN_PRECISION_RATIO, MARGIN_PRECISION, MAX_MARGIN_RATIO,
+    MIN_MARGIN_RATIO, SPOT_IMF_PRECISION, SPOT_WEIGHT_PRECISION,
 };
+use crate::math::safe_math::SafeMath;
 use crate::validate;
 use solana_program::msg;
 
@@ -120,3 +122,58 @@ pub fn validate_margin_weights(
 
     Ok(())
 }
+
+pub fn validate_volatility_margin_scale(
+    volatility_margin_reference: u16,
+    volatility_margin_scale_floor: u16,
+    volatility_margin_scale_ceiling: u16,
+) -> DriftResult {
+    if volatility_margin_reference == 0 {
+        return Ok(());
+    }
+
+    validate!(
+        volatility_margin_scale_floor > 0
+            && u32::from(volatility_margin_scale_floor) <= MARGIN_PRECISION
+            && u32::from(volatility_margin_scale_ceiling) >= MARGIN_PRECISION,
+        ErrorCode::InvalidMarginRatio,
+        "volatility margin scale floor ({}) must be in (0, {}] and ceiling ({}) at least {}",
+        volatility_margin_scale_floor,
+        MARGIN_PRECISION,
+        volatility_margin_scale_ceiling,
+        MARGIN_PRECISION
+    )?;
+
+    Ok(())
+}
+
+/// Volatility scaling can shrink the maintenance margin ratio down to its floor, where it must
+/// still cover the liquidator and insurance fund fees paid out of it
+pub fn validate_volatility_margin_scale_floor(
+    margin_ratio_maintenance: u32,
+    volatility_margin_reference: u16,
+    volatility_margin_scale_floor: u16,
+    liquidation_fee: u32,
+    if_liquidation_fee: u32,
+) -> DriftResult {
+    if volatility_margin_reference == 0 {
+        return Ok(());
+    }
+
+    let margin_ratio_maintenance_floor = margin_ratio_maintenance
+        .safe_mul(volatility_margin_scale_floor.cast()?)?
+        .safe_div(MARGIN_PRECISION)?
+        .max(MIN_MARGIN_RATIO);
+
+    validate!(
+        margin_ratio_maintenance_floor.safe_mul(LIQUIDATION_FEE_TO_MARGIN_PRECISION_RATIO)?
+            > liquidation_fee.safe_add(if_liquidation_fee)?,
+        ErrorCode::InvalidMarginRatio,
+        "margin_ratio_maintenance at volatility scale floor ({}) must be greater than liquidator fee ({}) + if liquidation fee ({})",
+        margin_ratio_maintenance_floor,
+        liquidation_fee,
+        if_liquidation_fee
+    )?;
+
+    Ok(())
+}
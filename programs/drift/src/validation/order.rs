@@ -7,9 +7,11 @@ use crate::math::casting::Cast;
 use crate::math::orders::{
     calculate_base_asset_amount_to_fill_up_to_limit_price, is_multiple_of_step_size,
 };
+use crate::math::safe_math::SafeMath;
 use crate::state::perp_market::PerpMarket;
-use crate::state::user::{Order, OrderTriggerCondition, OrderType};
+use crate::state::user::{Order, OrderBitFlag, OrderStatus, OrderTriggerCondition, OrderType};
 use crate::validate;
+use crate::PERCENTAGE_PRECISION_U64;
 
 pub fn validate_order(
     order: &Order,
@@ -17,6 +19,12 @@ pub fn validate_order(
     valid_oracle_price: Option<i64>,
     slot: u64,
 ) -> DriftResult {
+    validate_trailing_offset(order)?;
+    validate_twap_params(order, market.amm.order_step_size, market.amm.min_order_size)?;
+    validate_min_fill_params(order, market.amm.order_step_size)?;
+    validate_time_window_params(order)?;
+    validate_iceberg_params(order, market.amm.order_step_size, market.amm.min_order_size)?;
+
     match order.order_type {
         OrderType::Market => {
             validate_market_order(order, market.amm.order_step_size, market.amm.min_order_size)?
@@ -35,6 +43,11 @@ pub fn validate_order(
         OrderType::Oracle => {
             validate_oracle_order(order, market.amm.order_step_size, market.amm.min_order_size)?
         }
+        OrderType::TrailingStop => validate_trailing_stop_order(
+            order,
+            market.amm.order_step_size,
+            market.amm.min_order_size,
+        )?,
     }
 
     Ok(())
@@ -340,6 +353,216 @@ fn validate_trigger_market_order(
     Ok(())
 }
 
+fn validate_trailing_stop_order(order: &Order, step_size: u64, min_order_size: u64) -> DriftResult {
+    validate_base_asset_amount(order, step_size, min_order_size, order.reduce_only)?;
+
+    if !matches!(
+        order.trigger_condition,
+        OrderTriggerCondition::Above | OrderTriggerCondition::Below
+    ) {
+        msg!("Invalid trigger condition, must be Above or Below");
+        return Err(ErrorCode::InvalidTriggerOrderCondition);
+    }
+
+    if order.trailing_offset == 0 {
+        msg!("Trailing stop order trailing_offset == 0");
+        return Err(ErrorCode::InvalidOrderTrailingOffset);
+    }
+
+    if order.is_bit_flag_set(OrderBitFlag::TrailingPercentage)
+        && order.trailing_offset >= PERCENTAGE_PRECISION_U64
+    {
+        msg!(
+            "Trailing stop order trailing_offset ({}) must be less than 100%",
+            order.trailing_offset
+        );
+        return Err(ErrorCode::InvalidOrderTrailingOffset);
+    }
+
+    if order.price > 0 {
+        msg!("Trailing stop order should not have price");
+        return Err(ErrorCode::InvalidOrderLimitPrice);
+    }
+
+    if order.trigger_price == 0 {
+        msg!("Trailing stop order trigger_price == 0");
+        return Err(ErrorCode::InvalidOrderTrigger);
+    }
+
+    if order.post_only {
+        msg!("Trailing stop order can not be post only");
+        return Err(ErrorCode::InvalidOrderPostOnly);
+    }
+
+    if order.has_oracle_price_offset() {
+        msg!("Trailing stop order can not have oracle offset");
+        return Err(ErrorCode::InvalidOrderOracleOffset);
+    }
+
+    Ok(())
+}
+
+fn validate_trailing_offset(order: &Order) -> DriftResult {
+    validate!(
+        order.is_trailing_stop()
+            || (order.trailing_offset == 0
+                && !order.is_bit_flag_set(OrderBitFlag::TrailingPercentage)),
+        ErrorCode::InvalidOrderTrailingOffset,
+        "Only trailing stop orders can have a trailing offset"
+    )?;
+
+    Ok(())
+}
+
+fn validate_twap_params(order: &Order, step_size: u64, min_order_size: u64) -> DriftResult {
+    if !order.is_twap() {
+        validate!(
+            order.twap_interval == 0,
+            ErrorCode::InvalidOrderTwap,
+            "Only twap orders can have a twap interval"
+        )?;
+
+        return Ok(());
+    }
+
+    validate!(
+        order.order_type == OrderType::Market,
+        ErrorCode::InvalidOrderTwap,
+        "Twap order must be a market order"
+    )?;
+
+    validate!(
+        order.twap_interval > 0,
+        ErrorCode::InvalidOrderTwap,
+        "Twap order must have a twap interval"
+    )?;
+
+    validate!(
+        !order.post_only,
+        ErrorCode::InvalidOrderTwap,
+        "Twap order can not be post only"
+    )?;
+
+    let slice_base_asset_amount = order
+        .base_asset_amount
+        .safe_div(order.twap_slices.cast()?)?;
+    validate!(
+        slice_base_asset_amount >= step_size.max(min_order_size),
+        ErrorCode::InvalidOrderTwap,
+        "Twap slice base_asset_amount ({}) < step_size ({}) or min_order_size ({})",
+        slice_base_asset_amount,
+        step_size,
+        min_order_size
+    )?;
+
+    Ok(())
+}
+
+fn validate_min_fill_params(order: &Order, step_size: u64) -> DriftResult {
+    if order.is_fill_or_kill() {
+        validate!(
+            order.immediate_or_cancel,
+            ErrorCode::InvalidOrderMinFill,
+            "Fill or kill order must be immediate or cancel"
+        )?;
+
+        validate!(
+            !order.post_only,
+            ErrorCode::InvalidOrderMinFill,
+            "Fill or kill order can not be post only"
+        )?;
+    }
+
+    if order.min_fill_base_asset_amount == 0 {
+        return Ok(());
+    }
+
+    validate!(
+        order.min_fill_base_asset_amount <= order.base_asset_amount,
+        ErrorCode::InvalidOrderMinFill,
+        "min_fill_base_asset_amount ({}) > base_asset_amount ({})",
+        order.min_fill_base_asset_amount,
+        order.base_asset_amount
+    )?;
+
+    validate!(
+        is_multiple_of_step_size(order.min_fill_base_asset_amount, step_size)?,
+        ErrorCode::InvalidOrderMinFill,
+        "min_fill_base_asset_amount ({}) not a multiple of step size ({})",
+        order.min_fill_base_asset_amount,
+        step_size
+    )?;
+
+    Ok(())
+}
+
+fn validate_time_window_params(order: &Order) -> DriftResult {
+    if order.good_after_ts == 0 {
+        return Ok(());
+    }
+
+    // auctions start at the order slot so they would run out while the order is dormant
+    validate!(
+        order.auction_duration == 0,
+        ErrorCode::InvalidOrderTimeWindow,
+        "Order with an auction can not have good_after_ts"
+    )?;
+
+    validate!(
+        !order.immediate_or_cancel,
+        ErrorCode::InvalidOrderTimeWindow,
+        "Immediate or cancel order can not have good_after_ts"
+    )?;
+
+    validate!(
+        order.max_ts == 0 || order.good_after_ts < order.max_ts,
+        ErrorCode::InvalidOrderTimeWindow,
+        "good_after_ts ({}) must be before max_ts ({})",
+        order.good_after_ts,
+        order.max_ts
+    )?;
+
+    Ok(())
+}
+
+fn validate_iceberg_params(order: &Order, step_size: u64, min_order_size: u64) -> DriftResult {
+    if !order.is_iceberg() {
+        return Ok(());
+    }
+
+    validate!(
+        order.order_type == OrderType::Limit && !order.immediate_or_cancel,
+        ErrorCode::InvalidOrderIceberg,
+        "Iceberg order must be a resting limit order"
+    )?;
+
+    validate!(
+        !order.is_twap(),
+        ErrorCode::InvalidOrderIceberg,
+        "Iceberg order can not be a twap order"
+    )?;
+
+    validate!(
+        order.display_base_asset_amount <= order.base_asset_amount,
+        ErrorCode::InvalidOrderIceberg,
+        "display_base_asset_amount ({}) > base_asset_amount ({})",
+        order.display_base_asset_amount,
+        order.base_asset_amount
+    )?;
+
+    validate!(
+        order.display_base_asset_amount >= min_order_size
+            && is_multiple_of_step_size(order.display_base_asset_amount, step_size)?,
+        ErrorCode::InvalidOrderIceberg,
+        "display_base_asset_amount ({}) must be at least the min order size ({}) and a multiple of step size ({})",
+        order.display_base_asset_amount,
+        min_order_size,
+        step_size
+    )?;
+
+    Ok(())
+}
+
 fn validate_base_asset_amount(
     order: &Order,
     step_size: u64,
@@ -428,6 +651,12 @@ fn validate_auction_params(order: &Order) -> DriftResult {
 }
 
 pub fn validate_spot_order(order: &Order, step_size: u64, min_order_size: u64) -> DriftResult {
+    validate_trailing_offset(order)?;
+    validate_twap_params(order, step_size, min_order_size)?;
+    validate_min_fill_params(order, step_size)?;
+    validate_time_window_params(order)?;
+    validate_iceberg_params(order, step_size, min_order_size)?;
+
     match order.order_type {
         OrderType::Market => validate_market_order(order, step_size, min_order_size)?,
         OrderType::Limit => validate_spot_limit_order(order, step_size, min_order_size)?,
@@ -436,6 +665,7 @@ pub fn validate_spot_order(order: &Order, step_size: u64, min_order_size: u64) -
         }
         OrderType::TriggerLimit => validate_trigger_limit_order(order, step_size, min_order_size)?,
         OrderType::Oracle => validate_oracle_order(order, step_size, min_order_size)?,
+        OrderType::TrailingStop => validate_trailing_stop_order(order, step_size, min_order_size)?,
     }
 
     Ok(())
@@ -499,3 +729,47 @@ pub fn validate_order_for_force_reduce_only(order: &Order, existing_position: i6
 
     Ok(())
 }
+
+pub fn validate_order_link_group(order: &Order, user_orders: &[Order]) -> DriftResult {
+    if !order.is_linked() {
+        validate!(
+            !order.is_bracket_child(),
+            ErrorCode::InvalidOrderLinkGroup,
+            "Bracket child order must have a link group"
+        )?;
+
+        return Ok(());
+    }
+
+    let mut has_parent = false;
+    for linked_order in user_orders.iter().filter(|linked_order| {
+        linked_order.status == OrderStatus::Open
+            && linked_order.link_group == order.link_group
+            && linked_order.order_id != order.order_id
+    }) {
+        validate!(
+            linked_order.market_type == order.market_type
+                && linked_order.market_index == order.market_index,
+            ErrorCode::InvalidOrderLinkGroup,
+            "Linked orders must be in the same market"
+        )?;
+
+        if linked_order.is_bracket_child() != order.is_bracket_child() {
+            validate!(
+                linked_order.direction != order.direction,
+                ErrorCode::InvalidOrderLinkGroup,
+                "Bracket child orders must be in the opposite direction of their parent"
+            )?;
+
+            has_parent |= order.is_bracket_child();
+        }
+    }
+
+    validate!(
+        !order.is_bracket_child() || has_parent,
+        ErrorCode::InvalidOrderLinkGroup,
+        "Bracket child order must be placed after its parent order"
+    )?;
+
+    Ok(())
+}
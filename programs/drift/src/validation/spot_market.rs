@@ -1,5 +1,7 @@
 use crate::error::{DriftResult, ErrorCode};
-use crate::math::constants::SPOT_UTILIZATION_PRECISION_U32;
+use crate::math::constants::{MARGIN_PRECISION, SPOT_UTILIZATION_PRECISION_U32};
+use crate::math::spot_balance::get_borrow_rate_curve;
+use crate::state::spot_market::BorrowRateKink;
 use crate::validate;
 use solana_program::msg;
 
@@ -25,3 +27,101 @@ pub fn validate_borrow_rate(
 
     Ok(())
 }
+
+/// Kinks are kept sorted by utilization with unused kinks last, so accruing interest can walk the
+/// curve without sorting it
+pub fn validate_borrow_rate_kinks(
+    optimal_utilization: u32,
+    optimal_borrow_rate: u32,
+    max_borrow_rate: u32,
+    borrow_rate_kinks: &[BorrowRateKink],
+) -> DriftResult {
+    let mut previous_utilization = 0_u32;
+    for kink in borrow_rate_kinks.iter() {
+        if kink.utilization == 0 {
+            validate!(
+                kink.borrow_rate == 0,
+                ErrorCode::InvalidSpotMarketInitialization,
+                "For spot market, unused borrow rate kink must have zero borrow rate ({})",
+                kink.borrow_rate
+            )?;
+
+            previous_utilization = SPOT_UTILIZATION_PRECISION_U32;
+            continue;
+        }
+
+        validate!(
+            kink.utilization > previous_utilization,
+            ErrorCode::InvalidSpotMarketInitialization,
+            "For spot market, borrow rate kinks must be sorted by utilization ({}) with unused kinks last",
+            kink.utilization
+        )?;
+        previous_utilization = kink.utilization;
+
+        validate!(
+            kink.utilization < SPOT_UTILIZATION_PRECISION_U32
+                && kink.utilization != optimal_utilization,
+            ErrorCode::InvalidSpotMarketInitialization,
+            "For spot market, borrow rate kink utilization ({}) must be < {} and differ from optimal utilization ({})",
+            kink.utilization,
+            SPOT_UTILIZATION_PRECISION_U32,
+            optimal_utilization
+        )?;
+
+        validate!(
+            kink.borrow_rate <= max_borrow_rate,
+            ErrorCode::InvalidSpotMarketInitialization,
+            "For spot market, borrow rate kink rate ({}) must be <= max borrow rate ({})",
+            kink.borrow_rate,
+            max_borrow_rate
+        )?;
+    }
+
+    let mut previous_point: Option<(u32, u32)> = None;
+    for (end_utilization, end_borrow_rate) in get_borrow_rate_curve(
+        optimal_utilization,
+        optimal_borrow_rate,
+        max_borrow_rate,
+        borrow_rate_kinks,
+    ) {
+        if let Some((start_utilization, start_borrow_rate)) = previous_point {
+            validate!(
+                start_borrow_rate <= end_borrow_rate,
+                ErrorCode::InvalidSpotMarketInitialization,
+                "For spot market, borrow rate ({}) at utilization {} must be <= borrow rate ({}) at utilization {}",
+                start_borrow_rate,
+                start_utilization,
+                end_borrow_rate,
+                end_utilization
+            )?;
+        }
+
+        previous_point = Some((end_utilization, end_borrow_rate));
+    }
+
+    Ok(())
+}
+
+pub fn validate_adaptive_borrow_rate(
+    adaptive_borrow_rate_speed: u16,
+    adaptive_borrow_rate_scale_floor: u16,
+    adaptive_borrow_rate_scale_ceiling: u16,
+) -> DriftResult {
+    if adaptive_borrow_rate_speed == 0 {
+        return Ok(());
+    }
+
+    validate!(
+        adaptive_borrow_rate_scale_floor > 0
+            && u32::from(adaptive_borrow_rate_scale_floor) <= MARGIN_PRECISION
+            && u32::from(adaptive_borrow_rate_scale_ceiling) >= MARGIN_PRECISION,
+        ErrorCode::InvalidSpotMarketInitialization,
+        "adaptive borrow rate scale floor ({}) must be in (0, {}] and ceiling ({}) at least {}",
+        adaptive_borrow_rate_scale_floor,
+        MARGIN_PRECISION,
+        adaptive_borrow_rate_scale_ceiling,
+        MARGIN_PRECISION
+    )?;
+
+    Ok(())
+}
@@ -54,6 +54,12 @@ pub fn validate_user_deletion(
         )?;
     }
 
+    validate!(
+        !user.has_fixed_term_position(),
+        ErrorCode::UserCantBeDeleted,
+        "user has a fixed term position or order"
+    )?;
+
     if state.max_initialize_user_fee > 0 {
         let estimated_user_stats_age = user_stats.get_age_ts(now);
         if estimated_user_stats_age < THIRTEEN_DAY {
@@ -132,5 +138,11 @@ pub fn validate_user_is_idle(user: &User, slot: u64, accelerated: bool) -> Drift
         )?;
     }
 
+    validate!(
+        !user.has_fixed_term_position(),
+        ErrorCode::UserNotInactive,
+        "user has a fixed term position or order"
+    )?;
+
     Ok(())
 }
@@ -1,4 +1,6 @@
 import {
+	FixedTermDirection,
+	FixedTermPosition,
 	MarketType,
 	Order,
 	OrderStatus,
@@ -6,6 +8,7 @@ import {
 	OrderType,
 	PerpPosition,
 	PositionDirection,
+	SelfTradePreventionMode,
 	SpotBalanceType,
 	SpotPosition,
 	UserAccount,
@@ -90,7 +93,7 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			quoteAssetAmount.eq(ZERO) &&
 			lpShares.eq(ZERO)
 		) {
-			offset += 96;
+			offset += 128;
 			continue;
 		}
 
@@ -116,6 +119,17 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		offset += 3;
 		const perLpBase = buffer.readUInt8(offset);
 		offset += 1;
+		const isolatedPositionScaledBalance = readUnsignedBigInt64LE(
+			buffer,
+			offset
+		);
+		offset += 8;
+		const liquidationStartSlot = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const liquidationMarginFreed = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const positionFlag = buffer.readUInt8(offset);
+		offset += 8;
 
 		perpPositions.push({
 			lastCumulativeFundingRate,
@@ -133,6 +147,10 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			marketIndex,
 			openOrders,
 			perLpBase,
+			isolatedPositionScaledBalance,
+			liquidationStartSlot,
+			liquidationMarginFreed,
+			positionFlag,
 		});
 	}
 
@@ -140,7 +158,7 @@ export function decodeUser(buffer: Buffer): UserAccount {
 	for (let i = 0; i < 32; i++) {
 		// skip order if it's not open
 		if (buffer.readUint8(offset + 82) === 0) {
-			offset += 96;
+			offset += 160;
 			continue;
 		}
 
@@ -189,6 +207,8 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			orderType = OrderType.TRIGGER_LIMIT;
 		} else if (orderTypeNum === 4) {
 			orderType = OrderType.ORACLE;
+		} else if (orderTypeNum === 5) {
+			orderType = OrderType.TRAILING_STOP;
 		}
 		offset += 1;
 		const marketTypeNum = buffer.readUInt8(offset);
@@ -237,7 +257,44 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		offset += 1;
 		const auctionDuration = buffer.readUInt8(offset);
 		offset += 1;
-		offset += 3; // padding
+		const bitFlags = buffer.readUInt8(offset);
+		offset += 1;
+		const linkGroup = buffer.readUInt8(offset);
+		offset += 1;
+		const selfTradePreventionModeNum = buffer.readUInt8(offset);
+		let selfTradePreventionMode: SelfTradePreventionMode;
+		if (selfTradePreventionModeNum === 0) {
+			selfTradePreventionMode = SelfTradePreventionMode.NONE;
+		} else if (selfTradePreventionModeNum === 1) {
+			selfTradePreventionMode = SelfTradePreventionMode.CANCEL_TAKER;
+		} else if (selfTradePreventionModeNum === 2) {
+			selfTradePreventionMode = SelfTradePreventionMode.CANCEL_MAKER;
+		} else if (selfTradePreventionModeNum === 3) {
+			selfTradePreventionMode = SelfTradePreventionMode.CANCEL_BOTH;
+		} else if (selfTradePreventionModeNum === 4) {
+			selfTradePreventionMode = SelfTradePreventionMode.DECREMENT_AND_CANCEL;
+		}
+		offset += 1;
+		const trailingOffset = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const twapStartTs = readSignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const twapInterval = buffer.readUInt32LE(offset);
+		offset += 4;
+		const twapSlices = buffer.readUInt16LE(offset);
+		offset += 2;
+		const twapSlicesReleased = buffer.readUInt16LE(offset);
+		offset += 2;
+		const minFillBaseAssetAmount = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const maxSlot = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const goodAfterTs = readSignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const displayBaseAssetAmount = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
+		const visibleBaseAssetAmount = readUnsignedBigInt64LE(buffer, offset);
+		offset += 8;
 		orders.push({
 			slot,
 			price,
@@ -263,6 +320,19 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			immediateOrCancel,
 			triggerCondition,
 			auctionDuration,
+			bitFlags,
+			linkGroup,
+			selfTradePreventionMode,
+			trailingOffset,
+			twapStartTs,
+			twapInterval,
+			twapSlices,
+			twapSlicesReleased,
+			minFillBaseAssetAmount,
+			maxSlot,
+			goodAfterTs,
+			displayBaseAssetAmount,
+			visibleBaseAssetAmount,
 		});
 	}
 
@@ -326,6 +396,42 @@ export function decodeUser(buffer: Buffer): UserAccount {
 	const hasOpenAuction = buffer.readUInt8(offset) === 1;
 	offset += 1;
 
+	offset += 5; // padding1
+
+	const liquidationStartSlot = readUnsignedBigInt64LE(buffer, offset);
+	offset += 8;
+
+	offset += 8; // padding
+
+	const fixedTermPositions: FixedTermPosition[] = [];
+	for (let i = 0; i < 4; i++) {
+		const amountAtMaturity = readSignedBigInt64LE(buffer, offset);
+		const maturityTs = readSignedBigInt64LE(buffer, offset + 8);
+		const openOrderPrincipal = readUnsignedBigInt64LE(buffer, offset + 16);
+		const openOrderSlot = readUnsignedBigInt64LE(buffer, offset + 24);
+		const openOrderRate = buffer.readUInt32LE(offset + 32);
+		const marketIndex = buffer.readUInt16LE(offset + 36);
+		const openOrderDirection =
+			buffer.readUInt8(offset + 38) === 0
+				? FixedTermDirection.LEND
+				: FixedTermDirection.BORROW;
+		offset += 40;
+
+		if (amountAtMaturity.eq(ZERO) && openOrderPrincipal.eq(ZERO)) {
+			continue;
+		}
+
+		fixedTermPositions.push({
+			amountAtMaturity,
+			maturityTs,
+			openOrderPrincipal,
+			openOrderSlot,
+			openOrderRate,
+			marketIndex,
+			openOrderDirection,
+		});
+	}
+
 	// @ts-ignore
 	return {
 		authority,
@@ -354,5 +460,7 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		hasOpenOrder,
 		openAuctions,
 		hasOpenAuction,
+		liquidationStartSlot,
+		fixedTermPositions,
 	};
 }
@@ -1316,6 +1316,36 @@ export class DriftClient {
 		});
 	}
 
+	/**
+	 * Reallocates a user account created before orders and perp positions were extended and
+	 * rewrites it into the current layout. The payer covers the additional rent
+	 */
+	public async migrateUser(
+		userAccountPublicKey: PublicKey,
+		txParams?: TxParams
+	): Promise<TransactionSignature> {
+		const ix = await this.getMigrateUserIx(userAccountPublicKey);
+
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(ix, txParams),
+			[],
+			this.opts
+		);
+
+		return txSig;
+	}
+
+	public async getMigrateUserIx(userAccountPublicKey: PublicKey) {
+		return await this.program.instruction.migrateUser({
+			accounts: {
+				user: userAccountPublicKey,
+				payer: this.wallet.publicKey,
+				rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
 	public getUser(subAccountId?: number, authority?: PublicKey): User {
 		subAccountId = subAccountId ?? this.activeSubAccountId;
 		authority = authority ?? this.authority;
@@ -55,6 +55,8 @@ export enum UserStatus {
 	BANKRUPT = 2,
 	REDUCE_ONLY = 4,
 	ADVANCED_LP = 8,
+	PORTFOLIO_MARGIN = 16,
+	SHARED_MARGIN = 32,
 }
 
 export class ContractType {
@@ -115,6 +117,7 @@ export class OrderType {
 	static readonly TRIGGER_LIMIT = { triggerLimit: {} };
 	static readonly MARKET = { market: {} };
 	static readonly ORACLE = { oracle: {} };
+	static readonly TRAILING_STOP = { trailingStop: {} };
 }
 
 export declare type MarketTypeStr = 'perp' | 'spot';
@@ -198,6 +201,25 @@ export class OrderTriggerCondition {
 	static readonly TRIGGERED_BELOW = { triggeredBelow: {} }; // below condition has been triggered
 }
 
+export class SelfTradePreventionMode {
+	static readonly NONE = { none: {} };
+	static readonly CANCEL_TAKER = { cancelTaker: {} };
+	static readonly CANCEL_MAKER = { cancelMaker: {} };
+	static readonly CANCEL_BOTH = { cancelBoth: {} };
+	static readonly DECREMENT_AND_CANCEL = { decrementAndCancel: {} };
+}
+
+export class OrderParamsBitFlag {
+	static readonly TRAILING_PERCENTAGE = 1;
+	static readonly BRACKET_CHILD = 2;
+	static readonly FILL_OR_KILL = 4;
+}
+
+export class FixedTermDirection {
+	static readonly LEND = { lend: {} };
+	static readonly BORROW = { borrow: {} };
+}
+
 export class SpotFulfillmentType {
 	static readonly EXTERNAL = { external: {} };
 	static readonly MATCH = { match: {} };
@@ -627,6 +649,14 @@ export type PerpMarketAccount = {
 	quoteSpotMarketIndex: number;
 	feeAdjustment: number;
 	pausedOperations: number;
+	volatilityMarginReference: number;
+	volatilityMarginScaleFloor: number;
+	volatilityMarginScaleCeiling: number;
+	maxPositionBaseAssetAmount: BN;
+	maxPositionNotional: BN;
+	maxPositionOpenInterestFraction: number;
+	liquidationAuctionEndLiquidatorFee: number;
+	liquidationAuctionMinNotional: BN;
 };
 
 export type HistoricalOracleData = {
@@ -722,6 +752,21 @@ export type SpotMarketAccount = {
 	pausedOperations: number;
 
 	ifPausedOperations: number;
+
+	volatilityMarginReference: number;
+	volatilityMarginScaleFloor: number;
+	volatilityMarginScaleCeiling: number;
+	adaptiveBorrowRateSpeed: number;
+	adaptiveBorrowRateScaleFloor: number;
+	adaptiveBorrowRateScaleCeiling: number;
+	adaptiveBorrowRateScale: number;
+	borrowRateKinks: BorrowRateKink[];
+	oracleRealizedVolatility: number;
+};
+
+export type BorrowRateKink = {
+	utilization: number;
+	borrowRate: number;
 };
 
 export type PoolBalance = {
@@ -822,6 +867,9 @@ export type AMM = {
 	netUnsettledFundingPnl: BN;
 	quoteAssetAmountWithUnsettledLp: BN;
 	referencePriceOffset: number;
+	fundingPremiumClamp: number;
+	fundingInterestRate: number;
+	lastPremiumIndex: BN;
 };
 
 // # User Account Types
@@ -841,6 +889,28 @@ export type PerpPosition = {
 	lastBaseAssetAmountPerLp: BN;
 	lastQuoteAssetAmountPerLp: BN;
 	perLpBase: number;
+	isolatedPositionScaledBalance: BN;
+	liquidationStartSlot: BN;
+	liquidationMarginFreed: BN;
+	positionFlag: number;
+};
+
+export type FixedTermPosition = {
+	amountAtMaturity: BN;
+	maturityTs: BN;
+	openOrderPrincipal: BN;
+	openOrderSlot: BN;
+	openOrderRate: number;
+	marketIndex: number;
+	openOrderDirection: FixedTermDirection;
+};
+
+export type FixedTermPoolAccount = {
+	scaledBalance: BN;
+	maturityTs: BN;
+	lendAmountAtMaturity: BN;
+	borrowAmountAtMaturity: BN;
+	marketIndex: number;
 };
 
 export type UserStatsAccount = {
@@ -864,6 +934,13 @@ export type UserStatsAccount = {
 	isReferrer: boolean;
 	authority: PublicKey;
 	ifStakedQuoteAssetAmount: BN;
+	numberOfSharedMarginSubAccounts: number;
+	limitedPerpPositions: AuthorityPerpPosition[];
+};
+
+export type AuthorityPerpPosition = {
+	baseAssetAmount: BN;
+	marketIndex: number;
 };
 
 export type UserAccount = {
@@ -893,6 +970,8 @@ export type UserAccount = {
 	hasOpenOrder: boolean;
 	openAuctions: number;
 	hasOpenAuction: boolean;
+	liquidationStartSlot: BN;
+	fixedTermPositions: FixedTermPosition[];
 };
 
 export type SpotPosition = {
@@ -930,6 +1009,19 @@ export type Order = {
 	auctionStartPrice: BN;
 	auctionEndPrice: BN;
 	maxTs: BN;
+	bitFlags: number;
+	linkGroup: number;
+	selfTradePreventionMode: SelfTradePreventionMode;
+	trailingOffset: BN;
+	twapStartTs: BN;
+	twapInterval: number;
+	twapSlices: number;
+	twapSlicesReleased: number;
+	minFillBaseAssetAmount: BN;
+	maxSlot: BN;
+	goodAfterTs: BN;
+	displayBaseAssetAmount: BN;
+	visibleBaseAssetAmount: BN;
 };
 
 export type OrderParams = {
@@ -950,6 +1042,16 @@ export type OrderParams = {
 	maxTs: BN | null;
 	auctionStartPrice: BN | null;
 	auctionEndPrice: BN | null;
+	trailingOffset: BN | null;
+	bitFlags: number;
+	linkGroup: number;
+	selfTradePreventionMode: SelfTradePreventionMode;
+	twapSlices: number | null;
+	twapInterval: number | null;
+	minFillBaseAssetAmount: BN | null;
+	maxSlot: BN | null;
+	goodAfterTs: BN | null;
+	displayBaseAssetAmount: BN | null;
 };
 
 export class PostOnlyParams {
@@ -997,6 +1099,16 @@ export const DefaultOrderParams: OrderParams = {
 	maxTs: null,
 	auctionStartPrice: null,
 	auctionEndPrice: null,
+	trailingOffset: null,
+	bitFlags: 0,
+	linkGroup: 0,
+	selfTradePreventionMode: SelfTradePreventionMode.NONE,
+	twapSlices: null,
+	twapInterval: null,
+	minFillBaseAssetAmount: null,
+	maxSlot: null,
+	goodAfterTs: null,
+	displayBaseAssetAmount: null,
 };
 
 export type MakerInfo = {
@@ -279,6 +279,10 @@ export class User {
 			lastBaseAssetAmountPerLp: ZERO,
 			lastQuoteAssetAmountPerLp: ZERO,
 			perLpBase: 0,
+			isolatedPositionScaledBalance: ZERO,
+			liquidationStartSlot: ZERO,
+			liquidationMarginFreed: ZERO,
+			positionFlag: 0,
 		};
 	}
 
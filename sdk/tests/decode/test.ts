@@ -191,6 +191,14 @@ function testPerpPosition(anchor: PerpPosition, custom: PerpPosition) {
 	assert(anchor.lastQuoteAssetAmountPerLp.eq(custom.lastQuoteAssetAmountPerLp));
 	assert(anchor.openOrders === custom.openOrders);
 	assert(anchor.perLpBase === custom.perLpBase);
+	assert(
+		anchor.isolatedPositionScaledBalance.eq(
+			custom.isolatedPositionScaledBalance
+		)
+	);
+	assert(anchor.liquidationStartSlot.eq(custom.liquidationStartSlot));
+	assert(anchor.liquidationMarginFreed.eq(custom.liquidationMarginFreed));
+	assert(anchor.positionFlag === custom.positionFlag);
 }
 
 function* getOrders(orders: Order[]) {
@@ -230,6 +238,24 @@ function testOrder(anchor: Order, custom: Order) {
 	assert(anchor.auctionStartPrice.eq(custom.auctionStartPrice));
 	assert(anchor.auctionEndPrice.eq(custom.auctionEndPrice));
 	assert(anchor.maxTs.eq(custom.maxTs));
+	assert(anchor.bitFlags === custom.bitFlags);
+	assert(anchor.linkGroup === custom.linkGroup);
+	assert(
+		enumsAreEqual(
+			anchor.selfTradePreventionMode,
+			custom.selfTradePreventionMode
+		)
+	);
+	assert(anchor.trailingOffset.eq(custom.trailingOffset));
+	assert(anchor.twapStartTs.eq(custom.twapStartTs));
+	assert(anchor.twapInterval === custom.twapInterval);
+	assert(anchor.twapSlices === custom.twapSlices);
+	assert(anchor.twapSlicesReleased === custom.twapSlicesReleased);
+	assert(anchor.minFillBaseAssetAmount.eq(custom.minFillBaseAssetAmount));
+	assert(anchor.maxSlot.eq(custom.maxSlot));
+	assert(anchor.goodAfterTs.eq(custom.goodAfterTs));
+	assert(anchor.displayBaseAssetAmount.eq(custom.displayBaseAssetAmount));
+	assert(anchor.visibleBaseAssetAmount.eq(custom.visibleBaseAssetAmount));
 }
 
 function enumsAreEqual(e1: any, e2: any) {
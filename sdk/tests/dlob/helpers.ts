@@ -43,6 +43,10 @@ export const mockPerpPosition: PerpPosition = {
 	lastBaseAssetAmountPerLp: new BN(0),
 	lastQuoteAssetAmountPerLp: new BN(0),
 	perLpBase: 0,
+	isolatedPositionScaledBalance: new BN(0),
+	liquidationStartSlot: new BN(0),
+	liquidationMarginFreed: new BN(0),
+	positionFlag: 0,
 };
 
 export const mockAMM: AMM = {
@@ -9,6 +9,7 @@ import {
 	OrderType,
 	PositionDirection,
 	OrderTriggerCondition,
+	SelfTradePreventionMode,
 	UserAccount,
 	ZERO,
 } from '../../src';
@@ -40,6 +41,19 @@ export const mockOrder: Order = {
 	auctionStartPrice: ZERO,
 	auctionEndPrice: ZERO,
 	maxTs: ZERO,
+	bitFlags: 0,
+	linkGroup: 0,
+	selfTradePreventionMode: SelfTradePreventionMode.NONE,
+	trailingOffset: ZERO,
+	twapStartTs: ZERO,
+	twapInterval: 0,
+	twapSlices: 0,
+	twapSlicesReleased: 0,
+	minFillBaseAssetAmount: ZERO,
+	maxSlot: ZERO,
+	goodAfterTs: ZERO,
+	displayBaseAssetAmount: ZERO,
+	visibleBaseAssetAmount: ZERO,
 };
 
 export const mockSpotPosition: SpotPosition = {
@@ -85,4 +99,5 @@ export const mockUserAccount: UserAccount = {
 	hasOpenOrder: false,
 	openAuctions: 0,
 	hasOpenAuction: false,
+	fixedTermPositions: [],
 };